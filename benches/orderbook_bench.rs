@@ -1,5 +1,7 @@
 use criterion::{BatchSize, Criterion, black_box, criterion_group, criterion_main};
-use orderbook::{OrderTicket, OrderType, Side, book::Orderbook};
+use orderbook::{
+    OrderTicket, OrderType, Side, TimeInForce, book::Orderbook, command_log::replay_file,
+};
 
 const BASE_PRICE: i64 = 10_000;
 
@@ -10,6 +12,10 @@ fn seed_deep_book(ob: &mut Orderbook) {
             side: Side::Buy,
             size: 100,
             order_type: OrderType::Limit(BASE_PRICE - i),
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
         })
         .unwrap();
 
@@ -17,6 +23,10 @@ fn seed_deep_book(ob: &mut Orderbook) {
             side: Side::Sell,
             size: 100,
             order_type: OrderType::Limit(BASE_PRICE + 1 + i),
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
         })
         .unwrap();
     }
@@ -28,6 +38,10 @@ fn seed_book(ob: &mut Orderbook, levels: i64, size: i64) {
             side: Side::Buy,
             size,
             order_type: OrderType::Limit(10_000 - i),
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
         })
         .unwrap();
 
@@ -35,6 +49,10 @@ fn seed_book(ob: &mut Orderbook, levels: i64, size: i64) {
             side: Side::Sell,
             size,
             order_type: OrderType::Limit(10_001 + i),
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
         })
         .unwrap();
     }
@@ -59,6 +77,10 @@ fn bench_one_million_events(c: &mut Criterion) {
                             side: if i % 2 == 0 { Side::Buy } else { Side::Sell },
                             size: 10,
                             order_type: OrderType::Market,
+                            time_in_force: TimeInForce::Gtc,
+                            min_qty: None,
+                            owner: 0,
+                            iceberg: None,
                         }
                     } else {
                         // Tight spread-making around mid
@@ -72,6 +94,10 @@ fn bench_one_million_events(c: &mut Criterion) {
                             } else {
                                 BASE_PRICE + offset + 1
                             }),
+                            time_in_force: TimeInForce::Gtc,
+                            min_qty: None,
+                            owner: 0,
+                            iceberg: None,
                         }
                     };
 
@@ -99,6 +125,10 @@ fn bench_market_sweeps(c: &mut Criterion) {
                         side: Side::Buy,
                         size: 10_000, // sweep whole ask side
                         order_type: OrderType::Market,
+                        time_in_force: TimeInForce::Gtc,
+                        min_qty: None,
+                        owner: 0,
+                        iceberg: None,
                     })
                     .unwrap(),
                 );
@@ -118,6 +148,10 @@ fn bench_heavy_limit_insert(c: &mut Criterion) {
                         side: if i % 2 == 0 { Side::Buy } else { Side::Sell },
                         size: 1,
                         order_type: OrderType::Limit(10_000 + (i % 50) as i64),
+                        time_in_force: TimeInForce::Gtc,
+                        min_qty: None,
+                        owner: 0,
+                        iceberg: None,
                     })
                     .unwrap(),
                 );
@@ -141,18 +175,30 @@ fn bench_mixed_hft_flow(c: &mut Criterion) {
                             side: Side::Buy,
                             size: 5,
                             order_type: OrderType::Market,
+                            time_in_force: TimeInForce::Gtc,
+                            min_qty: None,
+                            owner: 0,
+                            iceberg: None,
                         }
                     } else if i % 5 == 1 {
                         OrderTicket {
                             side: Side::Sell,
                             size: 3,
                             order_type: OrderType::Market,
+                            time_in_force: TimeInForce::Gtc,
+                            min_qty: None,
+                            owner: 0,
+                            iceberg: None,
                         }
                     } else {
                         OrderTicket {
                             side: if i % 2 == 0 { Side::Buy } else { Side::Sell },
                             size: 1,
                             order_type: OrderType::Limit(10_000 + (i % 20) as i64),
+                            time_in_force: TimeInForce::Gtc,
+                            min_qty: None,
+                            owner: 0,
+                            iceberg: None,
                         }
                     };
 
@@ -176,6 +222,10 @@ fn bench_fifo_queue_depth(c: &mut Criterion) {
                         side: Side::Sell,
                         size: 1,
                         order_type: OrderType::Limit(10_000),
+                        time_in_force: TimeInForce::Gtc,
+                        min_qty: None,
+                        owner: 0,
+                        iceberg: None,
                     })
                     .unwrap();
                 }
@@ -188,6 +238,10 @@ fn bench_fifo_queue_depth(c: &mut Criterion) {
                         side: Side::Buy,
                         size: 20_000,
                         order_type: OrderType::Market,
+                        time_in_force: TimeInForce::Gtc,
+                        min_qty: None,
+                        owner: 0,
+                        iceberg: None,
                     })
                     .unwrap(),
                 );
@@ -212,12 +266,20 @@ fn bench_large_steady_state(c: &mut Criterion) {
                             side: Side::Buy,
                             size: 10,
                             order_type: OrderType::Market,
+                            time_in_force: TimeInForce::Gtc,
+                            min_qty: None,
+                            owner: 0,
+                            iceberg: None,
                         }
                     } else {
                         OrderTicket {
                             side: if i % 2 == 0 { Side::Buy } else { Side::Sell },
                             size: 2,
                             order_type: OrderType::Limit(10_000 + (i % 100) as i64),
+                            time_in_force: TimeInForce::Gtc,
+                            min_qty: None,
+                            owner: 0,
+                            iceberg: None,
                         }
                     };
 
@@ -229,6 +291,32 @@ fn bench_large_steady_state(c: &mut Criterion) {
     });
 }
 
+/// Replays a recording written by `FileLog` when `ORDERBOOK_REPLAY` points
+/// at one, so real flow can be benchmarked next to the synthetic loops
+fn bench_recorded_replay(c: &mut Criterion) {
+    let Ok(path) = std::env::var("ORDERBOOK_REPLAY") else {
+        return;
+    };
+    // decoding is not what we are measuring, do it once up front
+    let tickets: Vec<OrderTicket> = replay_file(&path)
+        .and_then(|stream| stream.collect())
+        .unwrap_or_else(|e| panic!("Cannot load {}: {}", path, e));
+
+    c.bench_function("recorded_replay", |b| {
+        b.iter_batched(
+            Orderbook::new,
+            |mut ob| {
+                for ticket in &tickets {
+                    // rejections were part of the recorded flow too
+                    let _ = black_box(ob.accept_order(ticket.clone()));
+                }
+                black_box(ob)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
 criterion_group!(
     benches,
     bench_one_million_events,
@@ -236,6 +324,7 @@ criterion_group!(
     bench_heavy_limit_insert,
     bench_mixed_hft_flow,
     bench_fifo_queue_depth,
-    bench_large_steady_state
+    bench_large_steady_state,
+    bench_recorded_replay
 );
 criterion_main!(benches);
@@ -0,0 +1,669 @@
+use std::collections::HashMap;
+
+use crate::{
+    Error, ExecType, ExecutionReport, OrderResponse, OrderTicket, OrderType, Side, book::Orderbook,
+    view::BookView,
+};
+
+/// A trading strategy driven by historical flow
+pub trait Strategy {
+    /// Called after each historical ticket has been applied to the book,
+    /// whatever tickets come back are sent through the real matching engine
+    fn on_market(&mut self, ticket: &OrderTicket, book: BookView<'_>) -> Vec<OrderTicket>;
+
+    /// Called whenever one of the strategy's own orders trades
+    fn on_fill(&mut self, _fill: &StrategyFill) {}
+}
+
+/// Called after every aggressive strategy fill so the book doesn't have to
+/// be infinitely resilient. It can slip the fill price by adjusting the
+/// notional and/or perturb the book, e.g. pull or reprice liquidity.
+pub trait MarketImpact {
+    fn impact(&mut self, fill: &mut StrategyFill, book: &mut Orderbook);
+}
+
+impl<F> MarketImpact for F
+where
+    F: FnMut(&mut StrategyFill, &mut Orderbook),
+{
+    fn impact(&mut self, fill: &mut StrategyFill, book: &mut Orderbook) {
+        self(fill, book)
+    }
+}
+
+/// One execution of a strategy order
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrategyFill {
+    pub side: Side,
+    pub size: i64,
+    pub notional: i64,
+    /// true when a resting strategy order was hit by historical flow
+    pub passive: bool,
+}
+
+/// An order the book refused, historical or the strategy's own
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejection {
+    pub ticket: OrderTicket,
+    pub reason: Error,
+    /// false for historical flow
+    pub strategy: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct BacktestReport {
+    pub fills: Vec<StrategyFill>,
+    /// every order the book refused, in the order it happened
+    pub rejections: Vec<Rejection>,
+    /// net base position, positive is long
+    pub position: i64,
+    /// quote spent (negative) or received (positive)
+    pub cash: i64,
+    /// the largest absolute position held at any point
+    pub max_exposure: i64,
+    /// price used to mark the final position, mid or last trade
+    pub mark_price: Option<i64>,
+}
+
+impl BacktestReport {
+    /// Cash plus the final position marked at `mark_price`
+    pub fn pnl(&self) -> i64 {
+        self.cash + self.position * self.mark_price.unwrap_or_default()
+    }
+
+    fn record(&mut self, fill: StrategyFill) {
+        match fill.side {
+            Side::Buy => {
+                self.position += fill.size;
+                self.cash -= fill.notional;
+            }
+            Side::Sell => {
+                self.position -= fill.size;
+                self.cash += fill.notional;
+            }
+        }
+        self.max_exposure = self.max_exposure.max(self.position.abs());
+        self.fills.push(fill);
+    }
+}
+
+/// How strategy orders get their fills
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum FillModel {
+    /// strategy orders go through the matching engine like everyone else
+    #[default]
+    Matching,
+    /// strategy orders never touch the book. Resting ones are filled from
+    /// their simulated queue position and aggressive ones off the top of book
+    QueuePosition,
+}
+
+/// A strategy order that only exists in the queue-position model
+struct VirtualOrder {
+    side: Side,
+    price: i64,
+    /// historical size in front of us at our level
+    queue_ahead: i64,
+    remaining: i64,
+}
+
+/// What we remember about a strategy order sitting in the book
+struct RestingOrder {
+    side: Side,
+    /// a stop trades as an aggressor once it fires
+    passive: bool,
+}
+
+/// Interleaves historical tickets with strategy orders on one book
+pub struct Backtest<S: Strategy> {
+    pub book: Orderbook,
+    pub strategy: S,
+    pub fill_model: FillModel,
+    market_impact: Option<Box<dyn MarketImpact>>,
+    /// strategy orders resting in the book by id, stops included
+    resting: HashMap<u64, RestingOrder>,
+    /// strategy orders queued alongside the book in `FillModel::QueuePosition`
+    virtual_orders: Vec<VirtualOrder>,
+    report: BacktestReport,
+}
+
+impl<S: Strategy> Backtest<S> {
+    pub fn new(book: Orderbook, strategy: S) -> Self {
+        Self::with_fill_model(book, strategy, FillModel::Matching)
+    }
+
+    pub fn with_fill_model(book: Orderbook, strategy: S, fill_model: FillModel) -> Self {
+        Self {
+            book,
+            strategy,
+            fill_model,
+            market_impact: None,
+            resting: HashMap::new(),
+            virtual_orders: Vec::new(),
+            report: BacktestReport::default(),
+        }
+    }
+
+    pub fn set_market_impact(&mut self, market_impact: impl MarketImpact + 'static) {
+        self.market_impact = Some(Box::new(market_impact));
+    }
+
+    /// Replay `history`, letting the strategy trade after every ticket.
+    /// Rejected orders are recorded in the report and the run goes on.
+    pub fn run(&mut self, history: impl IntoIterator<Item = OrderTicket>) -> BacktestReport {
+        for ticket in history {
+            let before: Vec<i64> = self
+                .virtual_orders
+                .iter()
+                .map(|order| self.book.size_at(order.side, order.price))
+                .collect();
+
+            let traded = match self.book.accept_order(ticket.clone()) {
+                Ok(OrderResponse::Market(response)) => response.size > 0,
+                Ok(OrderResponse::Limit(_)) => false,
+                Err(reason) => {
+                    self.reject(ticket.clone(), reason, false);
+                    false
+                }
+            };
+
+            self.collect_passive_fills();
+            if traded {
+                self.collect_queue_fills(ticket.side, &before);
+            } else {
+                self.shrink_queues();
+            }
+
+            for order in self.strategy.on_market(&ticket, self.book.view()) {
+                match self.fill_model {
+                    FillModel::Matching => self.submit(order),
+                    FillModel::QueuePosition => self.submit_virtual(order),
+                }
+            }
+        }
+
+        self.report.mark_price = self.book.reference_price();
+        std::mem::take(&mut self.report)
+    }
+
+    fn submit(&mut self, ticket: OrderTicket) {
+        let response = self.book.accept_order(ticket.clone());
+        // fills of our resting orders first, an aggressor hits them first
+        self.collect_passive_fills();
+
+        match response {
+            Ok(OrderResponse::Market(response)) => {
+                if let Some(id) = response.resting_id {
+                    self.track(id, ticket.side, true);
+                }
+                if response.size > 0 {
+                    self.aggressive_fill(StrategyFill {
+                        side: ticket.side,
+                        size: response.size,
+                        notional: response.notional,
+                        passive: false,
+                    });
+                }
+            }
+            Ok(OrderResponse::Limit(response)) => {
+                let passive = !matches!(ticket.order_type, OrderType::Stop { .. });
+                self.track(response.id, ticket.side, passive);
+            }
+            Err(reason) => self.reject(ticket, reason, true),
+        }
+    }
+
+    fn track(&mut self, id: u64, side: Side, passive: bool) {
+        self.resting.insert(id, RestingOrder { side, passive });
+    }
+
+    fn reject(&mut self, ticket: OrderTicket, reason: Error, strategy: bool) {
+        self.report.rejections.push(Rejection {
+            ticket,
+            reason,
+            strategy,
+        });
+    }
+
+    /// Take what we can off the top of book without touching it,
+    /// or join the back of the queue at our price
+    fn submit_virtual(&mut self, ticket: OrderTicket) {
+        let opposite = match ticket.side {
+            Side::Buy => self.book.get_best_ask(),
+            Side::Sell => self.book.get_best_bid(),
+        };
+
+        let crosses = match (&ticket.order_type, opposite) {
+            (OrderType::Market | OrderType::QuoteMarket, _) => true,
+            (OrderType::Limit(price) | OrderType::ImmediateOrCancel(price), Some(best)) => {
+                match ticket.side {
+                    Side::Buy => best.price <= *price,
+                    Side::Sell => best.price >= *price,
+                }
+            }
+            (OrderType::Limit(_) | OrderType::ImmediateOrCancel(_), None) => false,
+            // the queue model has no trigger book or repricing, so
+            // stops and pegs are dropped
+            (OrderType::Stop { .. } | OrderType::Pegged { .. }, _) => false,
+        };
+
+        if crosses {
+            if let Some(best) = opposite {
+                let size = match ticket.order_type {
+                    OrderType::QuoteMarket => ticket.size / best.price,
+                    _ => ticket.size,
+                }
+                .min(best.size);
+                self.aggressive_fill(StrategyFill {
+                    side: ticket.side,
+                    size,
+                    notional: size * best.price,
+                    passive: false,
+                });
+            }
+        } else if let OrderType::Limit(price) = ticket.order_type {
+            self.virtual_orders.push(VirtualOrder {
+                side: ticket.side,
+                price,
+                queue_ahead: self.book.size_at(ticket.side, price),
+                remaining: ticket.size,
+            });
+        }
+    }
+
+    /// An aggressor traded, whatever it took from our level goes to the
+    /// queue ahead of us first. If it printed through our price it would
+    /// have taken us entirely.
+    fn collect_queue_fills(&mut self, aggressor: Side, before: &[i64]) {
+        let mut fills = Vec::new();
+        let last_trade_price = self.book.last_trade_price;
+
+        for (order, before) in self.virtual_orders.iter_mut().zip(before) {
+            if order.side == aggressor {
+                continue;
+            }
+
+            let after = self.book.size_at(order.side, order.price);
+            let traded = (before - after).max(0);
+            let ahead = order.queue_ahead.min(traded);
+            order.queue_ahead -= ahead;
+
+            let traded_through = last_trade_price.is_some_and(|price| match order.side {
+                Side::Buy => price < order.price,
+                Side::Sell => price > order.price,
+            });
+
+            let size = if traded_through {
+                order.remaining
+            } else {
+                (traded - ahead).min(order.remaining)
+            };
+
+            if size > 0 {
+                order.remaining -= size;
+                fills.push(StrategyFill {
+                    side: order.side,
+                    size,
+                    notional: size * order.price,
+                    passive: true,
+                });
+            }
+        }
+
+        self.virtual_orders.retain(|order| order.remaining > 0);
+        self.shrink_queues();
+
+        for fill in fills {
+            self.fill(fill);
+        }
+    }
+
+    /// Size that leaves a level without trading was cancelled, and the
+    /// queue ahead of us can never be longer than the level itself
+    fn shrink_queues(&mut self) {
+        for order in self.virtual_orders.iter_mut() {
+            order.queue_ahead = order
+                .queue_ahead
+                .min(self.book.size_at(order.side, order.price));
+        }
+    }
+
+    /// Turn the book's execution reports for our orders into fills at
+    /// the price each one actually traded at. The book's other buffers
+    /// are drained along the way so a long run doesn't grow them.
+    fn collect_passive_fills(&mut self) {
+        let reports = self.book.drain_execution_reports();
+        self.book.drain_trades();
+        self.book.drain_level_updates();
+        self.book.drain_events();
+
+        for ExecutionReport {
+            order_id,
+            exec_type,
+            traded_size,
+            price,
+            ..
+        } in reports
+        {
+            let Some(order) = self.resting.get(&order_id) else {
+                continue;
+            };
+            let fill = StrategyFill {
+                side: order.side,
+                size: traded_size,
+                notional: traded_size * price,
+                passive: order.passive,
+            };
+            // filled in full or pulled, say by self-trade prevention
+            if exec_type != ExecType::PartialFill {
+                self.resting.remove(&order_id);
+            }
+            if traded_size > 0 {
+                self.fill(fill);
+            }
+        }
+    }
+
+    fn aggressive_fill(&mut self, mut fill: StrategyFill) {
+        if let Some(market_impact) = self.market_impact.as_mut() {
+            market_impact.impact(&mut fill, &mut self.book);
+        }
+        self.fill(fill);
+        // the impact may have traded with our own resting orders
+        self.collect_passive_fills();
+    }
+
+    fn fill(&mut self, fill: StrategyFill) {
+        self.strategy.on_fill(&fill);
+        self.report.record(fill);
+    }
+}
+
+/// Backtest independent symbols, each replaying its own history on a
+/// book and strategy `setup` builds for it. With the `rayon` feature the
+/// symbols run in parallel. Every symbol has a book of its own, so the
+/// reports come back the same either way, sorted by symbol.
+pub fn run_symbols<S, F>(
+    histories: Vec<(String, Vec<OrderTicket>)>,
+    setup: F,
+) -> Vec<(String, BacktestReport)>
+where
+    S: Strategy,
+    F: Fn(&str) -> Backtest<S> + Sync,
+{
+    let run = |(symbol, history): (String, Vec<OrderTicket>)| {
+        let report = setup(&symbol).run(history);
+        (symbol, report)
+    };
+
+    #[cfg(feature = "rayon")]
+    let mut reports: Vec<(String, BacktestReport)> = {
+        use rayon::prelude::*;
+        histories.into_par_iter().map(run).collect()
+    };
+    #[cfg(not(feature = "rayon"))]
+    let mut reports: Vec<(String, BacktestReport)> = histories.into_iter().map(run).collect();
+
+    // a stable sort keeps a symbol given twice in the order it came
+    reports.sort_by(|(a, _), (b, _)| a.cmp(b));
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TimeInForce;
+
+    fn limit(side: Side, price: i64, size: i64) -> OrderTicket {
+        OrderTicket {
+            side,
+            size,
+            order_type: OrderType::Limit(price),
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        }
+    }
+
+    fn market(side: Side, size: i64) -> OrderTicket {
+        OrderTicket {
+            side,
+            size,
+            order_type: OrderType::Market,
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        }
+    }
+
+    /// Joins the bid once and lifts the offer once
+    #[derive(Default)]
+    struct JoinThenLift {
+        events: usize,
+        fills: usize,
+    }
+
+    impl Strategy for JoinThenLift {
+        fn on_market(&mut self, _ticket: &OrderTicket, book: BookView<'_>) -> Vec<OrderTicket> {
+            self.events += 1;
+            match self.events {
+                2 => vec![limit(Side::Buy, book.get_best_bid().unwrap().price, 5)],
+                4 => vec![market(Side::Buy, 2)],
+                _ => vec![],
+            }
+        }
+
+        fn on_fill(&mut self, _fill: &StrategyFill) {
+            self.fills += 1;
+        }
+    }
+
+    #[test]
+    fn strategy_orders_trade_against_historical_flow() {
+        let history = vec![
+            limit(Side::Buy, 100, 10),
+            limit(Side::Sell, 102, 10),
+            // hits the historical bid first, then 2 of our 5
+            market(Side::Sell, 12),
+            limit(Side::Buy, 99, 1),
+            // takes the rest of our bid
+            market(Side::Sell, 3),
+        ];
+
+        let mut backtest = Backtest::new(Orderbook::new(), JoinThenLift::default());
+        let report = backtest.run(history);
+
+        assert_eq!(
+            report.fills,
+            vec![
+                StrategyFill {
+                    side: Side::Buy,
+                    size: 2,
+                    notional: 200,
+                    passive: true,
+                },
+                StrategyFill {
+                    side: Side::Buy,
+                    size: 2,
+                    notional: 204,
+                    passive: false,
+                },
+                StrategyFill {
+                    side: Side::Buy,
+                    size: 3,
+                    notional: 300,
+                    passive: true,
+                },
+            ]
+        );
+        assert_eq!(report.position, 7);
+        assert_eq!(report.max_exposure, 7);
+        assert_eq!(report.cash, -704);
+
+        // last trade was our bid getting hit at 100
+        assert_eq!(report.mark_price, Some(100));
+        assert_eq!(report.pnl(), -4);
+        assert_eq!(backtest.strategy.fills, 3);
+    }
+
+    #[test]
+    fn symbols_run_independently_and_merge_in_order() {
+        let history = |price| {
+            vec![
+                limit(Side::Buy, price, 10),
+                limit(Side::Sell, price + 2, 10),
+                market(Side::Sell, 12),
+                limit(Side::Buy, price - 1, 1),
+                market(Side::Sell, 3),
+            ]
+        };
+        let histories: Vec<(String, Vec<OrderTicket>)> = [("ETH", 200), ("BTC", 100), ("SOL", 50)]
+            .into_iter()
+            .map(|(symbol, price)| (symbol.to_string(), history(price)))
+            .collect();
+
+        let reports = run_symbols(histories.clone(), |_| {
+            Backtest::new(Orderbook::new(), JoinThenLift::default())
+        });
+        let symbols: Vec<&str> = reports.iter().map(|(symbol, _)| symbol.as_str()).collect();
+        assert_eq!(symbols, vec!["BTC", "ETH", "SOL"]);
+
+        for (symbol, history) in histories {
+            let alone = Backtest::new(Orderbook::new(), JoinThenLift::default()).run(history);
+            let (_, report) = reports.iter().find(|(other, _)| *other == symbol).unwrap();
+            assert_eq!(report.fills, alone.fills);
+            assert_eq!(report.pnl(), alone.pnl());
+        }
+    }
+
+    /// Joins the bid once and lifts the offer once, without ever
+    /// touching the historical book
+    #[derive(Default)]
+    struct VirtualJoinThenLift {
+        events: usize,
+    }
+
+    impl Strategy for VirtualJoinThenLift {
+        fn on_market(&mut self, _ticket: &OrderTicket, _book: BookView<'_>) -> Vec<OrderTicket> {
+            self.events += 1;
+            match self.events {
+                2 => vec![limit(Side::Buy, 100, 5)],
+                3 => vec![market(Side::Buy, 3)],
+                _ => vec![],
+            }
+        }
+    }
+
+    #[test]
+    fn queue_position_model_waits_for_the_queue_ahead() {
+        let history = vec![
+            limit(Side::Buy, 100, 10),
+            limit(Side::Sell, 102, 10),
+            // eats 4 of the 10 ahead of us
+            market(Side::Sell, 4),
+            // joins behind us, never in front
+            limit(Side::Buy, 100, 3),
+            // 6 ahead of us then 1 for us
+            market(Side::Sell, 7),
+            limit(Side::Buy, 99, 5),
+            // 2 more at our level, then prints through us at 99
+            market(Side::Sell, 3),
+        ];
+
+        let mut backtest = Backtest::with_fill_model(
+            Orderbook::new(),
+            VirtualJoinThenLift::default(),
+            FillModel::QueuePosition,
+        );
+        let report = backtest.run(history);
+
+        let passive: Vec<i64> = report
+            .fills
+            .iter()
+            .filter(|fill| fill.passive)
+            .map(|fill| fill.size)
+            .collect();
+        assert_eq!(passive, vec![1, 4]);
+
+        // lifted the offer at 102 without consuming it
+        assert_eq!(report.fills[0].notional, 3 * 102);
+        assert_eq!(backtest.book.size_at(Side::Sell, 102), 10);
+
+        assert_eq!(report.position, 8);
+        assert_eq!(report.cash, -(3 * 102 + 5 * 100));
+    }
+
+    #[test]
+    fn market_impact_slips_fills_and_moves_the_book() {
+        let history = vec![
+            limit(Side::Buy, 100, 10),
+            limit(Side::Sell, 102, 10),
+            limit(Side::Sell, 103, 10),
+            limit(Side::Buy, 99, 1),
+        ];
+
+        let mut backtest = Backtest::new(Orderbook::new(), JoinThenLift::default());
+        backtest.set_market_impact(|fill: &mut StrategyFill, book: &mut Orderbook| {
+            // one tick of slippage per unit and the offer fades behind us
+            fill.notional += fill.size;
+            book.accept_order(market(Side::Buy, 10)).unwrap();
+        });
+        let report = backtest.run(history);
+
+        assert_eq!(report.fills.len(), 1);
+        assert_eq!(report.fills[0].notional, 2 * 102 + 2);
+        assert_eq!(backtest.book.get_best_ask().unwrap().price, 103);
+    }
+
+    /// Sends a nonsense order and a buy stop on the first event
+    struct StopAndNonsense;
+
+    impl Strategy for StopAndNonsense {
+        fn on_market(&mut self, ticket: &OrderTicket, _book: BookView<'_>) -> Vec<OrderTicket> {
+            if ticket.order_type != OrderType::Limit(101) {
+                return vec![];
+            }
+            vec![
+                limit(Side::Buy, 0, 1),
+                OrderTicket {
+                    order_type: OrderType::Stop { trigger: 102 },
+                    ..market(Side::Buy, 2)
+                },
+            ]
+        }
+    }
+
+    #[test]
+    fn rejections_are_recorded_and_stops_fill_when_they_fire() {
+        let history = vec![
+            limit(Side::Sell, 101, 5),
+            limit(Side::Sell, 102, 5),
+            limit(Side::Buy, 0, 1),
+            // prints at 102, which sets our stop off
+            market(Side::Buy, 6),
+        ];
+
+        let mut backtest = Backtest::new(Orderbook::new(), StopAndNonsense);
+        let report = backtest.run(history);
+
+        let strategy: Vec<bool> = report
+            .rejections
+            .iter()
+            .map(|rejection| rejection.strategy)
+            .collect();
+        assert_eq!(strategy, vec![true, false]);
+        assert_eq!(
+            report.fills,
+            vec![StrategyFill {
+                side: Side::Buy,
+                size: 2,
+                notional: 2 * 102,
+                passive: false,
+            }]
+        );
+        assert_eq!(report.position, 2);
+        assert!(backtest.book.drain_trades().is_empty());
+    }
+}
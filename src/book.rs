@@ -1,32 +1,619 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, VecDeque},
+    sync::Arc,
+};
+
 use crate::{
-    LimitOrderResponse, MarketOrderResponse, OrderResponse, OrderTicket, OrderType, PriceSize,
-    Result, Side, half::HalfBook,
+    BookConfig, CancelResponse, DelayedOrder, EarlyCancelAction, Event, EventKind, ExecType,
+    ExecutionReport, Fill, Iceberg, L3Book, LevelSizes, LevelUpdate, LimitBreach,
+    LimitOrderResponse, LimitReject, LockedPolicy, MarketDataMode, MarketOrderResponse,
+    MarketPolicy, MarketStatus, MinRestingTime, MmpLimits, MmpTrigger, OrderResponse, OrderTicket,
+    OrderType, OwnerLimits, ParkedPeg, PegBreachAction, PegReference, PegReject, PeggedOrder,
+    PriceBand, PriceLimits, PriceMoveAction, PriceMoveGuard, PriceSize, QuoteLevel, ReleasedOrder,
+    ReplaceResponse, Result, SelfTradePrevention, SessionState, Side, SpeedBump, StatusEvent,
+    TimeInForce, Trade, TradeBust,
+    depth::DepthViews,
+    diagnostics::CrossedBookDetector,
+    digest::StateDigest,
+    half::{HalfBook, validate_iceberg},
+    lifecycle::{HistoryEntry, OrderHistory, OrderUpdate},
+    perp::FundingEvent,
+    quote_cache::{Quote, QuoteCache},
+    scale::SizeScale,
+    settlement::{FeeSchedule, PostTradeHook},
+    snapshot::BookSnapshot,
+    stats::{QuoteStats, TradeStats},
+    stop::{StopBook, StopOrder},
+    tick::TickTable,
+    view::BookView,
 };
 
-const MIN_PRICE: i64 = 1;
-const MAX_PRICE: i64 = 999999;
-const TICK_SIZE: i64 = 1;
+const CROSSED_BOOK_HISTORY: usize = 64;
+/// how many of the latest trades can still be busted
+const BUSTABLE_TRADES: usize = 10_000;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Orderbook {
     /// Bids are an arena
     pub bids: HalfBook,
     /// Asks are an arena
     pub asks: HalfBook,
 
-    pub event_log: Vec<OrderTicket>,
+    /// every input in the order it arrived, rejected ones included
+    pub event_log: Vec<Event>,
+    /// events handed out by `drain_events`, numbering carries on after them
+    pub events_drained: u64,
 
     pub current_id: u64,
+
+    /// price of the most recent match on either side
+    pub last_trade_price: Option<i64>,
+    /// every match since the last drain
+    trades: Vec<Trade>,
+    next_trade_id: u64,
+    /// the latest trades, oldest first, kept so they can be busted
+    recent_trades: VecDeque<Trade>,
+    /// trades taken back since the last drain
+    busts: Vec<TradeBust>,
+    /// fills and shortfalls of stops that fired since the last drain
+    stop_reports: Vec<ExecutionReport>,
+    /// levels that changed size since the last drain
+    level_updates: Vec<LevelUpdate>,
+    /// funding settled against the book since the last drain
+    funding_events: Vec<FundingEvent>,
+    /// stamped on trades, the caller's clock as of `set_clock`
+    pub clock: u64,
+    /// stop orders waiting on the last trade price
+    pub stops: StopBook,
+    /// resting orders repriced after every change to the book
+    pub pegs: Vec<PeggedOrder>,
+    /// what a peg priced off the ladder or outside the band does
+    pub peg_breach_action: PegBreachAction,
+    /// pegs off the book until their price is good again, oldest first
+    parked_pegs: Vec<ParkedPeg>,
+    /// pegs cancelled for breaching since the last drain
+    peg_rejects: Vec<PegReject>,
+    /// when day orders expire, in the caller's clock
+    pub day_end: Option<u64>,
+    /// (expiry, id) of every order that rested with one, soonest first
+    expiries: BinaryHeap<Reverse<(u64, u64)>>,
+    /// optional collar around the reference price for incoming limits
+    pub price_band: Option<PriceBand>,
+    /// clock when trading last resumed after a halt
+    pub resumed_at: Option<u64>,
+    /// optional LULD limits that aggressive orders cannot trade through
+    pub price_limits: Option<PriceLimits>,
+    /// optional cap on how far one sweep can move from the last trade
+    pub price_move_guard: Option<PriceMoveGuard>,
+    /// optional minimum time in the queue before an order can be cancelled
+    pub min_resting_time: Option<MinRestingTime>,
+    /// holds aggressive orders back before they match
+    pub speed_bump: Option<SpeedBump>,
+    /// aggressive orders waiting out the speed bump, soonest first
+    delayed_orders: Vec<DelayedOrder>,
+    /// delayed orders let through since the last drain
+    released_orders: Vec<ReleasedOrder>,
+    /// (clock, id) of cancels held until their order is old enough,
+    /// soonest first
+    queued_cancels: BinaryHeap<Reverse<(u64, u64)>>,
+    /// caps per owner, anyone not in here is uncapped
+    pub owner_limits: BTreeMap<u64, OwnerLimits>,
+    /// net position each owner has traded into, positive is long
+    positions: BTreeMap<u64, i64>,
+    /// maker rebates each owner accrued this session
+    rebates: BTreeMap<u64, i64>,
+    /// orders refused on owner limits since the last drain
+    limit_rejects: Vec<LimitReject>,
+    /// market maker protection per owner, anyone not in here is unchecked
+    pub mmp_limits: BTreeMap<u64, MmpLimits>,
+    /// (clock, size) of each protected owner's fills within their window
+    maker_fills: BTreeMap<u64, VecDeque<(u64, i64)>>,
+    /// owners protection tripped on during the order being handled
+    mmp_tripped: Vec<u64>,
+    /// protection tripping since the last drain
+    mmp_triggers: Vec<MmpTrigger>,
+
+    pub session_state: SessionState,
+    /// halts and resumes since the last drain
+    status_events: Vec<StatusEvent>,
+
+    /// how a limit that exactly locks the opposite best is handled
+    pub locked_policy: LockedPolicy,
+    /// whether level updates, depth views and the BBO leave hidden size out
+    pub market_data_mode: MarketDataMode,
+    /// whether market orders sweep or stop at the best level
+    pub market_policy: MarketPolicy,
+    /// what an aggressor meeting its own resting order does
+    pub self_trade_prevention: SelfTradePrevention,
+    /// sizes must be a multiple of this
+    pub lot_size: i64,
+    pub symbol: String,
+
+    /// how many decimals of the instrument one unit of size represents
+    pub size_scale: SizeScale,
+    /// orders smaller than this still trade but are not displayed
+    pub round_lot: Option<i64>,
+    /// what each side of a trade pays, see `set_post_trade_hook`
+    pub fee_schedule: FeeSchedule,
+
+    /// on by default in debug builds, opt in for long-running simulations
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub crossed_book_detector: Option<CrossedBookDetector>,
+
+    /// top of book published for readers on other threads
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub quote_cache: Option<Arc<QuoteCache>>,
+    /// depth at fixed resolutions, refreshed after every change
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub depth_views: Option<DepthViews>,
+    /// volatility and averages of every trade printed since enabled
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub trade_stats: Option<TradeStats>,
+    /// level churn and quote lifetimes since enabled
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub quote_stats: Option<QuoteStats>,
+    /// what happened to each order that rested since enabled
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub order_history: Option<OrderHistory>,
+    /// told about every change to the best bid or ask
+    #[cfg_attr(feature = "serde", serde(skip))]
+    bbo_observer: Option<BboObserver>,
+    /// told about every trade as it prints
+    #[cfg_attr(feature = "serde", serde(skip))]
+    post_trade_hook: Option<PostTradeHookBox>,
+    /// best bid and ask as of the last notification
+    #[cfg_attr(feature = "serde", serde(skip))]
+    last_bbo: (Option<PriceSize>, Option<PriceSize>),
+}
+
+/// Called with the new best bid and ask
+type BboCallback = Box<dyn FnMut(Option<PriceSize>, Option<PriceSize>) + Send>;
+
+struct BboObserver(BboCallback);
+
+impl std::fmt::Debug for BboObserver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BboObserver")
+    }
+}
+
+struct PostTradeHookBox(Box<dyn PostTradeHook + Send>);
+
+impl std::fmt::Debug for PostTradeHookBox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PostTradeHook")
+    }
+}
+
+impl Default for Orderbook {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Orderbook {
     pub fn new() -> Self {
+        let config = BookConfig::default();
+        Self::with_tick_table(
+            config.max_price,
+            TickTable::fixed(config.min_price, config.tick_size),
+        )
+    }
+
+    /// A book with its own price range, tick and lot size
+    pub fn with_config(config: BookConfig) -> Result<Self> {
+        if config.min_price <= 0 || config.max_price < config.min_price {
+            return Err(format!(
+                "Price range {} to {} must be positive and not empty",
+                config.min_price, config.max_price
+            ));
+        }
+        if config.tick_size <= 0 {
+            return Err(format!("Tick size {} must be positive", config.tick_size));
+        }
+        if config.lot_size <= 0 {
+            return Err(format!("Lot size {} must be positive", config.lot_size));
+        }
+
+        Ok(Self {
+            market_policy: config.market_policy,
+            self_trade_prevention: config.self_trade_prevention,
+            lot_size: config.lot_size,
+            symbol: config.symbol,
+            ..Self::with_tick_table(
+                config.max_price,
+                TickTable::fixed(config.min_price, config.tick_size),
+            )
+        })
+    }
+
+    /// A book whose tick size depends on the price band
+    pub fn with_tick_table(max_price: i64, tick_table: TickTable) -> Self {
         Self {
-            bids: HalfBook::new(Side::Buy, MAX_PRICE, MIN_PRICE, TICK_SIZE),
-            asks: HalfBook::new(Side::Sell, MAX_PRICE, MIN_PRICE, TICK_SIZE),
+            bids: HalfBook::with_tick_table(Side::Buy, max_price, tick_table.clone()),
+            asks: HalfBook::with_tick_table(Side::Sell, max_price, tick_table),
             event_log: Vec::with_capacity(1000),
+            events_drained: 0,
             current_id: 0,
+            last_trade_price: None,
+            trades: Vec::new(),
+            next_trade_id: 0,
+            recent_trades: VecDeque::new(),
+            busts: Vec::new(),
+            stop_reports: Vec::new(),
+            funding_events: Vec::new(),
+            level_updates: Vec::new(),
+            clock: 0,
+            stops: StopBook::default(),
+            pegs: Vec::new(),
+            peg_breach_action: PegBreachAction::default(),
+            parked_pegs: Vec::new(),
+            peg_rejects: Vec::new(),
+            day_end: None,
+            expiries: BinaryHeap::new(),
+            price_band: None,
+            resumed_at: None,
+            price_limits: None,
+            price_move_guard: None,
+            min_resting_time: None,
+            speed_bump: None,
+            delayed_orders: Vec::new(),
+            released_orders: Vec::new(),
+            queued_cancels: BinaryHeap::new(),
+            owner_limits: BTreeMap::new(),
+            positions: BTreeMap::new(),
+            rebates: BTreeMap::new(),
+            limit_rejects: Vec::new(),
+            mmp_limits: BTreeMap::new(),
+            maker_fills: BTreeMap::new(),
+            mmp_tripped: Vec::new(),
+            mmp_triggers: Vec::new(),
+            session_state: SessionState::Continuous,
+            status_events: Vec::new(),
+            locked_policy: LockedPolicy::default(),
+            market_data_mode: MarketDataMode::default(),
+            market_policy: MarketPolicy::default(),
+            self_trade_prevention: SelfTradePrevention::default(),
+            lot_size: 1,
+            symbol: String::new(),
+            size_scale: SizeScale::default(),
+            round_lot: None,
+            fee_schedule: FeeSchedule::default(),
+            crossed_book_detector: cfg!(debug_assertions)
+                .then(|| CrossedBookDetector::new(CROSSED_BOOK_HISTORY)),
+            quote_cache: None,
+            depth_views: None,
+            trade_stats: None,
+            quote_stats: None,
+            order_history: None,
+            bbo_observer: None,
+            post_trade_hook: None,
+            last_bbo: (None, None),
+        }
+    }
+
+    pub fn enable_crossed_book_detector(&mut self, history: usize) {
+        self.crossed_book_detector = Some(CrossedBookDetector::new(history));
+    }
+
+    /// Publish the top of book into `quote_cache` after every order
+    pub fn set_quote_cache(&mut self, quote_cache: Option<Arc<QuoteCache>>) {
+        self.quote_cache = quote_cache;
+        self.publish_changes();
+    }
+
+    /// Call `observer` with the best bid and ask whenever either of them
+    /// changes, price or size, after an order, cancel, replace or expiry.
+    /// Replaces any observer set before.
+    pub fn on_bbo_change(
+        &mut self,
+        observer: impl FnMut(Option<PriceSize>, Option<PriceSize>) + Send + 'static,
+    ) {
+        self.bbo_observer = Some(BboObserver(Box::new(observer)));
+        self.last_bbo = (self.get_best_bid(), self.get_best_ask());
+    }
+
+    /// Keep depth views at these resolutions up to date, e.g. `&[1, 10, 100]`
+    pub fn enable_depth_views(&mut self, resolutions: &[usize]) {
+        self.depth_views = Some(DepthViews::new(resolutions));
+        self.refresh_depth_views();
+    }
+
+    /// Track realized volatility, VWAP and TWAP over these windows, in
+    /// the book's clock, from every trade printed from now on
+    pub fn enable_trade_stats(&mut self, windows: &[u64]) {
+        self.trade_stats = Some(TradeStats::new(windows));
+    }
+
+    /// Track add and cancel churn per level and how long quotes last
+    /// from now on
+    pub fn enable_quote_stats(&mut self) {
+        self.quote_stats = Some(QuoteStats::new());
+    }
+
+    /// Keep every resting order's amendments and fills from now on, see
+    /// `order_history`
+    pub fn enable_order_history(&mut self) {
+        self.order_history = Some(OrderHistory::new());
+    }
+
+    /// What happened to an order since it rested, oldest first
+    pub fn order_history(&self, id: u64) -> Option<&[HistoryEntry]> {
+        self.order_history.as_ref()?.get(id)
+    }
+
+    fn record_update(&mut self, id: u64, update: OrderUpdate) {
+        if let Some(history) = &mut self.order_history {
+            history.record(id, self.clock, update);
+        }
+    }
+
+    pub fn set_locked_policy(&mut self, locked_policy: LockedPolicy) {
+        self.locked_policy = locked_policy;
+    }
+
+    /// Switch what market data shows from now on and republish the BBO
+    /// and depth views under it
+    pub fn set_market_data_mode(&mut self, market_data_mode: MarketDataMode) {
+        self.market_data_mode = market_data_mode;
+        self.publish_changes();
+        self.refresh_depth_views();
+    }
+
+    /// Advance the time trades are stamped with
+    pub fn set_clock(&mut self, now: u64) {
+        // logged at the new time so the halves age their orders by it
+        self.clock = now;
+        self.log(EventKind::SetClock(now));
+        if let Some(stats) = &mut self.trade_stats {
+            stats.evict(now);
+        }
+        self.release_queued_cancels(now);
+        self.release_delayed_orders(now);
+    }
+
+    /// Let through the delayed orders due by `now`. Each one is checked
+    /// and matched against the book as it is by then, like an order
+    /// arriving just now.
+    fn release_delayed_orders(&mut self, now: u64) {
+        let due = self
+            .delayed_orders
+            .partition_point(|delayed| delayed.release_at <= now);
+        if due == 0 {
+            return;
+        }
+
+        for delayed in self.delayed_orders.drain(..due).collect::<Vec<_>>() {
+            let result =
+                self.process_order_with_tif(delayed.ticket)
+                    .map(|response| match response {
+                        OrderResponse::Limit(limit) => limit.id,
+                        OrderResponse::Market(market) => market.id,
+                    });
+            self.fire_stops();
+            self.pull_tripped_quotes();
+            self.released_orders.push(ReleasedOrder {
+                delayed_id: delayed.id,
+                result,
+                timestamp: now,
+            });
+        }
+        self.reprice_pegs();
+        self.check_crossed_book();
+        self.publish_changes();
+        self.refresh_depth_views();
+    }
+
+    /// Market, quote market and IOC orders, and limits that would cross
+    fn is_aggressive(&self, ticket: &OrderTicket) -> bool {
+        match ticket.order_type {
+            OrderType::Market | OrderType::QuoteMarket | OrderType::ImmediateOrCancel(_) => true,
+            OrderType::Limit(price) => self.crosses_book(ticket.side, price),
+            OrderType::Stop { .. } | OrderType::Pegged { .. } => false,
+        }
+    }
+
+    /// Hold an aggressive order until the speed bump lets it through,
+    /// answering with the id its outcome is reported under
+    fn delay_order(&mut self, speed_bump: SpeedBump, ticket: OrderTicket) -> Result<OrderResponse> {
+        if self.session_state == SessionState::Paused {
+            return Err("Trading is paused".into());
+        }
+        if ticket.order_type != OrderType::QuoteMarket {
+            self.check_lot_size(ticket.size)?;
+        }
+        if ticket.size <= 0 {
+            return Err(format!("Size {} must be positive", ticket.size));
+        }
+
+        let id = self.get_next_id();
+        // derived from the order rather than drawn, so replays agree
+        let mut z = speed_bump.seed ^ id.rotate_left(32);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        let jitter = z % speed_bump.jitter.saturating_add(1);
+        let release_at = self
+            .clock
+            .saturating_add(speed_bump.delay)
+            .saturating_add(jitter);
+
+        let at = self
+            .delayed_orders
+            .partition_point(|delayed| (delayed.release_at, delayed.id) <= (release_at, id));
+        self.delayed_orders.insert(
+            at,
+            DelayedOrder {
+                id,
+                ticket,
+                release_at,
+            },
+        );
+        Ok(OrderResponse::Limit(LimitOrderResponse { id }))
+    }
+
+    /// Carry out the queued cancels whose order is old enough by `now`.
+    /// Orders that already left the book are skipped.
+    fn release_queued_cancels(&mut self, now: u64) {
+        let mut released = false;
+        while let Some(Reverse((eligible_at, id))) = self.queued_cancels.peek().copied()
+            && eligible_at <= now
+        {
+            self.queued_cancels.pop();
+            released |= self.remove_order(id).is_ok();
+        }
+
+        if released {
+            self.reprice_pegs();
+            self.check_crossed_book();
+            self.publish_changes();
+            self.refresh_depth_views();
+        }
+    }
+
+    pub fn set_day_end(&mut self, day_end: Option<u64>) {
+        self.log(EventKind::SetDayEnd(day_end));
+        self.day_end = day_end;
+    }
+
+    pub fn set_price_band(&mut self, price_band: Option<PriceBand>) {
+        self.price_band = price_band;
+    }
+
+    pub fn set_size_scale(&mut self, size_scale: SizeScale) {
+        self.size_scale = size_scale;
+    }
+
+    pub fn set_round_lot(&mut self, round_lot: Option<i64>) {
+        self.round_lot = round_lot;
+    }
+
+    pub fn set_fee_schedule(&mut self, fee_schedule: FeeSchedule) {
+        self.fee_schedule = fee_schedule;
+    }
+
+    /// Hand every trade from now on to `hook` with its fees under the
+    /// book's fee schedule, right after it prints. Replaces any hook set
+    /// before.
+    pub fn set_post_trade_hook(&mut self, hook: impl PostTradeHook + Send + 'static) {
+        self.post_trade_hook = Some(PostTradeHookBox(Box::new(hook)));
+    }
+
+    pub fn set_price_limits(&mut self, price_limits: Option<PriceLimits>) {
+        self.price_limits = price_limits;
+    }
+
+    pub fn set_price_move_guard(&mut self, price_move_guard: Option<PriceMoveGuard>) {
+        self.price_move_guard = price_move_guard;
+    }
+
+    pub fn set_peg_breach_action(&mut self, peg_breach_action: PegBreachAction) {
+        self.peg_breach_action = peg_breach_action;
+    }
+
+    pub fn set_min_resting_time(&mut self, min_resting_time: Option<MinRestingTime>) {
+        self.min_resting_time = min_resting_time;
+    }
+
+    /// Hold aggressive orders back from now on. Orders already delayed
+    /// keep their release time.
+    pub fn set_speed_bump(&mut self, speed_bump: Option<SpeedBump>) {
+        self.speed_bump = speed_bump;
+    }
+
+    /// Aggressive orders still waiting out the speed bump, soonest first
+    pub fn delayed_orders(&self) -> &[DelayedOrder] {
+        &self.delayed_orders
+    }
+
+    /// What every delayed order let through since the last drain came to,
+    /// in the order they were let through
+    pub fn drain_released_orders(&mut self) -> Vec<ReleasedOrder> {
+        std::mem::take(&mut self.released_orders)
+    }
+
+    /// Cap what `owner` can have on the book, None to lift the caps.
+    /// Orders already resting are left alone.
+    pub fn set_owner_limits(&mut self, owner: u64, limits: Option<OwnerLimits>) {
+        match limits {
+            Some(limits) => self.owner_limits.insert(owner, limits),
+            None => self.owner_limits.remove(&owner),
+        };
+    }
+
+    /// Protect `owner`'s quotes, None to stop. Fills before the call don't
+    /// count towards it.
+    pub fn set_mmp(&mut self, owner: u64, mmp_limits: Option<MmpLimits>) {
+        self.maker_fills.remove(&owner);
+        match mmp_limits {
+            Some(mmp_limits) => self.mmp_limits.insert(owner, mmp_limits),
+            None => self.mmp_limits.remove(&owner),
+        };
+    }
+
+    /// Net position `owner` has traded into, positive is long. Anonymous
+    /// orders are not tracked.
+    pub fn position(&self, owner: u64) -> i64 {
+        self.positions.get(&owner).copied().unwrap_or_default()
+    }
+
+    /// Maker rebates `owner` has accrued this session under the book's
+    /// fee schedule. Anonymous orders are not tracked and busts do not
+    /// take a rebate back.
+    pub fn rebate(&self, owner: u64) -> i64 {
+        self.rebates.get(&owner).copied().unwrap_or_default()
+    }
+
+    /// Close the session's rebate accounting, handing back what every
+    /// owner accrued and starting the next session from zero
+    pub fn settle_rebates(&mut self) -> BTreeMap<u64, i64> {
+        std::mem::take(&mut self.rebates)
+    }
+
+    /// Lift a LULD pause and go back to continuous matching
+    pub fn resume_trading(&mut self) {
+        self.log(EventKind::ResumeTrading);
+        if self.session_state == SessionState::Paused {
+            self.resumed_at = Some(self.clock);
+            self.publish_status(MarketStatus::Resumed);
+        }
+        self.session_state = SessionState::Continuous;
+    }
+
+    /// How wide the price band is right now, in basis points. Wider
+    /// straight after a halt if the band says so.
+    pub fn price_band_bps(&self) -> Option<i64> {
+        let band = self.price_band?;
+        let (Some(widening), Some(resumed_at)) = (band.after_halt, self.resumed_at) else {
+            return Some(band.bps);
+        };
+
+        let elapsed = self.clock.saturating_sub(resumed_at);
+        if elapsed >= widening.decay {
+            return Some(band.bps);
         }
+        let extra = band.bps * (widening.multiplier - 1);
+        let left = (widening.decay - elapsed) as i64;
+        Some(band.bps + extra * left / widening.decay as i64)
+    }
+
+    /// The (lower, upper) prices trades are allowed to print at right now
+    pub fn price_limit_band(&self) -> Option<(i64, i64)> {
+        let limits = self.price_limits?;
+        let reference = self.reference_price()?;
+        let width = reference * limits.bps / 10_000;
+        Some((reference - width, reference + width))
+    }
+
+    /// The last trade if we have one, otherwise the mid of the BBO
+    pub fn reference_price(&self) -> Option<i64> {
+        self.last_trade_price.or_else(|| {
+            let bid = self.get_best_bid()?;
+            let ask = self.get_best_ask()?;
+            Some((bid.price + ask.price) / 2)
+        })
     }
 
     fn get_top_of_book(&self, side: Side) -> Option<PriceSize> {
@@ -36,6 +623,332 @@ impl Orderbook {
         }
     }
 
+    /// Copy out the book's state, e.g. to persist it
+    pub fn snapshot(&self) -> BookSnapshot {
+        BookSnapshot {
+            bids: self.bids.snapshot(),
+            asks: self.asks.snapshot(),
+            event_log: self.event_log.clone(),
+            events_drained: self.events_drained,
+            current_id: self.current_id,
+            last_trade_price: self.last_trade_price,
+            trades: self.trades.clone(),
+            next_trade_id: self.next_trade_id,
+            recent_trades: self.recent_trades.clone(),
+            busts: self.busts.clone(),
+            stop_reports: self.stop_reports.clone(),
+            funding_events: self.funding_events.clone(),
+            level_updates: self.level_updates.clone(),
+            clock: self.clock,
+            stops: self.stops.clone(),
+            pegs: self.pegs.clone(),
+            peg_breach_action: self.peg_breach_action,
+            parked_pegs: self.parked_pegs.clone(),
+            peg_rejects: self.peg_rejects.clone(),
+            day_end: self.day_end,
+            expiries: self.expiries.clone(),
+            price_band: self.price_band,
+            resumed_at: self.resumed_at,
+            price_limits: self.price_limits,
+            price_move_guard: self.price_move_guard,
+            min_resting_time: self.min_resting_time,
+            speed_bump: self.speed_bump,
+            delayed_orders: self.delayed_orders.clone(),
+            released_orders: self.released_orders.clone(),
+            queued_cancels: self.queued_cancels.clone(),
+            owner_limits: self.owner_limits.clone(),
+            positions: self.positions.clone(),
+            rebates: self.rebates.clone(),
+            limit_rejects: self.limit_rejects.clone(),
+            mmp_limits: self.mmp_limits.clone(),
+            maker_fills: self.maker_fills.clone(),
+            mmp_triggers: self.mmp_triggers.clone(),
+            session_state: self.session_state,
+            status_events: self.status_events.clone(),
+            locked_policy: self.locked_policy,
+            market_data_mode: self.market_data_mode,
+            market_policy: self.market_policy,
+            self_trade_prevention: self.self_trade_prevention,
+            lot_size: self.lot_size,
+            symbol: self.symbol.clone(),
+            size_scale: self.size_scale,
+            round_lot: self.round_lot,
+            fee_schedule: self.fee_schedule,
+        }
+    }
+
+    /// Pick up from a snapshot. The crossed book detector comes back on
+    /// in debug builds like it does for a new book, anything else that
+    /// watches the book has to be set up again. Fails on a snapshot whose
+    /// orders don't fit back onto their ladder.
+    pub fn from_snapshot(snapshot: BookSnapshot) -> Result<Self> {
+        Ok(Self {
+            bids: HalfBook::from_snapshot(snapshot.bids)?,
+            asks: HalfBook::from_snapshot(snapshot.asks)?,
+            event_log: snapshot.event_log,
+            events_drained: snapshot.events_drained,
+            current_id: snapshot.current_id,
+            last_trade_price: snapshot.last_trade_price,
+            trades: snapshot.trades,
+            next_trade_id: snapshot.next_trade_id,
+            recent_trades: snapshot.recent_trades,
+            busts: snapshot.busts,
+            stop_reports: snapshot.stop_reports,
+            funding_events: snapshot.funding_events,
+            level_updates: snapshot.level_updates,
+            clock: snapshot.clock,
+            stops: snapshot.stops,
+            pegs: snapshot.pegs,
+            peg_breach_action: snapshot.peg_breach_action,
+            parked_pegs: snapshot.parked_pegs,
+            peg_rejects: snapshot.peg_rejects,
+            day_end: snapshot.day_end,
+            expiries: snapshot.expiries,
+            price_band: snapshot.price_band,
+            resumed_at: snapshot.resumed_at,
+            price_limits: snapshot.price_limits,
+            price_move_guard: snapshot.price_move_guard,
+            min_resting_time: snapshot.min_resting_time,
+            speed_bump: snapshot.speed_bump,
+            delayed_orders: snapshot.delayed_orders,
+            released_orders: snapshot.released_orders,
+            queued_cancels: snapshot.queued_cancels,
+            owner_limits: snapshot.owner_limits,
+            positions: snapshot.positions,
+            rebates: snapshot.rebates,
+            limit_rejects: snapshot.limit_rejects,
+            mmp_limits: snapshot.mmp_limits,
+            maker_fills: snapshot.maker_fills,
+            mmp_tripped: Vec::new(),
+            mmp_triggers: snapshot.mmp_triggers,
+            session_state: snapshot.session_state,
+            status_events: snapshot.status_events,
+            locked_policy: snapshot.locked_policy,
+            market_data_mode: snapshot.market_data_mode,
+            market_policy: snapshot.market_policy,
+            self_trade_prevention: snapshot.self_trade_prevention,
+            lot_size: snapshot.lot_size,
+            symbol: snapshot.symbol,
+            size_scale: snapshot.size_scale,
+            round_lot: snapshot.round_lot,
+            fee_schedule: snapshot.fee_schedule,
+            crossed_book_detector: cfg!(debug_assertions)
+                .then(|| CrossedBookDetector::new(CROSSED_BOOK_HISTORY)),
+            quote_cache: None,
+            depth_views: None,
+            trade_stats: None,
+            quote_stats: None,
+            order_history: None,
+            bbo_observer: None,
+            post_trade_hook: None,
+            last_bbo: (None, None),
+        })
+    }
+
+    /// Rebuild a book from its event log. Inputs that were rejected live
+    /// are rejected again, only a gap in the sequence is an error. The
+    /// book starts from `Orderbook::new()`, so anything configured on the
+    /// original has to be set up before replaying with `apply_event`.
+    pub fn replay(events: impl IntoIterator<Item = Event>) -> Result<Orderbook> {
+        let mut book = Orderbook::new();
+        for event in events {
+            let expected = book.next_event_seq();
+            if event.seq != expected {
+                return Err(format!(
+                    "Expected event {} but got event {}",
+                    expected, event.seq
+                ));
+            }
+            book.apply_event(event.kind);
+        }
+        Ok(book)
+    }
+
+    /// The book as it was just before event `seq`, rebuilt from the event
+    /// log on an empty book set up like this one. Settings are not
+    /// logged, so they are taken to be what they are now. Once events
+    /// have been drained rewind from the WAL instead, see
+    /// `LoggedOrderbook::rewind_to`.
+    pub fn rewind_to(&self, seq: u64) -> Result<Orderbook> {
+        if self.events_drained > 0 {
+            return Err(format!(
+                "Events before {} were drained, rewind from the log they went to",
+                self.events_drained
+            ));
+        }
+        if seq > self.next_event_seq() {
+            return Err(format!(
+                "Cannot rewind to event {}, the log ends at {}",
+                seq,
+                self.next_event_seq()
+            ));
+        }
+
+        let mut book = self.empty_copy();
+        for event in self.event_log.iter().take(seq as usize) {
+            book.apply_event(event.kind.clone());
+        }
+        Ok(book)
+    }
+
+    /// A book with this one's ladder and settings and nothing else, to
+    /// replay a log onto
+    pub fn empty_copy(&self) -> Orderbook {
+        Self {
+            price_band: self.price_band,
+            price_limits: self.price_limits,
+            price_move_guard: self.price_move_guard,
+            min_resting_time: self.min_resting_time,
+            speed_bump: self.speed_bump,
+            owner_limits: self.owner_limits.clone(),
+            mmp_limits: self.mmp_limits.clone(),
+            peg_breach_action: self.peg_breach_action,
+            locked_policy: self.locked_policy,
+            market_data_mode: self.market_data_mode,
+            market_policy: self.market_policy,
+            self_trade_prevention: self.self_trade_prevention,
+            lot_size: self.lot_size,
+            symbol: self.symbol.clone(),
+            size_scale: self.size_scale,
+            round_lot: self.round_lot,
+            fee_schedule: self.fee_schedule,
+            ..Self::with_tick_table(self.bids.max_price, self.bids.tick_table.clone())
+        }
+    }
+
+    /// Feed one logged input back in, ignoring whatever it returns
+    pub fn apply_event(&mut self, kind: EventKind) {
+        match kind {
+            EventKind::Order(ticket) => {
+                let _ = self.accept_order(ticket);
+            }
+            EventKind::Cancel(id) => {
+                let _ = self.cancel_order(id);
+            }
+            EventKind::Replace { id, price, size } => {
+                let _ = self.replace_order(id, price, size);
+            }
+            EventKind::MassQuote {
+                owner,
+                cancel,
+                levels,
+            } => {
+                let _ = self.mass_quote(owner, &cancel, &levels);
+            }
+            EventKind::Expire(now) => {
+                self.expire(now);
+            }
+            EventKind::ResumeTrading => self.resume_trading(),
+            EventKind::SetClock(now) => self.set_clock(now),
+            EventKind::SetDayEnd(day_end) => self.set_day_end(day_end),
+            EventKind::Bust(trade_id) => {
+                let _ = self.bust_trade(trade_id);
+            }
+        }
+    }
+
+    /// Logged events from `seq` on, so a consumer can tail the log by
+    /// asking for one past the last sequence number it saw
+    pub fn events_since(&self, seq: u64) -> &[Event] {
+        let start = (seq.saturating_sub(self.events_drained) as usize).min(self.event_log.len());
+        &self.event_log[start..]
+    }
+
+    /// Hand the logged events over to whoever keeps them, so a book that
+    /// runs for a long time does not hold on to its whole history.
+    /// Numbering carries on where it left off.
+    pub fn drain_events(&mut self) -> Vec<Event> {
+        self.events_drained += self.event_log.len() as u64;
+        std::mem::take(&mut self.event_log)
+    }
+
+    fn next_event_seq(&self) -> u64 {
+        self.events_drained + self.event_log.len() as u64
+    }
+
+    /// Every trade since the last drain, oldest first
+    pub fn drain_trades(&mut self) -> Vec<Trade> {
+        std::mem::take(&mut self.trades)
+    }
+
+    /// Drop every trade, bust, report, level update and funding payment
+    /// not yet drained, for a copy of the book nobody listens to
+    pub fn discard_feeds(&mut self) {
+        self.drain_trades();
+        self.drain_busts();
+        self.drain_level_updates();
+        self.drain_execution_reports();
+        self.drain_funding_events();
+    }
+
+    /// Every halt and resume since the last drain, oldest first
+    pub fn drain_status_events(&mut self) -> Vec<StatusEvent> {
+        std::mem::take(&mut self.status_events)
+    }
+
+    fn publish_status(&mut self, status: MarketStatus) {
+        self.status_events.push(StatusEvent {
+            status,
+            timestamp: self.clock,
+        });
+    }
+
+    /// Every time market maker protection tripped since the last drain,
+    /// oldest first
+    pub fn drain_mmp_triggers(&mut self) -> Vec<MmpTrigger> {
+        std::mem::take(&mut self.mmp_triggers)
+    }
+
+    /// Every pegged order cancelled for breaching since the last drain,
+    /// oldest first
+    pub fn drain_peg_rejects(&mut self) -> Vec<PegReject> {
+        std::mem::take(&mut self.peg_rejects)
+    }
+
+    /// Every order refused on its owner's limits since the last drain,
+    /// oldest first
+    pub fn drain_limit_rejects(&mut self) -> Vec<LimitReject> {
+        std::mem::take(&mut self.limit_rejects)
+    }
+
+    /// Every trade taken back since the last drain, oldest first. A
+    /// consumer applies one by reversing the trade it names.
+    pub fn drain_busts(&mut self) -> Vec<TradeBust> {
+        std::mem::take(&mut self.busts)
+    }
+
+    /// Every level whose total size changed, one update per level per
+    /// order, cancel, replace or expiry in the order they happened. Bids
+    /// come before asks within one change.
+    pub fn drain_level_updates(&mut self) -> Vec<LevelUpdate> {
+        std::mem::take(&mut self.level_updates)
+    }
+
+    /// Fills and cancels of resting orders since the last drain, bids
+    /// first and each side in the order they happened, then those of
+    /// stops that fired
+    pub fn drain_execution_reports(&mut self) -> Vec<ExecutionReport> {
+        let mut reports = self.bids.drain_reports();
+        reports.extend(self.asks.drain_reports());
+        reports.append(&mut self.stop_reports);
+        reports
+    }
+
+    /// Every funding payment settled since the last drain, oldest first,
+    /// see `Funding::accrue`
+    pub fn drain_funding_events(&mut self) -> Vec<FundingEvent> {
+        std::mem::take(&mut self.funding_events)
+    }
+
+    pub(crate) fn publish_funding(&mut self, event: FundingEvent) {
+        self.funding_events.push(event);
+    }
+
+    /// Query-only access for callbacks that must not touch the book
+    pub fn view(&self) -> BookView<'_> {
+        BookView::from(self)
+    }
+
     pub fn get_best_bid(&self) -> Option<PriceSize> {
         self.get_top_of_book(Side::Buy)
     }
@@ -44,6 +957,131 @@ impl Orderbook {
         self.get_top_of_book(Side::Sell)
     }
 
+    /// The top `levels` price levels with everything resting at them,
+    /// odd lots included
+    pub fn depth(&self, side: Side, levels: usize) -> Vec<PriceSize> {
+        match side {
+            Side::Buy => self.bids.levels().take(levels).collect(),
+            Side::Sell => self.asks.levels().take(levels).collect(),
+        }
+    }
+
+    /// The best level with its displayed size and everything resting
+    /// there, iceberg reserves included
+    pub fn top_of_book_sizes(&self, side: Side) -> Option<LevelSizes> {
+        self.resting_half(side).top_level_sizes()
+    }
+
+    /// `depth` with the hidden size of each level alongside
+    pub fn depth_sizes(&self, side: Side, levels: usize) -> Vec<LevelSizes> {
+        self.resting_half(side).level_sizes().take(levels).collect()
+    }
+
+    /// Every resting order, level by level and FIFO within a level
+    pub fn full_l3(&self) -> L3Book {
+        let walk = |half: &HalfBook| {
+            half.levels()
+                .flat_map(|level| half.orders_at(level.price))
+                .collect()
+        };
+        L3Book {
+            bids: walk(&self.bids),
+            asks: walk(&self.asks),
+        }
+    }
+
+    /// Depth as the market sees it, odd lots left out when a round lot
+    /// is configured
+    pub fn displayed_depth(&self, side: Side, levels: usize) -> Vec<PriceSize> {
+        let round_lot = self.round_lot.unwrap_or(1);
+        match side {
+            Side::Buy => self.bids.displayed_levels(round_lot, levels),
+            Side::Sell => self.asks.displayed_levels(round_lot, levels),
+        }
+    }
+
+    /// Displayed depth folded into buckets `ticks` ticks wide, for clients
+    /// that do not need every level. Bids are labelled with the bottom of
+    /// their bucket and asks with the top, so a bucket never quotes better
+    /// than any price inside it.
+    pub fn bucketed_depth(&self, side: Side, ticks: usize, buckets: usize) -> Vec<PriceSize> {
+        let ticks = ticks.max(1);
+        let tick_table = &self.bids.tick_table;
+        let mut bucketed: Vec<PriceSize> = Vec::new();
+
+        for level in self.displayed_depth(side, usize::MAX) {
+            let Some(index) = tick_table.index_of(level.price) else {
+                continue;
+            };
+            let first = index / ticks * ticks;
+            let price = match side {
+                Side::Buy => tick_table.price_of(first),
+                Side::Sell => tick_table.price_of(first + ticks - 1),
+            };
+
+            if let Some(bucket) = bucketed.last_mut()
+                && bucket.price == price
+            {
+                bucket.size += level.size;
+                continue;
+            }
+            if bucketed.len() == buckets {
+                break;
+            }
+            bucketed.push(PriceSize {
+                price,
+                size: level.size,
+            });
+        }
+
+        bucketed
+    }
+
+    /// Depth as it goes out on market data under `market_data_mode`
+    pub fn published_depth(&self, side: Side, levels: usize) -> Vec<PriceSize> {
+        match self.market_data_mode {
+            MarketDataMode::VisibleOnly => self.displayed_depth(side, levels),
+            MarketDataMode::Full => self
+                .resting_half(side)
+                .level_sizes()
+                .take(levels)
+                .map(|level| PriceSize {
+                    price: level.price,
+                    size: level.total_size,
+                })
+                .collect(),
+        }
+    }
+
+    /// The best level as it goes out on market data, see `published_depth`
+    pub fn published_top_of_book(&self, side: Side) -> Option<PriceSize> {
+        self.published_depth(side, 1).pop()
+    }
+
+    /// Fingerprint of the top `levels` of published depth on both sides,
+    /// for feed consumers to check the book they built against
+    pub fn depth_checksum(&self, levels: usize) -> u64 {
+        let mut digest = StateDigest::default();
+        for side in [Side::Buy, Side::Sell] {
+            for level in self.published_depth(side, levels) {
+                digest.write_i64(level.price);
+                digest.write_i64(level.size);
+            }
+            // keeps a level moving across from one side to the other visible
+            digest.write_option(None);
+        }
+        digest.finish()
+    }
+
+    /// The best bid made of round lots, odd lots may sit in front of it
+    pub fn displayed_best_bid(&self) -> Option<PriceSize> {
+        self.displayed_depth(Side::Buy, 1).pop()
+    }
+
+    pub fn displayed_best_ask(&self) -> Option<PriceSize> {
+        self.displayed_depth(Side::Sell, 1).pop()
+    }
+
     pub fn total_liquidity(&self, side: Side) -> i64 {
         match side {
             Side::Sell => self.asks.get_total_liquidity(),
@@ -51,56 +1089,1506 @@ impl Orderbook {
         }
     }
 
-    pub fn accept_order(&mut self, order_ticket: OrderTicket) -> Result<OrderResponse> {
-        match order_ticket.order_type {
-            OrderType::Market => self
-                .handle_taker(order_ticket.side, order_ticket.size)
-                .map(OrderResponse::Market),
-            OrderType::Limit(price) => {
-                let crosses_book = match order_ticket.side {
-                    Side::Buy => self
-                        .get_best_ask()
-                        .map(|order| order.price <= price)
-                        .unwrap_or_default(),
-                    Side::Sell => self
-                        .get_best_bid()
-                        .map(|order| order.price >= price)
-                        .unwrap_or_default(),
-                };
+    pub fn size_at(&self, side: Side, price: i64) -> i64 {
+        match side {
+            Side::Sell => self.asks.size_at(price),
+            Side::Buy => self.bids.size_at(price),
+        }
+    }
 
-                if crosses_book {
-                    self.handle_taker(order_ticket.side, order_ticket.size)
-                        .map(OrderResponse::Market)
-                } else {
-                    self.handle_maker(order_ticket.side, price, order_ticket.size)
-                        .map(OrderResponse::Limit)
-                }
+    /// Deterministic fingerprint of everything matching depends on: both
+    /// ladders with every order in queue order, stops, pegs, pending
+    /// expiries, the session and its controls, the clock and the id
+    /// counters. Replicas that applied the same events agree on it,
+    /// anything else should not. Undrained output is left out.
+    pub fn state_digest(&self) -> u64 {
+        let mut digest = StateDigest::default();
+        digest.write_u64(self.current_id);
+        digest.write_u64(self.next_trade_id);
+        digest.write_option(self.last_trade_price);
+        digest.write_u64(self.clock);
+        digest.write_option(self.day_end.map(|day_end| day_end as i64));
+        self.bids.digest(&mut digest);
+        self.asks.digest(&mut digest);
+        self.stops.digest(&mut digest);
+
+        digest.write_u64(self.positions.len() as u64);
+        for (owner, position) in self.positions.iter() {
+            digest.write_u64(*owner);
+            digest.write_i64(*position);
+        }
+
+        digest.write_u64(self.rebates.len() as u64);
+        for (owner, rebate) in self.rebates.iter() {
+            digest.write_u64(*owner);
+            digest.write_i64(*rebate);
+        }
+
+        digest.write_u64(self.maker_fills.len() as u64);
+        for (owner, fills) in self.maker_fills.iter() {
+            digest.write_u64(*owner);
+            digest.write_u64(fills.len() as u64);
+            for (at, size) in fills.iter() {
+                digest.write_u64(*at);
+                digest.write_i64(*size);
             }
         }
+
+        // which trades a bust can still find
+        digest.write_u64(self.recent_trades.len() as u64);
+        for trade in self.recent_trades.iter() {
+            digest.write_u64(trade.trade_id);
+        }
+
+        digest.write_u64(self.pegs.len() as u64);
+        for peg in self.pegs.iter() {
+            digest.write_u64(peg.id);
+            digest.write_u64(peg.side as u64);
+            digest.write_u64(peg.side_ref as u64);
+            digest.write_i64(peg.offset);
+        }
+
+        digest.write_u64(self.parked_pegs.len() as u64);
+        for parked in self.parked_pegs.iter() {
+            digest.write_u64(parked.peg.id);
+            digest.write_i64(parked.size);
+            digest.write_u64(parked.owner);
+        }
+
+        // the heap's layout depends on the order things were pushed
+        let mut expiries: Vec<(u64, u64)> = self
+            .expiries
+            .iter()
+            .map(|Reverse(expiry)| *expiry)
+            .collect();
+        expiries.sort_unstable();
+        digest.write_u64(expiries.len() as u64);
+        for (expires_at, id) in expiries {
+            digest.write_u64(expires_at);
+            digest.write_u64(id);
+        }
+
+        let mut queued_cancels: Vec<(u64, u64)> = self
+            .queued_cancels
+            .iter()
+            .map(|Reverse(cancel)| *cancel)
+            .collect();
+        queued_cancels.sort_unstable();
+        digest.write_u64(queued_cancels.len() as u64);
+        for (eligible_at, id) in queued_cancels {
+            digest.write_u64(eligible_at);
+            digest.write_u64(id);
+        }
+
+        digest.write_u64(self.delayed_orders.len() as u64);
+        for delayed in self.delayed_orders.iter() {
+            digest.write_u64(delayed.id);
+            digest.write_u64(delayed.release_at);
+        }
+
+        digest.write_u64(self.session_state as u64);
+        digest.write_u64(self.locked_policy as u64);
+        digest.write_u64(self.market_policy as u64);
+        digest.write_u64(self.self_trade_prevention as u64);
+        digest.write_i64(self.lot_size);
+        digest.write_option(self.round_lot);
+        digest.write_i64(self.fee_schedule.maker_bps);
+        digest.write_i64(self.fee_schedule.taker_bps);
+        digest.write_option(self.price_band.map(|band| band.bps));
+        let widening = self.price_band.and_then(|band| band.after_halt);
+        digest.write_option(widening.map(|widening| widening.multiplier));
+        digest.write_option(widening.map(|widening| widening.decay as i64));
+        digest.write_option(self.resumed_at.map(|resumed_at| resumed_at as i64));
+        digest.write_option(self.price_limits.map(|limits| limits.bps));
+        digest.write_option(self.price_move_guard.map(|guard| guard.max_move));
+        digest.write_option(self.price_move_guard.map(|guard| guard.action as i64));
+        digest.write_option(self.speed_bump.map(|bump| bump.delay as i64));
+        digest.write_option(self.speed_bump.map(|bump| bump.jitter as i64));
+        digest.write_option(self.speed_bump.map(|bump| bump.seed as i64));
+        digest.finish()
     }
 
-    fn handle_taker(&mut self, side: Side, size: i64) -> Result<MarketOrderResponse> {
-        let notional = match side {
-            Side::Sell => self.bids.match_size(size)?,
-            Side::Buy => self.asks.match_size(size)?,
-        };
+    /// Which side a resting order is on, its price and remaining size
+    pub fn get_order(&self, id: u64) -> Option<(Side, PriceSize)> {
+        self.bids
+            .get_order(id)
+            .map(|order| (Side::Buy, order))
+            .or_else(|| self.asks.get_order(id).map(|order| (Side::Sell, order)))
+    }
+
+    /// Pull a resting order. Allowed while trading is paused so people
+    /// can get out of the way before the book reopens. Under a minimum
+    /// resting time an order too young to cancel is refused, or its
+    /// cancel queued, and either way Err says until when.
+    pub fn cancel_order(&mut self, id: u64) -> Result<CancelResponse> {
+        self.log(EventKind::Cancel(id));
+        if let Some(min_resting_time) = self.min_resting_time
+            && let Some(entered_at) = self
+                .bids
+                .get_entered_at(id)
+                .or(self.asks.get_entered_at(id))
+            && self.clock < entered_at + min_resting_time.min_time
+        {
+            let eligible_at = entered_at + min_resting_time.min_time;
+            if min_resting_time.action == EarlyCancelAction::Queue {
+                self.queued_cancels.push(Reverse((eligible_at, id)));
+                return Err(format!(
+                    "Order {} is too young to cancel, queued until {}",
+                    id, eligible_at
+                ));
+            }
+            return Err(format!(
+                "Order {} is too young to cancel until {}",
+                id, eligible_at
+            ));
+        }
 
-        Ok(MarketOrderResponse { notional, size })
+        let response = self.remove_order(id)?;
+        self.reprice_pegs();
+        self.check_crossed_book();
+        self.publish_changes();
+        self.refresh_depth_views();
+        Ok(response)
     }
 
-    fn handle_maker(&mut self, side: Side, price: i64, size: i64) -> Result<LimitOrderResponse> {
-        let id = self.get_next_id();
-        match side {
-            Side::Sell => self.asks.insert(id, price, size)?,
-            Side::Buy => self.bids.insert(id, price, size)?,
+    /// Take back one of the latest trades. The maker gets the size back
+    /// in place if it is still resting, otherwise the bust only records
+    /// the restore for downstream to apply. The taker has left the book
+    /// either way and is not put back. A trade can be busted once.
+    pub fn bust_trade(&mut self, trade_id: u64) -> Result<TradeBust> {
+        self.log(EventKind::Bust(trade_id));
+        let Some(index) = self
+            .recent_trades
+            .iter()
+            .position(|trade| trade.trade_id == trade_id)
+        else {
+            return Err(format!("No bustable trade with id {}", trade_id));
         };
+        let trade = self.recent_trades.remove(index).expect("found above");
+        book_position(&mut self.positions, &trade, -trade.size);
+
+        let maker = match trade.aggressor_side {
+            Side::Buy => &mut self.asks,
+            Side::Sell => &mut self.bids,
+        };
+        let restored_order_id = maker
+            .restore(trade.maker_order_id, trade.size)
+            .is_ok()
+            .then_some(trade.maker_order_id);
+        if let Some(id) = restored_order_id {
+            let size = trade.size;
+            self.record_update(id, OrderUpdate::Restored { size });
+        }
+
+        let bust = TradeBust {
+            trade,
+            restored_order_id,
+            timestamp: self.clock,
+        };
+        self.busts.push(bust.clone());
+        self.reprice_pegs();
+        self.check_crossed_book();
+        self.publish_changes();
+        self.refresh_depth_views();
+        Ok(bust)
+    }
+
+    /// Amend a resting order. Shrinking it in place keeps its queue
+    /// position and id, anything else goes to the back of the queue under
+    /// a new id. A replacement that would trade is rejected and the
+    /// original order is left alone.
+    pub fn replace_order(&mut self, id: u64, price: i64, size: i64) -> Result<ReplaceResponse> {
+        self.log(EventKind::Replace { id, price, size });
+        if self.session_state == SessionState::Paused {
+            return Err("Trading is paused".into());
+        }
+        if size <= 0 {
+            return Err(format!("Size {} must be positive, cancel instead", size));
+        }
+        self.check_lot_size(size)?;
+        let Some((side, order)) = self.get_order(id) else {
+            return Err(format!("No resting order with id {}", id));
+        };
+
+        let response = if price == order.price && size <= order.size {
+            match side {
+                Side::Buy => self.bids.modify(id, price, size)?,
+                Side::Sell => self.asks.modify(id, price, size)?,
+            }
+            self.record_update(id, OrderUpdate::Amended { price, size });
+            ReplaceResponse {
+                id,
+                requeued: false,
+            }
+        } else {
+            self.bids.validate_price(price)?;
+            self.check_price_band(price)?;
+            let rests_locked =
+                self.locked_policy == LockedPolicy::RestAndLock && self.locks_book(side, price);
+            if self.crosses_book(side, price) && !rests_locked {
+                return Err(format!("Replacement at {} would cross the book", price));
+            }
+
+            // only what it adds over the order it replaces counts
+            let owner = self.resting_half(side).get_owner(id).unwrap_or_default();
+            let notional = self.limit_notional(owner, price, size)?
+                - self.limit_notional(owner, order.price, order.size)?;
+            let added = (size - order.size).max(0);
+            self.check_owner_limits(owner, side, 0, notional, added)?;
+
+            self.grow_ladders_down(price)?;
+            let new_id = self.get_next_id();
+            match side {
+                Side::Buy => self.bids.requeue(id, new_id, price, size)?,
+                Side::Sell => self.asks.requeue(id, new_id, price, size)?,
+            }
+            self.carry_expiry(id, new_id);
+            if let Some(stats) = &mut self.quote_stats {
+                stats.record_cancel(id, side, order.price, self.clock);
+                stats.record_add(new_id, side, price, self.clock);
+            }
+            self.record_update(
+                id,
+                OrderUpdate::Replaced {
+                    new_id,
+                    price,
+                    size,
+                },
+            );
+            for peg in self.pegs.iter_mut().filter(|peg| peg.id == id) {
+                peg.id = new_id;
+            }
+            ReplaceResponse {
+                id: new_id,
+                requeued: true,
+            }
+        };
+
+        self.reprice_pegs();
+        self.check_crossed_book();
+        self.publish_changes();
+        self.refresh_depth_views();
+        Ok(response)
+    }
+
+    /// A requeued order keeps its time in force under its new id. The
+    /// entry for the old id stays behind and is skipped when it comes up.
+    fn carry_expiry(&mut self, from: u64, to: u64) {
+        let expires_at = self
+            .expiries
+            .iter()
+            .find(|Reverse((_, id))| *id == from)
+            .map(|Reverse((expires_at, _))| *expires_at);
+        if let Some(expires_at) = expires_at {
+            self.expiries.push(Reverse((expires_at, to)));
+        }
+    }
+
+    fn remove_order(&mut self, id: u64) -> Result<CancelResponse> {
+        if let Some(stop) = self.stops.remove(id) {
+            return Ok(CancelResponse {
+                id,
+                side: stop.side,
+                price: stop.trigger,
+                size: stop.size,
+            });
+        }
+        if let Some(index) = self
+            .parked_pegs
+            .iter()
+            .position(|parked| parked.peg.id == id)
+        {
+            let parked = self.parked_pegs.remove(index);
+            self.record_update(id, OrderUpdate::Cancelled);
+            return Ok(CancelResponse {
+                id,
+                side: parked.peg.side,
+                price: 0,
+                size: parked.size,
+            });
+        }
+
+        let Some((side, order)) = self.get_order(id) else {
+            return Err(format!("No resting order with id {}", id));
+        };
+
+        match side {
+            Side::Buy => self.bids.remove(id)?,
+            Side::Sell => self.asks.remove(id)?,
+        }
+        if let Some(stats) = &mut self.quote_stats {
+            stats.record_cancel(id, side, order.price, self.clock);
+        }
+        self.record_update(id, OrderUpdate::Cancelled);
+        Ok(CancelResponse {
+            id,
+            side,
+            price: order.price,
+            size: order.size,
+        })
+    }
+
+    pub fn accept_order(&mut self, order_ticket: OrderTicket) -> Result<OrderResponse> {
+        self.log(EventKind::Order(order_ticket.clone()));
+
+        if let Some(speed_bump) = self.speed_bump
+            && self.is_aggressive(&order_ticket)
+        {
+            return self.delay_order(speed_bump, order_ticket);
+        }
+
+        let response = self.process_order_with_tif(order_ticket);
+        self.fire_stops();
+        self.pull_tripped_quotes();
+        self.reprice_pegs();
+        self.check_crossed_book();
+        self.publish_changes();
+        self.refresh_depth_views();
+        response
+    }
+
+    /// Cancel everything whose time in force ran out by `now`, soonest
+    /// first. Orders that already left the book are skipped.
+    pub fn expire(&mut self, now: u64) -> Vec<CancelResponse> {
+        self.log(EventKind::Expire(now));
+        let mut expired = Vec::new();
+        while let Some(Reverse((expires_at, id))) = self.expiries.peek().copied()
+            && expires_at <= now
+        {
+            self.expiries.pop();
+            if let Ok(response) = self.remove_order(id) {
+                expired.push(response);
+            }
+        }
+
+        if !expired.is_empty() {
+            self.reprice_pegs();
+            self.check_crossed_book();
+            self.publish_changes();
+            self.refresh_depth_views();
+        }
+        expired
+    }
+
+    fn process_order_with_tif(&mut self, order_ticket: OrderTicket) -> Result<OrderResponse> {
+        let expires_at = match order_ticket.time_in_force {
+            TimeInForce::Gtc => None,
+            TimeInForce::Day => Some(
+                self.day_end
+                    .ok_or("Day orders need the end of day to be set")?,
+            ),
+            TimeInForce::Gtd(expires_at) => Some(expires_at),
+        };
+
+        let response = self.process_order(order_ticket)?;
+        // only what rests can expire, stops and converted market orders included
+        let resting_id = match &response {
+            OrderResponse::Limit(limit) => Some(limit.id),
+            OrderResponse::Market(market) => market.resting_id,
+        };
+        if let Some((expires_at, id)) = expires_at.zip(resting_id) {
+            self.expiries.push(Reverse((expires_at, id)));
+        }
+        Ok(response)
+    }
+
+    fn process_order(&mut self, order_ticket: OrderTicket) -> Result<OrderResponse> {
+        if self.session_state == SessionState::Paused {
+            return Err("Trading is paused".into());
+        }
+
+        // a quote market order's size is a budget, not a quantity
+        if order_ticket.order_type != OrderType::QuoteMarket {
+            self.check_lot_size(order_ticket.size)?;
+        }
+        if order_ticket.iceberg.is_some() && !matches!(order_ticket.order_type, OrderType::Limit(_))
+        {
+            return Err("Only limit orders can rest as icebergs".into());
+        }
+
+        let owner = order_ticket.owner;
+        match order_ticket.order_type {
+            // a quote market order's size is a budget, so only the others
+            // count towards the position
+            OrderType::Market | OrderType::ImmediateOrCancel(_) => {
+                self.check_owner_limits(owner, order_ticket.side, 0, 0, order_ticket.size)?;
+            }
+            OrderType::Limit(price) => {
+                let notional = self.limit_notional(owner, price, order_ticket.size)?;
+                self.check_owner_limits(owner, order_ticket.side, 1, notional, order_ticket.size)?;
+            }
+            OrderType::Stop { .. } => {
+                self.check_owner_limits(owner, order_ticket.side, 1, 0, order_ticket.size)?;
+            }
+            OrderType::QuoteMarket | OrderType::Pegged { .. } => {}
+        }
+        match order_ticket.order_type {
+            OrderType::Market => {
+                let taker = self.new_taker(order_ticket.side, owner);
+                match self.market_policy {
+                    MarketPolicy::Sweep => self.handle_taker(taker, order_ticket.size, None),
+                    MarketPolicy::ToLimit => self.handle_market_to_limit(taker, order_ticket.size),
+                }
+                .map(OrderResponse::Market)
+            }
+            OrderType::QuoteMarket => {
+                let taker = self.new_taker(order_ticket.side, owner);
+                self.handle_quote_taker(taker, order_ticket.size)
+                    .map(OrderResponse::Market)
+            }
+            OrderType::Pegged { side_ref, offset } => {
+                let peg = PeggedOrder {
+                    id: self.current_id,
+                    side: order_ticket.side,
+                    side_ref,
+                    offset,
+                };
+                let Some(price) = self.peg_price(&peg) else {
+                    return Err("No reference price to peg to".into());
+                };
+                let size = order_ticket.size;
+                let price = match self.check_peg_price(price) {
+                    Ok(()) => price,
+                    Err(reason) => match self.peg_breach_action {
+                        PegBreachAction::Clamp => self.clamp_peg_price(price).ok_or(reason)?,
+                        PegBreachAction::Park => {
+                            if size <= 0 {
+                                return Err(format!("Size {} must be positive", size));
+                            }
+                            self.check_owner_limits(owner, peg.side, 1, 0, size)?;
+                            let id = self.get_next_id();
+                            self.parked_pegs.push(ParkedPeg { peg, size, owner });
+                            self.record_update(id, OrderUpdate::Parked);
+                            return Ok(OrderResponse::Limit(LimitOrderResponse { id }));
+                        }
+                        PegBreachAction::Hold | PegBreachAction::Cancel => return Err(reason),
+                    },
+                };
+                if self.crosses_book(peg.side, price) {
+                    return Err(format!("Pegged price {} would cross the book", price));
+                }
+                let notional = self.limit_notional(owner, price, size)?;
+                self.check_owner_limits(owner, peg.side, 1, notional, size)?;
+
+                let response =
+                    self.handle_maker(peg.side, price, order_ticket.size, owner, None)?;
+                self.pegs.push(peg);
+                Ok(OrderResponse::Limit(response))
+            }
+            OrderType::Stop { trigger } => {
+                self.bids.validate_price(trigger)?;
+                if order_ticket.size <= 0 {
+                    return Err(format!("Size {} must be positive", order_ticket.size));
+                }
+
+                let stop = StopOrder {
+                    id: self.current_id,
+                    side: order_ticket.side,
+                    trigger,
+                    size: order_ticket.size,
+                    owner,
+                };
+                if let Some(last) = self.last_trade_price
+                    && stop.is_triggered(last)
+                {
+                    return Err(format!(
+                        "Stop at {} is already through the last trade at {}",
+                        trigger, last
+                    ));
+                }
+
+                let id = self.get_next_id();
+                self.stops.insert(stop);
+                Ok(OrderResponse::Limit(LimitOrderResponse { id }))
+            }
+            OrderType::ImmediateOrCancel(price) => {
+                self.bids.validate_price(price)?;
+                self.check_price_band(price)?;
+                let taker = self.new_taker(order_ticket.side, owner);
+                self.handle_taker(taker, order_ticket.size, Some(price))
+                    .map(OrderResponse::Market)
+            }
+            OrderType::Limit(price) => {
+                // both halves share a tick table
+                self.bids.validate_price(price)?;
+                self.check_price_band(price)?;
+                if let Some(min_qty) = order_ticket.min_qty
+                    && !(1..=order_ticket.size).contains(&min_qty)
+                {
+                    return Err(format!(
+                        "Minimum quantity {} must be between 1 and the order size {}",
+                        min_qty, order_ticket.size
+                    ));
+                }
+
+                if let Some(iceberg) = order_ticket.iceberg {
+                    self.check_lot_size(iceberg.display_size)?;
+                    validate_iceberg(iceberg.display_size, order_ticket.size, iceberg.refresh)?;
+                }
+
+                let side = order_ticket.side;
+                let rests_locked = self.locks_book(side, price)
+                    && match self.locked_policy {
+                        LockedPolicy::Match => false,
+                        LockedPolicy::RestAndLock => true,
+                        LockedPolicy::Reject => {
+                            return Err(format!("Limit price {} would lock the book", price));
+                        }
+                    };
+
+                if self.crosses_book(side, price) && !rests_locked {
+                    let taker = self.new_taker(side, owner);
+                    let mut response = self.handle_taker(taker, order_ticket.size, Some(price))?;
+                    // what the limit price kept from trading rests there
+                    if response.remaining > 0
+                        && self.session_state == SessionState::Continuous
+                        && !self.crosses_book(side, price)
+                    {
+                        let resting = self.handle_maker(
+                            side,
+                            price,
+                            response.remaining,
+                            owner,
+                            order_ticket.iceberg,
+                        )?;
+                        if let Some(min_qty) = order_ticket.min_qty {
+                            let min_qty = min_qty.min(response.remaining);
+                            match side {
+                                Side::Buy => self.bids.set_min_qty(resting.id, min_qty)?,
+                                Side::Sell => self.asks.set_min_qty(resting.id, min_qty)?,
+                            }
+                        }
+                        response.resting_id = Some(resting.id);
+                    }
+                    Ok(OrderResponse::Market(response))
+                } else {
+                    let response = self.handle_maker(
+                        side,
+                        price,
+                        order_ticket.size,
+                        owner,
+                        order_ticket.iceberg,
+                    )?;
+                    if let Some(min_qty) = order_ticket.min_qty {
+                        match side {
+                            Side::Buy => self.bids.set_min_qty(response.id, min_qty)?,
+                            Side::Sell => self.asks.set_min_qty(response.id, min_qty)?,
+                        }
+                    }
+                    Ok(OrderResponse::Limit(response))
+                }
+            }
+        }
+    }
+
+    /// Send every stop the last trade has reached to the book as a market
+    /// order. Those can print further and set off more stops, so keep
+    /// going until nothing fires or trading is paused.
+    fn fire_stops(&mut self) {
+        while self.session_state == SessionState::Continuous
+            && let Some(last) = self.last_trade_price
+        {
+            let triggered = self.stops.take_triggered(last);
+            if triggered.is_empty() {
+                break;
+            }
+
+            for stop in triggered {
+                if self.session_state == SessionState::Paused {
+                    self.stops.insert(stop);
+                    continue;
+                }
+                let taker = Taker {
+                    id: stop.id,
+                    side: stop.side,
+                    owner: stop.owner,
+                };
+                let seen = self.trades.len();
+                // a rejected market order fills nothing, which is reported
+                let _ = self.handle_taker(taker, stop.size, None);
+                self.report_stop(&stop, seen);
+            }
+        }
+    }
+
+    /// Nobody is waiting on a response from a stop that fired, so it gets
+    /// a report per trade since `seen` and a cancel for whatever it could
+    /// not fill
+    fn report_stop(&mut self, stop: &StopOrder, seen: usize) {
+        let mut traded = 0;
+        for trade in self.trades[seen..]
+            .iter()
+            .filter(|trade| trade.taker_order_id == stop.id)
+        {
+            traded += trade.size;
+            self.stop_reports.push(ExecutionReport {
+                order_id: stop.id,
+                owner: stop.owner,
+                exec_type: if traded == stop.size {
+                    ExecType::Fill
+                } else {
+                    ExecType::PartialFill
+                },
+                traded_size: trade.size,
+                price: trade.price,
+                rebate: 0,
+            });
+        }
+
+        if traded < stop.size {
+            self.stop_reports.push(ExecutionReport {
+                order_id: stop.id,
+                owner: stop.owner,
+                exec_type: ExecType::Cancelled,
+                traded_size: 0,
+                price: stop.trigger,
+                rebate: 0,
+            });
+        }
+    }
+
+    /// The best price on the referenced side ignoring pegged orders, then
+    /// `offset` further from the spread
+    fn peg_price(&self, peg: &PeggedOrder) -> Option<i64> {
+        let quoted = match peg.side_ref {
+            PegReference::Primary => peg.side,
+            PegReference::Market => peg.side.opposite(),
+        };
+        let half = match quoted {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+
+        let reference = half
+            .displayed_levels(1, self.pegs.len() + 1)
+            .into_iter()
+            .find(|level| {
+                let pegged: i64 = self
+                    .pegs
+                    .iter()
+                    .filter(|other| other.side == quoted)
+                    .filter_map(|other| half.get_order(other.id))
+                    .filter(|other| other.price == level.price)
+                    .map(|other| other.size)
+                    .sum();
+                level.size > pegged
+            })?
+            .price;
+
+        Some(match peg.side {
+            Side::Buy => reference - peg.offset,
+            Side::Sell => reference + peg.offset,
+        })
+    }
+
+    /// Pull every resting order of the owners market maker protection
+    /// tripped on, starting their window over
+    fn pull_tripped_quotes(&mut self) {
+        for owner in std::mem::take(&mut self.mmp_tripped) {
+            let fills = self.maker_fills.remove(&owner).unwrap_or_default();
+            let mut cancelled = self.bids.ids_owned_by(owner);
+            cancelled.extend(self.asks.ids_owned_by(owner));
+            for id in cancelled.iter() {
+                let _ = self.remove_order(*id);
+            }
+
+            self.mmp_triggers.push(MmpTrigger {
+                owner,
+                fills: fills.len(),
+                volume: fills.iter().map(|(_, size)| size).sum(),
+                cancelled,
+                timestamp: self.clock,
+            });
+        }
+    }
+
+    /// Move every pegged order to where its reference now puts it, which
+    /// sends it to the back of the new level. A peg whose price would
+    /// cross stays put, one off the ladder or outside the band is handled
+    /// by `peg_breach_action`, and filled or cancelled pegs are dropped.
+    /// Parked pegs whose price is good again go back on first.
+    fn reprice_pegs(&mut self) {
+        self.unpark_pegs();
+        for index in 0..self.pegs.len() {
+            let peg = self.pegs[index].clone();
+            let Some((side, resting)) = self.get_order(peg.id) else {
+                continue;
+            };
+            let Some(mut price) = self.peg_price(&peg) else {
+                continue;
+            };
+
+            if let Err(reason) = self.check_peg_price(price) {
+                match self.peg_breach_action {
+                    PegBreachAction::Hold => continue,
+                    PegBreachAction::Clamp => match self.clamp_peg_price(price) {
+                        Some(clamped) => price = clamped,
+                        None => continue,
+                    },
+                    PegBreachAction::Park => {
+                        let half = match side {
+                            Side::Buy => &mut self.bids,
+                            Side::Sell => &mut self.asks,
+                        };
+                        let owner = half.get_owner(peg.id).unwrap_or_default();
+                        if half.withdraw(peg.id).is_ok() {
+                            if let Some(stats) = &mut self.quote_stats {
+                                stats.record_cancel(peg.id, side, resting.price, self.clock);
+                            }
+                            if let Some(history) = &mut self.order_history {
+                                history.record(peg.id, self.clock, OrderUpdate::Parked);
+                            }
+                            let size = resting.size;
+                            self.parked_pegs.push(ParkedPeg { peg, size, owner });
+                        }
+                        continue;
+                    }
+                    PegBreachAction::Cancel => {
+                        let owner = self.resting_half(side).get_owner(peg.id);
+                        if self.remove_order(peg.id).is_ok() {
+                            self.peg_rejects.push(PegReject {
+                                id: peg.id,
+                                owner: owner.unwrap_or_default(),
+                                price,
+                                reason,
+                                timestamp: self.clock,
+                            });
+                        }
+                        continue;
+                    }
+                }
+            }
+
+            if price != resting.price && !self.crosses_book(side, price) {
+                // both sides were just checked to hold the order
+                let moved = self.grow_ladders_down(price).and_then(|_| match side {
+                    Side::Buy => self.bids.modify(peg.id, price, resting.size),
+                    Side::Sell => self.asks.modify(peg.id, price, resting.size),
+                });
+                if moved.is_ok() {
+                    if let Some(stats) = &mut self.quote_stats {
+                        stats.record_move(side, resting.price, price);
+                    }
+                    self.record_update(peg.id, OrderUpdate::Repriced { price });
+                }
+            }
+        }
+
+        let (bids, asks) = (&self.bids, &self.asks);
+        self.pegs
+            .retain(|peg| bids.get_order(peg.id).is_some() || asks.get_order(peg.id).is_some());
+    }
+
+    /// Put parked pegs back on the book, under the same id, once their
+    /// reference gives them a good price that doesn't cross
+    fn unpark_pegs(&mut self) {
+        for parked in std::mem::take(&mut self.parked_pegs) {
+            let side = parked.peg.side;
+            let price = self.peg_price(&parked.peg).filter(|price| {
+                self.check_peg_price(*price).is_ok() && !self.crosses_book(side, *price)
+            });
+            let Some(price) = price else {
+                self.parked_pegs.push(parked);
+                continue;
+            };
+
+            let rested = self.grow_ladders_down(price).and_then(|_| {
+                let half = match side {
+                    Side::Buy => &mut self.bids,
+                    Side::Sell => &mut self.asks,
+                };
+                half.insert(parked.peg.id, price, parked.size)?;
+                match parked.owner {
+                    0 => Ok(()),
+                    owner => half.set_owner(parked.peg.id, owner),
+                }
+            });
+            match rested {
+                Ok(()) => {
+                    if let Some(stats) = &mut self.quote_stats {
+                        stats.record_add(parked.peg.id, side, price, self.clock);
+                    }
+                    let size = parked.size;
+                    self.record_update(parked.peg.id, OrderUpdate::Rested { price, size });
+                    self.pegs.push(parked.peg);
+                }
+                Err(_) => self.parked_pegs.push(parked),
+            }
+        }
+    }
+
+    /// Err with why a pegged order can't rest at `price`, off the ladder
+    /// or outside the band
+    fn check_peg_price(&self, price: i64) -> Result<()> {
+        if price <= 0 {
+            return Err(format!("Pegged price {} is below the ladder", price));
+        }
+        self.bids.validate_price(price)?;
+        self.check_price_band(price)
+    }
+
+    /// The nearest price to `price` a peg can rest at, inside the band
+    /// and on a tick, rounding in towards the reference. None when there
+    /// isn't one short of the reference.
+    fn clamp_peg_price(&self, price: i64) -> Option<i64> {
+        let reference = self.reference_price()?;
+        let mut clamped = price.max(1);
+        if let Some(bps) = self.price_band_bps() {
+            let width = reference * bps / 10_000;
+            clamped = clamped.clamp(reference - width, reference + width);
+        }
+
+        let tick_table = &self.bids.tick_table;
+        let (clamped, overshot) = if clamped > reference {
+            let clamped = tick_table.tick_at_or_below(clamped);
+            (clamped, clamped < reference)
+        } else {
+            let clamped = tick_table.tick_at_or_above(clamped);
+            (clamped, clamped > reference)
+        };
+        if overshot || self.check_peg_price(clamped).is_err() {
+            return None;
+        }
+        Some(clamped)
+    }
+
+    /// Pull `owner`'s quotes in `cancel` then post every level for them in
+    /// one pass. Quotes only ever rest, a level that would trade is
+    /// rejected on its own without affecting the others. Ids that are no
+    /// longer resting, say because they filled in the meantime, are
+    /// skipped, one resting for someone else rejects the whole request.
+    pub fn mass_quote(
+        &mut self,
+        owner: u64,
+        cancel: &[u64],
+        levels: &[QuoteLevel],
+    ) -> Result<Vec<Result<LimitOrderResponse>>> {
+        self.log(EventKind::MassQuote {
+            owner,
+            cancel: cancel.to_vec(),
+            levels: levels.to_vec(),
+        });
+        if self.session_state == SessionState::Paused {
+            return Err("Trading is paused".into());
+        }
+        // nothing is pulled unless every quote to pull is the owner's
+        for id in cancel {
+            let resting_owner = self.bids.get_owner(*id).or(self.asks.get_owner(*id));
+            if resting_owner.is_some_and(|resting_owner| resting_owner != owner) {
+                return Err(format!("Order {} does not belong to owner {}", id, owner));
+            }
+        }
+
+        for id in cancel {
+            if self.get_order(*id).is_some() {
+                self.remove_order(*id)?;
+            }
+        }
+
+        let responses = levels
+            .iter()
+            .map(|level| {
+                if level.size <= 0 {
+                    return Err(format!("Quote size {} must be positive", level.size));
+                }
+                self.check_lot_size(level.size)?;
+                self.bids.validate_price(level.price)?;
+                self.check_price_band(level.price)?;
+                let rests_locked = self.locked_policy == LockedPolicy::RestAndLock
+                    && self.locks_book(level.side, level.price);
+                if self.crosses_book(level.side, level.price) && !rests_locked {
+                    return Err(format!("Quote at {} would cross the book", level.price));
+                }
+                let notional = self.limit_notional(owner, level.price, level.size)?;
+                self.check_owner_limits(owner, level.side, 1, notional, level.size)?;
+
+                self.handle_maker(level.side, level.price, level.size, owner, None)
+            })
+            .collect();
+
+        self.reprice_pegs();
+        self.check_crossed_book();
+        self.publish_changes();
+        self.refresh_depth_views();
+        Ok(responses)
+    }
+
+    /// Priced exactly at the opposite best
+    fn locks_book(&self, side: Side, price: i64) -> bool {
+        self.get_top_of_book(side.opposite())
+            .is_some_and(|resting| resting.price == price)
+    }
+
+    fn crosses_book(&self, side: Side, price: i64) -> bool {
+        match side {
+            Side::Buy => self
+                .get_best_ask()
+                .map(|order| order.price <= price)
+                .unwrap_or_default(),
+            Side::Sell => self
+                .get_best_bid()
+                .map(|order| order.price >= price)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Take up to `size`, never trading worse than `limit_price`
+    fn handle_taker(
+        &mut self,
+        taker: Taker,
+        size: i64,
+        limit_price: Option<i64>,
+    ) -> Result<MarketOrderResponse> {
+        let side = taker.side;
+        let limits = self.taker_limits(side);
+        // the order's own price stopping it is not a breach
+        let own_limit_tighter = match (limit_price, limits.price(side)) {
+            (Some(own), Some(book)) => match side {
+                Side::Buy => own < book,
+                Side::Sell => own > book,
+            },
+            (own, _) => own.is_some(),
+        };
+        let until = if own_limit_tighter {
+            limit_price
+        } else {
+            limits.price(side)
+        };
+
+        let stp = self.self_trade_prevention;
+        let seen = self.resting_half(side.opposite()).reports().len();
+        let fill = match side {
+            Side::Sell => self.bids.match_size_as(size, until, taker.owner, stp)?,
+            Side::Buy => self.asks.match_size_as(size, until, taker.owner, stp)?,
+        };
+        self.record_trades(taker, seen);
+
+        let remaining = size - fill.size - fill.prevented;
+        self.finish_taker(side, limits, remaining > 0 && !own_limit_tighter, &fill);
+
+        Ok(MarketOrderResponse {
+            id: taker.id,
+            notional: fill.notional,
+            size: fill.size,
+            remaining,
+            resting_id: None,
+        })
+    }
+
+    /// Trade against the best level only and rest whatever is left there.
+    /// With nothing to trade against there is no price to rest at, so the
+    /// order comes back unfilled like any market order on an empty book.
+    fn handle_market_to_limit(&mut self, taker: Taker, size: i64) -> Result<MarketOrderResponse> {
+        let (side, owner) = (taker.side, taker.owner);
+        let Some(best) = self.get_top_of_book(side.opposite()) else {
+            return self.handle_taker(taker, size, None);
+        };
+
+        let mut response = self.handle_taker(taker, size, Some(best.price))?;
+        // a limit may have halted the sweep before it reached the level, and
+        // minimum quantity orders skipped there would leave it crossed
+        if let Some(price) = self.last_trade_price
+            && response.remaining > 0
+            && response.size > 0
+            && self.session_state == SessionState::Continuous
+            && !self.crosses_book(side, price)
+        {
+            response.resting_id = Some(
+                self.handle_maker(side, price, response.remaining, owner, None)?
+                    .id,
+            );
+        }
+        Ok(response)
+    }
+
+    fn handle_quote_taker(&mut self, taker: Taker, budget: i64) -> Result<MarketOrderResponse> {
+        let side = taker.side;
+        let limits = self.taker_limits(side);
+
+        let (until, stp) = (limits.price(side), self.self_trade_prevention);
+        let seen = self.resting_half(side.opposite()).reports().len();
+        let fill = match side {
+            Side::Sell => self
+                .bids
+                .match_notional_as(budget, until, taker.owner, stp)?,
+            Side::Buy => self
+                .asks
+                .match_notional_as(budget, until, taker.owner, stp)?,
+        };
+        self.record_trades(taker, seen);
+
+        // running out of budget is not stopping short, only
+        // leaving behind a level we could still have afforded
+        let remaining = budget - fill.notional;
+        let stopped_short = self
+            .get_top_of_book(side.opposite())
+            .is_some_and(|resting| remaining >= resting.price);
+        self.finish_taker(side, limits, stopped_short, &fill);
+
+        Ok(MarketOrderResponse {
+            id: taker.id,
+            notional: fill.notional,
+            size: fill.size,
+            remaining,
+            resting_id: None,
+        })
+    }
+
+    /// Limits are fixed for the whole sweep, buys stop at the upper
+    /// ones and sells stop at the lower ones
+    fn taker_limits(&self, side: Side) -> TakerLimits {
+        let luld = self.price_limit_band().map(|(lower, upper)| match side {
+            Side::Buy => upper,
+            Side::Sell => lower,
+        });
+
+        let price_move = self
+            .price_move_guard
+            .zip(self.last_trade_price)
+            .map(|(guard, last)| match side {
+                Side::Buy => last + guard.max_move,
+                Side::Sell => last - guard.max_move,
+            });
+
+        TakerLimits { luld, price_move }
+    }
+
+    /// Aggressors get an id like anything else, so trades can name them
+    fn new_taker(&mut self, side: Side, owner: u64) -> Taker {
+        Taker {
+            id: self.get_next_id(),
+            side,
+            owner,
+        }
+    }
+
+    fn resting_half(&self, side: Side) -> &HalfBook {
+        match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        }
+    }
+
+    /// Turn the fills the resting side reported since `seen` into trades
+    fn record_trades(&mut self, taker: Taker, seen: usize) {
+        let half = match taker.side {
+            Side::Buy => &self.asks,
+            Side::Sell => &self.bids,
+        };
+        let resting = taker.side.opposite();
+        let mut rebates = Vec::new();
+        for (index, report) in half.reports().iter().enumerate().skip(seen) {
+            if let Some(stats) = &mut self.quote_stats {
+                let (id, price, now) = (report.order_id, report.price, self.clock);
+                match report.exec_type {
+                    ExecType::Cancelled => stats.record_cancel(id, resting, price, now),
+                    ExecType::PartialFill => stats.record_fill(id, resting, price, now, false),
+                    ExecType::Fill => stats.record_fill(id, resting, price, now, true),
+                }
+            }
+            if let Some(history) = &mut self.order_history {
+                let (price, size) = (report.price, report.traded_size);
+                let update = match report.exec_type {
+                    ExecType::Cancelled => OrderUpdate::Cancelled,
+                    ExecType::PartialFill => OrderUpdate::PartialFill { price, size },
+                    ExecType::Fill => OrderUpdate::Filled { price, size },
+                };
+                history.record(report.order_id, self.clock, update);
+            }
+            if report.exec_type == ExecType::Cancelled {
+                continue;
+            }
+            let trade = Trade {
+                trade_id: self.next_trade_id,
+                price: report.price,
+                size: report.traded_size,
+                aggressor_side: taker.side,
+                maker_order_id: report.order_id,
+                taker_order_id: taker.id,
+                maker_owner: report.owner,
+                taker_owner: taker.owner,
+                timestamp: self.clock,
+            };
+            book_position(&mut self.positions, &trade, trade.size);
+            let maker = trade.maker_owner;
+            if maker != 0
+                && let Some(mmp) = self.mmp_limits.get(&maker)
+            {
+                let fills = self.maker_fills.entry(maker).or_default();
+                fills.push_back((trade.timestamp, trade.size));
+                while let Some((at, _)) = fills.front()
+                    && trade.timestamp.saturating_sub(*at) >= mmp.window
+                {
+                    fills.pop_front();
+                }
+                let volume: i64 = fills.iter().map(|(_, size)| size).sum();
+                let tripped = mmp.max_fills.is_some_and(|max| fills.len() > max)
+                    || mmp.max_volume.is_some_and(|max| volume > max);
+                if tripped && !self.mmp_tripped.contains(&maker) {
+                    self.mmp_tripped.push(maker);
+                }
+            }
+            if let Some(stats) = &mut self.trade_stats {
+                stats.record(&trade);
+            }
+            let fees = self.fee_schedule.fees(&trade);
+            if fees.maker < 0 {
+                rebates.push((index, -fees.maker));
+                if maker != 0 {
+                    *self.rebates.entry(maker).or_default() -= fees.maker;
+                }
+            }
+            if let Some(PostTradeHookBox(hook)) = &mut self.post_trade_hook {
+                hook.on_trade(&trade, fees);
+            }
+            if self.recent_trades.len() == BUSTABLE_TRADES {
+                self.recent_trades.pop_front();
+            }
+            self.recent_trades.push_back(trade.clone());
+            self.trades.push(trade);
+            self.next_trade_id += 1;
+        }
+
+        let half = match taker.side {
+            Side::Buy => &mut self.asks,
+            Side::Sell => &mut self.bids,
+        };
+        for (index, rebate) in rebates {
+            half.set_rebate(index, rebate);
+        }
+    }
+
+    /// Refuse an order that would take `owner` over their limits. It adds
+    /// `orders` open orders and `notional` resting on `side` if it rests
+    /// in full, and `size` to the position if it fills in full.
+    fn check_owner_limits(
+        &mut self,
+        owner: u64,
+        side: Side,
+        orders: usize,
+        notional: i64,
+        size: i64,
+    ) -> Result<()> {
+        let Some(limits) = self.owner_limits.get(&owner).copied() else {
+            return Ok(());
+        };
+
+        let breach = self.owner_limit_breach(owner, limits, side, orders, notional, size);
+        if let Some(breach) = breach {
+            self.limit_rejects.push(LimitReject {
+                owner,
+                breach,
+                timestamp: self.clock,
+            });
+            return Err(breach.to_string());
+        }
+        Ok(())
+    }
+
+    /// The notional `size` at `price` counts towards `owner`'s resting
+    /// cap, 0 when they have none so nothing is multiplied needlessly
+    fn limit_notional(&self, owner: u64, price: i64, size: i64) -> Result<i64> {
+        let capped = self
+            .owner_limits
+            .get(&owner)
+            .is_some_and(|limits| limits.max_resting_notional.is_some());
+        if !capped {
+            return Ok(0);
+        }
+        price
+            .checked_mul(size)
+            .ok_or_else(|| "notional overflow".to_string())
+    }
+
+    fn owner_limit_breach(
+        &self,
+        owner: u64,
+        limits: OwnerLimits,
+        side: Side,
+        orders: usize,
+        notional: i64,
+        size: i64,
+    ) -> Option<LimitBreach> {
+        if orders > 0
+            && let Some(limit) = limits.max_open_orders
+        {
+            let open = self.bids.owner_exposure(owner).0
+                + self.asks.owner_exposure(owner).0
+                + self.stops.owned_by(owner)
+                + self
+                    .parked_pegs
+                    .iter()
+                    .filter(|parked| parked.owner == owner)
+                    .count();
+            if open + orders > limit {
+                return Some(LimitBreach::OpenOrders { limit });
+            }
+        }
+
+        if notional > 0
+            && let Some(limit) = limits.max_resting_notional
+        {
+            let notional = self
+                .resting_half(side)
+                .owner_exposure(owner)
+                .1
+                .saturating_add(notional);
+            if notional > limit {
+                return Some(LimitBreach::RestingNotional {
+                    side,
+                    notional,
+                    limit,
+                });
+            }
+        }
+
+        if let Some(limit) = limits.max_net_position {
+            let position = self.position(owner);
+            let after = match side {
+                Side::Buy => position + size,
+                Side::Sell => position - size,
+            };
+            if after.abs() > limit && after.abs() > position.abs() {
+                return Some(LimitBreach::NetPosition {
+                    position: after,
+                    limit,
+                });
+            }
+        }
+        None
+    }
+
+    fn finish_taker(&mut self, side: Side, limits: TakerLimits, stopped_short: bool, fill: &Fill) {
+        // we stopped short with liquidity left beyond a limit, a LULD
+        // breach always halts, a price move only when configured to
+        if stopped_short && let Some(resting) = self.get_top_of_book(side.opposite()) {
+            let beyond = |limit: Option<i64>| {
+                limit.is_some_and(|limit| match side {
+                    Side::Buy => resting.price > limit,
+                    Side::Sell => resting.price < limit,
+                })
+            };
+            let halts_on_move = self
+                .price_move_guard
+                .is_some_and(|guard| guard.action == PriceMoveAction::Halt);
+
+            if (beyond(limits.luld) || (halts_on_move && beyond(limits.price_move)))
+                && self.session_state != SessionState::Paused
+            {
+                self.session_state = SessionState::Paused;
+                self.publish_status(MarketStatus::Halted);
+            }
+        }
+
+        if fill.last_price.is_some() {
+            self.last_trade_price = fill.last_price;
+        }
+    }
+
+    fn handle_maker(
+        &mut self,
+        side: Side,
+        price: i64,
+        size: i64,
+        owner: u64,
+        iceberg: Option<Iceberg>,
+    ) -> Result<LimitOrderResponse> {
+        self.grow_ladders_down(price)?;
+        let id = self.get_next_id();
+        let half = match side {
+            Side::Sell => &mut self.asks,
+            Side::Buy => &mut self.bids,
+        };
+        match iceberg {
+            // whatever is left after trading may be less than one clip
+            Some(iceberg) => half.insert_iceberg_with(
+                id,
+                price,
+                iceberg.display_size.min(size),
+                size,
+                iceberg.refresh,
+            )?,
+            None => half.insert(id, price, size)?,
+        }
+        if owner != 0 {
+            half.set_owner(id, owner)?;
+        }
+        if let Some(stats) = &mut self.quote_stats {
+            stats.record_add(id, side, price, self.clock);
+        }
+        self.record_update(id, OrderUpdate::Rested { price, size });
 
         Ok(LimitOrderResponse { id })
     }
 
+    /// Both halves share a tick table, so a price below the ladder grows
+    /// them together
+    fn grow_ladders_down(&mut self, price: i64) -> Result<()> {
+        self.bids.grow_ladder_down(price)?;
+        self.asks.grow_ladder_down(price)
+    }
+
+    /// Reject a limit price that sits outside the band around the
+    /// reference price. With no band or no reference anything goes.
+    fn check_price_band(&self, price: i64) -> Result<()> {
+        let (Some(bps), Some(reference)) = (self.price_band_bps(), self.reference_price()) else {
+            return Ok(());
+        };
+
+        if (price - reference).abs() * 10_000 > reference * bps {
+            return Err(format!(
+                "Limit price {} is outside the {}bps band around {}",
+                price, bps, reference
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn check_lot_size(&self, size: i64) -> Result<()> {
+        if size % self.lot_size != 0 {
+            return Err(format!(
+                "Size {} is not a multiple of the lot size {}",
+                size, self.lot_size
+            ));
+        }
+        Ok(())
+    }
+
+    fn refresh_depth_views(&mut self) {
+        if let Some(mut views) = self.depth_views.take() {
+            views.refresh(self);
+            self.depth_views = Some(views);
+        }
+    }
+
+    /// Hand the level changes to the depth feed, the top of book to the
+    /// quote cache and, when it moved, the BBO observer
+    fn publish_changes(&mut self) {
+        // each level goes out the way `published_depth` shows it
+        let round_lot = self.round_lot.unwrap_or(1);
+        for (side, half) in [(Side::Buy, &mut self.bids), (Side::Sell, &mut self.asks)] {
+            for level in half.drain_level_sizes() {
+                let new_total_size = match self.market_data_mode {
+                    MarketDataMode::Full => level.total_size,
+                    MarketDataMode::VisibleOnly => half.displayed_size_at(level.price, round_lot),
+                };
+                self.level_updates.push(LevelUpdate {
+                    side,
+                    price: level.price,
+                    new_total_size,
+                });
+            }
+        }
+
+        let (bid, ask) = (
+            self.published_top_of_book(Side::Buy),
+            self.published_top_of_book(Side::Sell),
+        );
+        if let Some(quote_cache) = &self.quote_cache {
+            quote_cache.publish(Quote { bid, ask });
+        }
+
+        if let Some(BboObserver(observer)) = self.bbo_observer.as_mut()
+            && self.last_bbo != (bid, ask)
+        {
+            self.last_bbo = (bid, ask);
+            observer(bid, ask);
+        }
+    }
+
+    /// Bid >= ask should never happen, if it does dump what we know
+    fn check_crossed_book(&mut self) {
+        let best_bid = self.get_best_bid();
+        let best_ask = self.get_best_ask();
+        let Some(detector) = self.crossed_book_detector.as_mut() else {
+            return;
+        };
+
+        detector.record_top_of_book(best_bid, best_ask);
+
+        // a lock we were asked to allow is not worth a report
+        let allowed_lock = self.locked_policy == LockedPolicy::RestAndLock
+            && best_bid
+                .zip(best_ask)
+                .is_some_and(|(bid, ask)| bid.price == ask.price);
+
+        if let (Some(bid), Some(ask)) = (best_bid, best_ask)
+            && CrossedBookDetector::is_crossed(best_bid, best_ask)
+            && !allowed_lock
+        {
+            detector.report(
+                bid,
+                ask,
+                self.bids.top_of_book_orders(),
+                self.asks.top_of_book_orders(),
+            );
+        }
+    }
+
+    fn log(&mut self, kind: EventKind) {
+        let event = Event {
+            seq: self.next_event_seq(),
+            kind,
+        };
+        if let Some(detector) = self.crossed_book_detector.as_mut() {
+            detector.record_event(&event);
+        }
+        self.bids.set_stamp(self.clock, event.seq);
+        self.asks.set_stamp(self.clock, event.seq);
+        self.event_log.push(event);
+    }
+
     fn get_next_id(&mut self) -> u64 {
         let id = self.current_id;
         self.current_id += 1;
         id
     }
 }
+
+/// Who is on the aggressive side of a sweep
+#[derive(Debug, Clone, Copy)]
+struct Taker {
+    id: u64,
+    side: Side,
+    owner: u64,
+}
+
+/// Where an aggressive sweep has to stop, and why
+#[derive(Debug, Clone, Copy)]
+struct TakerLimits {
+    luld: Option<i64>,
+    price_move: Option<i64>,
+}
+
+impl TakerLimits {
+    /// the tighter of the two
+    fn price(&self, side: Side) -> Option<i64> {
+        [self.luld, self.price_move]
+            .into_iter()
+            .flatten()
+            .reduce(|a, b| match side {
+                Side::Buy => a.min(b),
+                Side::Sell => a.max(b),
+            })
+    }
+}
+
+/// Move both owners' positions by `size` of the trade, negative to
+/// take it back
+fn book_position(positions: &mut BTreeMap<u64, i64>, trade: &Trade, size: i64) {
+    let (buyer, seller) = match trade.aggressor_side {
+        Side::Buy => (trade.taker_owner, trade.maker_owner),
+        Side::Sell => (trade.maker_owner, trade.taker_owner),
+    };
+    for (owner, change) in [(buyer, size), (seller, -size)] {
+        if owner == 0 {
+            continue;
+        }
+        let position = positions.entry(owner).or_default();
+        *position += change;
+        if *position == 0 {
+            positions.remove(&owner);
+        }
+    }
+}
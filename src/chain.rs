@@ -0,0 +1,355 @@
+use std::collections::BTreeMap;
+
+use crate::{OrderResponse, OrderTicket, PriceSize, Result, Side, book::Orderbook};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OptionKind {
+    Call,
+    Put,
+}
+
+/// One listed contract. Ordering is expiry first, then strike, so a range
+/// over the chain walks one expiry at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ContractKey {
+    pub expiry: u64,
+    pub strike: i64,
+    pub kind: OptionKind,
+}
+
+/// Top of book for one contract
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContractQuote {
+    pub key: ContractKey,
+    pub bid: Option<PriceSize>,
+    pub ask: Option<PriceSize>,
+}
+
+#[derive(Debug)]
+struct Contract {
+    book: Orderbook,
+    /// owner of every order or stop still resting, by id
+    owners: BTreeMap<u64, u64>,
+    /// net contracts held by each owner, positive is long
+    positions: BTreeMap<u64, i64>,
+    /// contracts traded
+    volume: i64,
+}
+
+impl Contract {
+    /// Book the trades `owner`'s order just caused. A taker id the chain
+    /// hasn't seen is that order's own.
+    fn settle_trades(&mut self, owner: u64) {
+        for trade in self.book.drain_trades() {
+            let maker = self.owners.get(&trade.maker_order_id).copied();
+            let taker = self
+                .owners
+                .get(&trade.taker_order_id)
+                .copied()
+                .unwrap_or(owner);
+            let (buyer, seller) = match trade.aggressor_side {
+                Side::Buy => (taker, maker.unwrap_or(owner)),
+                Side::Sell => (maker.unwrap_or(owner), taker),
+            };
+            *self.positions.entry(buyer).or_default() += trade.size;
+            *self.positions.entry(seller).or_default() -= trade.size;
+            self.volume += trade.size;
+        }
+        self.positions.retain(|_, position| *position != 0);
+
+        let book = &self.book;
+        self.owners
+            .retain(|id, _| book.get_order(*id).is_some() || book.stops.get_order(*id).is_some());
+    }
+
+    fn open_interest(&self) -> i64 {
+        self.positions
+            .values()
+            .filter(|position| **position > 0)
+            .sum()
+    }
+}
+
+/// Every option book listed on one underlying
+#[derive(Debug, Default)]
+pub struct OptionChain {
+    pub underlying: String,
+    contracts: BTreeMap<ContractKey, Contract>,
+}
+
+impl OptionChain {
+    pub fn new(underlying: impl Into<String>) -> Self {
+        Self {
+            underlying: underlying.into(),
+            contracts: BTreeMap::new(),
+        }
+    }
+
+    /// List a contract on its own book, which carries whatever tick table
+    /// and limits the caller configured
+    pub fn list(&mut self, key: ContractKey, book: Orderbook) -> Result<()> {
+        if self.contracts.contains_key(&key) {
+            return Err(format!("{:?} is already listed", key));
+        }
+        self.contracts.insert(
+            key,
+            Contract {
+                book,
+                owners: BTreeMap::new(),
+                positions: BTreeMap::new(),
+                volume: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Drop a contract and its book, e.g. once it has expired
+    pub fn delist(&mut self, key: &ContractKey) -> Result<Orderbook> {
+        self.contracts
+            .remove(key)
+            .map(|contract| contract.book)
+            .ok_or_else(|| format!("{:?} is not listed", key))
+    }
+
+    pub fn book(&self, key: &ContractKey) -> Option<&Orderbook> {
+        self.contracts.get(key).map(|contract| &contract.book)
+    }
+
+    pub fn contracts(&self) -> impl Iterator<Item = &ContractKey> {
+        self.contracts.keys()
+    }
+
+    pub fn submit(&mut self, key: &ContractKey, ticket: OrderTicket) -> Result<OrderResponse> {
+        let contract = self
+            .contracts
+            .get_mut(key)
+            .ok_or_else(|| format!("{:?} is not listed", key))?;
+
+        let owner = ticket.owner;
+        let response = contract.book.accept_order(ticket)?;
+        let resting_id = match &response {
+            OrderResponse::Limit(limit) => Some(limit.id),
+            OrderResponse::Market(market) => market.resting_id,
+        };
+        if let Some(id) = resting_id {
+            contract.owners.insert(id, owner);
+        }
+        contract.settle_trades(owner);
+        Ok(response)
+    }
+
+    /// Best bid and ask for every strike of one expiry, lowest strike first
+    pub fn quotes(&self, expiry: u64) -> Vec<ContractQuote> {
+        self.expiry(expiry)
+            .map(|(key, contract)| ContractQuote {
+                key: *key,
+                bid: contract.book.get_best_bid(),
+                ask: contract.book.get_best_ask(),
+            })
+            .collect()
+    }
+
+    /// Contracts traded across one expiry, resting orders that were hit
+    /// and stops that fired included
+    pub fn volume(&self, expiry: u64) -> i64 {
+        self.expiry(expiry)
+            .map(|(_, contract)| contract.volume)
+            .sum()
+    }
+
+    /// Contracts held long across one expiry, which always equals those
+    /// held short. Positions are kept per order owner from every trade, so
+    /// opening and closing trades net out.
+    pub fn open_interest(&self, expiry: u64) -> i64 {
+        self.expiry(expiry)
+            .map(|(_, contract)| contract.open_interest())
+            .sum()
+    }
+
+    /// Size resting on one side across one expiry
+    pub fn resting_size(&self, expiry: u64, side: Side) -> i64 {
+        self.expiry(expiry)
+            .map(|(_, contract)| contract.book.total_liquidity(side))
+            .sum()
+    }
+
+    /// Pull every order and stop resting on one expiry, returning how
+    /// many went
+    pub fn cancel_expiry(&mut self, expiry: u64) -> Result<usize> {
+        let mut cancelled = 0;
+        for (_, contract) in self.expiry_mut(expiry) {
+            for id in std::mem::take(&mut contract.owners).into_keys() {
+                // anything missing filled since it rested
+                if contract.book.get_order(id).is_some()
+                    || contract.book.stops.get_order(id).is_some()
+                {
+                    contract.book.cancel_order(id)?;
+                    cancelled += 1;
+                }
+            }
+        }
+        Ok(cancelled)
+    }
+
+    fn expiry(&self, expiry: u64) -> impl Iterator<Item = (&ContractKey, &Contract)> {
+        self.contracts
+            .range(Self::expiry_start(expiry)..)
+            .take_while(move |(key, _)| key.expiry == expiry)
+    }
+
+    fn expiry_mut(&mut self, expiry: u64) -> impl Iterator<Item = (&ContractKey, &mut Contract)> {
+        self.contracts
+            .range_mut(Self::expiry_start(expiry)..)
+            .take_while(move |(key, _)| key.expiry == expiry)
+    }
+
+    fn expiry_start(expiry: u64) -> ContractKey {
+        ContractKey {
+            expiry,
+            strike: i64::MIN,
+            kind: OptionKind::Call,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BookConfig, MarketPolicy, OrderType, TimeInForce, tick::TickTable};
+
+    const MAY: u64 = 20260515;
+    const JUNE: u64 = 20260619;
+
+    fn key(expiry: u64, strike: i64, kind: OptionKind) -> ContractKey {
+        ContractKey {
+            expiry,
+            strike,
+            kind,
+        }
+    }
+
+    fn limit(side: Side, price: i64, size: i64) -> OrderTicket {
+        OrderTicket {
+            side,
+            size,
+            order_type: OrderType::Limit(price),
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        }
+    }
+
+    fn chain() -> OptionChain {
+        let mut chain = OptionChain::new("ABC");
+        for expiry in [JUNE, MAY] {
+            for strike in [110, 90, 100] {
+                for kind in [OptionKind::Put, OptionKind::Call] {
+                    let book = Orderbook::with_tick_table(1_000, TickTable::fixed(1, 1));
+                    chain.list(key(expiry, strike, kind), book).unwrap();
+                }
+            }
+        }
+        chain
+    }
+
+    #[test]
+    fn queries_walk_one_expiry_by_strike() {
+        let mut chain = chain();
+        let call = key(MAY, 100, OptionKind::Call);
+        assert!(chain.list(call, Orderbook::default()).is_err());
+
+        chain.submit(&call, limit(Side::Buy, 5, 10)).unwrap();
+        chain.submit(&call, limit(Side::Sell, 7, 10)).unwrap();
+        chain
+            .submit(&key(JUNE, 100, OptionKind::Call), limit(Side::Sell, 9, 4))
+            .unwrap();
+        chain.submit(&call, limit(Side::Buy, 7, 3)).unwrap();
+
+        let quotes = chain.quotes(MAY);
+        assert_eq!(quotes.len(), 6);
+        assert!(quotes.windows(2).all(|pair| pair[0].key < pair[1].key));
+        let quote = quotes.iter().find(|quote| quote.key == call).unwrap();
+        assert_eq!(quote.bid, Some(PriceSize { price: 5, size: 10 }));
+        assert_eq!(quote.ask, Some(PriceSize { price: 7, size: 7 }));
+
+        assert_eq!(chain.volume(MAY), 3);
+        assert_eq!(chain.volume(JUNE), 0);
+        assert_eq!(chain.resting_size(JUNE, Side::Sell), 4);
+    }
+
+    #[test]
+    fn cancel_expiry_leaves_other_expiries_alone() {
+        let mut chain = chain();
+        let may = key(MAY, 90, OptionKind::Put);
+        let june = key(JUNE, 90, OptionKind::Put);
+        chain.submit(&may, limit(Side::Buy, 2, 5)).unwrap();
+        chain.submit(&may, limit(Side::Sell, 4, 5)).unwrap();
+        chain.submit(&june, limit(Side::Buy, 3, 5)).unwrap();
+
+        assert_eq!(chain.cancel_expiry(MAY).unwrap(), 2);
+        assert_eq!(chain.resting_size(MAY, Side::Buy), 0);
+        assert_eq!(chain.resting_size(MAY, Side::Sell), 0);
+        assert_eq!(chain.resting_size(JUNE, Side::Buy), 5);
+        assert_eq!(chain.cancel_expiry(MAY).unwrap(), 0);
+
+        assert!(chain.delist(&may).is_ok());
+        assert!(chain.submit(&may, limit(Side::Buy, 2, 5)).is_err());
+    }
+
+    #[test]
+    fn open_interest_nets_out_and_every_kind_of_resting_order_is_pulled() {
+        let mut chain = chain();
+        let call = key(MAY, 110, OptionKind::Call);
+        let owned = |owner, ticket: OrderTicket| OrderTicket { owner, ..ticket };
+
+        chain
+            .submit(&call, owned(1, limit(Side::Sell, 7, 10)))
+            .unwrap();
+        // a crossing limit opens 4 against the resting seller
+        chain
+            .submit(&call, owned(2, limit(Side::Buy, 7, 4)))
+            .unwrap();
+        assert_eq!(chain.open_interest(MAY), 4);
+
+        // owner 2 closes out to owner 3, interest stays where it was
+        chain
+            .submit(&call, owned(3, limit(Side::Buy, 6, 5)))
+            .unwrap();
+        let market = OrderTicket {
+            order_type: OrderType::Market,
+            ..owned(2, limit(Side::Sell, 0, 4))
+        };
+        chain.submit(&call, market).unwrap();
+        assert_eq!(chain.open_interest(MAY), 4);
+        assert_eq!(chain.volume(MAY), 8);
+
+        let stop = OrderTicket {
+            order_type: OrderType::Stop { trigger: 8 },
+            ..owned(4, limit(Side::Buy, 0, 1))
+        };
+        chain.submit(&call, stop).unwrap();
+
+        // what a market order leaves resting under `MarketPolicy::ToLimit`
+        let june = key(JUNE, 200, OptionKind::Call);
+        let book = Orderbook::with_config(BookConfig {
+            market_policy: MarketPolicy::ToLimit,
+            ..Default::default()
+        })
+        .unwrap();
+        chain.list(june, book).unwrap();
+        chain.submit(&june, limit(Side::Sell, 9, 2)).unwrap();
+        let market = OrderTicket {
+            order_type: OrderType::Market,
+            ..limit(Side::Buy, 0, 5)
+        };
+        chain.submit(&june, market).unwrap();
+        assert_eq!(chain.resting_size(JUNE, Side::Buy), 3);
+
+        // the ask, the leftover bid and the stop
+        assert_eq!(chain.cancel_expiry(MAY).unwrap(), 3);
+        assert!(chain.book(&call).unwrap().stops.is_empty());
+        assert_eq!(chain.cancel_expiry(JUNE).unwrap(), 1);
+        assert_eq!(chain.resting_size(JUNE, Side::Buy), 0);
+    }
+}
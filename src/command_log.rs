@@ -0,0 +1,1092 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    CancelResponse, ClipSize, EventKind, Iceberg, IcebergRefresh, LimitOrderResponse,
+    OrderResponse, OrderTicket, OrderType, PegReference, QuoteLevel, RefreshPriority,
+    ReplaceResponse, Result, Side, TimeInForce, TradeBust, book::Orderbook, snapshot::BookSnapshot,
+};
+
+/// Where inputs are made durable before the book applies them. The
+/// book is deterministic, so replaying a log into a fresh book rebuilds
+/// it exactly, whether the log is a local file, a Raft log or a bus.
+pub trait CommandLog {
+    /// Durably record an input, returning its sequence number
+    fn append(&mut self, event: &EventKind) -> Result<u64>;
+
+    /// Every input from `seq` onwards in the order they were appended
+    fn read_from(&mut self, seq: u64) -> Result<Vec<EventKind>>;
+
+    /// The sequence number the next input will get
+    fn next_seq(&self) -> u64;
+
+    /// Durably keep `snapshot`, the book just before input `seq`, then
+    /// drop the inputs before it. A crash part way leaves either the old
+    /// log or the new one, each readable with the checkpoint.
+    fn compact(&mut self, _seq: u64, _snapshot: &BookSnapshot) -> Result<()> {
+        Err("This log cannot be compacted".into())
+    }
+
+    /// The latest checkpoint and the input it was taken before, if the
+    /// log was ever compacted
+    fn checkpoint(&mut self) -> Result<Option<(u64, BookSnapshot)>> {
+        Ok(None)
+    }
+}
+
+/// Keeps inputs in memory, for tests and simulations
+#[derive(Debug, Default)]
+pub struct MemoryLog {
+    /// sequence number of the first input still held
+    base: u64,
+    events: Vec<EventKind>,
+    checkpoint: Option<(u64, BookSnapshot)>,
+}
+
+impl CommandLog for MemoryLog {
+    fn append(&mut self, event: &EventKind) -> Result<u64> {
+        self.events.push(event.clone());
+        Ok(self.next_seq() - 1)
+    }
+
+    fn read_from(&mut self, seq: u64) -> Result<Vec<EventKind>> {
+        let start = compacted_offset(self.base, seq)?;
+        Ok(self.events.iter().skip(start).cloned().collect())
+    }
+
+    fn next_seq(&self) -> u64 {
+        self.base + self.events.len() as u64
+    }
+
+    fn compact(&mut self, seq: u64, snapshot: &BookSnapshot) -> Result<()> {
+        let start = compacted_offset(self.base, seq)?;
+        self.checkpoint = Some((seq, snapshot.clone()));
+        self.events.drain(..start.min(self.events.len()));
+        self.base = seq;
+        Ok(())
+    }
+
+    fn checkpoint(&mut self) -> Result<Option<(u64, BookSnapshot)>> {
+        Ok(self.checkpoint.clone())
+    }
+}
+
+/// How far into a log starting at `base` input `seq` is
+fn compacted_offset(base: u64, seq: u64) -> Result<usize> {
+    match seq.checked_sub(base) {
+        Some(offset) => Ok(offset as usize),
+        None => Err(format!(
+            "Inputs before {} were compacted into a checkpoint",
+            base
+        )),
+    }
+}
+
+/// Appends one input per line to a local file and syncs it before
+/// returning, so an acknowledged input survives a crash. A compacted log
+/// starts with `BASE seq`, the sequence number of its first input, and
+/// its checkpoint sits next to it in `<path>.checkpoint`, which needs the
+/// serde feature.
+#[derive(Debug)]
+pub struct FileLog {
+    path: PathBuf,
+    file: File,
+    /// sequence number of the first input in the file
+    base: u64,
+    next_seq: u64,
+}
+
+impl FileLog {
+    /// Open or create a log, continuing the sequence of what is there.
+    /// A final line without its newline was torn by a crash mid-write and
+    /// never acknowledged, so it is cut off.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to open command log {}: {}", path.display(), e))?;
+
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)
+            .map_err(|e| format!("Failed to read command log {}: {}", path.display(), e))?;
+
+        let complete = contents.rfind('\n').map_or(0, |newline| newline + 1);
+        if complete < contents.len() {
+            file.set_len(complete as u64)
+                .and_then(|_| file.sync_data())
+                .map_err(|e| format!("Failed to truncate {}: {}", path.display(), e))?;
+        }
+
+        let (base, inputs) = split_base(&contents[..complete])?;
+        // counted the way `replay_events` reads them back
+        let next_seq = base
+            + inputs
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .count() as u64;
+
+        Ok(Self {
+            path,
+            file,
+            base,
+            next_seq,
+        })
+    }
+
+    #[cfg(feature = "serde")]
+    fn checkpoint_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".checkpoint");
+        path.into()
+    }
+}
+
+/// The `BASE` a compacted log starts with, zero without one, and the
+/// inputs after it
+fn split_base(contents: &str) -> Result<(u64, &str)> {
+    let Some(rest) = contents.strip_prefix("BASE ") else {
+        return Ok((0, contents));
+    };
+    let (base, inputs) = rest.split_once('\n').unwrap_or((rest, ""));
+    let base = base
+        .trim()
+        .parse()
+        .map_err(|_| format!("Malformed log header {:?}", base))?;
+    Ok((base, inputs))
+}
+
+/// Write `contents` next to `path` and move it into place, so a crash
+/// leaves either the old file or the new one complete
+#[cfg(feature = "serde")]
+fn replace_file(path: &Path, contents: &str) -> Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+
+    let written = File::create(&temporary)
+        .and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&temporary, path));
+    // the rename itself is only durable once the directory is
+    let directory = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty());
+    written
+        .and_then(|_| File::open(directory.unwrap_or(Path::new("."))))
+        .and_then(|directory| directory.sync_all())
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+impl CommandLog for FileLog {
+    fn append(&mut self, event: &EventKind) -> Result<u64> {
+        writeln!(self.file, "{}", encode_event(event))
+            .and_then(|_| self.file.sync_data())
+            .map_err(|e| format!("Failed to append to {}: {}", self.path.display(), e))?;
+
+        self.next_seq += 1;
+        Ok(self.next_seq - 1)
+    }
+
+    fn read_from(&mut self, seq: u64) -> Result<Vec<EventKind>> {
+        let start = compacted_offset(self.base, seq)?;
+        let contents = std::fs::read_to_string(&self.path)
+            .map_err(|e| format!("Failed to read {}: {}", self.path.display(), e))?;
+        let (_, inputs) = split_base(&contents)?;
+
+        replay_events(inputs.as_bytes()).skip(start).collect()
+    }
+
+    fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    /// The checkpoint goes down first, then the log is rewritten without
+    /// what it covers, each into a temporary file that is moved into
+    /// place. Crashing in between leaves the old log, which still reads
+    /// from the checkpoint on since it knows its own base. Snapshots only
+    /// hold what rests, so a checkpoint costs as much as the book holds
+    /// however wide its ladder is.
+    #[cfg(feature = "serde")]
+    fn compact(&mut self, seq: u64, snapshot: &BookSnapshot) -> Result<()> {
+        let snapshot = serde_json::to_string(snapshot)
+            .map_err(|e| format!("Failed to encode checkpoint: {}", e))?;
+        replace_file(&self.checkpoint_path(), &format!("{}\n{}\n", seq, snapshot))?;
+
+        let mut log = format!("BASE {}\n", seq);
+        for event in self.read_from(seq)? {
+            log.push_str(&encode_event(&event));
+            log.push('\n');
+        }
+        replace_file(&self.path, &log)?;
+
+        self.file = OpenOptions::new()
+            .append(true)
+            .read(true)
+            .open(&self.path)
+            .map_err(|e| format!("Failed to reopen {}: {}", self.path.display(), e))?;
+        self.base = seq;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    fn checkpoint(&mut self) -> Result<Option<(u64, BookSnapshot)>> {
+        let path = self.checkpoint_path();
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+
+        let malformed = || format!("Malformed checkpoint {}", path.display());
+        let (seq, snapshot) = contents.split_once('\n').ok_or_else(malformed)?;
+        let seq = seq.parse().map_err(|_| malformed())?;
+        let snapshot = serde_json::from_str(snapshot).map_err(|_| malformed())?;
+        Ok(Some((seq, snapshot)))
+    }
+}
+
+/// Stream commands out of a recording one line at a time, without
+/// holding the whole file in memory. Blank lines are skipped.
+pub fn replay(reader: impl BufRead) -> impl Iterator<Item = Result<OrderTicket>> {
+    reader
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| {
+            line.map_err(|e| format!("Failed to read command: {}", e))
+                .and_then(|line| decode(&line))
+        })
+}
+
+/// Same as `replay` for a log of every kind of input, as `FileLog`
+/// writes it. A recording of orders only reads as one too.
+pub fn replay_events(reader: impl BufRead) -> impl Iterator<Item = Result<EventKind>> {
+    reader
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| {
+            line.map_err(|e| format!("Failed to read command: {}", e))
+                .and_then(|line| decode_event(&line))
+        })
+}
+
+/// `replay` a recording on disk
+pub fn replay_file(path: impl AsRef<Path>) -> Result<impl Iterator<Item = Result<OrderTicket>>> {
+    let file = File::open(path.as_ref())
+        .map_err(|e| format!("Failed to read {}: {}", path.as_ref().display(), e))?;
+    Ok(replay(BufReader::new(file)))
+}
+
+/// `replay_events` a recording on disk
+pub fn replay_events_file(
+    path: impl AsRef<Path>,
+) -> Result<impl Iterator<Item = Result<EventKind>>> {
+    let file = File::open(path.as_ref())
+        .map_err(|e| format!("Failed to read {}: {}", path.as_ref().display(), e))?;
+    Ok(replay_events(BufReader::new(file)))
+}
+
+/// An order is encoded as a ticket, everything else starts with its own
+/// word: `X id` to cancel, `R id price size` to replace, `E now` to
+/// expire, `RT` to resume trading, `C now` to set the clock, `DE ts` or
+/// `DE -` for the end of day, `BT trade_id` to bust a trade and
+/// `MQ owner n id.. [<B|S> price size]..` for a mass quote pulling `n`
+/// ids then posting each level.
+pub fn encode_event(event: &EventKind) -> String {
+    match event {
+        EventKind::Order(ticket) => encode(ticket),
+        EventKind::Cancel(id) => format!("X {}", id),
+        EventKind::Replace { id, price, size } => format!("R {} {} {}", id, price, size),
+        EventKind::MassQuote {
+            owner,
+            cancel,
+            levels,
+        } => {
+            let mut words = vec![
+                "MQ".to_string(),
+                owner.to_string(),
+                cancel.len().to_string(),
+            ];
+            words.extend(cancel.iter().map(|id| id.to_string()));
+            for level in levels {
+                words.push(
+                    match level.side {
+                        Side::Buy => "B",
+                        Side::Sell => "S",
+                    }
+                    .to_string(),
+                );
+                words.push(level.price.to_string());
+                words.push(level.size.to_string());
+            }
+            words.join(" ")
+        }
+        EventKind::Expire(now) => format!("E {}", now),
+        EventKind::ResumeTrading => "RT".to_string(),
+        EventKind::SetClock(now) => format!("C {}", now),
+        EventKind::SetDayEnd(Some(day_end)) => format!("DE {}", day_end),
+        EventKind::SetDayEnd(None) => "DE -".to_string(),
+        EventKind::Bust(trade_id) => format!("BT {}", trade_id),
+    }
+}
+
+pub fn decode_event(line: &str) -> Result<EventKind> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let malformed = || format!("Malformed command {:?}", line);
+    let number = |index: usize| -> Result<u64> {
+        words
+            .get(index)
+            .and_then(|word| word.parse().ok())
+            .ok_or_else(malformed)
+    };
+    let signed = |index: usize| -> Result<i64> {
+        words
+            .get(index)
+            .and_then(|word| word.parse().ok())
+            .ok_or_else(malformed)
+    };
+    let arity = |count: usize| -> Result<()> {
+        match words.len() == count {
+            true => Ok(()),
+            false => Err(malformed()),
+        }
+    };
+
+    match words.first() {
+        Some(&"B") | Some(&"S") => decode(line).map(EventKind::Order),
+        Some(&"X") => arity(2).and(Ok(EventKind::Cancel(number(1)?))),
+        Some(&"R") => arity(4).and(Ok(EventKind::Replace {
+            id: number(1)?,
+            price: signed(2)?,
+            size: signed(3)?,
+        })),
+        Some(&"MQ") => {
+            // the count comes off the log, so don't trust it to fit
+            let levels_at = usize::try_from(number(2)?)
+                .ok()
+                .and_then(|count| count.checked_add(3))
+                .filter(|levels_at| *levels_at <= words.len())
+                .ok_or_else(malformed)?;
+            let cancel = (3..levels_at).map(number).collect::<Result<Vec<u64>>>()?;
+            let rest = &words[levels_at..];
+            if !rest.len().is_multiple_of(3) {
+                return Err(malformed());
+            }
+            let levels = rest
+                .chunks(3)
+                .enumerate()
+                .map(|(level, chunk)| {
+                    let at = levels_at + level * 3;
+                    let side = match chunk[0] {
+                        "B" => Side::Buy,
+                        "S" => Side::Sell,
+                        _ => return Err(malformed()),
+                    };
+                    Ok(QuoteLevel {
+                        side,
+                        price: signed(at + 1)?,
+                        size: signed(at + 2)?,
+                    })
+                })
+                .collect::<Result<Vec<QuoteLevel>>>()?;
+            Ok(EventKind::MassQuote {
+                owner: number(1)?,
+                cancel,
+                levels,
+            })
+        }
+        Some(&"E") => arity(2).and(Ok(EventKind::Expire(number(1)?))),
+        Some(&"RT") => arity(1).and(Ok(EventKind::ResumeTrading)),
+        Some(&"C") => arity(2).and(Ok(EventKind::SetClock(number(1)?))),
+        Some(&"DE") => match words.get(1) {
+            Some(&"-") => arity(2).and(Ok(EventKind::SetDayEnd(None))),
+            _ => arity(2).and(Ok(EventKind::SetDayEnd(Some(number(1)?)))),
+        },
+        Some(&"BT") => arity(2).and(Ok(EventKind::Bust(number(1)?))),
+        _ => Err(malformed()),
+    }
+}
+
+/// `<B|S> <L price|I price|T trigger|P <P|M> offset|M|Q> <size> [D|G ts] [MQ min] [O owner]
+/// [IB display [RC min max] [RR]]`, e.g. `B L 100 10`, `S M 5`, `S P M 2 5` (offering two
+/// above the bid), `B L 99 3 G 1700 MQ 3 O 7` (good till 1700, all or none, for owner 7) or
+/// `S L 101 50 IB 5 RC 3 8 RR` (an iceberg showing 5 first, then clips of 3 to 8 that keep
+/// their place). Good till cancelled, no minimum quantity, anonymous, no iceberg, fixed
+/// clips and back of the queue are left off.
+pub fn encode(ticket: &OrderTicket) -> String {
+    let side = match ticket.side {
+        Side::Buy => "B",
+        Side::Sell => "S",
+    };
+
+    let order = match ticket.order_type {
+        OrderType::Limit(price) => format!("{} L {} {}", side, price, ticket.size),
+        OrderType::ImmediateOrCancel(price) => format!("{} I {} {}", side, price, ticket.size),
+        OrderType::Stop { trigger } => format!("{} T {} {}", side, trigger, ticket.size),
+        OrderType::Pegged { side_ref, offset } => {
+            let side_ref = match side_ref {
+                PegReference::Primary => "P",
+                PegReference::Market => "M",
+            };
+            format!("{} P {} {} {}", side, side_ref, offset, ticket.size)
+        }
+        OrderType::Market => format!("{} M {}", side, ticket.size),
+        OrderType::QuoteMarket => format!("{} Q {}", side, ticket.size),
+    };
+
+    let order = match ticket.time_in_force {
+        TimeInForce::Gtc => order,
+        TimeInForce::Day => format!("{} D", order),
+        TimeInForce::Gtd(expires_at) => format!("{} G {}", order, expires_at),
+    };
+
+    let order = match ticket.min_qty {
+        Some(min_qty) => format!("{} MQ {}", order, min_qty),
+        None => order,
+    };
+
+    let order = match ticket.owner {
+        0 => order,
+        owner => format!("{} O {}", order, owner),
+    };
+
+    let Some(iceberg) = ticket.iceberg else {
+        return order;
+    };
+    let order = format!("{} IB {}", order, iceberg.display_size);
+    let order = match iceberg.refresh.clip {
+        ClipSize::Fixed => order,
+        ClipSize::Random { min, max } => format!("{} RC {} {}", order, min, max),
+    };
+    match iceberg.refresh.priority {
+        RefreshPriority::BackOfQueue => order,
+        RefreshPriority::Retain => format!("{} RR", order),
+    }
+}
+
+pub fn decode(line: &str) -> Result<OrderTicket> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let number = |index: usize| -> Result<i64> {
+        words
+            .get(index)
+            .and_then(|word| word.parse().ok())
+            .ok_or_else(|| format!("Malformed command {:?}", line))
+    };
+
+    let side = match words.first() {
+        Some(&"B") => Side::Buy,
+        Some(&"S") => Side::Sell,
+        _ => return Err(format!("Malformed command {:?}", line)),
+    };
+
+    // the type decides how many words come before the size
+    let (order_type, size_at) = match words.get(1) {
+        Some(&"L") => (OrderType::Limit(number(2)?), 3),
+        Some(&"I") => (OrderType::ImmediateOrCancel(number(2)?), 3),
+        Some(&"T") => (
+            OrderType::Stop {
+                trigger: number(2)?,
+            },
+            3,
+        ),
+        Some(&"P") => {
+            let side_ref = match words.get(2) {
+                Some(&"P") => PegReference::Primary,
+                Some(&"M") => PegReference::Market,
+                _ => return Err(format!("Malformed command {:?}", line)),
+            };
+            (
+                OrderType::Pegged {
+                    side_ref,
+                    offset: number(3)?,
+                },
+                4,
+            )
+        }
+        Some(&"M") => (OrderType::Market, 2),
+        Some(&"Q") => (OrderType::QuoteMarket, 2),
+        _ => return Err(format!("Malformed command {:?}", line)),
+    };
+
+    // optional trailing flags
+    let mut time_in_force = TimeInForce::Gtc;
+    let mut min_qty = None;
+    let mut owner = 0;
+    let mut iceberg: Option<Iceberg> = None;
+    let mut refresh = IcebergRefresh::default();
+    let mut flag_at = size_at + 1;
+    while let Some(flag) = words.get(flag_at) {
+        match *flag {
+            "D" => time_in_force = TimeInForce::Day,
+            "G" => {
+                time_in_force = TimeInForce::Gtd(
+                    words
+                        .get(flag_at + 1)
+                        .and_then(|word| word.parse().ok())
+                        .ok_or_else(|| format!("Malformed command {:?}", line))?,
+                );
+                flag_at += 1;
+            }
+            "MQ" => {
+                min_qty = Some(number(flag_at + 1)?);
+                flag_at += 1;
+            }
+            "O" => {
+                owner = words
+                    .get(flag_at + 1)
+                    .and_then(|word| word.parse().ok())
+                    .ok_or_else(|| format!("Malformed command {:?}", line))?;
+                flag_at += 1;
+            }
+            "IB" => {
+                iceberg = Some(Iceberg {
+                    display_size: number(flag_at + 1)?,
+                    refresh: IcebergRefresh::default(),
+                });
+                flag_at += 1;
+            }
+            "RC" => {
+                refresh.clip = ClipSize::Random {
+                    min: number(flag_at + 1)?,
+                    max: number(flag_at + 2)?,
+                };
+                flag_at += 2;
+            }
+            "RR" => refresh.priority = RefreshPriority::Retain,
+            _ => return Err(format!("Malformed command {:?}", line)),
+        }
+        flag_at += 1;
+    }
+    // a refresh policy only means something for an iceberg
+    let iceberg = match iceberg {
+        Some(iceberg) => Some(Iceberg { refresh, ..iceberg }),
+        None if refresh != IcebergRefresh::default() => {
+            return Err(format!("Malformed command {:?}", line));
+        }
+        None => None,
+    };
+
+    Ok(OrderTicket {
+        order_type,
+        size: number(size_at)?,
+        side,
+        time_in_force,
+        min_qty,
+        owner,
+        iceberg,
+    })
+}
+
+/// A book that writes every input to its log before applying it
+#[derive(Debug)]
+pub struct LoggedOrderbook<L: CommandLog> {
+    pub book: Orderbook,
+    pub log: L,
+    /// compact the log once this many inputs were appended since the last
+    /// checkpoint, never when None
+    pub checkpoint_interval: Option<u64>,
+    /// the input the last checkpoint was taken before
+    last_checkpoint: u64,
+}
+
+impl<L: CommandLog> LoggedOrderbook<L> {
+    pub fn new(book: Orderbook, log: L) -> Self {
+        let last_checkpoint = log.next_seq();
+        Self {
+            book,
+            log,
+            checkpoint_interval: None,
+            last_checkpoint,
+        }
+    }
+
+    /// Rebuild from the log on top of a freshly configured book. Inputs
+    /// that were rejected the first time are rejected the same way again,
+    /// so their errors are ignored. A compacted log starts from its
+    /// checkpoint instead, settings included, and only replays what came
+    /// after it.
+    pub fn recover(mut book: Orderbook, mut log: L) -> Result<Self> {
+        let mut last_checkpoint = 0;
+        if let Some((seq, snapshot)) = log.checkpoint()? {
+            book = Orderbook::from_snapshot(snapshot)?;
+            last_checkpoint = seq;
+        }
+        for event in log.read_from(last_checkpoint)? {
+            book.apply_event(event);
+        }
+
+        Ok(Self {
+            book,
+            log,
+            checkpoint_interval: None,
+            last_checkpoint,
+        })
+    }
+
+    pub fn set_checkpoint_interval(&mut self, checkpoint_interval: Option<u64>) {
+        self.checkpoint_interval = checkpoint_interval;
+    }
+
+    /// Checkpoint the book and drop the inputs it covers from the log
+    pub fn compact(&mut self) -> Result<()> {
+        let seq = self.log.next_seq();
+        let mut snapshot = self.book.snapshot();
+        // the checkpoint stands in for the log, it does not carry it
+        snapshot.events_drained += snapshot.event_log.len() as u64;
+        snapshot.event_log.clear();
+
+        self.log.compact(seq, &snapshot)?;
+        self.last_checkpoint = seq;
+        Ok(())
+    }
+
+    /// Compact first when a checkpoint is due, so a failure leaves the
+    /// input neither logged nor applied
+    fn append(&mut self, event: &EventKind) -> Result<u64> {
+        if let Some(interval) = self.checkpoint_interval
+            && self.log.next_seq() >= self.last_checkpoint + interval
+        {
+            self.compact()?;
+        }
+        self.log.append(event)
+    }
+
+    /// The book as it was just before input `seq`, rebuilt from the log
+    /// on an empty copy of this one, so it reaches back past anything the
+    /// book itself has drained
+    pub fn rewind_to(&mut self, seq: u64) -> Result<Orderbook> {
+        let events = self.log.read_from(0)?;
+        if seq > events.len() as u64 {
+            return Err(format!(
+                "Cannot rewind to event {}, the log ends at {}",
+                seq,
+                events.len()
+            ));
+        }
+
+        let mut book = self.book.empty_copy();
+        for event in events.into_iter().take(seq as usize) {
+            book.apply_event(event);
+        }
+        Ok(book)
+    }
+
+    pub fn accept_order(&mut self, ticket: OrderTicket) -> Result<OrderResponse> {
+        self.append(&EventKind::Order(ticket.clone()))?;
+        self.book.accept_order(ticket)
+    }
+
+    pub fn cancel_order(&mut self, id: u64) -> Result<CancelResponse> {
+        self.append(&EventKind::Cancel(id))?;
+        self.book.cancel_order(id)
+    }
+
+    pub fn replace_order(&mut self, id: u64, price: i64, size: i64) -> Result<ReplaceResponse> {
+        self.append(&EventKind::Replace { id, price, size })?;
+        self.book.replace_order(id, price, size)
+    }
+
+    pub fn mass_quote(
+        &mut self,
+        owner: u64,
+        cancel: &[u64],
+        levels: &[QuoteLevel],
+    ) -> Result<Vec<Result<LimitOrderResponse>>> {
+        self.append(&EventKind::MassQuote {
+            owner,
+            cancel: cancel.to_vec(),
+            levels: levels.to_vec(),
+        })?;
+        self.book.mass_quote(owner, cancel, levels)
+    }
+
+    pub fn expire(&mut self, now: u64) -> Result<Vec<CancelResponse>> {
+        self.append(&EventKind::Expire(now))?;
+        Ok(self.book.expire(now))
+    }
+
+    pub fn resume_trading(&mut self) -> Result<()> {
+        self.append(&EventKind::ResumeTrading)?;
+        self.book.resume_trading();
+        Ok(())
+    }
+
+    pub fn set_clock(&mut self, now: u64) -> Result<()> {
+        self.append(&EventKind::SetClock(now))?;
+        self.book.set_clock(now);
+        Ok(())
+    }
+
+    pub fn set_day_end(&mut self, day_end: Option<u64>) -> Result<()> {
+        self.append(&EventKind::SetDayEnd(day_end))?;
+        self.book.set_day_end(day_end);
+        Ok(())
+    }
+
+    pub fn bust_trade(&mut self, trade_id: u64) -> Result<TradeBust> {
+        self.append(&EventKind::Bust(trade_id))?;
+        self.book.bust_trade(trade_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tickets() -> Vec<OrderTicket> {
+        vec![
+            OrderTicket {
+                order_type: OrderType::Limit(100),
+                size: 10,
+                side: Side::Buy,
+                time_in_force: TimeInForce::Gtc,
+                min_qty: None,
+                owner: 0,
+                iceberg: None,
+            },
+            OrderTicket {
+                order_type: OrderType::Limit(102),
+                size: 7,
+                side: Side::Sell,
+                time_in_force: TimeInForce::Gtc,
+                min_qty: None,
+                owner: 0,
+                iceberg: None,
+            },
+            // rejected, and must be rejected again on recovery
+            OrderTicket {
+                order_type: OrderType::Limit(0),
+                size: 1,
+                side: Side::Sell,
+                time_in_force: TimeInForce::Gtc,
+                min_qty: None,
+                owner: 0,
+                iceberg: None,
+            },
+            OrderTicket {
+                order_type: OrderType::Market,
+                size: 4,
+                side: Side::Sell,
+                time_in_force: TimeInForce::Gtc,
+                min_qty: None,
+                owner: 0,
+                iceberg: None,
+            },
+            OrderTicket {
+                order_type: OrderType::QuoteMarket,
+                size: 204,
+                side: Side::Buy,
+                time_in_force: TimeInForce::Gtc,
+                min_qty: None,
+                owner: 0,
+                iceberg: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn encoding_round_trips() {
+        let ioc = OrderTicket {
+            order_type: OrderType::ImmediateOrCancel(99),
+            size: 3,
+            side: Side::Sell,
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        };
+        let stop = OrderTicket {
+            order_type: OrderType::Stop { trigger: 105 },
+            size: 2,
+            side: Side::Buy,
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        };
+        let peg = OrderTicket {
+            order_type: OrderType::Pegged {
+                side_ref: PegReference::Market,
+                offset: 2,
+            },
+            size: 5,
+            side: Side::Sell,
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        };
+        let day = OrderTicket {
+            time_in_force: TimeInForce::Day,
+            ..tickets()[0].clone()
+        };
+        let good_till = OrderTicket {
+            time_in_force: TimeInForce::Gtd(1_700),
+            ..peg.clone()
+        };
+        let all_or_none = OrderTicket {
+            time_in_force: TimeInForce::Gtd(1_700),
+            min_qty: Some(10),
+            ..tickets()[0].clone()
+        };
+        let owned = OrderTicket {
+            owner: 7,
+            ..tickets()[1].clone()
+        };
+        let iceberg = OrderTicket {
+            iceberg: Some(Iceberg {
+                display_size: 2,
+                refresh: IcebergRefresh {
+                    clip: ClipSize::Random { min: 1, max: 3 },
+                    priority: RefreshPriority::Retain,
+                },
+            }),
+            ..tickets()[0].clone()
+        };
+        for ticket in tickets().into_iter().chain([
+            ioc,
+            stop,
+            peg,
+            day,
+            good_till,
+            all_or_none,
+            owned,
+            iceberg,
+        ]) {
+            assert_eq!(decode(&encode(&ticket)).unwrap(), ticket);
+        }
+        assert_eq!(
+            encode(&decode("S L 101 50 IB 5").unwrap()),
+            "S L 101 50 IB 5"
+        );
+
+        assert!(decode("B L 100").is_err());
+        assert!(decode("X M 1").is_err());
+        assert!(decode("").is_err());
+        assert!(decode("B L 100 1 G").is_err());
+        assert!(decode("B L 100 1 X").is_err());
+        assert!(decode("B L 100 1 RR").is_err());
+    }
+
+    #[test]
+    fn every_event_round_trips() {
+        let events = [
+            EventKind::Order(tickets()[0].clone()),
+            EventKind::Cancel(3),
+            EventKind::Replace {
+                id: 4,
+                price: 101,
+                size: 6,
+            },
+            EventKind::MassQuote {
+                owner: 7,
+                cancel: vec![1, 2],
+                levels: vec![
+                    QuoteLevel {
+                        side: Side::Buy,
+                        price: 99,
+                        size: 5,
+                    },
+                    QuoteLevel {
+                        side: Side::Sell,
+                        price: 103,
+                        size: 5,
+                    },
+                ],
+            },
+            EventKind::MassQuote {
+                owner: 0,
+                cancel: vec![],
+                levels: vec![],
+            },
+            EventKind::Expire(400),
+            EventKind::ResumeTrading,
+            EventKind::SetClock(250),
+            EventKind::SetDayEnd(Some(500)),
+            EventKind::SetDayEnd(None),
+            EventKind::Bust(3),
+        ];
+        for event in events {
+            assert_eq!(decode_event(&encode_event(&event)).unwrap(), event);
+        }
+
+        assert!(decode_event("X").is_err());
+        assert!(decode_event("X 1 2").is_err());
+        assert!(decode_event("MQ 0 2 1").is_err());
+        assert!(decode_event("MQ 0 0 B 100").is_err());
+        assert!(decode_event("MQ 0 18446744073709551615 1").is_err());
+        assert!(decode_event("MQ 0 0 Z 100 1").is_err());
+        assert!(decode_event("RT now").is_err());
+    }
+
+    #[test]
+    fn replay_streams_a_recording() {
+        let recording = "B L 100 10\n\nS M 4\n";
+        let tickets: Vec<OrderTicket> =
+            replay(recording.as_bytes()).collect::<Result<_>>().unwrap();
+        assert_eq!(tickets.len(), 2);
+        assert_eq!(tickets[1].order_type, OrderType::Market);
+
+        let mut stream = replay("B L 100 10\nnonsense\n".as_bytes());
+        assert!(stream.next().unwrap().is_ok());
+        assert!(stream.next().unwrap().is_err());
+    }
+
+    /// Orders plus every other kind of input, some of them rejected
+    fn run<L: CommandLog>(live: &mut LoggedOrderbook<L>) {
+        live.set_day_end(Some(500)).unwrap();
+        for ticket in tickets() {
+            let _ = live.accept_order(ticket);
+        }
+        live.set_clock(100).unwrap();
+        let _ = live.replace_order(0, 99, 12);
+        let _ = live.cancel_order(1);
+        let _ = live.cancel_order(1);
+        let quote = QuoteLevel {
+            side: Side::Sell,
+            price: 104,
+            size: 3,
+        };
+        let _ = live.mass_quote(7, &[], &[quote]);
+        live.expire(400).unwrap();
+        live.resume_trading().unwrap();
+    }
+
+    #[test]
+    fn memory_log_recovers_the_same_book() {
+        let mut live = LoggedOrderbook::new(Orderbook::new(), MemoryLog::default());
+        run(&mut live);
+
+        assert_eq!(live.log.read_from(3).unwrap().len(), 10);
+        assert_eq!(live.book.event_log.len(), 13);
+
+        let digest = live.book.state_digest();
+        let recovered = LoggedOrderbook::recover(Orderbook::new(), live.log).unwrap();
+        assert_eq!(recovered.book.state_digest(), digest);
+    }
+
+    #[test]
+    fn rewind_reaches_past_drained_events() {
+        let mut live = LoggedOrderbook::new(Orderbook::new(), MemoryLog::default());
+        live.set_day_end(Some(500)).unwrap();
+        for ticket in tickets() {
+            let _ = live.accept_order(ticket);
+        }
+        let digest = live.book.state_digest();
+        let seq = live.book.event_log.len() as u64;
+        run(&mut live);
+        live.book.drain_events();
+
+        assert!(live.book.rewind_to(seq).is_err());
+        assert_eq!(live.rewind_to(seq).unwrap().state_digest(), digest);
+        assert_eq!(
+            live.rewind_to(seq + 13).unwrap().state_digest(),
+            live.book.state_digest()
+        );
+        assert!(live.rewind_to(seq + 14).is_err());
+    }
+
+    #[test]
+    fn checkpoints_compact_the_log() {
+        let mut live = LoggedOrderbook::new(Orderbook::new(), MemoryLog::default());
+        live.set_checkpoint_interval(Some(4));
+        run(&mut live);
+
+        // compacted before inputs 4, 8 and 12
+        assert_eq!(live.log.next_seq(), 13);
+        assert_eq!(live.log.read_from(12).unwrap().len(), 1);
+        assert!(live.log.read_from(11).is_err());
+        assert!(live.rewind_to(3).is_err());
+
+        let digest = live.book.state_digest();
+        let mut recovered = LoggedOrderbook::recover(Orderbook::new(), live.log).unwrap();
+        assert_eq!(recovered.book.state_digest(), digest);
+        recovered.set_clock(900).unwrap();
+        assert_eq!(recovered.book.event_log[1].seq, 13);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn file_log_compaction_survives_a_crash_half_way() {
+        let path = std::env::temp_dir().join(format!(
+            "orderbook-compacted-log-{}.txt",
+            std::process::id()
+        ));
+        let checkpoint = std::env::temp_dir().join(format!(
+            "orderbook-compacted-log-{}.txt.checkpoint",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&checkpoint);
+
+        let mut live = LoggedOrderbook::new(Orderbook::new(), FileLog::open(&path).unwrap());
+        live.set_checkpoint_interval(Some(5));
+        run(&mut live);
+        let digest = live.book.state_digest();
+        let uncompacted = std::fs::read_to_string(&path).unwrap();
+        live.compact().unwrap();
+        drop(live);
+        assert!(
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .starts_with("BASE 13\n")
+        );
+        // the checkpoint holds what rests, not the ladder it rests on
+        let written = std::fs::metadata(&checkpoint).unwrap().len();
+        assert!(written < 16_384, "{} byte checkpoint", written);
+
+        let recovered =
+            LoggedOrderbook::recover(Orderbook::new(), FileLog::open(&path).unwrap()).unwrap();
+        assert_eq!(recovered.book.state_digest(), digest);
+
+        // died after writing the checkpoint but before rewriting the log
+        std::fs::write(&path, uncompacted).unwrap();
+        let recovered =
+            LoggedOrderbook::recover(Orderbook::new(), FileLog::open(&path).unwrap()).unwrap();
+        assert_eq!(recovered.book.state_digest(), digest);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&checkpoint).unwrap();
+    }
+
+    #[test]
+    fn file_log_survives_reopening() {
+        let path =
+            std::env::temp_dir().join(format!("orderbook-command-log-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let digest = {
+            let mut live = LoggedOrderbook::new(Orderbook::new(), FileLog::open(&path).unwrap());
+            run(&mut live);
+            live.book.state_digest()
+        };
+
+        // a crash halfway through writing a line, with a blank one before
+        // it that replay skips
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "\nB L 10").unwrap();
+        drop(file);
+
+        let mut recovered =
+            LoggedOrderbook::recover(Orderbook::new(), FileLog::open(&path).unwrap()).unwrap();
+        assert_eq!(recovered.book.state_digest(), digest);
+        assert!(std::fs::read_to_string(&path).unwrap().ends_with("\n\n"));
+
+        // the sequence picks up where the file left off
+        let seq = recovered.log.append(&EventKind::Cancel(0)).unwrap();
+        assert_eq!(seq, 13);
+        assert_eq!(
+            recovered.log.read_from(13).unwrap(),
+            vec![EventKind::Cancel(0)]
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,232 @@
+use crate::{LevelUpdate, PriceSize, Result, Side, book::Orderbook};
+
+/// One conflated message, the latest state of everything that changed
+/// since the last one
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConflatedUpdate {
+    /// the book's clock when it was published
+    pub timestamp: u64,
+    /// the last total of each level that changed, first touch first
+    pub levels: Vec<LevelUpdate>,
+    pub best_bid: Option<PriceSize>,
+    pub best_ask: Option<PriceSize>,
+}
+
+/// Coalesces bursts of level and BBO changes into one message per
+/// interval of the book's clock. Poll it after every change to the book.
+/// The first change it sees opens a window and everything up to its end
+/// goes out together, each level and the BBO only with their final
+/// values, so a consumer ends up with the same book as one that saw every
+/// update. Consumes the book's level updates.
+#[derive(Debug, Default)]
+pub struct ConflatingPublisher {
+    /// book time a window stays open
+    pub interval: u64,
+    /// clock of the first change not yet published
+    pending_since: Option<u64>,
+    levels: Vec<LevelUpdate>,
+    /// best bid and ask as of the last message
+    published_bbo: (Option<PriceSize>, Option<PriceSize>),
+}
+
+impl ConflatingPublisher {
+    pub fn new(interval: u64) -> Result<Self> {
+        if interval == 0 {
+            return Err("Conflation interval must be positive".into());
+        }
+
+        Ok(Self {
+            interval,
+            ..Default::default()
+        })
+    }
+
+    /// Pick up what changed and publish if the window is over
+    pub fn poll(&mut self, book: &mut Orderbook) -> Option<ConflatedUpdate> {
+        self.record(book);
+        let since = self.pending_since?;
+        (book.clock >= since + self.interval).then(|| self.publish(book))
+    }
+
+    /// Publish whatever is pending now, e.g. before shutting down
+    pub fn flush(&mut self, book: &mut Orderbook) -> Option<ConflatedUpdate> {
+        self.record(book);
+        self.pending_since?;
+        Some(self.publish(book))
+    }
+
+    fn record(&mut self, book: &mut Orderbook) {
+        let updates = book.drain_level_updates();
+        let bbo = (
+            book.published_top_of_book(Side::Buy),
+            book.published_top_of_book(Side::Sell),
+        );
+        if updates.is_empty() && bbo == self.published_bbo {
+            return;
+        }
+
+        self.pending_since.get_or_insert(book.clock);
+        merge(&mut self.levels, updates);
+    }
+
+    fn publish(&mut self, book: &Orderbook) -> ConflatedUpdate {
+        self.pending_since = None;
+        self.published_bbo = (
+            book.published_top_of_book(Side::Buy),
+            book.published_top_of_book(Side::Sell),
+        );
+        ConflatedUpdate {
+            timestamp: book.clock,
+            levels: std::mem::take(&mut self.levels),
+            best_bid: self.published_bbo.0,
+            best_ask: self.published_bbo.1,
+        }
+    }
+}
+
+/// Publishes the levels that changed since the last publish, and only
+/// those, on a fixed cadence of the book's clock, e.g. every 100ms. An
+/// alternative to sending every update as it happens that bounds the
+/// message rate no matter how busy the book is. Consumes the book's level
+/// updates.
+#[derive(Debug, Default)]
+pub struct ThrottledDepthPublisher {
+    /// book time between publishes
+    pub cadence: u64,
+    /// the next multiple of the cadence to publish at
+    next_publish: u64,
+    /// levels changed since the last publish with their latest total
+    dirty: Vec<LevelUpdate>,
+}
+
+impl ThrottledDepthPublisher {
+    pub fn new(cadence: u64) -> Result<Self> {
+        if cadence == 0 {
+            return Err("Publishing cadence must be positive".into());
+        }
+
+        Ok(Self {
+            cadence,
+            ..Default::default()
+        })
+    }
+
+    /// Mark what changed as dirty and, once the clock reaches the next
+    /// tick of the cadence, hand out the dirty levels. Ticks without a
+    /// change publish nothing.
+    pub fn poll(&mut self, book: &mut Orderbook) -> Option<Vec<LevelUpdate>> {
+        merge(&mut self.dirty, book.drain_level_updates());
+        if book.clock < self.next_publish {
+            return None;
+        }
+
+        self.next_publish = (book.clock / self.cadence + 1) * self.cadence;
+        (!self.dirty.is_empty()).then(|| std::mem::take(&mut self.dirty))
+    }
+}
+
+/// Fold updates into `levels`, keeping one entry per level with its
+/// latest total
+fn merge(levels: &mut Vec<LevelUpdate>, updates: Vec<LevelUpdate>) {
+    for update in updates {
+        match levels
+            .iter_mut()
+            .find(|level| level.side == update.side && level.price == update.price)
+        {
+            Some(level) => level.new_total_size = update.new_total_size,
+            None => levels.push(update),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_log::decode;
+
+    fn send(book: &mut Orderbook, line: &str) {
+        book.accept_order(decode(line).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn coalesces_a_burst_into_its_final_state() {
+        let mut book = Orderbook::new();
+        let mut publisher = ConflatingPublisher::new(10).unwrap();
+        assert!(publisher.poll(&mut book).is_none());
+
+        book.set_clock(100);
+        for line in ["S L 101 5", "S L 101 3", "B L 99 2"] {
+            send(&mut book, line);
+            assert!(publisher.poll(&mut book).is_none());
+        }
+        book.set_clock(105);
+        send(&mut book, "B M 6");
+        assert!(publisher.poll(&mut book).is_none());
+
+        book.set_clock(110);
+        let update = publisher.poll(&mut book).unwrap();
+        assert_eq!(update.timestamp, 110);
+        assert_eq!(
+            update.levels,
+            vec![
+                LevelUpdate {
+                    side: Side::Sell,
+                    price: 101,
+                    new_total_size: 2
+                },
+                LevelUpdate {
+                    side: Side::Buy,
+                    price: 99,
+                    new_total_size: 2
+                },
+            ]
+        );
+        assert_eq!(update.best_ask, book.get_best_ask());
+        assert_eq!(update.best_bid, book.get_best_bid());
+
+        // nothing changed, nothing to send
+        book.set_clock(200);
+        assert!(publisher.poll(&mut book).is_none());
+        send(&mut book, "B L 98 1");
+        let update = publisher.flush(&mut book).unwrap();
+        assert_eq!(update.levels.len(), 1);
+        assert!(publisher.flush(&mut book).is_none());
+    }
+
+    #[test]
+    fn throttled_publisher_sends_dirty_levels_on_its_cadence() {
+        let mut book = Orderbook::new();
+        let mut publisher = ThrottledDepthPublisher::new(100).unwrap();
+        assert_eq!(publisher.poll(&mut book), None);
+
+        book.set_clock(10);
+        send(&mut book, "S L 101 5");
+        assert_eq!(publisher.poll(&mut book), None);
+        book.set_clock(50);
+        send(&mut book, "S L 102 1");
+        send(&mut book, "B M 2");
+        assert_eq!(publisher.poll(&mut book), None);
+
+        book.set_clock(130);
+        let update = |side, price, new_total_size| LevelUpdate {
+            side,
+            price,
+            new_total_size,
+        };
+        assert_eq!(
+            publisher.poll(&mut book),
+            Some(vec![update(Side::Sell, 101, 3), update(Side::Sell, 102, 1)])
+        );
+
+        // changes wait for the next tick at 200
+        send(&mut book, "B M 3");
+        assert_eq!(publisher.poll(&mut book), None);
+        book.set_clock(250);
+        assert_eq!(
+            publisher.poll(&mut book),
+            Some(vec![update(Side::Sell, 101, 0)])
+        );
+        book.set_clock(300);
+        assert_eq!(publisher.poll(&mut book), None);
+    }
+}
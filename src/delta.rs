@@ -0,0 +1,364 @@
+use std::collections::BTreeMap;
+
+use crate::{LevelUpdate, Result, Side, book::Orderbook};
+
+/// One depth message before encoding, the level updates of a change and
+/// the best prices they are sent relative to
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DepthDelta {
+    pub best_bid: Option<i64>,
+    pub best_ask: Option<i64>,
+    pub levels: Vec<LevelUpdate>,
+}
+
+/// What the encoder and decoder each remember between messages, so both
+/// sides agree on what a difference is relative to
+#[derive(Debug, Default)]
+struct DeltaState {
+    /// best prices of the last message
+    best_bid: i64,
+    best_ask: i64,
+    /// size of every level sent and not yet emptied
+    bids: BTreeMap<i64, i64>,
+    asks: BTreeMap<i64, i64>,
+}
+
+impl DeltaState {
+    fn sizes(&mut self, side: Side) -> &mut BTreeMap<i64, i64> {
+        match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        }
+    }
+
+    /// Prices on a side are sent relative to its best, or the other
+    /// side's best while it has none
+    fn reference(&self, side: Side, delta: &DepthDelta) -> i64 {
+        let (own, other) = match side {
+            Side::Buy => (delta.best_bid, delta.best_ask),
+            Side::Sell => (delta.best_ask, delta.best_bid),
+        };
+        own.or(other).unwrap_or_default()
+    }
+
+    /// Remember a level's new size, handing back what it was before
+    fn swap_size(&mut self, side: Side, price: i64, size: i64) -> i64 {
+        let sizes = self.sizes(side);
+        let before = match size {
+            0 => sizes.remove(&price),
+            _ => sizes.insert(price, size),
+        };
+        before.unwrap_or_default()
+    }
+}
+
+/// Compact binary depth feed. Each message starts with a byte flagging
+/// which best prices follow, then those prices as differences from the
+/// previous message's and the number of levels. Each level is its price
+/// as an offset from its side's best with the side in the low bit, then
+/// the change in its size. Every number is a zigzag varint, so the small
+/// offsets and changes of a busy book take a byte or two each.
+///
+/// The encoder and decoder keep the last best prices and level sizes in
+/// step, so every message has to be decoded in order. After a gap both
+/// sides `reset` and the consumer starts again from a snapshot.
+#[derive(Debug, Default)]
+pub struct DepthDeltaEncoder {
+    state: DeltaState,
+}
+
+impl DepthDeltaEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&mut self) {
+        self.state = DeltaState::default();
+    }
+
+    /// Encode the book's level updates since the last drain with its
+    /// published best prices. Consumes the book's level updates.
+    pub fn poll(&mut self, book: &mut Orderbook) -> Vec<u8> {
+        let delta = DepthDelta {
+            best_bid: book.published_top_of_book(Side::Buy).map(|top| top.price),
+            best_ask: book.published_top_of_book(Side::Sell).map(|top| top.price),
+            levels: book.drain_level_updates(),
+        };
+        self.encode(&delta)
+    }
+
+    pub fn encode(&mut self, delta: &DepthDelta) -> Vec<u8> {
+        let flags = u8::from(delta.best_bid.is_some()) | (u8::from(delta.best_ask.is_some()) << 1);
+        let mut bytes = vec![flags];
+        if let Some(best_bid) = delta.best_bid {
+            write_signed(&mut bytes, best_bid.wrapping_sub(self.state.best_bid));
+            self.state.best_bid = best_bid;
+        }
+        if let Some(best_ask) = delta.best_ask {
+            write_signed(&mut bytes, best_ask.wrapping_sub(self.state.best_ask));
+            self.state.best_ask = best_ask;
+        }
+
+        write_varint(&mut bytes, delta.levels.len() as u64);
+        for level in delta.levels.iter() {
+            let offset = level
+                .price
+                .wrapping_sub(self.state.reference(level.side, delta));
+            let side = match level.side {
+                Side::Buy => 0,
+                Side::Sell => 1,
+            };
+            write_varint(&mut bytes, (zigzag(offset) << 1) | side);
+            let before = self
+                .state
+                .swap_size(level.side, level.price, level.new_total_size);
+            write_signed(&mut bytes, level.new_total_size.wrapping_sub(before));
+        }
+        bytes
+    }
+}
+
+/// Reads what `DepthDeltaEncoder` wrote, message by message in order
+#[derive(Debug, Default)]
+pub struct DepthDeltaDecoder {
+    state: DeltaState,
+}
+
+impl DepthDeltaDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&mut self) {
+        self.state = DeltaState::default();
+    }
+
+    /// Fails on a message that is cut short or has bytes left over, and
+    /// leaves the decoder as it was
+    pub fn decode(&mut self, bytes: &[u8]) -> Result<DepthDelta> {
+        let mut reader = Reader { bytes, at: 0 };
+
+        let flags = reader.byte()?;
+        if flags > 0b11 {
+            return Err(format!("Unknown depth delta flags {:#04b}", flags));
+        }
+        let mut delta = DepthDelta::default();
+        if flags & 0b01 != 0 {
+            delta.best_bid = Some(self.state.best_bid.wrapping_add(reader.signed()?));
+        }
+        if flags & 0b10 != 0 {
+            delta.best_ask = Some(self.state.best_ask.wrapping_add(reader.signed()?));
+        }
+
+        let count = reader.varint()?;
+        // every level takes at least two bytes
+        if count > (bytes.len() - reader.at) as u64 / 2 {
+            return Err(format!("Depth delta claims {} levels", count));
+        }
+        // sizes this message moved, only written back once it all decodes
+        let mut touched: Vec<(Side, i64, i64)> = Vec::new();
+        for _ in 0..count {
+            let tagged = reader.varint()?;
+            let side = match tagged & 1 {
+                0 => Side::Buy,
+                _ => Side::Sell,
+            };
+            let price = self
+                .state
+                .reference(side, &delta)
+                .wrapping_add(unzigzag(tagged >> 1));
+            let before = match touched
+                .iter()
+                .rev()
+                .find(|(s, p, _)| *s == side && *p == price)
+            {
+                Some((_, _, size)) => *size,
+                None => self
+                    .state
+                    .sizes(side)
+                    .get(&price)
+                    .copied()
+                    .unwrap_or_default(),
+            };
+            let new_total_size = before.wrapping_add(reader.signed()?);
+            if new_total_size < 0 {
+                return Err(format!(
+                    "Level {} would go to a negative size {}",
+                    price, new_total_size
+                ));
+            }
+            touched.push((side, price, new_total_size));
+            delta.levels.push(LevelUpdate {
+                side,
+                price,
+                new_total_size,
+            });
+        }
+
+        if reader.at != bytes.len() {
+            return Err(format!(
+                "{} bytes left over after a depth delta",
+                bytes.len() - reader.at
+            ));
+        }
+        if let Some(best_bid) = delta.best_bid {
+            self.state.best_bid = best_bid;
+        }
+        if let Some(best_ask) = delta.best_ask {
+            self.state.best_ask = best_ask;
+        }
+        for (side, price, size) in touched {
+            self.state.swap_size(side, price, size);
+        }
+        Ok(delta)
+    }
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn write_signed(bytes: &mut Vec<u8>, value: i64) {
+    write_varint(bytes, zigzag(value));
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8> {
+        let byte = *self.bytes.get(self.at).ok_or("Depth delta is cut short")?;
+        self.at += 1;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let bits = u64::from(byte & 0x7f);
+            if shift == 63 && bits > 1 {
+                return Err("Varint does not fit in 64 bits".into());
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Varint does not fit in 64 bits".into())
+    }
+
+    fn signed(&mut self) -> Result<i64> {
+        self.varint().map(unzigzag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_log::decode;
+
+    fn send(book: &mut Orderbook, line: &str) {
+        book.accept_order(decode(line).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn decodes_what_the_book_published() {
+        let mut book = Orderbook::new();
+        let mut encoder = DepthDeltaEncoder::new();
+        let mut decoder = DepthDeltaDecoder::new();
+        let mut mirror: BTreeMap<(u8, i64), i64> = BTreeMap::new();
+
+        for line in [
+            "B L 9990 500",
+            "S L 10010 300",
+            "B L 9985 200",
+            "B L 9990 25",
+            "S M 100",
+            "S L 9990 525",
+            "B L 10010 10",
+        ] {
+            send(&mut book, line);
+            let bytes = encoder.poll(&mut book);
+            let delta = decoder.decode(&bytes).unwrap();
+            assert_eq!(
+                delta.best_bid,
+                book.published_top_of_book(Side::Buy).map(|top| top.price)
+            );
+            assert_eq!(
+                delta.best_ask,
+                book.published_top_of_book(Side::Sell).map(|top| top.price)
+            );
+            for level in delta.levels {
+                let key = (level.side as u8, level.price);
+                match level.new_total_size {
+                    0 => mirror.remove(&key),
+                    size => mirror.insert(key, size),
+                };
+            }
+        }
+
+        let expected: BTreeMap<(u8, i64), i64> = book
+            .published_depth(Side::Buy, usize::MAX)
+            .into_iter()
+            .map(|level| ((Side::Buy as u8, level.price), level.size))
+            .chain(
+                book.published_depth(Side::Sell, usize::MAX)
+                    .into_iter()
+                    .map(|level| ((Side::Sell as u8, level.price), level.size)),
+            )
+            .collect();
+        assert_eq!(mirror, expected);
+    }
+
+    #[test]
+    fn small_changes_near_the_top_stay_small() {
+        let mut encoder = DepthDeltaEncoder::new();
+        let level = |side, price, new_total_size| LevelUpdate {
+            side,
+            price,
+            new_total_size,
+        };
+        encoder.encode(&DepthDelta {
+            best_bid: Some(1_000_000),
+            best_ask: Some(1_000_005),
+            levels: vec![level(Side::Buy, 1_000_000, 5_000)],
+        });
+
+        // an unchanged top and one level a tick away growing by 3
+        let bytes = encoder.encode(&DepthDelta {
+            best_bid: Some(1_000_000),
+            best_ask: Some(1_000_005),
+            levels: vec![level(Side::Buy, 1_000_000, 5_003)],
+        });
+        assert_eq!(bytes, vec![0b11, 0, 0, 1, 0, 6]);
+
+        let mut decoder = DepthDeltaDecoder::new();
+        // cut short, left over and an overlong varint
+        assert!(decoder.decode(&[0b01]).is_err());
+        assert!(decoder.decode(&[0, 0, 0]).is_err());
+        assert!(
+            decoder
+                .decode(&[
+                    0b01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f
+                ])
+                .is_err()
+        );
+        // a failed message leaves the decoder where it was
+        assert_eq!(decoder.decode(&[0, 0]).unwrap(), DepthDelta::default());
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{PriceSize, Side, book::Orderbook};
+
+/// Depth at a few fixed resolutions (say top 1, 10 and 100) kept fresh
+/// by the book after every change, so consumers read a ready-made slice
+/// instead of walking the ladder per request. Only the deepest view is
+/// actually computed, the shallower ones are prefixes of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthViews {
+    resolutions: Vec<usize>,
+    bids: Vec<PriceSize>,
+    asks: Vec<PriceSize>,
+}
+
+impl DepthViews {
+    pub fn new(resolutions: &[usize]) -> Self {
+        let mut resolutions = resolutions.to_vec();
+        resolutions.sort_unstable();
+        resolutions.dedup();
+
+        Self {
+            resolutions,
+            bids: Vec::new(),
+            asks: Vec::new(),
+        }
+    }
+
+    pub fn resolutions(&self) -> &[usize] {
+        &self.resolutions
+    }
+
+    /// Bids and asks at one of the configured resolutions, best first
+    pub fn view(&self, resolution: usize) -> Option<(&[PriceSize], &[PriceSize])> {
+        if !self.resolutions.contains(&resolution) {
+            return None;
+        }
+
+        Some((
+            &self.bids[..resolution.min(self.bids.len())],
+            &self.asks[..resolution.min(self.asks.len())],
+        ))
+    }
+
+    pub fn refresh(&mut self, book: &Orderbook) {
+        let deepest = self.resolutions.last().copied().unwrap_or_default();
+        self.bids = book.published_depth(Side::Buy, deepest);
+        self.asks = book.published_depth(Side::Sell, deepest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OrderTicket, OrderType, TimeInForce};
+
+    #[test]
+    fn shallow_views_are_prefixes_of_the_deepest() {
+        let mut book = Orderbook::new();
+        for i in 0..20 {
+            book.accept_order(OrderTicket {
+                side: Side::Buy,
+                size: 1,
+                order_type: OrderType::Limit(100 - i),
+                time_in_force: TimeInForce::Gtc,
+                min_qty: None,
+                owner: 0,
+                iceberg: None,
+            })
+            .unwrap();
+        }
+
+        let mut views = DepthViews::new(&[10, 1, 10]);
+        assert_eq!(views.resolutions(), &[1, 10]);
+        views.refresh(&book);
+
+        let (bids, asks) = views.view(1).unwrap();
+        assert_eq!(
+            bids,
+            &[PriceSize {
+                price: 100,
+                size: 1
+            }]
+        );
+        assert!(asks.is_empty());
+
+        let (bids, _) = views.view(10).unwrap();
+        assert_eq!(bids.len(), 10);
+        assert_eq!(bids[9].price, 91);
+
+        assert!(views.view(5).is_none());
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+
+use crate::{Event, PriceSize};
+
+/// Keeps a short memory of what the book has been doing so that
+/// if it ever crosses (which should be impossible) we can see why.
+/// History and reports are both bounded by `capacity`, so it can stay
+/// on for as long as a simulation runs.
+#[derive(Debug)]
+pub struct CrossedBookDetector {
+    capacity: usize,
+    recent_events: VecDeque<Event>,
+    tob_history: VecDeque<(Option<PriceSize>, Option<PriceSize>)>,
+    reports: Vec<CrossedBookReport>,
+}
+
+/// Everything we know about the book at the moment it crossed
+#[derive(Debug, Clone)]
+pub struct CrossedBookReport {
+    pub best_bid: PriceSize,
+    pub best_ask: PriceSize,
+    /// (id, size) of each order at the best bid in FIFO order
+    pub bid_orders: Vec<(u64, i64)>,
+    /// (id, size) of each order at the best ask in FIFO order
+    pub ask_orders: Vec<(u64, i64)>,
+    /// every input, oldest first, the last one is the one that crossed us
+    pub recent_events: Vec<Event>,
+    /// (best bid, best ask) after each of the recent inputs that could
+    /// have changed them
+    pub tob_history: Vec<(Option<PriceSize>, Option<PriceSize>)>,
+}
+
+impl CrossedBookDetector {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            recent_events: VecDeque::with_capacity(capacity),
+            tob_history: VecDeque::with_capacity(capacity),
+            reports: Vec::new(),
+        }
+    }
+
+    pub fn record_event(&mut self, event: &Event) {
+        if self.recent_events.len() == self.capacity {
+            self.recent_events.pop_front();
+        }
+        self.recent_events.push_back(event.clone());
+    }
+
+    pub fn record_top_of_book(&mut self, best_bid: Option<PriceSize>, best_ask: Option<PriceSize>) {
+        if self.tob_history.len() == self.capacity {
+            self.tob_history.pop_front();
+        }
+        self.tob_history.push_back((best_bid, best_ask));
+    }
+
+    /// Returns true when the bid has caught up to the ask
+    pub fn is_crossed(best_bid: Option<PriceSize>, best_ask: Option<PriceSize>) -> bool {
+        match (best_bid, best_ask) {
+            (Some(bid), Some(ask)) => bid.price >= ask.price,
+            _ => false,
+        }
+    }
+
+    /// Store a report for a crossed book, dropping the oldest once there
+    /// are `capacity` of them
+    pub fn report(
+        &mut self,
+        best_bid: PriceSize,
+        best_ask: PriceSize,
+        bid_orders: Vec<(u64, i64)>,
+        ask_orders: Vec<(u64, i64)>,
+    ) {
+        let report = CrossedBookReport {
+            best_bid,
+            best_ask,
+            bid_orders,
+            ask_orders,
+            recent_events: self.recent_events.iter().cloned().collect(),
+            tob_history: self.tob_history.iter().copied().collect(),
+        };
+
+        if self.reports.len() == self.capacity {
+            self.reports.remove(0);
+        }
+        self.reports.push(report);
+    }
+
+    pub fn reports(&self) -> &[CrossedBookReport] {
+        &self.reports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EventKind, OrderTicket, OrderType, Side, TimeInForce};
+
+    fn price_size(price: i64, size: i64) -> Option<PriceSize> {
+        Some(PriceSize { price, size })
+    }
+
+    #[test]
+    fn detects_locked_and_crossed_but_not_one_sided() {
+        assert!(CrossedBookDetector::is_crossed(
+            price_size(10, 1),
+            price_size(10, 1)
+        ));
+        assert!(CrossedBookDetector::is_crossed(
+            price_size(11, 1),
+            price_size(10, 1)
+        ));
+        assert!(!CrossedBookDetector::is_crossed(
+            price_size(9, 1),
+            price_size(10, 1)
+        ));
+        assert!(!CrossedBookDetector::is_crossed(price_size(11, 1), None));
+    }
+
+    #[test]
+    fn history_is_bounded_to_capacity() {
+        let mut detector = CrossedBookDetector::new(2);
+
+        for price in 1..=3 {
+            detector.record_event(&Event {
+                seq: price as u64,
+                kind: EventKind::Order(OrderTicket {
+                    order_type: OrderType::Limit(price),
+                    size: 1,
+                    side: Side::Buy,
+                    time_in_force: TimeInForce::Gtc,
+                    min_qty: None,
+                    owner: 0,
+                    iceberg: None,
+                }),
+            });
+            detector.record_top_of_book(price_size(price, 1), None);
+        }
+
+        for ask in 1..=3 {
+            detector.report(
+                PriceSize { price: 3, size: 1 },
+                PriceSize {
+                    price: ask,
+                    size: 1,
+                },
+                vec![],
+                vec![],
+            );
+        }
+
+        let report = &detector.reports()[0];
+        assert_eq!(report.recent_events.len(), 2);
+        assert_eq!(report.recent_events[0].seq, 2);
+        assert_eq!(report.tob_history.len(), 2);
+        assert_eq!(report.tob_history[0].0, price_size(2, 1));
+
+        assert_eq!(detector.reports().len(), 2);
+        assert_eq!(detector.reports()[0].best_ask.price, 2);
+    }
+}
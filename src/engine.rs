@@ -0,0 +1,897 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc::{Receiver, Sender, channel},
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    CancelResponse, EventKind, ExecutionReport, LevelUpdate, LimitOrderResponse, LimitReject,
+    MmpTrigger, OrderResponse, OrderTicket, PegReject, ReleasedOrder, ReplaceResponse, Result,
+    StatusEvent, Trade, TradeBust, book::Orderbook, perp::FundingEvent,
+};
+
+/// how many of the latest events the engine keeps for resends
+const RESEND_WINDOW: usize = 10_000;
+
+/// What the book answered to a command
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandResponse {
+    Order(OrderResponse),
+    Cancel(CancelResponse),
+    Replace(ReplaceResponse),
+    MassQuote(Vec<Result<LimitOrderResponse>>),
+    Expire(Vec<CancelResponse>),
+    Bust(TradeBust),
+    /// resuming trading and setting the clock or end of day answer nothing
+    Applied,
+}
+
+/// Everything subscribers hear about, in the order it was applied
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineEvent {
+    /// the command's number in the book's event log
+    pub seq: u64,
+    pub command: EventKind,
+    pub response: Result<CommandResponse>,
+    /// trades the command printed, stops it set off included
+    pub trades: Vec<Trade>,
+    /// fills and cancels of resting orders and fired stops
+    pub reports: Vec<ExecutionReport>,
+    /// levels the command changed
+    pub level_updates: Vec<LevelUpdate>,
+    /// trades it busted
+    pub busts: Vec<TradeBust>,
+    /// market maker protection it tripped
+    pub mmp_triggers: Vec<MmpTrigger>,
+    /// pegs it cancelled for breaching the ladder or band
+    pub peg_rejects: Vec<PegReject>,
+    /// orders refused for going over their owner's limits
+    pub limit_rejects: Vec<LimitReject>,
+    /// funding settled on the book since the last command
+    pub funding_events: Vec<FundingEvent>,
+    /// halts and resumes it caused, and on an exchange the sessions
+    /// opening or closing as its clock moved
+    pub status_events: Vec<StatusEvent>,
+    /// orders the speed bump let through as the clock moved
+    pub released_orders: Vec<ReleasedOrder>,
+}
+
+/// How much the engine takes on before it starts refusing orders, to
+/// keep matching latency flat through a burst. Rates count commands the
+/// book accepted within `window` of the book's clock, e.g. 1_000 for a
+/// second on a millisecond clock. Cancels and the clock, session and
+/// bust commands only ever take risk off and are never throttled.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Throttle {
+    pub window: u64,
+    /// across every connection
+    pub max_commands: Option<usize>,
+    pub max_per_connection: Option<usize>,
+    /// commands queued at which orders start being shed
+    pub high_watermark: Option<usize>,
+    /// once shedding, orders are taken again when the queue is back
+    /// down to this
+    pub low_watermark: usize,
+}
+
+/// A command the engine refused without handing it to the book
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThrottleReject {
+    /// see `EngineHandle::connect`
+    pub connection: u64,
+    pub command: EventKind,
+    pub reason: String,
+    /// the book's clock when it was refused
+    pub at: u64,
+}
+
+impl EngineEvent {
+    /// Everything `command` caused, drained from the book it was just
+    /// applied to
+    pub fn drain(
+        book: &mut Orderbook,
+        command: EventKind,
+        response: Result<CommandResponse>,
+    ) -> Self {
+        // every command logs exactly one event
+        let events = book.drain_events();
+        Self {
+            seq: events.last().map(|event| event.seq).unwrap_or_default(),
+            command,
+            response,
+            trades: book.drain_trades(),
+            reports: book.drain_execution_reports(),
+            level_updates: book.drain_level_updates(),
+            busts: book.drain_busts(),
+            mmp_triggers: book.drain_mmp_triggers(),
+            peg_rejects: book.drain_peg_rejects(),
+            limit_rejects: book.drain_limit_rejects(),
+            funding_events: book.drain_funding_events(),
+            status_events: book.drain_status_events(),
+            released_orders: book.drain_released_orders(),
+        }
+    }
+}
+
+enum Request {
+    Command {
+        connection: u64,
+        command: EventKind,
+        reply: Option<Sender<Result<CommandResponse>>>,
+    },
+    Resend {
+        from: u64,
+        to: u64,
+        reply: Sender<Result<Vec<EngineEvent>>>,
+    },
+}
+
+/// Cheap to clone, hand one to every thread that submits orders. Clones
+/// share a connection, see `connect` for one of its own.
+#[derive(Clone)]
+pub struct EngineHandle {
+    requests: Sender<Request>,
+    connection: u64,
+    connections: Arc<AtomicU64>,
+    /// requests sent and not yet taken off the queue by the engine
+    queued: Arc<AtomicUsize>,
+}
+
+impl EngineHandle {
+    /// A handle on a new connection, throttled apart from this one
+    pub fn connect(&self) -> EngineHandle {
+        EngineHandle {
+            connection: self.connections.fetch_add(1, Ordering::Relaxed),
+            ..self.clone()
+        }
+    }
+
+    pub fn connection(&self) -> u64 {
+        self.connection
+    }
+
+    /// Submit an order and wait for the book's response
+    pub fn submit(&self, ticket: OrderTicket) -> Result<OrderResponse> {
+        match self.execute(EventKind::Order(ticket))? {
+            CommandResponse::Order(response) => Ok(response),
+            other => Err(format!("Expected an order response, got {:?}", other)),
+        }
+    }
+
+    /// Submit an order without waiting, the outcome still reaches
+    /// subscribers
+    pub fn send(&self, ticket: OrderTicket) -> Result<()> {
+        self.send_command(EventKind::Order(ticket))
+    }
+
+    /// Run any command, e.g. a cancel or replace, and wait for the answer
+    pub fn execute(&self, command: EventKind) -> Result<CommandResponse> {
+        let (reply, response) = channel();
+        self.request(Request::Command {
+            connection: self.connection,
+            command,
+            reply: Some(reply),
+        })?;
+
+        response
+            .recv()
+            .map_err(|_| "Engine has stopped".to_string())?
+    }
+
+    /// `execute` without waiting
+    pub fn send_command(&self, command: EventKind) -> Result<()> {
+        self.request(Request::Command {
+            connection: self.connection,
+            command,
+            reply: None,
+        })
+    }
+
+    /// Published events `from` to `to` inclusive again, for a subscriber
+    /// that spotted a gap in the sequence. Only the latest ones are kept,
+    /// anything older has to be recovered from a snapshot.
+    pub fn resend(&self, from: u64, to: u64) -> Result<Vec<EngineEvent>> {
+        let (reply, response) = channel();
+        self.request(Request::Resend { from, to, reply })?;
+
+        response
+            .recv()
+            .map_err(|_| "Engine has stopped".to_string())?
+    }
+
+    fn request(&self, request: Request) -> Result<()> {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        self.requests.send(request).map_err(|_| {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            "Engine has stopped".to_string()
+        })
+    }
+}
+
+/// The book is not Sync, so one thread owns it and everyone else talks
+/// to it through a queue. Commands are applied one at a time in arrival
+/// order and every outcome, with the trades, reports, level updates and
+/// protection or limit rejects it caused, is fanned out to the
+/// subscribers. The book's own buffers are
+/// drained as it goes so they never grow. The latest events are kept
+/// around so subscribers that missed some can ask for them again.
+/// Orders over the `Throttle` are refused before reaching the book and
+/// go out on a feed of their own.
+pub struct EngineLoop {
+    book: Orderbook,
+    requests: Receiver<Request>,
+    subscribers: Vec<Sender<EngineEvent>>,
+    /// the latest events published, oldest first
+    recent: VecDeque<EngineEvent>,
+    /// how many of them to keep
+    resend_window: usize,
+    throttle: Throttle,
+    /// clock of every command admitted within the window, overall and
+    /// per connection
+    admitted: VecDeque<u64>,
+    admitted_by: BTreeMap<u64, VecDeque<u64>>,
+    /// shared with the handles
+    queued: Arc<AtomicUsize>,
+    /// whether the queue went over the high watermark and has not yet
+    /// drained to the low one
+    shedding: bool,
+    throttle_subscribers: Vec<Sender<ThrottleReject>>,
+}
+
+impl EngineLoop {
+    pub fn new(book: Orderbook) -> (Self, EngineHandle) {
+        let (requests, receiver) = channel();
+        let queued = Arc::new(AtomicUsize::new(0));
+        let engine = Self {
+            book,
+            requests: receiver,
+            subscribers: Vec::new(),
+            recent: VecDeque::new(),
+            resend_window: RESEND_WINDOW,
+            throttle: Throttle::default(),
+            admitted: VecDeque::new(),
+            admitted_by: BTreeMap::new(),
+            queued: Arc::clone(&queued),
+            shedding: false,
+            throttle_subscribers: Vec::new(),
+        };
+        let handle = EngineHandle {
+            requests,
+            connection: 0,
+            connections: Arc::new(AtomicU64::new(1)),
+            queued,
+        };
+        (engine, handle)
+    }
+
+    /// Unthrottled until set
+    pub fn set_throttle(&mut self, throttle: Throttle) {
+        self.throttle = throttle;
+    }
+
+    /// Keep this many of the latest events for resends
+    pub fn set_resend_window(&mut self, resend_window: usize) {
+        self.resend_window = resend_window;
+        while self.recent.len() > resend_window {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Hear about every command applied from now on
+    pub fn subscribe(&mut self) -> Receiver<EngineEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Hear about every command refused on the throttle from now on
+    pub fn subscribe_throttle_rejects(&mut self) -> Receiver<ThrottleReject> {
+        let (sender, receiver) = channel();
+        self.throttle_subscribers.push(sender);
+        receiver
+    }
+
+    /// Apply commands until every handle has been dropped, then give
+    /// the book back
+    pub fn run(mut self) -> Orderbook {
+        while let Ok(request) = self.requests.recv() {
+            self.apply(request);
+        }
+        self.book
+    }
+
+    /// Apply the next queued command if there is one, for driving the
+    /// loop by hand instead of blocking in `run`
+    pub fn step(&mut self) -> bool {
+        match self.requests.try_recv() {
+            Ok(request) => {
+                self.apply(request);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub fn book(&self) -> &Orderbook {
+        &self.book
+    }
+
+    /// `run` on a thread of its own
+    pub fn spawn(self) -> JoinHandle<Orderbook> {
+        thread::spawn(move || self.run())
+    }
+
+    fn apply(&mut self, request: Request) {
+        let queued = self.queued.fetch_sub(1, Ordering::Relaxed) - 1;
+        if let Some(high_watermark) = self.throttle.high_watermark {
+            if queued >= high_watermark {
+                self.shedding = true;
+            } else if queued <= self.throttle.low_watermark {
+                self.shedding = false;
+            }
+        }
+
+        match request {
+            Request::Command {
+                connection,
+                command,
+                reply,
+            } => match self.admit(connection, &command) {
+                Ok(()) => self.apply_command(command, reply),
+                Err(reason) => {
+                    if let Some(reply) = reply {
+                        let _ = reply.send(Err(reason.clone()));
+                    }
+                    let reject = ThrottleReject {
+                        connection,
+                        command,
+                        reason,
+                        at: self.book.clock,
+                    };
+                    self.throttle_subscribers
+                        .retain(|subscriber| subscriber.send(reject.clone()).is_ok());
+                }
+            },
+            Request::Resend { from, to, reply } => {
+                let _ = reply.send(self.resend(from, to));
+            }
+        }
+    }
+
+    /// Count the command against the throttle, or say why it is refused
+    fn admit(&mut self, connection: u64, command: &EventKind) -> Result<()> {
+        if !matches!(
+            command,
+            EventKind::Order(_) | EventKind::Replace { .. } | EventKind::MassQuote { .. }
+        ) {
+            return Ok(());
+        }
+        if self.shedding {
+            return Err("Throttled, the engine's queue is over its high watermark".into());
+        }
+
+        let Throttle {
+            window,
+            max_commands,
+            max_per_connection,
+            ..
+        } = self.throttle;
+        let now = self.book.clock;
+        let evict = |admitted: &mut VecDeque<u64>| {
+            while let Some(at) = admitted.front()
+                && now.saturating_sub(*at) >= window
+            {
+                admitted.pop_front();
+            }
+        };
+
+        evict(&mut self.admitted);
+        if let Some(max) = max_commands
+            && self.admitted.len() >= max
+        {
+            return Err(format!(
+                "Throttled, more than {} commands within {}",
+                max, window
+            ));
+        }
+        if let Some(max) = max_per_connection {
+            let by_connection = self.admitted_by.entry(connection).or_default();
+            evict(by_connection);
+            if by_connection.len() >= max {
+                return Err(format!(
+                    "Throttled, more than {} commands within {} on connection {}",
+                    max, window, connection
+                ));
+            }
+            by_connection.push_back(now);
+        }
+
+        if max_commands.is_some() {
+            self.admitted.push_back(now);
+        }
+        Ok(())
+    }
+
+    fn resend(&self, from: u64, to: u64) -> Result<Vec<EngineEvent>> {
+        let events: Vec<EngineEvent> = self
+            .recent
+            .iter()
+            .filter(|event| (from..=to).contains(&event.seq))
+            .cloned()
+            .collect();
+        if to < from || events.len() as u64 != to - from + 1 {
+            return Err(format!(
+                "Cannot resend {} to {}, only {} to {} are kept",
+                from,
+                to,
+                self.recent
+                    .front()
+                    .map(|event| event.seq)
+                    .unwrap_or_default(),
+                self.recent
+                    .back()
+                    .map(|event| event.seq)
+                    .unwrap_or_default()
+            ));
+        }
+        Ok(events)
+    }
+
+    fn apply_command(
+        &mut self,
+        command: EventKind,
+        reply: Option<Sender<Result<CommandResponse>>>,
+    ) {
+        let response = execute(&mut self.book, command.clone());
+
+        if let Some(reply) = reply {
+            // the caller may have given up waiting, that's fine
+            let _ = reply.send(response.clone());
+        }
+
+        let event = EngineEvent::drain(&mut self.book, command, response);
+
+        // forget subscribers that hung up
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+
+        if self.resend_window > 0 {
+            if self.recent.len() == self.resend_window {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(event);
+        }
+    }
+}
+
+/// Apply any command to the book and hand back what it answered, the
+/// same way the engine does
+pub fn execute(book: &mut Orderbook, command: EventKind) -> Result<CommandResponse> {
+    match command {
+        EventKind::Order(ticket) => book.accept_order(ticket).map(CommandResponse::Order),
+        EventKind::Cancel(id) => book.cancel_order(id).map(CommandResponse::Cancel),
+        EventKind::Replace { id, price, size } => book
+            .replace_order(id, price, size)
+            .map(CommandResponse::Replace),
+        EventKind::MassQuote {
+            owner,
+            cancel,
+            levels,
+        } => book
+            .mass_quote(owner, &cancel, &levels)
+            .map(CommandResponse::MassQuote),
+        EventKind::Expire(now) => Ok(CommandResponse::Expire(book.expire(now))),
+        EventKind::ResumeTrading => {
+            book.resume_trading();
+            Ok(CommandResponse::Applied)
+        }
+        EventKind::SetClock(now) => {
+            book.set_clock(now);
+            Ok(CommandResponse::Applied)
+        }
+        EventKind::SetDayEnd(day_end) => {
+            book.set_day_end(day_end);
+            Ok(CommandResponse::Applied)
+        }
+        EventKind::Bust(trade_id) => book.bust_trade(trade_id).map(CommandResponse::Bust),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScheduledStep {
+    /// a producer handed its next command to the queue
+    Send(usize),
+    /// the engine applied one queued command
+    Apply,
+}
+
+/// Runs scripted producers and the engine on the calling thread, picking
+/// who goes next from a seeded generator. The same seed always gives the
+/// same interleaving, so an ordering bug found under one seed can be
+/// replayed exactly, and sweeping seeds explores many orderings.
+#[derive(Debug)]
+pub struct DeterministicScheduler {
+    state: u64,
+}
+
+impl DeterministicScheduler {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Interleave until every producer has sent everything and the queue
+    /// is drained, returning the order things happened in
+    pub fn run(
+        &mut self,
+        engine: &mut EngineLoop,
+        producers: Vec<(EngineHandle, Vec<OrderTicket>)>,
+    ) -> Result<Vec<ScheduledStep>> {
+        let mut producers: Vec<_> = producers
+            .into_iter()
+            .map(|(handle, tickets)| (handle, tickets.into_iter()))
+            .collect();
+        let mut remaining: usize = producers.iter().map(|(_, tickets)| tickets.len()).sum();
+        let mut queued = 0;
+        let mut trace = Vec::new();
+
+        while remaining + queued > 0 {
+            let ready: Vec<usize> = (0..producers.len())
+                .filter(|i| producers[*i].1.len() > 0)
+                .collect();
+            // one extra choice for the engine whenever it has work
+            let choices = ready.len() + usize::from(queued > 0);
+            let choice = (self.next() % choices as u64) as usize;
+
+            if choice < ready.len() {
+                let (handle, tickets) = &mut producers[ready[choice]];
+                if let Some(ticket) = tickets.next() {
+                    handle.send(ticket)?;
+                }
+                remaining -= 1;
+                queued += 1;
+                trace.push(ScheduledStep::Send(ready[choice]));
+            } else {
+                engine.step();
+                queued -= 1;
+                trace.push(ScheduledStep::Apply);
+            }
+        }
+
+        Ok(trace)
+    }
+
+    /// splitmix64, fine with any seed including zero
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ExecType, MmpLimits, OrderType, OwnerLimits, Side, TimeInForce};
+
+    fn limit(side: Side, price: i64, size: i64) -> OrderTicket {
+        OrderTicket {
+            side,
+            size,
+            order_type: OrderType::Limit(price),
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        }
+    }
+
+    #[test]
+    fn applies_commands_from_many_threads_in_order() {
+        let (mut engine, handle) = EngineLoop::new(Orderbook::new());
+        let events = engine.subscribe();
+        let engine = engine.spawn();
+
+        let submitters: Vec<_> = (0..4)
+            .map(|i| {
+                let handle = handle.clone();
+                thread::spawn(move || {
+                    for _ in 0..25 {
+                        handle.send(limit(Side::Buy, 100 + i, 1)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for submitter in submitters {
+            submitter.join().unwrap();
+        }
+
+        assert_eq!(
+            handle.submit(limit(Side::Sell, 200, 1)).unwrap(),
+            OrderResponse::Limit(LimitOrderResponse { id: 100 })
+        );
+        drop(handle);
+
+        let book = engine.join().unwrap();
+        assert_eq!(book.total_liquidity(Side::Buy), 100);
+
+        let events: Vec<EngineEvent> = events.iter().collect();
+        assert_eq!(events.len(), 101);
+        for (seq, event) in events.iter().enumerate() {
+            assert_eq!(event.seq, seq as u64);
+            assert_eq!(
+                event.response,
+                Ok(CommandResponse::Order(OrderResponse::Limit(
+                    LimitOrderResponse { id: seq as u64 }
+                )))
+            );
+        }
+    }
+
+    #[test]
+    fn every_command_fans_out_what_it_caused() {
+        let (mut engine, handle) = EngineLoop::new(Orderbook::new());
+        let events = engine.subscribe();
+        let engine = engine.spawn();
+
+        handle.submit(limit(Side::Sell, 101, 5)).unwrap();
+        handle.submit(limit(Side::Sell, 102, 5)).unwrap();
+        assert_eq!(
+            handle.execute(EventKind::Replace {
+                id: 1,
+                price: 102,
+                size: 2
+            }),
+            Ok(CommandResponse::Replace(ReplaceResponse {
+                id: 1,
+                requeued: false
+            }))
+        );
+        handle.submit(limit(Side::Buy, 102, 6)).unwrap();
+        assert!(matches!(
+            handle.execute(EventKind::Cancel(1)),
+            Ok(CommandResponse::Cancel(_))
+        ));
+        handle.send_command(EventKind::SetClock(5)).unwrap();
+        drop(handle);
+        let mut book = engine.join().unwrap();
+
+        let events: Vec<EngineEvent> = events.iter().collect();
+        assert_eq!(events.len(), 6);
+        assert_eq!(events[5].seq, 5);
+        assert_eq!(events[5].response, Ok(CommandResponse::Applied));
+
+        let cross = &events[3];
+        assert_eq!(cross.trades.len(), 2);
+        assert_eq!(
+            cross
+                .reports
+                .iter()
+                .map(|report| (report.order_id, report.exec_type))
+                .collect::<Vec<_>>(),
+            vec![(0, ExecType::Fill), (1, ExecType::PartialFill)]
+        );
+        assert_eq!(
+            cross.level_updates,
+            vec![
+                LevelUpdate {
+                    side: Side::Sell,
+                    price: 101,
+                    new_total_size: 0
+                },
+                LevelUpdate {
+                    side: Side::Sell,
+                    price: 102,
+                    new_total_size: 1
+                },
+            ]
+        );
+        assert_eq!(events[4].command, EventKind::Cancel(1));
+        assert_eq!(events[4].reports[0].exec_type, ExecType::Cancelled);
+
+        // nothing was left behind in the book
+        assert!(book.event_log.is_empty());
+        assert!(book.drain_trades().is_empty());
+        assert!(book.drain_execution_reports().is_empty());
+        assert_eq!(book.events_since(0), &[]);
+        assert_eq!(book.get_best_ask(), None);
+        assert_eq!(book.get_best_bid(), None);
+    }
+
+    #[test]
+    fn protection_and_limit_rejects_reach_subscribers() {
+        let mut book = Orderbook::new();
+        book.set_mmp(
+            7,
+            Some(MmpLimits {
+                window: 10,
+                max_fills: Some(1),
+                max_volume: None,
+            }),
+        );
+        book.set_owner_limits(
+            8,
+            Some(OwnerLimits {
+                max_open_orders: Some(0),
+                ..OwnerLimits::default()
+            }),
+        );
+        let (mut engine, handle) = EngineLoop::new(book);
+        let events = engine.subscribe();
+        let engine = engine.spawn();
+
+        let owned = |owner, ticket| OrderTicket { owner, ..ticket };
+        for price in [101, 102, 103] {
+            handle
+                .submit(owned(7, limit(Side::Sell, price, 1)))
+                .unwrap();
+        }
+        assert!(handle.submit(owned(8, limit(Side::Buy, 99, 1))).is_err());
+        handle
+            .submit(OrderTicket {
+                order_type: OrderType::Market,
+                ..limit(Side::Buy, 0, 2)
+            })
+            .unwrap();
+        drop(handle);
+        let mut book = engine.join().unwrap();
+
+        let events: Vec<EngineEvent> = events.iter().collect();
+        assert_eq!(events[3].limit_rejects[0].owner, 8);
+        assert_eq!(events[4].mmp_triggers[0].cancelled, vec![2]);
+        assert!(book.drain_mmp_triggers().is_empty());
+        assert!(book.drain_limit_rejects().is_empty());
+        assert_eq!(book.get_best_ask(), None);
+    }
+
+    #[test]
+    fn throttle_sheds_orders_over_rate_or_queue_depth() {
+        let (mut engine, first) = EngineLoop::new(Orderbook::new());
+        engine.set_throttle(Throttle {
+            window: 10,
+            max_commands: Some(3),
+            max_per_connection: Some(2),
+            ..Throttle::default()
+        });
+        let rejects = engine.subscribe_throttle_rejects();
+        let second = first.connect();
+        let step = |engine: &mut EngineLoop, handle: &EngineHandle, command| {
+            handle.send_command(command).unwrap();
+            engine.step();
+        };
+
+        for price in [97, 98, 99] {
+            step(
+                &mut engine,
+                &first,
+                EventKind::Order(limit(Side::Buy, price, 1)),
+            );
+        }
+        step(
+            &mut engine,
+            &second,
+            EventKind::Order(limit(Side::Buy, 96, 1)),
+        );
+        step(
+            &mut engine,
+            &second,
+            EventKind::Order(limit(Side::Buy, 95, 1)),
+        );
+        // taking risk off is never throttled
+        step(&mut engine, &first, EventKind::Cancel(0));
+        step(&mut engine, &first, EventKind::SetClock(10));
+        step(
+            &mut engine,
+            &first,
+            EventKind::Order(limit(Side::Buy, 94, 1)),
+        );
+
+        let rejects: Vec<ThrottleReject> = rejects.try_iter().collect();
+        assert_eq!(
+            rejects
+                .iter()
+                .map(|reject| (reject.connection, reject.at))
+                .collect::<Vec<_>>(),
+            vec![(0, 0), (1, 0)]
+        );
+        assert!(rejects[0].reason.contains("on connection 0"));
+        assert_eq!(
+            rejects[1].command,
+            EventKind::Order(limit(Side::Buy, 95, 1))
+        );
+        assert_eq!(engine.book().total_liquidity(Side::Buy), 3);
+
+        // a backlog sheds orders until it drains to the low watermark
+        engine.set_throttle(Throttle {
+            high_watermark: Some(3),
+            low_watermark: 1,
+            ..Throttle::default()
+        });
+        for _ in 0..4 {
+            first.send(limit(Side::Sell, 101, 1)).unwrap();
+        }
+        while engine.step() {}
+        assert_eq!(engine.book().total_liquidity(Side::Sell), 2);
+    }
+
+    #[test]
+    fn rejections_reach_the_caller_and_subscribers() {
+        let (mut engine, handle) = EngineLoop::new(Orderbook::new());
+        let events = engine.subscribe();
+        // a subscriber that goes away must not stall the loop
+        drop(engine.subscribe());
+        let engine = engine.spawn();
+
+        assert!(handle.submit(limit(Side::Buy, 0, 1)).is_err());
+        drop(handle);
+        engine.join().unwrap();
+
+        let event = events.recv().unwrap();
+        assert!(event.response.is_err());
+        assert!(events.recv().is_err());
+    }
+
+    #[test]
+    fn resends_the_latest_events_on_request() {
+        let (mut engine, handle) = EngineLoop::new(Orderbook::new());
+        engine.set_resend_window(3);
+        let events = engine.subscribe();
+        let engine = engine.spawn();
+
+        for price in 100..105 {
+            handle.submit(limit(Side::Buy, price, 1)).unwrap();
+        }
+        let published: Vec<EngineEvent> = events.iter().take(5).collect();
+        assert_eq!(handle.resend(2, 4).unwrap(), published[2..]);
+        assert_eq!(handle.resend(3, 3).unwrap(), published[3..4]);
+
+        // gone already, not published yet or backwards
+        assert!(handle.resend(1, 2).is_err());
+        assert!(handle.resend(4, 5).is_err());
+        assert!(handle.resend(4, 3).is_err());
+
+        drop(handle);
+        engine.join().unwrap();
+    }
+
+    #[test]
+    fn scheduler_interleavings_replay_by_seed() {
+        let script = |handle: &EngineHandle| {
+            vec![
+                (handle.clone(), vec![limit(Side::Buy, 100, 5); 3]),
+                (
+                    handle.clone(),
+                    vec![limit(Side::Sell, 100, 4), limit(Side::Sell, 100, 4)],
+                ),
+            ]
+        };
+        let run = |seed: u64| {
+            let (mut engine, handle) = EngineLoop::new(Orderbook::new());
+            let trace = DeterministicScheduler::new(seed)
+                .run(&mut engine, script(&handle))
+                .unwrap();
+            (trace, engine.book().state_digest())
+        };
+
+        let (trace, digest) = run(7);
+        assert_eq!(run(7), (trace.clone(), digest));
+        assert_eq!(trace.len(), 10);
+        assert_eq!(
+            trace
+                .iter()
+                .filter(|step| **step == ScheduledStep::Apply)
+                .count(),
+            5
+        );
+
+        // different seeds reach different interleavings, and with them
+        // different books since crossing depends on arrival order
+        let digests: std::collections::HashSet<u64> = (0..8).map(|seed| run(seed).1).collect();
+        assert!(digests.len() > 1);
+    }
+}
@@ -0,0 +1,454 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::mpsc::{Receiver, Sender, channel},
+};
+
+use crate::{
+    EventKind, MarketStatus, OrderResponse, OrderTicket, PriceSize, Result, Side, StatusEvent,
+    book::Orderbook,
+    engine::{CommandResponse, EngineEvent, execute},
+    registry::InstrumentRegistry,
+};
+
+/// Best bid and ask of one instrument across every book listing it, with
+/// the size of all of them at that price
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsolidatedQuote {
+    pub symbol: String,
+    pub bid: Option<PriceSize>,
+    pub ask: Option<PriceSize>,
+}
+
+/// One command on one book, numbered across the whole exchange
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExchangeEvent {
+    /// gapless across every book, in the order commands were applied
+    pub seq: u64,
+    /// the book it was applied to and the symbol that book lists
+    pub book: String,
+    pub symbol: String,
+    pub event: EngineEvent,
+}
+
+/// A subscription to the exchange's merged feed. Narrowing it down to some
+/// symbols happens on the receiving end, so a filtered feed sees gaps in
+/// `seq` where other symbols' events were skipped.
+#[derive(Debug)]
+pub struct ExchangeFeed {
+    events: Receiver<ExchangeEvent>,
+    /// empty for every symbol
+    symbols: BTreeSet<String>,
+}
+
+impl ExchangeFeed {
+    /// Keep `symbol`'s events, and those of any symbol kept before
+    pub fn only(mut self, symbol: &str) -> Self {
+        self.symbols.insert(symbol.to_string());
+        self
+    }
+
+    /// Events wanted until the exchange is dropped, waiting for each
+    pub fn iter(&self) -> impl Iterator<Item = ExchangeEvent> + '_ {
+        self.events.iter().filter(|event| self.wants(event))
+    }
+
+    /// Events wanted that have already arrived, without waiting
+    pub fn try_iter(&self) -> impl Iterator<Item = ExchangeEvent> + '_ {
+        self.events.try_iter().filter(|event| self.wants(event))
+    }
+
+    fn wants(&self, event: &ExchangeEvent) -> bool {
+        self.symbols.is_empty() || self.symbols.contains(&event.symbol)
+    }
+}
+
+#[derive(Debug)]
+struct Listing {
+    book: Orderbook,
+    /// published best bid and ask as of the last command
+    top: (Option<PriceSize>, Option<PriceSize>),
+    /// whether its instrument's session was open as of the last command,
+    /// None when it has no session times
+    in_session: Option<bool>,
+}
+
+/// Books run side by side, each under a name of its own. Books whose
+/// `symbol` matches list the same instrument, e.g. one per venue in a
+/// simulation, and the exchange keeps their consolidated best bid and
+/// ask up to date as commands change their tops. What every command
+/// caused goes out on one feed numbered across all the books, and the
+/// books' own buffers are drained as it goes. A command that moves a
+/// book's clock across its instrument's session times carries the
+/// session opening or closing in its status events.
+#[derive(Debug, Default)]
+pub struct Exchange {
+    books: BTreeMap<String, Listing>,
+    /// names of the books listing each symbol
+    venues: BTreeMap<String, Vec<String>>,
+    nbbo: BTreeMap<String, ConsolidatedQuote>,
+    /// consolidated quotes that changed since the last drain
+    nbbo_updates: Vec<ConsolidatedQuote>,
+    /// number of the next event on the feed
+    next_seq: u64,
+    subscribers: Vec<Sender<ExchangeEvent>>,
+    /// what `submit` validates orders against
+    registry: InstrumentRegistry,
+}
+
+impl Exchange {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A book for every instrument in `registry`, named by its symbol
+    pub fn from_registry(registry: InstrumentRegistry) -> Result<Self> {
+        let mut exchange = Self {
+            registry,
+            ..Self::default()
+        };
+        let books: Vec<(String, Orderbook)> = exchange
+            .registry
+            .iter()
+            .map(|instrument| (instrument.symbol.clone(), instrument.book()))
+            .collect();
+        for (symbol, book) in books {
+            exchange.add_book(&symbol, book)?;
+        }
+        Ok(exchange)
+    }
+
+    pub fn registry(&self) -> &InstrumentRegistry {
+        &self.registry
+    }
+
+    /// Check an order against `symbol`'s reference data and hand it to
+    /// the instrument's book. Refused orders never reach the book or
+    /// the feed.
+    pub fn submit(&mut self, symbol: &str, ticket: OrderTicket) -> Result<OrderResponse> {
+        let instrument = self
+            .registry
+            .get(symbol)
+            .ok_or_else(|| format!("Unknown instrument {}", symbol))?;
+        let book = self
+            .book(symbol)
+            .ok_or_else(|| format!("No book named {}", symbol))?;
+        instrument.validate(&ticket, book.clock)?;
+
+        match self.execute(symbol, EventKind::Order(ticket))? {
+            CommandResponse::Order(response) => Ok(response),
+            other => Err(format!("Expected an order response, got {:?}", other)),
+        }
+    }
+
+    pub fn add_book(&mut self, name: &str, book: Orderbook) -> Result<()> {
+        if self.books.contains_key(name) {
+            return Err(format!("There already is a book named {}", name));
+        }
+
+        let symbol = book.symbol.clone();
+        let top = (
+            book.published_top_of_book(Side::Buy),
+            book.published_top_of_book(Side::Sell),
+        );
+        let in_session = self.in_session(&book);
+        self.books.insert(
+            name.to_string(),
+            Listing {
+                book,
+                top,
+                in_session,
+            },
+        );
+        self.venues
+            .entry(symbol.clone())
+            .or_default()
+            .push(name.to_string());
+        self.consolidate(&symbol);
+        Ok(())
+    }
+
+    /// Hear about every command applied to any book from now on
+    pub fn subscribe(&mut self) -> ExchangeFeed {
+        let (sender, events) = channel();
+        self.subscribers.push(sender);
+        ExchangeFeed {
+            events,
+            symbols: BTreeSet::new(),
+        }
+    }
+
+    pub fn book(&self, name: &str) -> Option<&Orderbook> {
+        self.books.get(name).map(|listing| &listing.book)
+    }
+
+    /// Run a command on one book, the same way the engine does
+    pub fn execute(&mut self, name: &str, command: EventKind) -> Result<CommandResponse> {
+        let listing = self
+            .books
+            .get_mut(name)
+            .ok_or_else(|| format!("No book named {}", name))?;
+        let response = execute(&mut listing.book, command.clone());
+        let mut event = ExchangeEvent {
+            seq: self.next_seq,
+            book: name.to_string(),
+            symbol: listing.book.symbol.clone(),
+            event: EngineEvent::drain(&mut listing.book, command, response.clone()),
+        };
+
+        let session = self
+            .registry
+            .get(&listing.book.symbol)
+            .and_then(|instrument| instrument.session);
+        let in_session = session.map(|session| session.is_open(listing.book.clock));
+        if let (Some(before), Some(now)) = (listing.in_session, in_session)
+            && before != now
+        {
+            event.event.status_events.push(StatusEvent {
+                status: if now {
+                    MarketStatus::SessionOpen
+                } else {
+                    MarketStatus::SessionClose
+                },
+                timestamp: listing.book.clock,
+            });
+        }
+        listing.in_session = in_session;
+
+        self.next_seq += 1;
+        // forget subscribers that hung up
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+
+        let top = (
+            listing.book.published_top_of_book(Side::Buy),
+            listing.book.published_top_of_book(Side::Sell),
+        );
+        if top != listing.top {
+            listing.top = top;
+            let symbol = listing.book.symbol.clone();
+            self.consolidate(&symbol);
+        }
+        response
+    }
+
+    /// Best bid and ask of `symbol` across every book listing it
+    pub fn nbbo(&self, symbol: &str) -> Option<&ConsolidatedQuote> {
+        self.nbbo.get(symbol)
+    }
+
+    /// Every change to a consolidated quote since the last drain, oldest
+    /// first
+    pub fn drain_nbbo_updates(&mut self) -> Vec<ConsolidatedQuote> {
+        std::mem::take(&mut self.nbbo_updates)
+    }
+
+    fn in_session(&self, book: &Orderbook) -> Option<bool> {
+        let session = self.registry.get(&book.symbol)?.session?;
+        Some(session.is_open(book.clock))
+    }
+
+    /// Work out `symbol`'s quote again from its books' tops, publishing
+    /// it if it moved
+    fn consolidate(&mut self, symbol: &str) {
+        let mut quote = ConsolidatedQuote {
+            symbol: symbol.to_string(),
+            bid: None,
+            ask: None,
+        };
+        for name in self.venues.get(symbol).into_iter().flatten() {
+            let (bid, ask) = self.books[name].top;
+            quote.bid = better(Side::Buy, quote.bid, bid);
+            quote.ask = better(Side::Sell, quote.ask, ask);
+        }
+
+        if self.nbbo.get(symbol) != Some(&quote) {
+            self.nbbo.insert(symbol.to_string(), quote.clone());
+            self.nbbo_updates.push(quote);
+        }
+    }
+}
+
+/// The better of two tops on `side`, adding up their sizes on a tie
+fn better(side: Side, a: Option<PriceSize>, b: Option<PriceSize>) -> Option<PriceSize> {
+    match (a, b) {
+        (Some(a), Some(b)) if a.price == b.price => Some(PriceSize {
+            price: a.price,
+            size: a.size + b.size,
+        }),
+        (Some(a), Some(b)) => {
+            let a_better = match side {
+                Side::Buy => a.price > b.price,
+                Side::Sell => a.price < b.price,
+            };
+            Some(if a_better { a } else { b })
+        }
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        BookConfig,
+        command_log::decode,
+        registry::SessionTimes,
+        tick::{TickBand, TickTable},
+    };
+
+    fn venue(symbol: &str) -> Orderbook {
+        Orderbook::with_config(BookConfig {
+            symbol: symbol.to_string(),
+            max_price: 1_000,
+            ..BookConfig::default()
+        })
+        .unwrap()
+    }
+
+    fn send(exchange: &mut Exchange, name: &str, line: &str) {
+        exchange
+            .execute(name, EventKind::Order(decode(line).unwrap()))
+            .unwrap();
+    }
+
+    #[test]
+    fn consolidates_the_best_of_every_venue() {
+        let mut exchange = Exchange::new();
+        exchange.add_book("BTC.A", venue("BTC")).unwrap();
+        exchange.add_book("BTC.B", venue("BTC")).unwrap();
+        exchange.add_book("ETH.A", venue("ETH")).unwrap();
+        assert!(exchange.add_book("BTC.A", venue("BTC")).is_err());
+        exchange.drain_nbbo_updates();
+
+        let top = |price, size| Some(PriceSize { price, size });
+        send(&mut exchange, "BTC.A", "B L 99 5");
+        send(&mut exchange, "BTC.B", "B L 99 3");
+        send(&mut exchange, "BTC.B", "S L 101 2");
+        send(&mut exchange, "BTC.A", "S L 102 4");
+        // behind the best on both sides, nothing moves
+        send(&mut exchange, "BTC.A", "B L 98 1");
+        send(&mut exchange, "ETH.A", "B L 50 1");
+
+        let quote = |symbol: &str, bid, ask| ConsolidatedQuote {
+            symbol: symbol.to_string(),
+            bid,
+            ask,
+        };
+        assert_eq!(
+            exchange.drain_nbbo_updates(),
+            vec![
+                quote("BTC", top(99, 5), None),
+                quote("BTC", top(99, 8), None),
+                quote("BTC", top(99, 8), top(101, 2)),
+                quote("ETH", top(50, 1), None),
+            ]
+        );
+
+        // the best venue running dry falls back to the next one
+        send(&mut exchange, "BTC.B", "B M 2");
+        assert_eq!(
+            exchange.nbbo("BTC"),
+            Some(&quote("BTC", top(99, 8), top(102, 4)))
+        );
+        assert!(exchange.execute("SOL.A", EventKind::Cancel(0)).is_err());
+    }
+
+    #[test]
+    fn one_feed_numbers_every_book_in_order() {
+        let mut exchange = Exchange::new();
+        exchange.add_book("BTC", venue("BTC")).unwrap();
+        exchange.add_book("ETH", venue("ETH")).unwrap();
+        let everything = exchange.subscribe();
+        let eth = exchange.subscribe().only("ETH");
+
+        send(&mut exchange, "BTC", "S L 101 5");
+        send(&mut exchange, "ETH", "S L 51 5");
+        send(&mut exchange, "BTC", "B M 2");
+        send(&mut exchange, "ETH", "B M 5");
+
+        let events: Vec<ExchangeEvent> = everything.try_iter().collect();
+        assert_eq!(
+            events
+                .iter()
+                .map(|event| (event.seq, event.book.as_str(), event.event.seq))
+                .collect::<Vec<_>>(),
+            vec![(0, "BTC", 0), (1, "ETH", 0), (2, "BTC", 1), (3, "ETH", 1)]
+        );
+        assert_eq!(events[2].event.trades[0].size, 2);
+
+        // the filtered feed skips the other symbol's numbers
+        let seqs: Vec<u64> = eth.try_iter().map(|event| event.seq).collect();
+        assert_eq!(seqs, vec![1, 3]);
+
+        // nothing builds up in the books themselves
+        let btc = exchange.book("BTC").unwrap();
+        assert!(btc.event_log.is_empty());
+        assert_eq!(btc.events_since(0), &[]);
+    }
+
+    #[test]
+    fn routes_orders_checked_against_the_registry() {
+        let mut registry = InstrumentRegistry::new();
+        let bands = TickTable::new(vec![
+            TickBand {
+                from_price: 1,
+                tick_size: 1,
+            },
+            TickBand {
+                from_price: 100,
+                tick_size: 5,
+            },
+        ])
+        .unwrap();
+        registry
+            .register(
+                "BTC",
+                1_000,
+                bands,
+                10,
+                Some(SessionTimes {
+                    open: 10,
+                    close: 20,
+                }),
+            )
+            .unwrap();
+        registry
+            .register("ETH", 1_000, TickTable::fixed(1, 1), 1, None)
+            .unwrap();
+        let mut exchange = Exchange::from_registry(registry).unwrap();
+        let feed = exchange.subscribe();
+
+        let order = |line| decode(line).unwrap();
+        assert!(exchange.submit("ETH", order("B L 99 3")).is_ok());
+        assert!(exchange.submit("SOL", order("B L 99 3")).is_err());
+        // BTC is not open yet
+        assert!(exchange.submit("BTC", order("B L 105 10")).is_err());
+        exchange.execute("BTC", EventKind::SetClock(10)).unwrap();
+        assert!(exchange.submit("BTC", order("B L 105 10")).is_ok());
+        assert!(exchange.submit("BTC", order("B L 103 10")).is_err());
+        assert!(exchange.submit("BTC", order("B L 105 15")).is_err());
+
+        assert_eq!(exchange.book("BTC").unwrap().total_liquidity(Side::Buy), 10);
+        assert_eq!(exchange.registry().get("BTC").unwrap().id, 0);
+        exchange.execute("BTC", EventKind::SetClock(25)).unwrap();
+        exchange.execute("ETH", EventKind::SetClock(25)).unwrap();
+
+        // only what reached a book is on the feed, with the session's
+        // open and close on the commands that moved the clock
+        let statuses: Vec<(String, Vec<StatusEvent>)> = feed
+            .try_iter()
+            .map(|event| (event.symbol, event.event.status_events))
+            .collect();
+        let status = |status, timestamp| vec![StatusEvent { status, timestamp }];
+        assert_eq!(
+            statuses,
+            vec![
+                ("ETH".to_string(), vec![]),
+                ("BTC".to_string(), status(MarketStatus::SessionOpen, 10)),
+                ("BTC".to_string(), vec![]),
+                ("BTC".to_string(), status(MarketStatus::SessionClose, 25)),
+                ("ETH".to_string(), vec![]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use std::{fs, path::Path};
+
+use crate::{
+    EventKind, OrderResponse, Result,
+    book::Orderbook,
+    command_log::{encode_event, replay_events_file},
+    engine::{CommandResponse, execute},
+};
+
+/// at most this many differing lines are spelled out in a diff
+const DIFF_LINES: usize = 5;
+
+/// Replay commands and write down everything the book said, one line per
+/// command: `<seq> <command> => <outcome>`, followed by an indented line
+/// for each trade, execution report and level update it caused
+pub fn render(book: &mut Orderbook, commands: &[EventKind]) -> String {
+    let mut rendered = String::new();
+    for (seq, command) in commands.iter().enumerate() {
+        let outcome = match execute(book, command.clone()) {
+            Ok(response) => describe(&response),
+            Err(e) => format!("rejected {}", e),
+        };
+        rendered.push_str(&format!(
+            "{} {} => {}\n",
+            seq,
+            encode_event(command),
+            outcome
+        ));
+
+        for trade in book.drain_trades() {
+            rendered.push_str(&format!(
+                "  trade {} {} at {} maker {} taker {}\n",
+                trade.trade_id, trade.size, trade.price, trade.maker_order_id, trade.taker_order_id
+            ));
+        }
+        for report in book.drain_execution_reports() {
+            rendered.push_str(&format!(
+                "  report {} {:?} {} at {}\n",
+                report.order_id, report.exec_type, report.traded_size, report.price
+            ));
+        }
+        for update in book.drain_level_updates() {
+            rendered.push_str(&format!(
+                "  level {:?} {} {}\n",
+                update.side, update.price, update.new_total_size
+            ));
+        }
+    }
+    rendered
+}
+
+fn describe(response: &CommandResponse) -> String {
+    match response {
+        CommandResponse::Order(OrderResponse::Limit(limit)) => format!("rested {}", limit.id),
+        CommandResponse::Order(OrderResponse::Market(market)) => format!(
+            "filled {} notional {} remaining {}",
+            market.size, market.notional, market.remaining
+        ),
+        CommandResponse::Cancel(cancel) => format!(
+            "cancelled {} {:?} {} at {}",
+            cancel.id, cancel.side, cancel.size, cancel.price
+        ),
+        CommandResponse::Replace(replace) if replace.requeued => {
+            format!("requeued as {}", replace.id)
+        }
+        CommandResponse::Replace(replace) => format!("amended {}", replace.id),
+        CommandResponse::MassQuote(levels) => {
+            let levels: Vec<String> = levels
+                .iter()
+                .map(|level| match level {
+                    Ok(limit) => format!("rested {}", limit.id),
+                    Err(e) => format!("rejected {}", e),
+                })
+                .collect();
+            format!("quoted [{}]", levels.join(", "))
+        }
+        CommandResponse::Expire(expired) => {
+            let ids: Vec<String> = expired.iter().map(|cancel| cancel.id.to_string()).collect();
+            format!("expired [{}]", ids.join(" "))
+        }
+        CommandResponse::Bust(bust) => match bust.restored_order_id {
+            Some(id) => format!("busted {} restored {}", bust.trade.trade_id, id),
+            None => format!("busted {} maker gone", bust.trade.trade_id),
+        },
+        CommandResponse::Applied => "applied".into(),
+    }
+}
+
+/// Ok when both are identical, otherwise a readable diff of the first
+/// few lines that differ
+pub fn diff(expected: &str, actual: &str) -> Result<()> {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+
+    let differing: Vec<usize> = (0..expected.len().max(actual.len()))
+        .filter(|line| expected.get(*line) != actual.get(*line))
+        .collect();
+    if differing.is_empty() {
+        return Ok(());
+    }
+
+    let mut report = format!("{} lines differ from the golden output", differing.len());
+    for line in differing.iter().take(DIFF_LINES) {
+        report.push_str(&format!(
+            "\nline {}\n- {}\n+ {}",
+            line + 1,
+            expected.get(*line).unwrap_or(&"<missing>"),
+            actual.get(*line).unwrap_or(&"<missing>"),
+        ));
+    }
+    if differing.len() > DIFF_LINES {
+        report.push_str(&format!("\n... and {} more", differing.len() - DIFF_LINES));
+    }
+    Err(report)
+}
+
+/// Replay a recorded command file, as written by `FileLog`, into `book`
+/// and compare with the golden file next to it. A recording of orders
+/// only reads just as well.
+pub fn verify(
+    book: &mut Orderbook,
+    commands: impl AsRef<Path>,
+    golden: impl AsRef<Path>,
+) -> Result<()> {
+    let actual = render(book, &read_commands(commands.as_ref())?);
+    let expected = fs::read_to_string(golden.as_ref())
+        .map_err(|e| format!("Failed to read {}: {}", golden.as_ref().display(), e))?;
+    diff(&expected, &actual)
+}
+
+/// Record the current behaviour as the new golden output
+pub fn bless(
+    book: &mut Orderbook,
+    commands: impl AsRef<Path>,
+    golden: impl AsRef<Path>,
+) -> Result<()> {
+    let actual = render(book, &read_commands(commands.as_ref())?);
+    fs::write(golden.as_ref(), actual)
+        .map_err(|e| format!("Failed to write {}: {}", golden.as_ref().display(), e))
+}
+
+/// Rebuild `live` from its own event log and check both end up in the
+/// same state. Only meaningful for books built with `Orderbook::new()`.
+pub fn verify_replay(live: &Orderbook) -> Result<()> {
+    let replayed = Orderbook::replay(live.event_log.iter().cloned())?;
+    let (expected, actual) = (live.state_digest(), replayed.state_digest());
+    if expected != actual {
+        return Err(format!(
+            "Replayed state {:016x} differs from the live state {:016x}",
+            actual, expected
+        ));
+    }
+    Ok(())
+}
+
+fn read_commands(path: &Path) -> Result<Vec<EventKind>> {
+    replay_events_file(path)?.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_log::{decode, decode_event};
+
+    #[test]
+    fn renders_every_outcome() {
+        let commands: Vec<EventKind> = [
+            "B L 100 10",
+            "S L 0 1",
+            "S M 4",
+            "R 0 100 3",
+            "R 0 101 3",
+            "X 2",
+            "X 2",
+            "MQ 7 0 S 105 2",
+            "B L 99 1 G 5",
+            "E 10",
+        ]
+        .into_iter()
+        .map(|line| decode_event(line).unwrap())
+        .collect();
+
+        assert_eq!(
+            render(&mut Orderbook::new(), &commands),
+            "0 B L 100 10 => rested 0\n\
+             \x20 level Buy 100 10\n\
+             1 S L 0 1 => rejected Price 0 is not on a valid tick\n\
+             2 S M 4 => filled 4 notional 400 remaining 0\n\
+             \x20 trade 0 4 at 100 maker 0 taker 1\n\
+             \x20 report 0 PartialFill 4 at 100\n\
+             \x20 level Buy 100 6\n\
+             3 R 0 100 3 => amended 0\n\
+             \x20 level Buy 100 3\n\
+             4 R 0 101 3 => requeued as 2\n\
+             \x20 report 0 Cancelled 0 at 100\n\
+             \x20 level Buy 100 0\n\
+             \x20 level Buy 101 3\n\
+             5 X 2 => cancelled 2 Buy 3 at 101\n\
+             \x20 report 2 Cancelled 0 at 101\n\
+             \x20 level Buy 101 0\n\
+             6 X 2 => rejected No resting order with id 2\n\
+             7 MQ 7 0 S 105 2 => quoted [rested 3]\n\
+             \x20 level Sell 105 2\n\
+             8 B L 99 1 G 5 => rested 4\n\
+             \x20 level Buy 99 1\n\
+             9 E 10 => expired [4]\n\
+             \x20 report 4 Cancelled 0 at 99\n\
+             \x20 level Buy 99 0\n"
+        );
+    }
+
+    #[test]
+    fn diffs_point_at_changed_lines() {
+        assert!(diff("a\nb\n", "a\nb\n").is_ok());
+        assert_eq!(
+            diff("a\nb\n", "a\nc\nd\n").unwrap_err(),
+            "2 lines differ from the golden output\n\
+             line 2\n- b\n+ c\n\
+             line 3\n- <missing>\n+ d"
+        );
+
+        let many = diff("", &"x\n".repeat(7)).unwrap_err();
+        assert!(many.ends_with("... and 2 more"), "{}", many);
+    }
+
+    #[test]
+    fn blessed_files_verify_until_behaviour_changes() {
+        let dir = std::env::temp_dir();
+        let commands = dir.join(format!("orderbook-golden-{}.log", std::process::id()));
+        let golden = dir.join(format!("orderbook-golden-{}.golden", std::process::id()));
+        fs::write(&commands, "B L 100 10\nS L 101 5\nB M 2\nX 0\n").unwrap();
+
+        bless(&mut Orderbook::new(), &commands, &golden).unwrap();
+        verify(&mut Orderbook::new(), &commands, &golden).unwrap();
+
+        // a pre-populated book shifts every id, which must be caught
+        let mut seeded = Orderbook::new();
+        seeded.accept_order(decode("B L 1 1").unwrap()).unwrap();
+        assert!(verify(&mut seeded, &commands, &golden).is_err());
+
+        fs::remove_file(&commands).unwrap();
+        fs::remove_file(&golden).unwrap();
+    }
+
+    #[test]
+    fn replaying_the_event_log_rebuilds_the_same_book() {
+        let mut live = Orderbook::new();
+        live.set_day_end(Some(500));
+        for line in [
+            "B L 100 10",
+            "S L 103 5 D",
+            "S L 104 5 G 300",
+            "B T 103 2",
+            "S P P 1 4",
+            "S M 3",
+            "B M 6",
+            "B L 0 1",
+        ] {
+            let _ = live.accept_order(decode(line).unwrap());
+        }
+        live.replace_order(0, 99, 8).unwrap();
+        let _ = live.cancel_order(1);
+        live.expire(400);
+        live.mass_quote(0, &[], &[]).unwrap();
+        verify_replay(&live).unwrap();
+
+        let mut events = live.event_log.clone();
+        events.remove(3);
+        assert!(Orderbook::replay(events).is_err());
+
+        // a different history can't land on the same state
+        let mut events = live.event_log.clone();
+        events[0].kind = EventKind::Cancel(0);
+        let replayed = Orderbook::replay(events).unwrap();
+        assert_ne!(replayed.state_digest(), live.state_digest());
+    }
+}
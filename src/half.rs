@@ -1,78 +1,77 @@
 use std::collections::HashMap;
 
-use crate::{Order, PriceLevel, PriceSize, Result, Side};
-
-#[derive(Debug)]
+use crate::{
+    ClipSize, ExecType, ExecutionReport, Fill, IcebergRefresh, LevelSizes, Order, OrderView,
+    PriceLevel, PriceSize, RefreshPriority, RestingOrder, Result, SelfTradePrevention, Side,
+    digest::StateDigest, snapshot::HalfSnapshot, tick::TickTable,
+};
+
+/// The ladder never grows past this many levels, so a fat-fingered price
+/// cannot allocate the machine away
+const MAX_LADDER_LEN: usize = 1 << 22;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HalfBook {
+    /// lowest price on the ladder, lowered when an order comes in below it
     pub min_price: i64,
+    /// highest price on the ladder, raised when an order comes in above it
     pub max_price: i64,
-    pub tick_size: i64,
+    /// maps prices onto ladder indexes, one tick size or several bands
+    pub tick_table: TickTable,
     pub side: Side,
     orders: Vec<PriceLevel>,
     pub top_of_book: Option<usize>,
     arena: Vec<Order>,
     free_list: Vec<usize>,
     ids: HashMap<u64, usize>,
+    /// what happened to resting orders since the last drain
+    reports: Vec<ExecutionReport>,
+    /// levels whose size changed since the last drain, first touch first
+    dirty_levels: Vec<usize>,
+    /// (clock, event seq) stamped on every level that changes
+    stamp: (u64, u64),
 }
 
 impl HalfBook {
     pub fn new(side: Side, max_price: i64, min_price: i64, tick_size: i64) -> Self {
-        let ladder_size = ((max_price - min_price) / tick_size + 1) as usize;
+        Self::with_tick_table(side, max_price, TickTable::fixed(min_price, tick_size))
+    }
+
+    pub fn with_tick_table(side: Side, max_price: i64, tick_table: TickTable) -> Self {
+        let ladder_size = tick_table
+            .index_of(max_price)
+            .map(|index| index + 1)
+            .unwrap_or_default();
         Self {
-            min_price,
+            min_price: tick_table.min_price(),
             max_price,
-            tick_size,
+            tick_table,
             side,
             top_of_book: None,
             orders: (0..ladder_size).map(|_| Default::default()).collect(),
             arena: (0..ladder_size).map(|_| Default::default()).collect(),
             free_list: (0..ladder_size).collect(),
             ids: HashMap::with_capacity(1000),
+            reports: Vec::new(),
+            dirty_levels: Vec::new(),
+            stamp: (0, 0),
         }
     }
 
     pub fn insert(&mut self, id: u64, price: i64, size: i64) -> Result<()> {
         if price <= 0 || size <= 0 {
-            return Err(format!("Invalid order"));
+            return Err("Invalid order".into());
         }
+        self.validate_price(price)?;
+        self.grow_ladder_down(price)?;
+
         // Compute price_index.
         let price_index = self.calculate_price_index(price);
+        self.grow_ladder(price, price_index)?;
 
         // Push new Order into arena → get index.
-        let arena_index = match self.free_list.pop() {
-            Some(arena_index) => arena_index,
-            None => {
-                let order = Order::default();
-                self.arena.push(order);
-                self.arena.len() - 1
-            }
-        };
-
-        // Append to level tail.
-        let Some(level) = self.orders.get_mut(price_index) else {
-            return Err(format!(
-                "Out of bounds on the price level somehow with {}",
-                price_index
-            ));
-        };
-
-        level.total_size += size;
-
-        if level.head.is_none() {
-            level.head = Some(arena_index);
-        }
-
-        let old_tail = level.tail;
-        if let Some(tail_index) = old_tail {
-            let Some(prev_order) = self.arena.get_mut(tail_index) else {
-                return Err(format!(
-                    "The tail cant be gotten from the arena {}",
-                    tail_index
-                ));
-            };
-            prev_order.next = Some(arena_index);
-        }
-        level.tail = Some(arena_index);
+        let arena_index = self.allocate();
 
         let Some(order) = self.arena.get_mut(arena_index) else {
             return Err(format!(
@@ -80,13 +79,35 @@ impl HalfBook {
                 arena_index
             ));
         };
-        order.overwrite(id, price_index, size, old_tail, None);
+        order.overwrite(id, price_index, size, None, None);
+        order.entered_at = self.stamp.0;
+
+        // Append to level tail.
+        self.append_to_level(price_index, arena_index)?;
 
         // Insert into HashMap.
         self.ids.insert(id, arena_index);
 
         // self.emit(order.id, RECEIVED)
 
+        self.improve_top_of_book(price_index);
+
+        Ok(())
+    }
+
+    fn allocate(&mut self) -> usize {
+        match self.free_list.pop() {
+            Some(arena_index) => arena_index,
+            None => {
+                let order = Order::default();
+                self.arena.push(order);
+                self.arena.len() - 1
+            }
+        }
+    }
+
+    /// Move the top of book to `price_index` if an order there beats it
+    fn improve_top_of_book(&mut self, price_index: usize) {
         if matches!(self.side, Side::Buy) {
             match self.top_of_book {
                 None => {
@@ -110,11 +131,245 @@ impl HalfBook {
                 }
             }
         }
+    }
+
+    /// Rest `total_size` showing only `display_size` at a time. Each clip
+    /// trades like a normal order and once it is gone the next one joins
+    /// the back of the level.
+    pub fn insert_iceberg(
+        &mut self,
+        id: u64,
+        price: i64,
+        display_size: i64,
+        total_size: i64,
+    ) -> Result<()> {
+        self.insert_iceberg_with(
+            id,
+            price,
+            display_size,
+            total_size,
+            IcebergRefresh::default(),
+        )
+    }
 
+    /// Same as `insert_iceberg` with the clips after the first sized and
+    /// queued according to `refresh`
+    pub fn insert_iceberg_with(
+        &mut self,
+        id: u64,
+        price: i64,
+        display_size: i64,
+        total_size: i64,
+        refresh: IcebergRefresh,
+    ) -> Result<()> {
+        validate_iceberg(display_size, total_size, refresh)?;
+
+        self.insert(id, price, display_size)?;
+        let Some(order) = self
+            .ids
+            .get(&id)
+            .and_then(|index| self.arena.get_mut(*index))
+        else {
+            return Err(format!("This order with id {} is not in our ids map!", id));
+        };
+        order.display_size = display_size;
+        order.reserve = total_size - display_size;
+        order.refresh = refresh;
+        let price_index = order.price_index;
+        if let Some(level) = self.orders.get_mut(price_index) {
+            level.total_size += total_size - display_size;
+        }
         Ok(())
     }
 
+    /// Only let aggressors with at least `min_qty` left trade with a
+    /// resting order. Setting it to the order's size makes it all-or-none.
+    pub fn set_min_qty(&mut self, id: u64, min_qty: i64) -> Result<()> {
+        let Some(order) = self
+            .ids
+            .get(&id)
+            .and_then(|index| self.arena.get_mut(*index))
+        else {
+            return Err(format!("This order with id {} is not in our ids map!", id));
+        };
+        if min_qty < 0 {
+            return Err(format!("Minimum quantity {} can't be negative", min_qty));
+        }
+        order.min_qty = min_qty;
+        Ok(())
+    }
+
+    /// The clock when a resting order took its place in the queue
+    pub fn get_entered_at(&self, id: u64) -> Option<u64> {
+        let arena_index = self.ids.get(&id)?;
+        self.arena.get(*arena_index).map(|order| order.entered_at)
+    }
+
+    pub fn get_owner(&self, id: u64) -> Option<u64> {
+        let arena_index = self.ids.get(&id)?;
+        self.arena.get(*arena_index).map(|order| order.owner)
+    }
+
+    /// Ids of `owner`'s resting orders on this side, lowest first
+    pub fn ids_owned_by(&self, owner: u64) -> Vec<u64> {
+        let mut ids: Vec<u64> = self
+            .ids
+            .iter()
+            .filter(|(_, arena_index)| {
+                self.arena
+                    .get(**arena_index)
+                    .is_some_and(|order| order.owner == owner)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// How many orders `owner` has resting on this side and their
+    /// notional, hidden size included
+    pub fn owner_exposure(&self, owner: u64) -> (usize, i64) {
+        self.ids
+            .values()
+            .filter_map(|arena_index| self.arena.get(*arena_index))
+            .filter(|order| order.owner == owner)
+            .fold((0, 0), |(orders, notional), order| {
+                let price = self.get_price_from_index(order.price_index);
+                (orders + 1, notional + price * (order.size + order.reserve))
+            })
+    }
+
+    /// Tag a resting order with its owner for self-trade prevention
+    pub fn set_owner(&mut self, id: u64, owner: u64) -> Result<()> {
+        let Some(order) = self
+            .ids
+            .get(&id)
+            .and_then(|index| self.arena.get_mut(*index))
+        else {
+            return Err(format!("This order with id {} is not in our ids map!", id));
+        };
+        order.owner = owner;
+        Ok(())
+    }
+
+    /// Take `size` off a resting order, hidden reserve first, removing it
+    /// once nothing is left. Returns how much actually came off.
+    fn decrement(&mut self, id: u64, size: i64) -> Result<i64> {
+        let Some(&arena_index) = self.ids.get(&id) else {
+            return Err(format!("This order with id {} is not in our ids map!", id));
+        };
+        let Some(order) = self.arena.get_mut(arena_index) else {
+            return Err(format!("Arena access failed at {}", arena_index));
+        };
+
+        let cut = size.min(order.size + order.reserve);
+        if cut == order.size + order.reserve {
+            self.remove(id)?;
+            return Ok(cut);
+        }
+
+        let from_reserve = cut.min(order.reserve);
+        order.reserve -= from_reserve;
+        order.size -= cut - from_reserve;
+        let price_index = order.price_index;
+        if let Some(level) = self.orders.get_mut(price_index) {
+            level.total_size -= cut;
+            level.displayed_size -= cut - from_reserve;
+        }
+        self.mark_dirty(price_index);
+        Ok(cut)
+    }
+
     pub fn remove(&mut self, id: u64) -> Result<()> {
+        let owner = self.get_owner(id).unwrap_or_default();
+        let price_index = self.detach(id)?;
+        self.emit(
+            id,
+            owner,
+            ExecType::Cancelled,
+            0,
+            self.get_price_from_index(price_index),
+        );
+        Ok(())
+    }
+
+    /// Reports not yet drained, oldest first
+    pub fn reports(&self) -> &[ExecutionReport] {
+        &self.reports
+    }
+
+    /// Put the maker's rebate on a report not yet drained
+    pub(crate) fn set_rebate(&mut self, index: usize, rebate: i64) {
+        self.reports[index].rebate = rebate;
+    }
+
+    /// Every fill and cancel of a resting order since the last drain
+    pub fn drain_reports(&mut self) -> Vec<ExecutionReport> {
+        std::mem::take(&mut self.reports)
+    }
+
+    /// The new displayed size of every level that changed since the last
+    /// drain, in the order they were first touched. Emptied levels show up
+    /// with 0.
+    pub fn drain_level_updates(&mut self) -> Vec<PriceSize> {
+        self.drain_level_sizes()
+            .into_iter()
+            .map(|level| PriceSize {
+                price: level.price,
+                size: level.displayed_size,
+            })
+            .collect()
+    }
+
+    /// `drain_level_updates` with the total size of each level alongside
+    pub fn drain_level_sizes(&mut self) -> Vec<LevelSizes> {
+        std::mem::take(&mut self.dirty_levels)
+            .into_iter()
+            .map(|index| self.level_sizes_at(index))
+            .collect()
+    }
+
+    /// Stamp every level that changes from here on with the book's clock
+    /// and the event doing it
+    pub fn set_stamp(&mut self, clock: u64, seq: u64) {
+        self.stamp = (clock, seq);
+    }
+
+    fn mark_dirty(&mut self, price_index: usize) {
+        if let Some(level) = self.orders.get_mut(price_index) {
+            (level.updated_at, level.updated_seq) = self.stamp;
+        }
+        if !self.dirty_levels.contains(&price_index) {
+            self.dirty_levels.push(price_index);
+        }
+    }
+
+    fn emit(
+        &mut self,
+        order_id: u64,
+        owner: u64,
+        exec_type: ExecType,
+        traded_size: i64,
+        price: i64,
+    ) {
+        self.reports.push(ExecutionReport {
+            order_id,
+            owner,
+            exec_type,
+            traded_size,
+            price,
+            rebate: 0,
+        });
+    }
+
+    /// Take an order off the book without reporting it, e.g. to park it
+    pub fn withdraw(&mut self, id: u64) -> Result<()> {
+        self.detach(id).map(|_| ())
+    }
+
+    /// Take an order off the book without reporting it, returning the
+    /// index of the level it was on
+    fn detach(&mut self, id: u64) -> Result<usize> {
         // Lookup arena index via HashMap.
         let Some(arena_index) = self.ids.remove(&id) else {
             return Err(format!("This order with id {} is not in our ids map!", id));
@@ -142,7 +397,8 @@ impl HalfBook {
             level.tail = order.prev;
         }
 
-        level.total_size -= order.size;
+        level.total_size -= order.size + order.reserve;
+        level.displayed_size -= order.size;
 
         // these will prevent borrow issues
         let next = order.next;
@@ -151,23 +407,24 @@ impl HalfBook {
         let total_size = level.total_size;
 
         self.remove_order_from_linked_list(prev, next)?;
+        self.mark_dirty(price_index);
 
         // if we are removing our TOB
-        if let Some(tob) = self.top_of_book {
-            if tob == price_index && total_size == 0 {
-                self.top_of_book = self.find_next_best_level(tob);
-            }
+        if let Some(tob) = self.top_of_book
+            && tob == price_index
+            && total_size == 0
+        {
+            self.top_of_book = self.find_next_best_level(tob);
         }
 
         // Mark arena slot reusable.
         self.free_list.push(arena_index);
 
-        // self.emit(order.id, CANCELLED)
-
-        Ok(())
+        Ok(price_index)
     }
 
     pub fn modify(&mut self, id: u64, price: i64, size: i64) -> Result<()> {
+        self.validate_price(price)?;
         let price_index = self.calculate_price_index(price);
         let Some(arena_index) = self.ids.get(&id) else {
             return Err(format!("This order with id {} is not in our ids map!", id));
@@ -181,8 +438,10 @@ impl HalfBook {
         };
 
         if order.price_index != price_index {
-            self.remove(id)?;
-            self.insert(id, price, size)?;
+            let kept = order.clone();
+            // still the same order, just somewhere else
+            self.detach(id)?;
+            self.reinsert(id, price, size, &kept)?;
         } else {
             let Some(level) = self.orders.get_mut(order.price_index) else {
                 return Err(format!(
@@ -191,54 +450,144 @@ impl HalfBook {
                 ));
             };
 
-            level.total_size -= order.size;
-            level.total_size += size;
-            order.size = size;
+            // an iceberg keeps showing at most one clip of the new total
+            let shown = match order.display_size {
+                0 => size,
+                display_size => size.min(display_size),
+            };
+            level.total_size += size - order.size - order.reserve;
+            level.displayed_size += shown - order.size;
+            order.size = shown;
+            order.reserve = size - shown;
+            self.mark_dirty(price_index);
         }
 
         Ok(())
     }
 
-    pub fn match_size(&mut self, mut size: i64) -> Result<i64> {
+    /// Send a resting order to the back of the queue at `price` under
+    /// `new_id`, reporting the old id as cancelled. It keeps its clip
+    /// size and refresh policy, minimum quantity and owner.
+    pub fn requeue(&mut self, id: u64, new_id: u64, price: i64, size: i64) -> Result<()> {
+        if price <= 0 || size <= 0 {
+            return Err("Invalid order".into());
+        }
+        self.validate_price(price)?;
+        let Some(order) = self.ids.get(&id).and_then(|index| self.arena.get(*index)) else {
+            return Err(format!("This order with id {} is not in our ids map!", id));
+        };
+        let kept = order.clone();
+
+        self.remove(id)?;
+        self.reinsert(new_id, price, size, &kept)
+    }
+
+    /// Rest an order that was taken off the book with the attributes it
+    /// had there
+    fn reinsert(&mut self, id: u64, price: i64, size: i64, kept: &Order) -> Result<()> {
+        if kept.display_size > 0 {
+            self.insert_iceberg_with(id, price, kept.display_size.min(size), size, kept.refresh)?;
+        } else {
+            self.insert(id, price, size)?;
+        }
+        self.set_min_qty(id, kept.min_qty.min(size))?;
+        self.set_owner(id, kept.owner)
+    }
+
+    /// Walk the book from the top taking up to `size`,
+    /// reporting how much actually traded and for what notional
+    pub fn match_size(&mut self, size: i64) -> Result<Fill> {
+        self.match_size_until(size, None)
+    }
+
+    /// Same as `match_size` but stops before any level priced
+    /// worse than `limit_price` for the aggressor.
+    /// Orders whose minimum quantity is more than the aggressor has left
+    /// are stepped over and keep their place, everything behind them
+    /// still trades in FIFO order.
+    pub fn match_size_until(&mut self, size: i64, limit_price: Option<i64>) -> Result<Fill> {
+        self.match_size_as(size, limit_price, 0, SelfTradePrevention::default())
+    }
+
+    /// Same as `match_size_until` for an aggressor belonging to `owner`,
+    /// applying `stp` to any of its own orders it runs into
+    pub fn match_size_as(
+        &mut self,
+        mut size: i64,
+        limit_price: Option<i64>,
+        owner: u64,
+        stp: SelfTradePrevention,
+    ) -> Result<Fill> {
         if size == 0 {
-            return Err(format!("Invalid order"));
+            return Err("Invalid order".into());
         }
 
-        let mut notional = 0;
+        let mut fill = Fill::default();
+        // usually the top of book, deeper only past levels we can't trade
+        let mut current = self.top_of_book;
 
         while size > 0 {
-            let Some(tob) = self.top_of_book else {
-                return Ok(notional);
+            let Some(tob) = current else {
+                return Ok(fill);
             };
 
-            // We repeatedly reborrow the price level in small scopes
-            loop {
-                let order_index = {
-                    let Some(level) = self.orders.get_mut(tob) else {
-                        return Err("Failed to get price level".into());
-                    };
-
-                    if size <= 0 || level.total_size <= 0 {
-                        break;
-                    }
-
-                    level.head
-                };
+            if let Some(limit_price) = limit_price
+                && !self.is_within_limit(tob, limit_price)
+            {
+                return Ok(fill);
+            }
 
-                let Some(order_index) = order_index else {
-                    break;
+            let mut cursor = {
+                let Some(level) = self.orders.get(tob) else {
+                    return Err("Failed to get price level".into());
                 };
+                level.head
+            };
 
-                // Now arena borrow is separate
-                let (id, traded, order_empty) = {
+            while size > 0
+                && let Some(order_index) = cursor
+            {
+                // Arena borrow is separate from the level
+                let (id, maker, traded, order_empty, next) = {
                     let Some(order) = self.arena.get_mut(order_index) else {
                         return Err(format!("Arena access failed at {}", order_index));
                     };
 
+                    if order.min_qty.min(order.size) > size {
+                        cursor = order.next;
+                        continue;
+                    }
+
+                    if owner != 0 && order.owner == owner && stp != SelfTradePrevention::Allow {
+                        let resting_id = order.id;
+                        cursor = order.next;
+                        match stp {
+                            SelfTradePrevention::CancelResting => {
+                                self.remove(resting_id)?;
+                            }
+                            SelfTradePrevention::CancelAggressor => {
+                                fill.prevented += size;
+                                size = 0;
+                            }
+                            SelfTradePrevention::CancelBoth => {
+                                self.remove(resting_id)?;
+                                fill.prevented += size;
+                                size = 0;
+                            }
+                            SelfTradePrevention::Decrement => {
+                                let cut = self.decrement(resting_id, size)?;
+                                fill.prevented += cut;
+                                size -= cut;
+                            }
+                            SelfTradePrevention::Allow => unreachable!("let through above"),
+                        }
+                        continue;
+                    }
+
                     let traded = size.min(order.size);
                     order.size -= traded;
 
-                    (order.id, traded, order.size == 0)
+                    (order.id, order.owner, traded, order.size == 0, order.next)
                 };
 
                 // Now update size + price level again in fresh borrow
@@ -248,18 +597,34 @@ impl HalfBook {
                     };
 
                     level.total_size -= traded;
+                    level.displayed_size -= traded;
                 }
+                self.mark_dirty(tob);
 
+                let price = self.get_price_from_index(tob);
                 size -= traded;
-                notional += traded * self.get_price_from_index(tob);
-
-                if order_empty {
-                    self.remove_head_of_price_level(tob)?;
-                    self.ids.remove(&id);
-                    self.free_list.push(order_index);
-                    // self.emit(order.id, FILLED)
+                fill.size += traded;
+                fill.notional += traded * price;
+                fill.last_price = Some(price);
+
+                cursor = next;
+                let mut exec_type = ExecType::PartialFill;
+                if order_empty && self.refresh_in_place(tob, order_index)? {
+                    // it kept its place, so the aggressor meets it again
+                    cursor = Some(order_index);
+                } else if order_empty {
+                    self.unlink_from_level(tob, order_index)?;
+                    if self.replenish(tob, order_index)? {
+                        // the new clip went to the tail, which we may
+                        // already have walked past
+                        cursor = next.or(Some(order_index));
+                    } else {
+                        self.ids.remove(&id);
+                        self.free_list.push(order_index);
+                        exec_type = ExecType::Fill;
+                    }
                 }
-                // else self.emit(order.id, PARTIAL_FILL)
+                self.emit(id, maker, exec_type, traded, price);
             }
 
             // Fresh borrow again
@@ -269,70 +634,542 @@ impl HalfBook {
                 };
                 level.total_size == 0
             };
-
-            if empty {
-                self.top_of_book = self.find_next_best_level(tob);
-            }
+
+            if empty || size > 0 {
+                current = self.find_next_best_level(tob);
+                if empty && self.top_of_book == Some(tob) {
+                    self.top_of_book = current;
+                }
+            }
+        }
+
+        Ok(fill)
+    }
+
+    /// Total resting size at a price, zero for empty or unknown levels
+    pub fn size_at(&self, price: i64) -> i64 {
+        if price < self.min_price {
+            return 0;
+        }
+
+        self.orders
+            .get(self.calculate_price_index(price))
+            .map(|level| level.displayed_size)
+            .unwrap_or_default()
+    }
+
+    /// Give a resting order back `size` it traded, in place so it keeps
+    /// its queue position. Icebergs take it into their reserve once the
+    /// clip is full.
+    pub fn restore(&mut self, id: u64, size: i64) -> Result<()> {
+        if size <= 0 {
+            return Err("Invalid order".into());
+        }
+        let Some(order) = self.get_order(id) else {
+            return Err(format!("This order with id {} is not in our ids map!", id));
+        };
+        self.modify(id, order.price, order.size + size)
+    }
+
+    /// The price and remaining size of a resting order, including
+    /// whatever an iceberg still holds in reserve
+    pub fn get_order(&self, id: u64) -> Option<PriceSize> {
+        let arena_index = self.ids.get(&id)?;
+        self.arena.get(*arena_index).map(|order| PriceSize {
+            price: self.get_price_from_index(order.price_index),
+            size: order.size + order.reserve,
+        })
+    }
+
+    /// Walk the book trading at most `budget` of notional. Only whole
+    /// units trade, so each level takes budget / price rounded down and
+    /// any leftover too small to buy one more unit is left unspent.
+    pub fn match_notional_until(&mut self, budget: i64, limit_price: Option<i64>) -> Result<Fill> {
+        self.match_notional_as(budget, limit_price, 0, SelfTradePrevention::default())
+    }
+
+    /// Same as `match_notional_until` for an aggressor belonging to
+    /// `owner`. A budget can't be decremented in size, so any self-trade
+    /// that isn't just cancelling the resting order ends the sweep.
+    pub fn match_notional_as(
+        &mut self,
+        budget: i64,
+        limit_price: Option<i64>,
+        owner: u64,
+        stp: SelfTradePrevention,
+    ) -> Result<Fill> {
+        if budget <= 0 {
+            return Err("Invalid order".into());
+        }
+
+        let mut fill = Fill::default();
+
+        while let Some(top) = self.get_top_of_book() {
+            let size = ((budget - fill.notional) / top.price).min(top.size);
+            if size == 0 {
+                break;
+            }
+
+            if let Some(limit_price) = limit_price
+                && !self.is_within_limit(self.calculate_price_index(top.price), limit_price)
+            {
+                break;
+            }
+
+            // pinned to this level, orders stepped over for their minimum
+            // quantity must not let the budget spill into worse prices
+            let level_fill = self.match_size_as(size, Some(top.price), owner, stp)?;
+            fill.size += level_fill.size;
+            fill.notional += level_fill.notional;
+            fill.last_price = level_fill.last_price.or(fill.last_price);
+            fill.prevented += level_fill.prevented;
+
+            // cancelling our own resting orders can free up the level
+            let level_changed = self.get_top_of_book() != Some(top);
+            if level_fill.prevented > 0 || (level_fill.size == 0 && !level_changed) {
+                break;
+            }
+        }
+
+        Ok(fill)
+    }
+
+    pub fn get_total_liquidity(&self) -> i64 {
+        self.orders
+            .iter()
+            .fold(0, |acc, order| acc + order.displayed_size)
+    }
+
+    /// The best level's displayed size, which is what can trade against it
+    pub fn get_top_of_book(&self) -> Option<PriceSize> {
+        self.top_of_book.and_then(|tob| {
+            self.orders.get(tob).map(|order| PriceSize {
+                size: order.displayed_size,
+                price: self.get_price_from_index(tob),
+            })
+        })
+    }
+
+    /// The best level with both its displayed and total size
+    pub fn top_level_sizes(&self) -> Option<LevelSizes> {
+        self.level_sizes().next()
+    }
+
+    /// `levels` with the hidden size of each level alongside
+    pub fn level_sizes(&self) -> impl Iterator<Item = LevelSizes> + '_ {
+        std::iter::successors(self.top_of_book, |index| self.find_next_best_level(*index))
+            .map(|index| self.level_sizes_at(index))
+    }
+
+    fn level_sizes_at(&self, index: usize) -> LevelSizes {
+        let level = &self.orders[index];
+        LevelSizes {
+            price: self.get_price_from_index(index),
+            displayed_size: level.displayed_size,
+            total_size: level.total_size,
+            updated_at: level.updated_at,
+            updated_seq: level.updated_seq,
+        }
+    }
+
+    /// (id, size) of every order at the top of book in FIFO order
+    pub fn top_of_book_orders(&self) -> Vec<(u64, i64)> {
+        let mut orders = Vec::new();
+        let Some(level) = self.top_of_book.and_then(|tob| self.orders.get(tob)) else {
+            return orders;
+        };
+
+        let mut cursor = level.head;
+        while let Some(order) = cursor.and_then(|index| self.arena.get(index)) {
+            orders.push((order.id, order.size));
+            cursor = order.next;
+        }
+
+        orders
+    }
+
+    /// The orders resting at `price` in the order they will fill. Prices
+    /// off the ladder have no orders.
+    pub fn orders_at(&self, price: i64) -> impl Iterator<Item = OrderView> + '_ {
+        let head = if self.tick_table.is_valid_price(price) {
+            self.orders
+                .get(self.calculate_price_index(price))
+                .and_then(|level| level.head)
+        } else {
+            None
+        };
+
+        std::iter::successors(head.and_then(|index| self.arena.get(index)), |order| {
+            order.next.and_then(|index| self.arena.get(index))
+        })
+        .enumerate()
+        .map(move |(queue_position, order)| OrderView {
+            id: order.id,
+            price,
+            size: order.size,
+            queue_position,
+            entered_at: order.entered_at,
+            age: self.stamp.0.saturating_sub(order.entered_at),
+        })
+    }
+
+    /// Every populated level from the top of book outwards, jumping
+    /// straight from one to the next
+    pub fn levels(&self) -> impl Iterator<Item = PriceSize> + '_ {
+        std::iter::successors(self.top_of_book, |index| self.find_next_best_level(*index))
+            .filter_map(|index| {
+                self.orders.get(index).map(|level| PriceSize {
+                    price: self.get_price_from_index(index),
+                    size: level.displayed_size,
+                })
+            })
+    }
+
+    /// Up to `levels` populated levels from the top of book outwards,
+    /// counting only orders of at least `round_lot`. Levels holding
+    /// nothing but odd lots are skipped.
+    pub fn displayed_levels(&self, round_lot: i64, levels: usize) -> Vec<PriceSize> {
+        let mut displayed = Vec::new();
+        let mut cursor = self.top_of_book;
+
+        while let Some(index) = cursor
+            && displayed.len() < levels
+        {
+            let size = self.round_lots_at(index, round_lot);
+            if size > 0 {
+                displayed.push(PriceSize {
+                    price: self.get_price_from_index(index),
+                    size,
+                });
+            }
+            cursor = self.find_next_best_level(index);
+        }
+
+        displayed
+    }
+
+    /// What `displayed_levels` shows at `price`, 0 when it holds nothing
+    /// but odd lots
+    pub fn displayed_size_at(&self, price: i64, round_lot: i64) -> i64 {
+        if !self.tick_table.is_valid_price(price) {
+            return 0;
+        }
+        self.round_lots_at(self.calculate_price_index(price), round_lot)
+    }
+
+    fn round_lots_at(&self, index: usize, round_lot: i64) -> i64 {
+        let mut size = 0;
+        let mut cursor = self.orders.get(index).and_then(|level| level.head);
+        while let Some(order) = cursor.and_then(|order| self.arena.get(order)) {
+            if order.size >= round_lot {
+                size += order.size;
+            }
+            cursor = order.next;
+        }
+        size
+    }
+
+    /// Feed every populated level and its orders in FIFO order into the
+    /// digest. Walks the ladder rather than the ids map so the result
+    /// never depends on hash iteration order.
+    pub fn digest(&self, digest: &mut StateDigest) {
+        digest.write_option(self.top_of_book.map(|tob| tob as i64));
+
+        for (index, level) in self.orders.iter().enumerate() {
+            if level.head.is_none() {
+                continue;
+            }
+
+            digest.write_u64(index as u64);
+            digest.write_i64(level.total_size);
+            digest.write_i64(level.displayed_size);
+
+            let mut cursor = level.head;
+            while let Some(order) = cursor.and_then(|index| self.arena.get(index)) {
+                digest.write_u64(order.id);
+                digest.write_i64(order.size);
+                digest.write_i64(order.display_size);
+                digest.write_i64(order.reserve);
+                match order.refresh.clip {
+                    ClipSize::Fixed => digest.write_option(None),
+                    ClipSize::Random { min, max } => {
+                        digest.write_option(Some(min));
+                        digest.write_i64(max);
+                    }
+                }
+                digest.write_u64(order.refresh.priority as u64);
+                digest.write_i64(order.min_qty);
+                digest.write_u64(order.owner);
+                digest.write_u64(order.entered_at);
+                cursor = order.next;
+            }
+        }
+    }
+
+    /// The ladder's bounds and everything resting on it, leaving out the
+    /// empty levels and spare arena slots a fresh ladder has anyway
+    pub fn snapshot(&self) -> HalfSnapshot {
+        let levels: Vec<LevelSizes> = self.level_sizes().collect();
+        let orders = levels
+            .iter()
+            .flat_map(|level| {
+                let head = self
+                    .orders
+                    .get(self.calculate_price_index(level.price))
+                    .and_then(|level| level.head);
+                std::iter::successors(head.and_then(|index| self.arena.get(index)), |order| {
+                    order.next.and_then(|index| self.arena.get(index))
+                })
+                .map(|order| RestingOrder {
+                    id: order.id,
+                    side: self.side,
+                    price: level.price,
+                    size: order.size,
+                    display_size: order.display_size,
+                    reserve: order.reserve,
+                    refresh: order.refresh,
+                    min_qty: order.min_qty,
+                    owner: order.owner,
+                    entered_at: order.entered_at,
+                })
+            })
+            .collect();
+
+        HalfSnapshot {
+            side: self.side,
+            min_price: self.min_price,
+            max_price: self.max_price,
+            tick_table: self.tick_table.clone(),
+            levels,
+            orders,
+            reports: self.reports.clone(),
+            stamp: self.stamp,
+        }
+    }
+
+    /// Lay a snapshot's orders back onto a fresh ladder in the same queue
+    /// order, with each level as stale as it was
+    pub fn from_snapshot(snapshot: HalfSnapshot) -> Result<Self> {
+        let mut half =
+            Self::with_tick_table(snapshot.side, snapshot.max_price, snapshot.tick_table);
+        half.grow_ladder_down(snapshot.min_price)?;
+
+        for resting in snapshot.orders {
+            if resting.side != half.side {
+                return Err(format!("Order {} is on the wrong side", resting.id));
+            }
+            if half.ids.contains_key(&resting.id) {
+                return Err(format!("Order {} rests twice", resting.id));
+            }
+            if !half.tick_table.is_valid_price(resting.price) {
+                return Err(format!("Price {} is not on the ladder", resting.price));
+            }
+            if resting.size <= 0 || resting.reserve < 0 {
+                return Err(format!("Order {} has no size", resting.id));
+            }
+
+            let price_index = half.calculate_price_index(resting.price);
+            half.grow_ladder(resting.price, price_index)?;
+            let arena_index = half.allocate();
+            let Some(order) = half.arena.get_mut(arena_index) else {
+                return Err(format!("Arena access failed at {}", arena_index));
+            };
+            order.overwrite(resting.id, price_index, resting.size, None, None);
+            order.display_size = resting.display_size;
+            order.reserve = resting.reserve;
+            order.refresh = resting.refresh;
+            order.min_qty = resting.min_qty;
+            order.owner = resting.owner;
+            order.entered_at = resting.entered_at;
+
+            half.append_to_level(price_index, arena_index)?;
+            half.ids.insert(resting.id, arena_index);
+            half.improve_top_of_book(price_index);
+        }
+
+        for stamped in snapshot.levels {
+            let price_index = half.calculate_price_index(stamped.price);
+            if let Some(level) = half.orders.get_mut(price_index) {
+                level.updated_at = stamped.updated_at;
+                level.updated_seq = stamped.updated_seq;
+            }
+        }
+        half.dirty_levels.clear();
+        half.reports = snapshot.reports;
+        half.stamp = snapshot.stamp;
+        Ok(half)
+    }
+
+    /// Given the side and the current top of book,
+    /// scan for the nearest populated level
+    fn find_next_best_level(&self, mut tob: usize) -> Option<usize> {
+        if matches!(self.side, Side::Buy) {
+            // best bids are towards the end of array
+            // but we must look to the left for the next
+            // price level that has a size
+            if tob == 0 {
+                return None;
+            }
+
+            while tob > 0 {
+                tob -= 1;
+                if let Some(price_level) = self.orders.get(tob)
+                    && price_level.total_size != 0
+                {
+                    return Some(tob);
+                }
+            }
+
+            None
+        } else {
+            // best asks are towards the front of array
+            // but we must look to the right for the next
+            // price level that has a size
+            if tob == self.orders.len() {
+                return None;
+            }
+
+            while tob < self.orders.len() {
+                tob += 1;
+                if let Some(price_level) = self.orders.get(tob)
+                    && price_level.total_size != 0
+                {
+                    return Some(tob);
+                }
+            }
+
+            None
+        }
+    }
+
+    /// Extend the ladder up to `price_index` for an order priced above it.
+    /// Indexes count up from `min_price`, so every level and order
+    /// already resting keeps its index.
+    fn grow_ladder(&mut self, price: i64, price_index: usize) -> Result<()> {
+        if price_index < self.orders.len() {
+            return Ok(());
+        }
+        if price_index >= MAX_LADDER_LEN {
+            return Err(format!(
+                "Price {} is too far above {} to grow the ladder to",
+                price, self.max_price
+            ));
+        }
+
+        self.orders.resize_with(price_index + 1, Default::default);
+        self.max_price = price;
+        Ok(())
+    }
+
+    /// Extend the ladder down to a price below it. Orders and levels keep
+    /// their prices, their indexes all move up by the levels added.
+    pub fn grow_ladder_down(&mut self, price: i64) -> Result<()> {
+        if price >= self.min_price {
+            return Ok(());
+        }
+        let tick_size = self.tick_table.bands()[0].tick_size;
+        if ((self.min_price - price) / tick_size) as usize + self.orders.len() > MAX_LADDER_LEN {
+            return Err(format!(
+                "Price {} is too far below {} to grow the ladder to",
+                price, self.min_price
+            ));
+        }
+
+        let added = self.tick_table.extend_down(price)?;
+        self.orders.splice(
+            0..0,
+            std::iter::repeat_with(PriceLevel::default).take(added),
+        );
+        for order in self.arena.iter_mut() {
+            order.price_index += added;
+        }
+        for price_index in self.dirty_levels.iter_mut() {
+            *price_index += added;
+        }
+        self.top_of_book = self.top_of_book.map(|tob| tob + added);
+        self.min_price = price;
+        Ok(())
+    }
+
+    /// Link an order that is in no level yet onto the tail of one
+    fn append_to_level(&mut self, price_index: usize, arena_index: usize) -> Result<()> {
+        let Some(level) = self.orders.get_mut(price_index) else {
+            return Err(format!(
+                "Out of bounds on the price level somehow with {}",
+                price_index
+            ));
+        };
+        let Some(order) = self.arena.get_mut(arena_index) else {
+            return Err(format!(
+                "We tried to get from arena index {} but it was out of bounds!",
+                arena_index
+            ));
+        };
+
+        level.total_size += order.size + order.reserve;
+        level.displayed_size += order.size;
+
+        if level.head.is_none() {
+            level.head = Some(arena_index);
+        }
+
+        let old_tail = level.tail;
+        order.prev = old_tail;
+        order.next = None;
+        level.tail = Some(arena_index);
+
+        if let Some(tail_index) = old_tail {
+            let Some(prev_order) = self.arena.get_mut(tail_index) else {
+                return Err(format!(
+                    "The tail cant be gotten from the arena {}",
+                    tail_index
+                ));
+            };
+            prev_order.next = Some(arena_index);
         }
+        self.mark_dirty(price_index);
 
-        Ok(notional)
+        Ok(())
     }
 
-    pub fn get_total_liquidity(&self) -> i64 {
-        self.orders
-            .iter()
-            .fold(0, |acc, order| acc + order.total_size)
-    }
+    /// Show the next clip of an iceberg whose current one just traded
+    /// away, at the back of its level. False when nothing is left.
+    fn replenish(&mut self, price_index: usize, arena_index: usize) -> Result<bool> {
+        let Some(order) = self.arena.get_mut(arena_index) else {
+            return Err(format!("Arena access failed at {}", arena_index));
+        };
+        if order.reserve == 0 {
+            return Ok(false);
+        }
 
-    pub fn get_top_of_book(&self) -> Option<PriceSize> {
-        self.top_of_book.and_then(|tob| {
-            self.orders.get(tob).map(|order| PriceSize {
-                size: order.total_size,
-                price: self.get_price_from_index(tob),
-            })
-        })
+        let clip = next_clip(order);
+        order.reserve -= clip;
+        order.size = clip;
+        order.entered_at = self.stamp.0;
+        self.append_to_level(price_index, arena_index)?;
+        Ok(true)
     }
 
-    /// Given the side and the current top of book,
-    /// scan for the nearest populated level
-    fn find_next_best_level(&self, mut tob: usize) -> Option<usize> {
-        if matches!(self.side, Side::Buy) {
-            // best bids are towards the end of array
-            // but we must look to the left for the next
-            // price level that has a size
-            if tob == 0 {
-                return None;
-            }
-
-            while tob > 0 {
-                tob -= 1;
-                if let Some(price_level) = self.orders.get(tob) {
-                    if price_level.total_size != 0 {
-                        return Some(tob);
-                    }
-                }
-            }
-
-            return None;
-        } else {
-            // best asks are towards the front of array
-            // but we must look to the right for the next
-            // price level that has a size
-            if tob == self.orders.len() {
-                return None;
-            }
-
-            while tob < self.orders.len() {
-                tob += 1;
-                if let Some(price_level) = self.orders.get(tob) {
-                    if price_level.total_size != 0 {
-                        return Some(tob);
-                    }
-                }
-            }
-
-            return None;
+    /// Show the next clip of an iceberg that keeps its priority without
+    /// moving it. False when it goes to the back of the queue instead or
+    /// nothing is left.
+    fn refresh_in_place(&mut self, price_index: usize, arena_index: usize) -> Result<bool> {
+        let Some(order) = self.arena.get_mut(arena_index) else {
+            return Err(format!("Arena access failed at {}", arena_index));
+        };
+        if order.reserve == 0 || order.refresh.priority != RefreshPriority::Retain {
+            return Ok(false);
         }
+
+        let clip = next_clip(order);
+        order.reserve -= clip;
+        order.size = clip;
+        let Some(level) = self.orders.get_mut(price_index) else {
+            return Err(format!("Level missing at {}", price_index));
+        };
+        level.displayed_size += clip;
+        self.mark_dirty(price_index);
+        Ok(true)
     }
 
     /// Given an orders previous and next order pointers,
@@ -373,54 +1210,105 @@ impl HalfBook {
         Ok(())
     }
 
-    /// Given a price index, remove the head order
-    /// and keep the order chain up to date
-    fn remove_head_of_price_level(&mut self, index: usize) -> Result<()> {
+    /// Take an order out of its level's chain without freeing it,
+    /// keeping head, tail and the level size up to date
+    fn unlink_from_level(&mut self, index: usize, arena_index: usize) -> Result<()> {
         let Some(price_level) = self.orders.get_mut(index) else {
             return Err(format!(
                 "Failed to access the price level for this index {}",
                 index
             ));
         };
+        let Some(order) = self.arena.get_mut(arena_index) else {
+            return Err(format!("Arena access failed at {}", arena_index));
+        };
 
-        if let Some(head_arena_index) = price_level.head {
-            let Some(head_order) = self.arena.get_mut(head_arena_index) else {
-                return Err(format!(
-                    "Failed to access the price level for this index {}",
-                    index
-                ));
-            };
-            let prev = head_order.prev;
-            let next = head_order.next;
-            price_level.total_size -= head_order.size;
-
-            if let Some(tail) = price_level.tail {
-                if tail == head_arena_index {
-                    price_level.tail = None;
-                }
-            }
+        if price_level.head == Some(arena_index) {
+            price_level.head = order.next;
+        }
+        if price_level.tail == Some(arena_index) {
+            price_level.tail = order.prev;
+        }
+        price_level.total_size -= order.size + order.reserve;
+        price_level.displayed_size -= order.size;
 
-            price_level.head = head_order.next;
+        let prev = order.prev;
+        let next = order.next;
+        self.remove_order_from_linked_list(prev, next)
+    }
 
-            self.remove_order_from_linked_list(prev, next)?;
+    /// Resting bids can be hit down to the limit,
+    /// resting asks can be lifted up to the limit
+    fn is_within_limit(&self, index: usize, limit_price: i64) -> bool {
+        let price = self.get_price_from_index(index);
+        match self.side {
+            Side::Buy => price >= limit_price,
+            Side::Sell => price <= limit_price,
         }
+    }
 
+    /// Prices have to land exactly on a tick of their band, or on one
+    /// below the ladder, which grows down to it
+    pub fn validate_price(&self, price: i64) -> Result<()> {
+        if !self.tick_table.is_valid_price(price) && !self.tick_table.is_valid_below(price) {
+            return Err(format!("Price {} is not on a valid tick", price));
+        }
         Ok(())
     }
 
-    /// index = (price - min_price) / tick_size
+    /// index = ticks between min_price and price, walking the tick table.
+    /// Prices below the ladder map to an index that is never in bounds.
     fn calculate_price_index(&self, price: i64) -> usize {
-        ((price - self.min_price) / self.tick_size) as usize
+        self.tick_table.index_of(price).unwrap_or(usize::MAX)
     }
 
     fn get_price_from_index(&self, index: usize) -> i64 {
-        index as i64 + self.min_price
+        self.tick_table.price_of(index)
+    }
+}
+
+/// An iceberg has to show something, no more than it holds, and pick its
+/// clips from a range that is not empty
+pub fn validate_iceberg(display_size: i64, total_size: i64, refresh: IcebergRefresh) -> Result<()> {
+    if display_size <= 0 || display_size > total_size {
+        return Err(format!(
+            "Display size {} must be positive and at most the total {}",
+            display_size, total_size
+        ));
     }
+    if let ClipSize::Random { min, max } = refresh.clip
+        && (min <= 0 || min > max)
+    {
+        return Err(format!(
+            "Clip range {} to {} must be positive and not empty",
+            min, max
+        ));
+    }
+    Ok(())
+}
+
+/// Size of an iceberg's next clip, never more than it holds in reserve.
+/// Random sizes are derived from the order rather than drawn, so the
+/// same flow always shows the same clips.
+fn next_clip(order: &Order) -> i64 {
+    let clip = match order.refresh.clip {
+        ClipSize::Fixed => order.display_size,
+        ClipSize::Random { min, max } => {
+            // splitmix64's finalizer
+            let mut z = order.id ^ (order.reserve as u64).rotate_left(32);
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^= z >> 31;
+            min + (z % (max - min + 1) as u64) as i64
+        }
+    };
+    clip.min(order.reserve)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tick::TickBand;
 
     const MIN_PRICE: i64 = 1;
     const MAX_PRICE: i64 = 9;
@@ -448,7 +1336,7 @@ mod tests {
         let level = &book.orders[book.calculate_price_index(3)];
         assert!(level.head.is_some());
         assert_eq!(level.head, level.tail);
-        assert_eq!(book.ids.get(&1).is_some(), true);
+        assert!(book.ids.contains_key(&1));
     }
 
     #[test]
@@ -480,7 +1368,7 @@ mod tests {
     fn insert_out_of_bounds_price_fails() {
         let mut book = buy_book();
 
-        let result = book.insert(1, 999, 10);
+        let result = book.insert(1, i64::MAX, 10);
 
         assert!(result.is_err());
     }
@@ -536,7 +1424,7 @@ mod tests {
 
         let level = &book.orders[5];
         assert_ne!(level.head, Some(head_index));
-        assert!(book.ids.get(&head_id).is_none());
+        assert!(!book.ids.contains_key(&head_id));
     }
 
     #[test]
@@ -553,7 +1441,7 @@ mod tests {
 
         let level = &book.orders[book.calculate_price_index(price)];
         assert_ne!(level.tail, Some(tail_index));
-        assert!(book.ids.get(&tail_id).is_none());
+        assert!(!book.ids.contains_key(&tail_id));
     }
 
     #[test]
@@ -600,6 +1488,32 @@ mod tests {
         assert!(book.orders[book.calculate_price_index(2)].head.is_some());
     }
 
+    #[test]
+    fn get_order_reports_remaining_size() {
+        let mut book = sell_book();
+
+        book.insert(1, 4, 10).unwrap();
+        book.match_size(3).unwrap();
+
+        assert_eq!(book.get_order(1), Some(PriceSize { price: 4, size: 7 }));
+
+        book.match_size(7).unwrap();
+        assert_eq!(book.get_order(1), None);
+    }
+
+    #[test]
+    fn size_at_sums_the_level() {
+        let mut book = buy_book();
+
+        book.insert(1, 4, 10).unwrap();
+        book.insert(2, 4, 5).unwrap();
+
+        assert_eq!(book.size_at(4), 15);
+        assert_eq!(book.size_at(5), 0);
+        assert_eq!(book.size_at(0), 0);
+        assert_eq!(book.size_at(MAX_PRICE + 1), 0);
+    }
+
     #[test]
     fn modify_non_existent_order_fails() {
         let mut book = buy_book();
@@ -722,12 +1636,13 @@ mod tests {
         book.insert(3, 4, 20).unwrap(); // 20 @ 4
 
         // Market buy of size 12
-        let notional = book.match_size(12).unwrap();
+        let fill = book.match_size(12).unwrap();
 
         // Should consume:
         // 10 @ 2  = 20
         // 2  @ 3  = 6
-        assert_eq!(notional, 26);
+        assert_eq!(fill.notional, 26);
+        assert_eq!(fill.size, 12);
 
         // Remaining:
         // 3 @ 3
@@ -747,9 +1662,10 @@ mod tests {
 
         // Match 12 -> should fully consume id=1 (10)
         // and partially id=2 (2)
-        let notional = book.match_size(12).unwrap();
+        let fill = book.match_size(12).unwrap();
 
-        assert_eq!(notional, 12 * 5);
+        assert_eq!(fill.notional, 12 * 5);
+        assert_eq!(fill.size, 12);
 
         // Order 1 must be gone
         assert!(!book.ids.contains_key(&1));
@@ -770,9 +1686,10 @@ mod tests {
         book.insert(1, 2, 5).unwrap();
         book.insert(2, 3, 5).unwrap();
 
-        let notional = book.match_size(10).unwrap();
+        let fill = book.match_size(10).unwrap();
 
-        assert_eq!(notional, 5 * 2 + 5 * 3);
+        assert_eq!(fill.notional, 5 * 2 + 5 * 3);
+        assert_eq!(fill.size, 10);
 
         // Entire book empty
         assert!(book.top_of_book.is_none());
@@ -833,12 +1750,12 @@ mod tests {
         book.remove(2).unwrap();
 
         // Market buy 15
-        let notional = book.match_size(15).unwrap();
+        let fill = book.match_size(15).unwrap();
 
         // Should take:
         // 10 @ 2 = 20
         // 5  @ 4 = 20
-        assert_eq!(notional, 40);
+        assert_eq!(fill.notional, 40);
 
         // Only 5 left at price 4
         assert_eq!(book.top_of_book, Some(book.calculate_price_index(4)));
@@ -857,8 +1774,8 @@ mod tests {
         book.insert(1, 2, 10).unwrap();
 
         // Match less than available
-        let notional = book.match_size(5).unwrap();
-        assert_eq!(notional, 10);
+        let fill = book.match_size(5).unwrap();
+        assert_eq!(fill.notional, 10);
 
         // TOB should remain at price 2
         assert_eq!(book.top_of_book, Some(book.calculate_price_index(2)));
@@ -867,4 +1784,586 @@ mod tests {
         let order = &book.arena[*idx];
         assert_eq!(order.size, 5);
     }
+
+    // ------------------------------------------------------------
+    // 8. Exhausting the book reports only what actually filled
+    // ------------------------------------------------------------
+    #[test]
+    fn test_match_reports_filled_size_when_book_runs_dry() {
+        let mut book = sell_book();
+
+        book.insert(1, 2, 5).unwrap();
+        book.insert(2, 3, 5).unwrap();
+
+        let fill = book.match_size(25).unwrap();
+
+        assert_eq!(fill.size, 10);
+        assert_eq!(fill.notional, 5 * 2 + 5 * 3);
+        assert_eq!(fill.last_price, Some(3));
+        assert!(book.top_of_book.is_none());
+    }
+
+    // ------------------------------------------------------------
+    // 9. Limited match stops at the limit price
+    // ------------------------------------------------------------
+    #[test]
+    fn test_match_until_stops_at_limit_price() {
+        let mut book = sell_book();
+
+        book.insert(1, 2, 5).unwrap();
+        book.insert(2, 3, 5).unwrap();
+        book.insert(3, 4, 5).unwrap();
+
+        let fill = book.match_size_until(20, Some(3)).unwrap();
+
+        assert_eq!(fill.size, 10);
+        assert_eq!(fill.last_price, Some(3));
+        assert_eq!(book.top_of_book, Some(book.calculate_price_index(4)));
+
+        let mut book = buy_book();
+
+        book.insert(1, 8, 5).unwrap();
+        book.insert(2, 7, 5).unwrap();
+
+        let fill = book.match_size_until(20, Some(8)).unwrap();
+
+        assert_eq!(fill.size, 5);
+        assert_eq!(book.top_of_book, Some(book.calculate_price_index(7)));
+    }
+
+    #[test]
+    fn top_of_book_orders_are_in_fifo_order() {
+        let mut book = sell_book();
+
+        book.insert(1, 3, 10).unwrap();
+        book.insert(2, 2, 20).unwrap();
+        book.insert(3, 2, 30).unwrap();
+
+        assert_eq!(book.top_of_book_orders(), vec![(2, 20), (3, 30)]);
+
+        book.remove(2).unwrap();
+        book.remove(3).unwrap();
+        assert_eq!(book.top_of_book_orders(), vec![(1, 10)]);
+    }
+
+    // ------------------------------------------------------------
+    // 10. Tick tables drive indexing and validation
+    // ------------------------------------------------------------
+    #[test]
+    fn test_tick_table_ladder() {
+        let tick_table = TickTable::new(vec![
+            TickBand {
+                from_price: 1,
+                tick_size: 1,
+            },
+            TickBand {
+                from_price: 10,
+                tick_size: 5,
+            },
+        ])
+        .unwrap();
+        let mut book = HalfBook::with_tick_table(Side::Sell, 50, tick_table);
+
+        // 1..=9 then 10, 15, .., 50
+        assert_eq!(book.orders.len(), 9 + 9);
+
+        assert!(book.insert(1, 12, 10).is_err());
+        assert!(book.insert(1, 57, 10).is_err());
+
+        book.insert(1, 9, 10).unwrap();
+        book.insert(2, 15, 10).unwrap();
+        book.insert(3, 50, 10).unwrap();
+        assert!(book.modify(2, 17, 10).is_err());
+
+        let fill = book.match_size(25).unwrap();
+        assert_eq!(fill.notional, 10 * 9 + 10 * 15 + 5 * 50);
+        assert_eq!(book.get_order(3), Some(PriceSize { price: 50, size: 5 }));
+    }
+
+    // ------------------------------------------------------------
+    // 11. Notional budget rounds down to whole units
+    // ------------------------------------------------------------
+    #[test]
+    fn test_match_notional_spends_budget_level_by_level() {
+        let mut book = sell_book();
+
+        book.insert(1, 2, 5).unwrap();
+        book.insert(2, 3, 5).unwrap();
+        book.insert(3, 7, 5).unwrap();
+
+        // 5 @ 2 = 10, then 17 / 3 = 5 @ 3 = 15, then 2 left < 7
+        let fill = book.match_notional_until(27, None).unwrap();
+
+        assert_eq!(fill.size, 10);
+        assert_eq!(fill.notional, 25);
+        assert_eq!(fill.last_price, Some(3));
+        assert_eq!(book.top_of_book, Some(book.calculate_price_index(7)));
+
+        // 15 / 7 = 2 units, 1 unspent
+        let fill = book.match_notional_until(15, None).unwrap();
+        assert_eq!(fill.size, 2);
+        assert_eq!(fill.notional, 14);
+
+        // the limit stops us even with budget to spare
+        let fill = book.match_notional_until(100, Some(6)).unwrap();
+        assert_eq!(fill.size, 0);
+
+        assert!(book.match_notional_until(0, None).is_err());
+    }
+
+    // ------------------------------------------------------------
+    // 12. Odd lots are left out of displayed levels
+    // ------------------------------------------------------------
+    #[test]
+    fn test_displayed_levels_skip_odd_lots() {
+        let mut book = buy_book();
+
+        book.insert(1, 8, 50).unwrap();
+        book.insert(2, 7, 100).unwrap();
+        book.insert(3, 7, 250).unwrap();
+        book.insert(4, 7, 99).unwrap();
+        book.insert(5, 5, 100).unwrap();
+
+        assert_eq!(
+            book.displayed_levels(100, 5),
+            vec![
+                PriceSize {
+                    price: 7,
+                    size: 350
+                },
+                PriceSize {
+                    price: 5,
+                    size: 100
+                },
+            ]
+        );
+        assert_eq!(book.displayed_levels(100, 1).len(), 1);
+        assert_eq!(book.displayed_levels(1, 5)[0].price, 8);
+
+        // a partial fill can turn a round lot into an odd one
+        book.match_size(50 + 60).unwrap();
+        assert_eq!(book.displayed_levels(100, 1)[0].size, 250);
+    }
+
+    // ------------------------------------------------------------
+    // 13. Icebergs show one clip and requeue the next
+    // ------------------------------------------------------------
+    #[test]
+    fn test_iceberg_replenishes_at_the_back_of_the_level() {
+        let mut book = sell_book();
+
+        book.insert_iceberg(1, 5, 10, 25).unwrap();
+        book.insert(2, 5, 4).unwrap();
+        assert!(book.insert_iceberg(3, 5, 0, 10).is_err());
+        assert!(book.insert_iceberg(3, 5, 11, 10).is_err());
+
+        // only the clip is visible, the reserve still belongs to the order
+        assert_eq!(book.get_top_of_book().unwrap().size, 14);
+        assert_eq!(book.get_order(1).unwrap().size, 25);
+
+        // the first clip trades, the next one queues behind order 2
+        let fill = book.match_size(12).unwrap();
+        assert_eq!(fill.size, 12);
+        assert_eq!(book.top_of_book_orders(), vec![(2, 2), (1, 10)]);
+        assert_eq!(book.get_order(1).unwrap().size, 15);
+
+        // shrinking keeps priority and never shows more than a clip
+        book.modify(1, 5, 8).unwrap();
+        assert_eq!(book.top_of_book_orders(), vec![(2, 2), (1, 8)]);
+
+        let fill = book.match_size(20).unwrap();
+        assert_eq!(fill.size, 10);
+        assert!(book.get_order(1).is_none());
+        assert_eq!(book.top_of_book, None);
+    }
+
+    // ------------------------------------------------------------
+    // 14. Minimum quantity orders wait for a big enough aggressor
+    // ------------------------------------------------------------
+    #[test]
+    fn test_min_qty_orders_are_skipped_by_small_aggressors() {
+        let mut book = sell_book();
+
+        book.insert(1, 5, 10).unwrap();
+        book.set_min_qty(1, 10).unwrap();
+        book.insert(2, 5, 3).unwrap();
+        book.insert(3, 6, 4).unwrap();
+        assert!(book.set_min_qty(2, -1).is_err());
+        assert!(book.set_min_qty(9, 1).is_err());
+
+        // the all-or-none order is passed over, the rest fill in time order
+        let fill = book.match_size(5).unwrap();
+        assert_eq!(fill.size, 5);
+        assert_eq!(fill.notional, 3 * 5 + 2 * 6);
+        assert_eq!(book.get_order(1).unwrap().size, 10);
+        assert_eq!(book.get_order(2), None);
+        assert_eq!(book.top_of_book_orders(), vec![(1, 10)]);
+
+        // an aggressor large enough takes it whole
+        let fill = book.match_size(12).unwrap();
+        assert_eq!(fill.size, 12);
+        assert_eq!(book.get_order(1), None);
+        assert_eq!(book.top_of_book, None);
+    }
+
+    // ------------------------------------------------------------
+    // 15. Self-trade prevention never matches an owner with itself
+    // ------------------------------------------------------------
+    #[test]
+    fn test_self_trade_prevention_modes() {
+        let owned_book = || {
+            let mut book = sell_book();
+            book.insert(1, 5, 4).unwrap();
+            book.insert(2, 5, 6).unwrap();
+            book.insert(3, 6, 10).unwrap();
+            book.set_owner(2, 7).unwrap();
+            book
+        };
+
+        let mut book = owned_book();
+        let fill = book
+            .match_size_as(8, None, 7, SelfTradePrevention::CancelResting)
+            .unwrap();
+        assert_eq!((fill.size, fill.prevented), (8, 0));
+        assert_eq!(fill.notional, 4 * 5 + 4 * 6);
+        assert_eq!(book.get_order(2), None);
+
+        let mut book = owned_book();
+        let fill = book
+            .match_size_as(8, None, 7, SelfTradePrevention::CancelAggressor)
+            .unwrap();
+        assert_eq!((fill.size, fill.prevented), (4, 4));
+        assert_eq!(book.top_of_book_orders(), vec![(2, 6)]);
+
+        let mut book = owned_book();
+        let fill = book
+            .match_size_as(8, None, 7, SelfTradePrevention::CancelBoth)
+            .unwrap();
+        assert_eq!((fill.size, fill.prevented), (4, 4));
+        assert_eq!(book.get_order(2), None);
+        assert_eq!(book.get_top_of_book().unwrap().price, 6);
+
+        let mut book = owned_book();
+        let fill = book
+            .match_size_as(8, None, 7, SelfTradePrevention::Decrement)
+            .unwrap();
+        assert_eq!((fill.size, fill.prevented), (4, 4));
+        assert_eq!(book.top_of_book_orders(), vec![(2, 2)]);
+
+        // anonymous aggressors trade with anyone
+        let mut book = owned_book();
+        let fill = book
+            .match_size_as(8, None, 0, SelfTradePrevention::CancelAggressor)
+            .unwrap();
+        assert_eq!((fill.size, fill.prevented), (8, 0));
+    }
+
+    // ------------------------------------------------------------
+    // 16. Resting orders report every fill and cancel
+    // ------------------------------------------------------------
+    #[test]
+    fn test_execution_reports_for_resting_orders() {
+        let mut book = sell_book();
+        book.insert(1, 5, 4).unwrap();
+        book.insert(2, 5, 6).unwrap();
+        book.insert(3, 6, 10).unwrap();
+
+        book.match_size(7).unwrap();
+        // moving an order is not cancelling it
+        book.modify(3, 7, 10).unwrap();
+        book.remove(3).unwrap();
+
+        let report = |order_id, exec_type, traded_size, price| ExecutionReport {
+            order_id,
+            owner: 0,
+            exec_type,
+            traded_size,
+            price,
+            rebate: 0,
+        };
+        assert_eq!(
+            book.drain_reports(),
+            vec![
+                report(1, ExecType::Fill, 4, 5),
+                report(2, ExecType::PartialFill, 3, 5),
+                report(3, ExecType::Cancelled, 0, 7),
+            ]
+        );
+        assert!(book.drain_reports().is_empty());
+    }
+
+    // ------------------------------------------------------------
+    // 17. Levels walk outwards from the top of book
+    // ------------------------------------------------------------
+    #[test]
+    fn test_levels_iterate_from_the_top() {
+        let mut book = buy_book();
+        assert_eq!(book.levels().next(), None);
+
+        book.insert(1, 3, 5).unwrap();
+        book.insert(2, 8, 2).unwrap();
+        book.insert(3, 3, 4).unwrap();
+        book.insert(4, 1, 7).unwrap();
+
+        let levels: Vec<(i64, i64)> = book.levels().map(|l| (l.price, l.size)).collect();
+        assert_eq!(levels, vec![(8, 2), (3, 9), (1, 7)]);
+
+        let mut book = sell_book();
+        book.insert(1, 9, 1).unwrap();
+        book.insert(2, 2, 1).unwrap();
+        let prices: Vec<i64> = book.levels().map(|l| l.price).collect();
+        assert_eq!(prices, vec![2, 9]);
+    }
+
+    // ------------------------------------------------------------
+    // 18. Orders at a price come back in queue order
+    // ------------------------------------------------------------
+    #[test]
+    fn test_orders_at_walks_the_queue() {
+        let mut book = sell_book();
+        book.insert(1, 5, 4).unwrap();
+        book.insert(2, 5, 6).unwrap();
+        book.insert(3, 6, 1).unwrap();
+        book.insert(4, 5, 2).unwrap();
+        book.remove(2).unwrap();
+
+        let queue: Vec<(u64, i64, usize)> = book
+            .orders_at(5)
+            .map(|order| (order.id, order.size, order.queue_position))
+            .collect();
+        assert_eq!(queue, vec![(1, 4, 0), (4, 2, 1)]);
+        assert_eq!(book.orders_at(7).count(), 0);
+        assert_eq!(book.orders_at(-3).count(), 0);
+    }
+
+    // ------------------------------------------------------------
+    // 19. Every level that changed size is reported once per drain
+    // ------------------------------------------------------------
+    #[test]
+    fn test_level_updates_cover_inserts_matches_and_removes() {
+        let mut book = sell_book();
+        book.insert(1, 5, 4).unwrap();
+        book.insert(2, 6, 3).unwrap();
+        book.insert(3, 5, 2).unwrap();
+        let price_size = |price, size| PriceSize { price, size };
+        assert_eq!(
+            book.drain_level_updates(),
+            vec![price_size(5, 6), price_size(6, 3)]
+        );
+        assert!(book.drain_level_updates().is_empty());
+
+        book.match_size(7).unwrap();
+        assert_eq!(
+            book.drain_level_updates(),
+            vec![price_size(5, 0), price_size(6, 2)]
+        );
+
+        book.modify(2, 6, 5).unwrap();
+        book.remove(2).unwrap();
+        assert_eq!(book.drain_level_updates(), vec![price_size(6, 0)]);
+    }
+
+    // ------------------------------------------------------------
+    // 20. Orders above the ladder grow it in place
+    // ------------------------------------------------------------
+    #[test]
+    fn test_ladder_grows_for_orders_above_it() {
+        let mut book = buy_book();
+        book.insert(1, 5, 4).unwrap();
+        book.insert(2, 9, 3).unwrap();
+        let index_of_five = book.calculate_price_index(5);
+
+        book.insert(3, 20, 2).unwrap();
+        assert_eq!(book.max_price, 20);
+        assert_eq!(book.calculate_price_index(5), index_of_five);
+        assert_eq!(book.get_order(1), Some(PriceSize { price: 5, size: 4 }));
+        assert_eq!(
+            book.get_top_of_book(),
+            Some(PriceSize { price: 20, size: 2 })
+        );
+
+        let fill = book.match_size(6).unwrap();
+        assert_eq!(fill.notional, 2 * 20 + 3 * 9 + 5);
+        assert_eq!(
+            book.get_top_of_book(),
+            Some(PriceSize { price: 5, size: 3 })
+        );
+
+        let mut book = sell_book();
+        book.insert(1, 12, 1).unwrap();
+        book.insert(2, 3, 1).unwrap();
+        let prices: Vec<i64> = book.levels().map(|level| level.price).collect();
+        assert_eq!(prices, vec![3, 12]);
+    }
+
+    // ------------------------------------------------------------
+    // 21. A requeued order keeps everything but its place and id
+    // ------------------------------------------------------------
+    #[test]
+    fn test_requeue_keeps_clip_min_qty_and_owner() {
+        let mut book = buy_book();
+        book.insert_iceberg(1, 5, 4, 10).unwrap();
+        book.set_min_qty(1, 6).unwrap();
+        book.set_owner(1, 7).unwrap();
+
+        book.requeue(1, 2, 6, 12).unwrap();
+        assert_eq!(book.get_order(1), None);
+        assert_eq!(book.get_order(2), Some(PriceSize { price: 6, size: 12 }));
+        assert_eq!(book.get_owner(2), Some(7));
+        let order = &book.arena[book.ids[&2]];
+        assert_eq!((order.size, order.reserve, order.min_qty), (4, 8, 6));
+        assert_eq!(book.drain_reports()[0].exec_type, ExecType::Cancelled);
+
+        // moving it in place keeps them too
+        book.modify(2, 4, 3).unwrap();
+        let order = &book.arena[book.ids[&2]];
+        assert_eq!((order.size, order.reserve, order.min_qty), (3, 0, 3));
+        assert_eq!(book.get_owner(2), Some(7));
+    }
+
+    // ------------------------------------------------------------
+    // 22. Orders below the ladder grow it downwards
+    // ------------------------------------------------------------
+    #[test]
+    fn test_ladder_grows_for_orders_below_it() {
+        let mut book = HalfBook::new(Side::Buy, 20, 10, 2);
+        book.insert(1, 12, 4).unwrap();
+        book.insert(2, 16, 3).unwrap();
+        assert!(book.insert(3, 7, 1).is_err());
+
+        book.insert(3, 4, 2).unwrap();
+        assert_eq!(book.min_price, 4);
+        assert_eq!(book.calculate_price_index(4), 0);
+        assert_eq!(book.get_order(1), Some(PriceSize { price: 12, size: 4 }));
+        assert_eq!(
+            book.get_top_of_book(),
+            Some(PriceSize { price: 16, size: 3 })
+        );
+        assert_eq!(
+            book.drain_level_updates(),
+            vec![
+                PriceSize { price: 12, size: 4 },
+                PriceSize { price: 16, size: 3 },
+                PriceSize { price: 4, size: 2 },
+            ]
+        );
+
+        let fill = book.match_size(8).unwrap();
+        assert_eq!(fill.notional, 3 * 16 + 4 * 12 + 4);
+        assert_eq!(
+            book.get_top_of_book(),
+            Some(PriceSize { price: 4, size: 1 })
+        );
+    }
+
+    // ------------------------------------------------------------
+    // 23. Iceberg clips can keep their place or vary in size
+    // ------------------------------------------------------------
+    #[test]
+    fn test_iceberg_refresh_policies() {
+        let retain = IcebergRefresh {
+            clip: ClipSize::Fixed,
+            priority: RefreshPriority::Retain,
+        };
+        let mut book = sell_book();
+        book.insert_iceberg_with(1, 5, 10, 25, retain).unwrap();
+        book.insert(2, 5, 4).unwrap();
+
+        // the next clip shows where the last one was and keeps trading
+        let fill = book.match_size(12).unwrap();
+        assert_eq!(fill.size, 12);
+        assert_eq!(book.top_of_book_orders(), vec![(1, 8), (2, 4)]);
+        assert_eq!(book.get_order(1).unwrap().size, 13);
+        assert_eq!(book.get_top_of_book().unwrap().size, 12);
+        let reports = book.drain_reports();
+        assert_eq!(reports.len(), 2);
+        assert!(
+            reports
+                .iter()
+                .all(|report| report.exec_type == ExecType::PartialFill)
+        );
+
+        // a requeued order keeps its policy
+        book.requeue(1, 3, 5, 13).unwrap();
+        book.match_size(4 + 10).unwrap();
+        assert_eq!(book.top_of_book_orders(), vec![(3, 3)]);
+
+        let random = |min, max| IcebergRefresh {
+            clip: ClipSize::Random { min, max },
+            priority: RefreshPriority::BackOfQueue,
+        };
+        assert!(book.insert_iceberg_with(4, 6, 5, 40, random(0, 8)).is_err());
+        assert!(book.insert_iceberg_with(4, 6, 5, 40, random(9, 8)).is_err());
+
+        // every clip after the first lands in the range, the same on
+        // every run
+        let clips = |book: &mut HalfBook| {
+            book.insert_iceberg_with(4, 6, 5, 40, random(2, 8)).unwrap();
+            let mut clips = Vec::new();
+            while let Some(top) = book.get_top_of_book() {
+                clips.push(top.size);
+                book.match_size(top.size).unwrap();
+            }
+            clips
+        };
+        let mut book = sell_book();
+        let shown = clips(&mut book);
+        assert_eq!(shown[0], 5);
+        assert_eq!(shown.iter().sum::<i64>(), 40);
+        assert!(
+            shown[1..shown.len() - 1]
+                .iter()
+                .all(|clip| (2..=8).contains(clip))
+        );
+        assert!(shown.windows(2).any(|pair| pair[0] != pair[1]));
+        assert_eq!(clips(&mut sell_book()), shown);
+    }
+
+    // ------------------------------------------------------------
+    // 24. Levels keep displayed and total size apart
+    // ------------------------------------------------------------
+    #[test]
+    fn test_levels_track_displayed_and_total_size() {
+        let mut book = sell_book();
+        book.insert_iceberg(1, 5, 10, 25).unwrap();
+        book.insert(2, 5, 4).unwrap();
+        book.insert_iceberg(3, 6, 2, 5).unwrap();
+
+        let sizes = |book: &HalfBook| {
+            book.level_sizes()
+                .map(|level| (level.price, level.displayed_size, level.total_size))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sizes(&book), vec![(5, 14, 29), (6, 2, 5)]);
+        assert_eq!(book.get_top_of_book().unwrap().size, 14);
+
+        // a refresh moves size from hidden to shown, the total only
+        // drops by what traded
+        book.match_size(12).unwrap();
+        assert_eq!(sizes(&book), vec![(5, 12, 17), (6, 2, 5)]);
+
+        // shrinking and self-trade decrements come off the reserve first
+        book.modify(1, 5, 12).unwrap();
+        assert_eq!(sizes(&book), vec![(5, 12, 14), (6, 2, 5)]);
+        book.set_owner(3, 9).unwrap();
+        book.match_size_as(14, None, 9, SelfTradePrevention::Decrement)
+            .unwrap();
+        assert_eq!(
+            book.top_level_sizes(),
+            Some(LevelSizes {
+                price: 6,
+                displayed_size: 2,
+                total_size: 5,
+                updated_at: 0,
+                updated_seq: 0,
+            })
+        );
+        book.match_size_as(2, None, 9, SelfTradePrevention::Decrement)
+            .unwrap();
+        assert_eq!(sizes(&book), vec![(6, 2, 3)]);
+
+        book.remove(3).unwrap();
+        assert_eq!(book.top_level_sizes(), None);
+        assert_eq!(book.orders[book.calculate_price_index(6)].total_size, 0);
+    }
 }
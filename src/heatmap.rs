@@ -0,0 +1,186 @@
+use std::io::Write;
+
+use crate::{Result, Side, book::Orderbook, tick::TickTable};
+
+/// the widest price grid a heatmap will allocate
+const MAX_PRICES: usize = 100_000;
+
+/// Samples resting size over a fixed price grid so liquidity can be
+/// plotted as a heatmap over time. Samples are taken on the book's clock,
+/// see `Orderbook::set_clock`, at most one per interval.
+#[derive(Debug, Default)]
+pub struct DepthHeatmap {
+    /// book time between samples
+    pub interval: u64,
+    prices: Vec<i64>,
+    timestamps: Vec<u64>,
+    /// one row of `prices.len()` sizes per sample
+    bid_sizes: Vec<i64>,
+    ask_sizes: Vec<i64>,
+}
+
+impl DepthHeatmap {
+    /// Every valid tick between `min_price` and `max_price` inclusive,
+    /// sampled every `interval`. A range of more than `MAX_PRICES` ticks
+    /// is rejected.
+    pub fn new(
+        tick_table: &TickTable,
+        min_price: i64,
+        max_price: i64,
+        interval: u64,
+    ) -> Result<Self> {
+        if interval == 0 {
+            return Err("Sampling interval must be positive".into());
+        }
+
+        let mut prices = Vec::new();
+        if let Some(mut index) = tick_table.index_of(min_price.max(tick_table.min_price())) {
+            if tick_table.price_of(index) < min_price {
+                index += 1;
+            }
+
+            loop {
+                let price = tick_table.price_of(index);
+                if price > max_price {
+                    break;
+                }
+                if prices.len() == MAX_PRICES {
+                    return Err(format!(
+                        "Heatmap from {} to {} is wider than {} ticks",
+                        min_price, max_price, MAX_PRICES
+                    ));
+                }
+                prices.push(price);
+                index += 1;
+            }
+        }
+
+        Ok(Self {
+            interval,
+            prices,
+            ..Default::default()
+        })
+    }
+
+    /// Record the book as of its clock, unless the last sample is less
+    /// than an interval old. Returns whether a sample was taken.
+    pub fn sample(&mut self, book: &Orderbook) -> bool {
+        if let Some(last) = self.timestamps.last()
+            && book.clock < last + self.interval
+        {
+            return false;
+        }
+
+        self.timestamps.push(book.clock);
+        for price in &self.prices {
+            self.bid_sizes.push(book.size_at(Side::Buy, *price));
+            self.ask_sizes.push(book.size_at(Side::Sell, *price));
+        }
+        true
+    }
+
+    pub fn prices(&self) -> &[i64] {
+        &self.prices
+    }
+
+    pub fn timestamps(&self) -> &[u64] {
+        &self.timestamps
+    }
+
+    /// (bid sizes, ask sizes) across the grid for one sample
+    pub fn row(&self, sample: usize) -> (&[i64], &[i64]) {
+        let start = sample * self.prices.len();
+        let end = start + self.prices.len();
+        (&self.bid_sizes[start..end], &self.ask_sizes[start..end])
+    }
+
+    /// Long-format CSV, one line per non-empty cell
+    pub fn write_csv(&self, out: &mut impl Write) -> std::io::Result<()> {
+        writeln!(out, "timestamp,price,bid_size,ask_size")?;
+        for (sample, timestamp) in self.timestamps.iter().enumerate() {
+            let (bids, asks) = self.row(sample);
+            for ((price, bid), ask) in self.prices.iter().zip(bids).zip(asks) {
+                if *bid != 0 || *ask != 0 {
+                    writeln!(out, "{},{},{},{}", timestamp, price, bid, ask)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OrderTicket, OrderType, TimeInForce, tick::TickBand};
+
+    fn limit(side: Side, price: i64, size: i64) -> OrderTicket {
+        OrderTicket {
+            side,
+            size,
+            order_type: OrderType::Limit(price),
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        }
+    }
+
+    #[test]
+    fn grid_follows_the_tick_table() {
+        let tick_table = TickTable::new(vec![
+            TickBand {
+                from_price: 1,
+                tick_size: 1,
+            },
+            TickBand {
+                from_price: 10,
+                tick_size: 5,
+            },
+        ])
+        .unwrap();
+
+        let heatmap = DepthHeatmap::new(&tick_table, 8, 22, 1).unwrap();
+        assert_eq!(heatmap.prices(), &[8, 9, 10, 15, 20]);
+
+        let heatmap = DepthHeatmap::new(&tick_table, 11, 16, 1).unwrap();
+        assert_eq!(heatmap.prices(), &[15]);
+
+        assert!(DepthHeatmap::new(&tick_table, 1, i64::MAX, 1).is_err());
+        assert!(DepthHeatmap::new(&tick_table, 8, 22, 0).is_err());
+    }
+
+    #[test]
+    fn samples_and_exports_liquidity_over_time() {
+        let mut book = Orderbook::new();
+        let mut heatmap = DepthHeatmap::new(&book.bids.tick_table, 99, 102, 1_000).unwrap();
+
+        book.accept_order(limit(Side::Buy, 100, 10)).unwrap();
+        book.accept_order(limit(Side::Sell, 102, 7)).unwrap();
+        book.set_clock(1_000);
+        assert!(heatmap.sample(&book));
+
+        book.accept_order(limit(Side::Buy, 99, 3)).unwrap();
+        // not an interval on yet
+        book.set_clock(1_999);
+        assert!(!heatmap.sample(&book));
+        book.set_clock(2_000);
+        assert!(heatmap.sample(&book));
+
+        assert_eq!(heatmap.timestamps(), &[1_000, 2_000]);
+        assert_eq!(heatmap.row(0), (&[0, 10, 0, 0][..], &[0, 0, 0, 7][..]));
+        assert_eq!(heatmap.row(1), (&[3, 10, 0, 0][..], &[0, 0, 0, 7][..]));
+
+        let mut csv = Vec::new();
+        heatmap.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "timestamp,price,bid_size,ask_size\n\
+             1000,100,10,0\n\
+             1000,102,0,7\n\
+             2000,99,3,0\n\
+             2000,100,10,0\n\
+             2000,102,0,7\n"
+        );
+    }
+}
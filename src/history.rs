@@ -0,0 +1,219 @@
+use crate::{Event, EventKind, PriceSize, Result, Side, book::Orderbook, snapshot::BookSnapshot};
+
+/// Answers what the book looked like at any point of a recorded event
+/// stream. The stream is replayed once up front, keeping a snapshot every
+/// `interval` events, so a query only replays forward from the nearest
+/// checkpoint before it.
+#[derive(Debug)]
+pub struct HistoricalBook {
+    /// events between checkpoints
+    pub interval: u64,
+    first_seq: u64,
+    events: Vec<EventKind>,
+    /// (seq, book just before that event), oldest first
+    checkpoints: Vec<(u64, BookSnapshot)>,
+    /// (seq, time) of every clock change, in order
+    clock_changes: Vec<(u64, u64)>,
+}
+
+impl HistoricalBook {
+    /// Index `events`, recorded from `base`, which is the book as it was
+    /// set up when recording started. The events have to follow each
+    /// other without a gap.
+    pub fn new(mut base: Orderbook, events: Vec<Event>, interval: u64) -> Result<Self> {
+        if interval == 0 {
+            return Err("Checkpoint interval must be positive".into());
+        }
+
+        let first_seq = events.first().map(|event| event.seq).unwrap_or_default();
+        let mut checkpoints = Vec::new();
+        let mut clock_changes = Vec::new();
+        let mut kinds = Vec::with_capacity(events.len());
+        for (offset, event) in events.into_iter().enumerate() {
+            let expected = first_seq + offset as u64;
+            if event.seq != expected {
+                return Err(format!(
+                    "Expected event {} but got event {}",
+                    expected, event.seq
+                ));
+            }
+            if (offset as u64).is_multiple_of(interval) {
+                checkpoints.push((event.seq, checkpoint(&mut base)));
+            }
+            if let EventKind::SetClock(now) = event.kind {
+                clock_changes.push((event.seq, now));
+            }
+            base.apply_event(event.kind.clone());
+            kinds.push(event.kind);
+        }
+        if checkpoints.is_empty() {
+            checkpoints.push((first_seq, checkpoint(&mut base)));
+        }
+
+        Ok(Self {
+            interval,
+            first_seq,
+            events: kinds,
+            checkpoints,
+            clock_changes,
+        })
+    }
+
+    /// One past the last recorded event
+    pub fn end_seq(&self) -> u64 {
+        self.first_seq + self.events.len() as u64
+    }
+
+    /// The book just before event `seq`, `end_seq()` for the book after
+    /// the last one
+    pub fn book_at_seq(&self, seq: u64) -> Result<Orderbook> {
+        if seq < self.first_seq || seq > self.end_seq() {
+            return Err(format!(
+                "Event {} is outside the recording, {} to {}",
+                seq,
+                self.first_seq,
+                self.end_seq()
+            ));
+        }
+
+        let nearest = self
+            .checkpoints
+            .partition_point(|(checkpoint_seq, _)| *checkpoint_seq <= seq)
+            - 1;
+        let (from, snapshot) = &self.checkpoints[nearest];
+        let mut book = Orderbook::from_snapshot(snapshot.clone())?;
+        let start = (from - self.first_seq) as usize;
+        let end = (seq - self.first_seq) as usize;
+        for event in self.events[start..end].iter() {
+            book.apply_event(event.clone());
+        }
+        Ok(book)
+    }
+
+    /// The first event after the clock moved past `time`, so the book
+    /// just before it is the book as of `time`
+    pub fn seq_at_time(&self, time: u64) -> u64 {
+        self.clock_changes
+            .iter()
+            .find(|(_, now)| *now > time)
+            .map(|(seq, _)| *seq)
+            .unwrap_or_else(|| self.end_seq())
+    }
+
+    pub fn book_at_time(&self, time: u64) -> Result<Orderbook> {
+        self.book_at_seq(self.seq_at_time(time))
+    }
+
+    /// Best bid and ask just before event `seq`
+    pub fn bbo_at_seq(&self, seq: u64) -> Result<(Option<PriceSize>, Option<PriceSize>)> {
+        let book = self.book_at_seq(seq)?;
+        Ok((book.get_best_bid(), book.get_best_ask()))
+    }
+
+    pub fn bbo_at_time(&self, time: u64) -> Result<(Option<PriceSize>, Option<PriceSize>)> {
+        self.bbo_at_seq(self.seq_at_time(time))
+    }
+
+    /// Bids then asks, `levels` deep, just before event `seq`
+    pub fn depth_at_seq(
+        &self,
+        seq: u64,
+        levels: usize,
+    ) -> Result<(Vec<PriceSize>, Vec<PriceSize>)> {
+        let book = self.book_at_seq(seq)?;
+        Ok((
+            book.depth(Side::Buy, levels),
+            book.depth(Side::Sell, levels),
+        ))
+    }
+
+    pub fn depth_at_time(
+        &self,
+        time: u64,
+        levels: usize,
+    ) -> Result<(Vec<PriceSize>, Vec<PriceSize>)> {
+        self.depth_at_seq(self.seq_at_time(time), levels)
+    }
+}
+
+/// Snapshot the book without the feeds and log it has built up, which
+/// every checkpoint would otherwise copy again
+fn checkpoint(book: &mut Orderbook) -> BookSnapshot {
+    book.drain_events();
+    book.discard_feeds();
+    book.snapshot()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OrderTicket, OrderType, TimeInForce};
+
+    fn ticket(side: Side, order_type: OrderType, size: i64) -> OrderTicket {
+        OrderTicket {
+            order_type,
+            size,
+            side,
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        }
+    }
+
+    fn recorded() -> Orderbook {
+        let mut book = Orderbook::new();
+        for (now, price) in [(10, 100), (20, 101), (30, 102), (40, 99)] {
+            book.set_clock(now);
+            book.accept_order(ticket(Side::Sell, OrderType::Limit(price + 5), 3))
+                .unwrap();
+            book.accept_order(ticket(Side::Buy, OrderType::Limit(price), 2))
+                .unwrap();
+        }
+        book.accept_order(ticket(Side::Buy, OrderType::Market, 4))
+            .unwrap();
+        let _ = book.cancel_order(1);
+        book
+    }
+
+    #[test]
+    fn matches_rewinding_at_every_seq() {
+        let live = recorded();
+        let history = HistoricalBook::new(Orderbook::new(), live.event_log.clone(), 3).unwrap();
+        assert_eq!(history.end_seq(), 14);
+
+        for seq in 0..=history.end_seq() {
+            assert_eq!(
+                history.book_at_seq(seq).unwrap().state_digest(),
+                live.rewind_to(seq).unwrap().state_digest()
+            );
+        }
+        assert!(history.book_at_seq(15).is_err());
+    }
+
+    #[test]
+    fn answers_by_time() {
+        let history = HistoricalBook::new(Orderbook::new(), recorded().event_log, 4).unwrap();
+
+        // up to 20 the second quote is in, at 25 still nothing new
+        assert_eq!(history.seq_at_time(20), 6);
+        assert_eq!(history.seq_at_time(25), 6);
+        let (bid, ask) = history.bbo_at_time(20).unwrap();
+        assert_eq!(bid.unwrap().price, 101);
+        assert_eq!(ask.unwrap().price, 105);
+
+        // by the end the market order took 104 and some of 105, and the
+        // bid at 100 was cancelled
+        let (bids, asks) = history.depth_at_time(40, 10).unwrap();
+        let prices: Vec<i64> = bids.iter().map(|level| level.price).collect();
+        assert_eq!(prices, vec![102, 101, 99]);
+        assert_eq!(
+            asks[0],
+            PriceSize {
+                price: 105,
+                size: 2
+            }
+        );
+        assert_eq!(history.bbo_at_time(5).unwrap(), (None, None));
+    }
+}
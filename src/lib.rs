@@ -1,38 +1,645 @@
+pub mod backtest;
 pub mod book;
+pub mod chain;
+pub mod command_log;
+pub mod conflation;
+pub mod delta;
+pub mod depth;
+pub mod diagnostics;
+pub mod digest;
+pub mod engine;
+pub mod exchange;
+pub mod golden;
 pub mod half;
+pub mod heatmap;
+pub mod history;
+pub mod lifecycle;
+pub mod midpoint;
+pub mod perp;
+pub mod quote_cache;
+pub mod registry;
+pub mod replication;
+pub mod retail;
+pub mod rfq;
+pub mod risk;
+pub mod scale;
+pub mod scenario;
+pub mod settlement;
+pub mod shadow;
+pub mod snapshot;
+pub mod stats;
+pub mod stop;
+pub mod surveillance;
+pub mod tick;
+pub mod view;
 
 pub type Error = String;
 pub type Result<T> = std::result::Result<T, Error>;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct PriceSize {
     pub price: i64,
     pub size: i64,
 }
 
-#[derive(Debug, Clone)]
+/// what came out of walking one side of the book
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Fill {
+    /// how much size actually traded
+    pub size: i64,
+    /// sum of price * size over every match
+    pub notional: i64,
+    /// price of the final match, if anything traded
+    pub last_price: Option<i64>,
+    /// aggressor size cancelled or decremented by self-trade prevention
+    pub prevented: i64,
+}
+
+/// limit orders priced further than `bps` basis points
+/// away from the reference price are rejected
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PriceBand {
+    pub bps: i64,
+    /// how the band opens up once trading resumes after a halt
+    pub after_halt: Option<BandWidening>,
+}
+
+/// right after a halt the band is `multiplier` times as wide, narrowing
+/// back linearly over `decay` of the book's clock
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BandWidening {
+    pub multiplier: i64,
+    pub decay: u64,
+}
+
+/// limit-up/limit-down: trades may only print within `bps`
+/// basis points of the reference price
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PriceLimits {
+    pub bps: i64,
+}
+
+/// an aggressive order may not print more than `max_move` away from the
+/// last trade in a single sweep
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PriceMoveGuard {
+    pub max_move: i64,
+    pub action: PriceMoveAction,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PriceMoveAction {
+    /// pause trading like a LULD breach
+    #[default]
+    Halt,
+    /// stop the sweep and drop whatever is left of the order
+    RejectRemainder,
+}
+
+/// aggressive orders wait `delay` of the book's clock before they match,
+/// plus up to `jitter` more picked per order from `seed`, so resting
+/// quotes get the chance to move first
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpeedBump {
+    pub delay: u64,
+    pub jitter: u64,
+    pub seed: u64,
+}
+
+/// an aggressive order waiting out the speed bump
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DelayedOrder {
+    /// handed back when it was accepted
+    pub id: u64,
+    pub ticket: OrderTicket,
+    /// the book's clock from which it goes on to match
+    pub release_at: u64,
+}
+
+/// what a delayed order came to once it was let through
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReleasedOrder {
+    pub delayed_id: u64,
+    /// the id it traded or rested under, or why the book as it was by
+    /// then refused it
+    pub result: Result<u64>,
+    /// the book's clock when it was let through
+    pub timestamp: u64,
+}
+
+/// resting orders can't be cancelled until they have been in the queue
+/// for `min_time` of the book's clock, against quote flicker
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MinRestingTime {
+    pub min_time: u64,
+    pub action: EarlyCancelAction,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EarlyCancelAction {
+    /// refuse the cancel, the order stays as it is
+    #[default]
+    Reject,
+    /// hold on to the cancel and carry it out once the clock gets there,
+    /// reporting it like any other cancel
+    Queue,
+}
+
+/// market maker protection for one owner, see `Orderbook::set_mmp`.
+/// Once their resting orders trade more than `max_fills` times or more
+/// than `max_volume` within `window` of the book's clock, all of them are
+/// pulled. None leaves that one unchecked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MmpLimits {
+    pub window: u64,
+    pub max_fills: Option<usize>,
+    pub max_volume: Option<i64>,
+}
+
+/// A change in whether an instrument is trading, announced so consumers
+/// don't have to infer it from trades stopping
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MarketStatus {
+    /// a price limit was breached and the book stopped taking orders
+    Halted,
+    /// back to continuous matching after a halt
+    Resumed,
+    /// the instrument's session opened or closed, see
+    /// `registry::SessionTimes`
+    SessionOpen,
+    SessionClose,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StatusEvent {
+    pub status: MarketStatus,
+    /// the book's clock when it changed
+    pub timestamp: u64,
+}
+
+/// market maker protection tripping on an owner
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MmpTrigger {
+    pub owner: u64,
+    /// fills and volume within the window that tripped it
+    pub fills: usize,
+    pub volume: i64,
+    /// every resting order of theirs that was pulled
+    pub cancelled: Vec<u64>,
+    /// the book's clock when it tripped
+    pub timestamp: u64,
+}
+
+/// caps on what one owner can have on the book, see
+/// `Orderbook::set_owner_limits`. None leaves that one uncapped.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OwnerLimits {
+    /// resting orders and waiting stops, both sides together
+    pub max_open_orders: Option<usize>,
+    /// price times size of everything resting on one side, hidden size
+    /// included
+    pub max_resting_notional: Option<i64>,
+    /// net position either way, counting an order as filled in full
+    pub max_net_position: Option<i64>,
+}
+
+/// the owner limit an order would have gone over
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LimitBreach {
+    OpenOrders {
+        limit: usize,
+    },
+    /// resting in full would take the side's notional to `notional`
+    RestingNotional {
+        side: Side,
+        notional: i64,
+        limit: i64,
+    },
+    /// filling in full would take the net position to `position`
+    NetPosition {
+        position: i64,
+        limit: i64,
+    },
+}
+
+impl std::fmt::Display for LimitBreach {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitBreach::OpenOrders { limit } => {
+                write!(f, "Owner already has the limit of {} orders open", limit)
+            }
+            LimitBreach::RestingNotional {
+                side,
+                notional,
+                limit,
+            } => write!(
+                f,
+                "{:?} notional would reach {} against a limit of {}",
+                side, notional, limit
+            ),
+            LimitBreach::NetPosition { position, limit } => write!(
+                f,
+                "Position would reach {} against a limit of {}",
+                position, limit
+            ),
+        }
+    }
+}
+
+/// an order refused for going over its owner's limits
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LimitReject {
+    pub owner: u64,
+    pub breach: LimitBreach,
+    /// the book's clock when it was refused
+    pub timestamp: u64,
+}
+
+/// what a limit priced exactly at the opposite best does
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LockedPolicy {
+    /// trade against it like any other crossing limit
+    #[default]
+    Match,
+    /// rest and leave the book locked, bid equal to ask
+    RestAndLock,
+    /// refuse the order
+    Reject,
+}
+
+/// how much of the hidden liquidity market data shows, matching always
+/// trades against all of it
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MarketDataMode {
+    /// only what is on show, the way a venue disseminates it
+    #[default]
+    VisibleOnly,
+    /// every resting order in full, iceberg reserves and odd lots included
+    Full,
+}
+
+/// what a market order does once it has cleared the best level
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MarketPolicy {
+    /// keep walking the book until filled or it runs dry
+    #[default]
+    Sweep,
+    /// rest the remainder as a limit at the price it traded at
+    ToLimit,
+}
+
+/// what happens when an aggressor meets a resting order with the same
+/// owner. Owner 0 is anonymous and never counts as a self-trade.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SelfTradePrevention {
+    /// pull the resting order and keep matching behind it
+    #[default]
+    CancelResting,
+    /// stop matching and drop whatever is left of the aggressor
+    CancelAggressor,
+    /// pull the resting order and drop the rest of the aggressor
+    CancelBoth,
+    /// take the smaller size off both without a trade
+    Decrement,
+    /// let it trade like any other, e.g. in simulation, leaving it to
+    /// `surveillance::WashTradeMonitor` to flag
+    Allow,
+}
+
+/// settings fixed when a book is created
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookConfig {
+    pub market_policy: MarketPolicy,
+    pub self_trade_prevention: SelfTradePrevention,
+    /// price range of the ladder to start with, it grows either way on demand
+    pub min_price: i64,
+    pub max_price: i64,
+    /// every price is `min_price` plus a whole number of ticks
+    pub tick_size: i64,
+    /// every size is a whole number of lots
+    pub lot_size: i64,
+    /// the instrument, only carried along for whoever runs the book
+    pub symbol: String,
+}
+
+impl Default for BookConfig {
+    fn default() -> Self {
+        Self {
+            market_policy: MarketPolicy::default(),
+            self_trade_prevention: SelfTradePrevention::default(),
+            min_price: 1,
+            max_price: 999_999,
+            tick_size: 1,
+            lot_size: 1,
+            symbol: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SessionState {
+    #[default]
+    Continuous,
+    /// a price limit was breached and no orders are accepted
+    Paused,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OrderType {
     Market,
     Limit(i64),
+    /// a market order whose size is a quote budget to spend (buys)
+    /// or to raise (sells) rather than a base size
+    QuoteMarket,
+    /// trades whatever crosses up to its price, the rest is cancelled
+    /// instead of resting
+    ImmediateOrCancel(i64),
+    /// a market order held back until the last trade reaches `trigger`
+    Stop {
+        trigger: i64,
+    },
+    /// rests `offset` behind a reference price and follows it around
+    Pegged {
+        side_ref: PegReference,
+        offset: i64,
+    },
+}
+
+/// what a pegged order whose price falls off the ladder or outside the
+/// price band does
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PegBreachAction {
+    /// stay at the last good price, or be refused on entry
+    #[default]
+    Hold,
+    /// come off the book unpriced and go back on once the price is good
+    Park,
+    /// rest at the nearest good price towards the reference instead
+    Clamp,
+    /// get cancelled with a `PegReject` saying why, or refused on entry
+    Cancel,
+}
+
+/// a pegged order off the book until its price is good again, see
+/// `PegBreachAction::Park`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParkedPeg {
+    pub peg: PeggedOrder,
+    pub size: i64,
+    pub owner: u64,
+}
+
+/// a resting pegged order cancelled for breaching, see
+/// `PegBreachAction::Cancel`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PegReject {
+    pub id: u64,
+    pub owner: u64,
+    /// where its reference would have put it
+    pub price: i64,
+    pub reason: String,
+    /// the book's clock when it was cancelled
+    pub timestamp: u64,
+}
+
+/// What a pegged order follows. Other pegged orders never count towards
+/// the reference, so pegs can't end up chasing each other.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PegReference {
+    /// the best price on the order's own side
+    Primary,
+    /// the best price on the opposite side
+    Market,
+}
+
+/// A resting order the book reprices as its reference moves
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PeggedOrder {
+    pub id: u64,
+    pub side: Side,
+    pub side_ref: PegReference,
+    pub offset: i64,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Side {
     Buy,
     Sell,
 }
 
-#[derive(Debug, Clone)]
+impl Side {
+    /// the side an aggressor on this side trades against
+    pub fn opposite(&self) -> Side {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrderTicket {
     pub order_type: OrderType,
     pub size: i64,
     pub side: Side,
+    /// how long whatever rests may stay on the book
+    pub time_in_force: TimeInForce,
+    /// once resting, only aggressors with at least this much left can
+    /// trade against it. All-or-none is `Some(size)`.
+    pub min_qty: Option<i64>,
+    /// participant the order belongs to, 0 for anonymous
+    pub owner: u64,
+    /// rest a limit order as an iceberg, showing only part of it at a time
+    pub iceberg: Option<Iceberg>,
+}
+
+/// how much of an iceberg is on show and how it shows the rest
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Iceberg {
+    /// the first clip, and every clip after it unless `refresh` picks
+    /// their sizes
+    pub display_size: i64,
+    pub refresh: IcebergRefresh,
+}
+
+/// Timestamps are the caller's clock, the same one passed to
+/// `Orderbook::expire`
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TimeInForce {
+    /// good till cancelled
+    #[default]
+    Gtc,
+    /// good for the trading day, see `Orderbook::set_day_end`
+    Day,
+    /// good till date, expires once the clock reaches it
+    Gtd(u64),
+}
+
+/// One input that changed the book. Settings like bands and limits are
+/// part of how the book was built and are not logged.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EventKind {
+    Order(OrderTicket),
+    Cancel(u64),
+    Replace {
+        id: u64,
+        price: i64,
+        size: i64,
+    },
+    MassQuote {
+        owner: u64,
+        cancel: Vec<u64>,
+        levels: Vec<QuoteLevel>,
+    },
+    Expire(u64),
+    ResumeTrading,
+    SetClock(u64),
+    SetDayEnd(Option<u64>),
+    /// take back a trade by its id, see `Orderbook::bust_trade`
+    Bust(u64),
+}
+
+/// an entry in `Orderbook::event_log`, numbered from zero
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Event {
+    pub seq: u64,
+    pub kind: EventKind,
+}
+
+/// one resting order in an L3 view
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrderView {
+    pub id: u64,
+    pub price: i64,
+    /// the visible size, just the current clip for icebergs
+    pub size: i64,
+    /// orders ahead of it at its price, zero at the front
+    pub queue_position: usize,
+    /// the book's clock when it took its place in the queue
+    pub entered_at: u64,
+    /// how long it has held that place as of the book's clock
+    pub age: u64,
+}
+
+/// every resting order on both sides, best price first and FIFO within
+/// each price
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct L3Book {
+    pub bids: Vec<OrderView>,
+    pub asks: Vec<OrderView>,
+}
+
+/// one price level of a mass quote, always posted as a resting order
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QuoteLevel {
+    pub side: Side,
+    pub price: i64,
+    pub size: i64,
+}
+
+/// how big an iceberg's next clip is
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ClipSize {
+    /// always the display size it was entered with
+    #[default]
+    Fixed,
+    /// anywhere from `min` to `max` inclusive, picked afresh for each clip
+    Random { min: i64, max: i64 },
+}
+
+/// where an iceberg's next clip goes in the queue
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RefreshPriority {
+    /// behind everything already at the level, like a new order
+    #[default]
+    BackOfQueue,
+    /// right where the clip that traded away was
+    Retain,
+}
+
+/// what an iceberg does once its current clip has traded away
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IcebergRefresh {
+    pub clip: ClipSize,
+    pub priority: RefreshPriority,
 }
 
-#[derive(Default, Debug)]
+/// A resting order as a snapshot keeps it, without its place in the
+/// arena so it can be laid back onto a fresh ladder
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RestingOrder {
+    pub id: u64,
+    pub side: Side,
+    pub price: i64,
+    /// the visible, matchable part
+    pub size: i64,
+    /// clip shown at a time for icebergs, zero for plain orders
+    pub display_size: i64,
+    pub reserve: i64,
+    pub refresh: IcebergRefresh,
+    pub min_qty: i64,
+    pub owner: u64,
+    pub entered_at: u64,
+}
+
+#[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Order {
     pub id: u64,
     pub price_index: usize,
+    /// the visible, matchable part
     pub size: i64,
+    /// clip shown at a time for icebergs, zero for plain orders
+    pub display_size: i64,
+    /// hidden size an iceberg replenishes from
+    pub reserve: i64,
+    /// how an iceberg shows its next clip
+    pub refresh: IcebergRefresh,
+    /// smallest aggressor allowed to trade with it, zero for anyone
+    pub min_qty: i64,
+    /// participant it belongs to, zero for anonymous
+    pub owner: u64,
+    /// the book's clock when it joined the back of its level
+    pub entered_at: u64,
 
     pub prev: Option<usize>,
     pub next: Option<usize>,
@@ -52,6 +659,7 @@ impl Order {
             size,
             prev,
             next,
+            ..Self::default()
         }
     }
 
@@ -66,33 +674,156 @@ impl Order {
         self.id = id;
         self.price_index = price_index;
         self.size = size;
+        self.display_size = 0;
+        self.reserve = 0;
+        self.refresh = IcebergRefresh::default();
+        self.min_qty = 0;
+        self.owner = 0;
         self.prev = prev;
         self.next = next;
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PriceLevel {
     pub head: Option<usize>,
     pub tail: Option<usize>,
+    /// everything resting here, iceberg reserves included
+    pub total_size: i64,
+    /// just the part on show, which is also all that can trade right now
+    pub displayed_size: i64,
+    /// the book's clock when this level last changed
+    pub updated_at: u64,
+    /// the event that last changed it
+    pub updated_seq: u64,
+}
+
+/// one price level seen both ways, see `Orderbook::depth_sizes`
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LevelSizes {
+    pub price: i64,
+    pub displayed_size: i64,
+    /// the displayed size plus whatever is hidden behind it
     pub total_size: i64,
+    /// the book's clock and event seq of the last change, to tell how
+    /// stale a level is
+    pub updated_at: u64,
+    pub updated_seq: u64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum OrderResponse {
     Market(MarketOrderResponse),
     Limit(LimitOrderResponse),
 }
 
 /// tell the caller how much they bought and at what price
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct MarketOrderResponse {
+    /// the aggressor's id, as named on its trades
+    pub id: u64,
     pub notional: i64,
+    /// the size that was filled, not the size that was asked for
     pub size: i64,
+    /// whatever could not be filled because the book ran dry,
+    /// for quote market orders this is the unspent quote budget.
+    /// Size dropped by self-trade prevention is not included.
+    pub remaining: i64,
+    /// set when the remaining size was converted into a resting limit,
+    /// see `MarketPolicy::ToLimit`
+    pub resting_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExecType {
+    /// traded and some of it is still resting
+    PartialFill,
+    /// traded and nothing is left
+    Fill,
+    /// came off the book without trading
+    Cancelled,
+}
+
+/// tells the owner of a resting order what just happened to it
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExecutionReport {
+    pub order_id: u64,
+    /// whoever placed the order, 0 when nobody said
+    pub owner: u64,
+    pub exec_type: ExecType,
+    /// zero for cancels
+    pub traded_size: i64,
+    pub price: i64,
+    /// paid to the maker for this fill under the book's fee schedule,
+    /// zero for cancels and takers
+    pub rebate: i64,
+}
+
+/// one match between an aggressor and a resting order
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Trade {
+    pub trade_id: u64,
+    pub price: i64,
+    pub size: i64,
+    pub aggressor_side: Side,
+    pub maker_order_id: u64,
+    pub taker_order_id: u64,
+    /// owners of either order, 0 for anonymous
+    pub maker_owner: u64,
+    pub taker_owner: u64,
+    /// the book's clock when it happened, see `Orderbook::set_clock`
+    pub timestamp: u64,
+}
+
+/// a trade taken back with `Orderbook::bust_trade`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TradeBust {
+    pub trade: Trade,
+    /// the maker order that got its size back, None when it had already
+    /// left the book and the restore is only recorded here
+    pub restored_order_id: Option<u64>,
+    /// the book's clock when it was busted
+    pub timestamp: u64,
+}
+
+/// the new total at one price level, see `Orderbook::drain_level_updates`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LevelUpdate {
+    pub side: Side,
+    pub price: i64,
+    /// as `Orderbook::published_depth` shows it, 0 once the level is empty
+    /// or holds nothing but odd lots
+    pub new_total_size: i64,
 }
 
 /// tell the user their id so they can cancel or replace
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct LimitOrderResponse {
     pub id: u64,
 }
+
+/// the id to use from now on, which only changes when the replace
+/// cost the order its place in the queue
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplaceResponse {
+    pub id: u64,
+    pub requeued: bool,
+}
+
+/// what came off the book, so the caller can reconcile
+#[derive(Debug, Clone, PartialEq)]
+pub struct CancelResponse {
+    pub id: u64,
+    pub side: Side,
+    /// 0 for a parked peg, which has no price
+    pub price: i64,
+    /// the size still resting at the time of the cancel
+    pub size: i64,
+}
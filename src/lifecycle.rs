@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+/// Something that happened to a resting order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderUpdate {
+    /// came to rest, what was left of it after trading on entry
+    Rested {
+        price: i64,
+        size: i64,
+    },
+    /// shrunk in place by a replace, keeping its queue position
+    Amended {
+        price: i64,
+        size: i64,
+    },
+    /// replaced by a requeued order under `new_id`, whose history starts
+    /// with this order's
+    Replaced {
+        new_id: u64,
+        price: i64,
+        size: i64,
+    },
+    /// a peg following its reference
+    Repriced {
+        price: i64,
+    },
+    PartialFill {
+        price: i64,
+        size: i64,
+    },
+    Filled {
+        price: i64,
+        size: i64,
+    },
+    /// a busted trade gave it `size` back
+    Restored {
+        size: i64,
+    },
+    /// a peg taken off the book until its price is good again
+    Parked,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistoryEntry {
+    /// the book's clock when it happened
+    pub timestamp: u64,
+    pub update: OrderUpdate,
+}
+
+/// Everything that happened to every order that rested since it was
+/// enabled, see `Orderbook::enable_order_history`, so a gateway can
+/// answer an order status request with the whole lifecycle. Orders are
+/// kept until forgotten.
+#[derive(Debug, Default)]
+pub struct OrderHistory {
+    orders: HashMap<u64, Vec<HistoryEntry>>,
+}
+
+impl OrderHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, id: u64, timestamp: u64, update: OrderUpdate) {
+        let entries = self.orders.entry(id).or_default();
+        entries.push(HistoryEntry { timestamp, update });
+        if let OrderUpdate::Replaced { new_id, .. } = update {
+            let carried = entries.clone();
+            self.orders.insert(new_id, carried);
+        }
+    }
+
+    /// Oldest first, None for an order it never saw
+    pub fn get(&self, id: u64) -> Option<&[HistoryEntry]> {
+        self.orders.get(&id).map(|entries| entries.as_slice())
+    }
+
+    /// Drop an order's history, e.g. once it is done and reported
+    pub fn forget(&mut self, id: u64) {
+        self.orders.remove(&id);
+    }
+}
@@ -1,20 +1,53 @@
 use orderbook::Result;
 
+mod repl;
+
 fn main() -> Result<()> {
-    println!("Hello, world!");
+    println!("Orderbook REPL, type help for commands");
 
-    Ok(())
+    repl::run(std::io::stdin().lock(), std::io::stdout())
 }
 
 #[cfg(test)]
 mod tests {
-    use orderbook::{OrderResponse, OrderTicket, OrderType, Side, book::Orderbook};
+    use std::sync::Arc;
+
+    use orderbook::{
+        BandWidening, BookConfig, CancelResponse, ClipSize, EarlyCancelAction, EventKind, ExecType,
+        ExecutionReport, Iceberg, IcebergRefresh, L3Book, LevelUpdate, LimitBreach, LockedPolicy,
+        MarketDataMode, MarketOrderResponse, MarketPolicy, MarketStatus, MinRestingTime, MmpLimits,
+        OrderResponse, OrderTicket, OrderType, OrderView, OwnerLimits, PegBreachAction,
+        PegReference, PriceBand, PriceLimits, PriceMoveAction, PriceMoveGuard, PriceSize,
+        QuoteLevel, RefreshPriority, ReplaceResponse, RestingOrder, SelfTradePrevention,
+        SessionState, Side, SpeedBump, StatusEvent, TimeInForce, Trade,
+        book::Orderbook,
+        lifecycle::OrderUpdate,
+        quote_cache::{Quote, QuoteCache},
+        scale::SizeScale,
+        settlement::{FeeSchedule, TradeFees},
+        stats::{LevelChurn, QuoteLife},
+        tick::{TickBand, TickTable},
+    };
 
     fn limit(side: Side, price: i64, size: i64) -> OrderTicket {
         OrderTicket {
             side,
             size,
             order_type: OrderType::Limit(price),
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        }
+    }
+
+    fn iceberg(side: Side, price: i64, display_size: i64, size: i64) -> OrderTicket {
+        OrderTicket {
+            iceberg: Some(Iceberg {
+                display_size,
+                refresh: IcebergRefresh::default(),
+            }),
+            ..limit(side, price, size)
         }
     }
 
@@ -23,6 +56,10 @@ mod tests {
             side,
             size,
             order_type: OrderType::Market,
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
         }
     }
 
@@ -100,6 +137,27 @@ mod tests {
         assert_eq!(best_ask.size, 5);
     }
 
+    #[test]
+    fn test_market_order_reports_unfilled_remainder() {
+        let mut ob = Orderbook::new();
+
+        ob.accept_order(limit(Side::Sell, 100, 10)).unwrap();
+        ob.accept_order(limit(Side::Sell, 101, 5)).unwrap();
+
+        let response = ob.accept_order(market(Side::Buy, 40)).unwrap();
+
+        match response {
+            OrderResponse::Market(m) => {
+                assert_eq!(m.size, 15);
+                assert_eq!(m.remaining, 25);
+                assert_eq!(m.notional, 10 * 100 + 5 * 101);
+            }
+            _ => panic!("Expected market response"),
+        }
+
+        assert!(ob.get_best_ask().is_none());
+    }
+
     #[test]
     fn test_crossing_limit_becomes_taker() {
         let mut ob = Orderbook::new();
@@ -119,6 +177,31 @@ mod tests {
 
         let best_ask = ob.get_best_ask().unwrap();
         assert_eq!(best_ask.size, 5);
+
+        // never trades through its limit, the rest of it stays there until it expires
+        ob.accept_order(limit(Side::Sell, 110, 10)).unwrap();
+        let OrderResponse::Market(m) = ob
+            .accept_order(OrderTicket {
+                time_in_force: TimeInForce::Gtd(1_000),
+                ..limit(Side::Buy, 105, 8)
+            })
+            .unwrap()
+        else {
+            panic!("Crossing limit should execute as market");
+        };
+        assert_eq!((m.size, m.notional, m.remaining), (5, 5 * 100, 3));
+        let resting_id = m.resting_id.unwrap();
+        assert_eq!(
+            ob.get_best_bid(),
+            Some(PriceSize {
+                price: 105,
+                size: 3
+            })
+        );
+        assert_eq!(ob.get_best_ask().unwrap().price, 110);
+
+        assert_eq!(ob.expire(1_000)[0].id, resting_id);
+        assert_eq!(ob.get_best_bid(), None);
     }
 
     #[test]
@@ -171,4 +254,2013 @@ mod tests {
         assert!(total_bid >= 0);
         assert!(total_ask >= 0);
     }
+
+    #[test]
+    fn test_price_band_uses_mid_then_last_trade() {
+        let mut ob = Orderbook::new();
+        ob.set_price_band(Some(PriceBand {
+            bps: 500,
+            after_halt: None,
+        }));
+
+        // no reference yet, anything goes
+        ob.accept_order(limit(Side::Buy, 98, 10)).unwrap();
+        ob.accept_order(limit(Side::Sell, 102, 10)).unwrap();
+        assert_eq!(ob.reference_price(), Some(100));
+
+        // 5% around a mid of 100
+        assert!(ob.accept_order(limit(Side::Buy, 95, 1)).is_ok());
+        assert!(ob.accept_order(limit(Side::Buy, 94, 1)).is_err());
+        assert!(ob.accept_order(limit(Side::Sell, 106, 1)).is_err());
+
+        // trade at 102 moves the reference
+        ob.accept_order(market(Side::Buy, 1)).unwrap();
+        assert_eq!(ob.last_trade_price, Some(102));
+        assert!(ob.accept_order(limit(Side::Sell, 107, 1)).is_ok());
+        assert!(ob.accept_order(limit(Side::Buy, 96, 1)).is_err());
+
+        // market orders are never banded
+        assert!(ob.accept_order(market(Side::Sell, 1)).is_ok());
+    }
+
+    #[test]
+    fn test_price_band_widens_after_a_halt() {
+        let mut ob = Orderbook::new();
+        ob.accept_order(limit(Side::Buy, 99, 10)).unwrap();
+        ob.accept_order(limit(Side::Sell, 100, 10)).unwrap();
+        ob.accept_order(limit(Side::Sell, 120, 10)).unwrap();
+        ob.set_price_limits(Some(PriceLimits { bps: 500 }));
+        ob.set_price_band(Some(PriceBand {
+            bps: 500,
+            after_halt: Some(BandWidening {
+                multiplier: 3,
+                decay: 100,
+            }),
+        }));
+        assert_eq!(ob.price_band_bps(), Some(500));
+
+        ob.accept_order(market(Side::Buy, 20)).unwrap();
+        assert_eq!(ob.session_state, SessionState::Paused);
+        assert_eq!(ob.last_trade_price, Some(100));
+
+        // 15% around 100 straight after resuming
+        ob.set_clock(1_000);
+        ob.resume_trading();
+        assert!(ob.accept_order(limit(Side::Sell, 115, 1)).is_ok());
+        assert!(ob.accept_order(limit(Side::Sell, 116, 1)).is_err());
+
+        // halfway back
+        ob.set_clock(1_050);
+        assert_eq!(ob.price_band_bps(), Some(1_000));
+        assert!(ob.accept_order(limit(Side::Sell, 110, 1)).is_ok());
+        assert!(ob.accept_order(limit(Side::Sell, 111, 1)).is_err());
+
+        ob.set_clock(1_100);
+        assert_eq!(ob.price_band_bps(), Some(500));
+        assert!(ob.accept_order(limit(Side::Sell, 106, 1)).is_err());
+    }
+
+    #[test]
+    fn test_price_limits_stop_sweep_and_pause() {
+        let mut ob = Orderbook::new();
+
+        ob.accept_order(limit(Side::Buy, 99, 10)).unwrap();
+        ob.accept_order(limit(Side::Sell, 101, 10)).unwrap();
+        ob.accept_order(limit(Side::Sell, 104, 10)).unwrap();
+        ob.accept_order(limit(Side::Sell, 110, 10)).unwrap();
+
+        // 5% around a mid of 100
+        ob.set_price_limits(Some(PriceLimits { bps: 500 }));
+        assert_eq!(ob.price_limit_band(), Some((95, 105)));
+
+        let response = ob.accept_order(market(Side::Buy, 30)).unwrap();
+
+        match response {
+            OrderResponse::Market(m) => {
+                assert_eq!(m.size, 20);
+                assert_eq!(m.remaining, 10);
+                assert_eq!(m.notional, 10 * 101 + 10 * 104);
+            }
+            _ => panic!("Expected market response"),
+        }
+
+        // nothing printed at 110 and the session is paused
+        assert_eq!(ob.last_trade_price, Some(104));
+        assert_eq!(ob.session_state, SessionState::Paused);
+        assert!(ob.accept_order(limit(Side::Buy, 100, 1)).is_err());
+
+        ob.set_clock(3);
+        ob.resume_trading();
+        assert!(ob.accept_order(limit(Side::Buy, 100, 1)).is_ok());
+        // resuming while already trading announces nothing
+        ob.resume_trading();
+        assert_eq!(
+            ob.drain_status_events(),
+            vec![
+                StatusEvent {
+                    status: MarketStatus::Halted,
+                    timestamp: 0
+                },
+                StatusEvent {
+                    status: MarketStatus::Resumed,
+                    timestamp: 3
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_price_limits_do_not_pause_when_book_runs_dry() {
+        let mut ob = Orderbook::new();
+
+        ob.accept_order(limit(Side::Buy, 99, 10)).unwrap();
+        ob.accept_order(limit(Side::Sell, 101, 10)).unwrap();
+        ob.set_price_limits(Some(PriceLimits { bps: 500 }));
+
+        ob.accept_order(market(Side::Buy, 30)).unwrap();
+
+        assert_eq!(ob.session_state, SessionState::Continuous);
+    }
+
+    #[test]
+    fn test_price_move_guard_rejects_remainder_or_halts() {
+        let mut ob = Orderbook::new();
+
+        ob.accept_order(limit(Side::Sell, 100, 1)).unwrap();
+        ob.accept_order(limit(Side::Sell, 101, 10)).unwrap();
+        ob.accept_order(limit(Side::Sell, 104, 10)).unwrap();
+        ob.accept_order(market(Side::Buy, 1)).unwrap();
+        assert_eq!(ob.last_trade_price, Some(100));
+
+        ob.set_price_move_guard(Some(PriceMoveGuard {
+            max_move: 2,
+            action: PriceMoveAction::RejectRemainder,
+        }));
+        match ob.accept_order(market(Side::Buy, 25)).unwrap() {
+            OrderResponse::Market(m) => {
+                assert_eq!(m.size, 10);
+                assert_eq!(m.remaining, 15);
+            }
+            _ => panic!("Expected market response"),
+        }
+        assert_eq!(ob.session_state, SessionState::Continuous);
+        assert_eq!(ob.last_trade_price, Some(101));
+
+        // 104 is more than 2 away from 101
+        ob.set_price_move_guard(Some(PriceMoveGuard {
+            max_move: 2,
+            action: PriceMoveAction::Halt,
+        }));
+        ob.accept_order(market(Side::Buy, 5)).unwrap();
+        assert_eq!(ob.session_state, SessionState::Paused);
+        assert_eq!(ob.size_at(Side::Sell, 104), 10);
+    }
+
+    #[test]
+    fn test_crossed_book_detector_reports_offending_orders() {
+        let mut ob = Orderbook::new();
+        ob.enable_crossed_book_detector(8);
+
+        ob.accept_order(limit(Side::Buy, 99, 10)).unwrap();
+        ob.accept_order(limit(Side::Sell, 101, 10)).unwrap();
+        ob.accept_order(limit(Side::Buy, 97, 1)).unwrap();
+        ob.cancel_order(2).unwrap();
+        assert!(
+            ob.crossed_book_detector
+                .as_ref()
+                .unwrap()
+                .reports()
+                .is_empty()
+        );
+
+        // a corrupt snapshot brings back a bid that crosses the book
+        let mut snapshot = ob.snapshot();
+        snapshot.bids.orders.insert(
+            0,
+            RestingOrder {
+                id: 1_000,
+                side: Side::Buy,
+                price: 102,
+                size: 5,
+                display_size: 0,
+                reserve: 0,
+                refresh: IcebergRefresh::default(),
+                min_qty: 0,
+                owner: 0,
+                entered_at: 0,
+            },
+        );
+        let mut ob = Orderbook::from_snapshot(snapshot).unwrap();
+        ob.enable_crossed_book_detector(8);
+        ob.cancel_order(0).unwrap();
+        ob.accept_order(limit(Side::Buy, 98, 1)).unwrap();
+
+        let reports = ob.crossed_book_detector.as_ref().unwrap().reports();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[1].best_bid.price, 102);
+        assert_eq!(reports[1].bid_orders, vec![(1_000, 5)]);
+        assert_eq!(reports[1].ask_orders, vec![(1, 10)]);
+        assert_eq!(reports[1].recent_events.len(), 2);
+        assert_eq!(reports[1].recent_events[0].kind, EventKind::Cancel(0));
+        assert_eq!(reports[1].tob_history.len(), 2);
+    }
+
+    #[test]
+    fn test_tick_table_rejects_off_tick_limits() {
+        let tick_table = TickTable::new(vec![
+            TickBand {
+                from_price: 1,
+                tick_size: 1,
+            },
+            TickBand {
+                from_price: 100,
+                tick_size: 5,
+            },
+        ])
+        .unwrap();
+        let mut ob = Orderbook::with_tick_table(1_000, tick_table);
+
+        assert!(ob.accept_order(limit(Side::Buy, 99, 10)).is_ok());
+        assert!(ob.accept_order(limit(Side::Sell, 102, 10)).is_err());
+        assert!(ob.accept_order(limit(Side::Sell, 105, 10)).is_ok());
+
+        // crossing limits are checked too, not just resting ones
+        assert!(ob.accept_order(limit(Side::Buy, 107, 1)).is_err());
+
+        assert_eq!(ob.get_best_bid().unwrap().price, 99);
+        assert_eq!(ob.get_best_ask().unwrap().price, 105);
+    }
+
+    #[test]
+    fn test_fractional_sizes_end_to_end() {
+        let mut ob = Orderbook::new();
+        ob.set_size_scale(SizeScale::new(4));
+        let scale = ob.size_scale;
+
+        ob.accept_order(
+            scale
+                .ticket(Side::Sell, OrderType::Limit(100), "0.5")
+                .unwrap(),
+        )
+        .unwrap();
+        ob.accept_order(
+            scale
+                .ticket(Side::Sell, OrderType::Limit(101), "0.25")
+                .unwrap(),
+        )
+        .unwrap();
+
+        let response = ob
+            .accept_order(scale.ticket(Side::Buy, OrderType::Market, "0.6").unwrap())
+            .unwrap();
+
+        match response {
+            OrderResponse::Market(m) => {
+                assert_eq!(scale.to_display(m.size), "0.6000");
+                assert_eq!(scale.to_display(m.notional), "60.1000");
+            }
+            _ => panic!("Expected market response"),
+        }
+
+        assert_eq!(scale.to_display(ob.get_best_ask().unwrap().size), "0.1500");
+        assert!(
+            scale
+                .ticket(Side::Buy, OrderType::Market, "0.00001")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_quote_market_order_spends_budget() {
+        let mut ob = Orderbook::new();
+
+        ob.accept_order(limit(Side::Sell, 100, 10)).unwrap();
+        ob.accept_order(limit(Side::Sell, 101, 10)).unwrap();
+
+        let spend = OrderTicket {
+            side: Side::Buy,
+            size: 1_550,
+            order_type: OrderType::QuoteMarket,
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        };
+        let response = ob.accept_order(spend).unwrap();
+
+        match response {
+            OrderResponse::Market(m) => {
+                // 10 @ 100, then 550 / 101 = 5 units with 45 left over
+                assert_eq!(m.size, 15);
+                assert_eq!(m.notional, 1_000 + 505);
+                assert_eq!(m.remaining, 45);
+            }
+            _ => panic!("Expected market response"),
+        }
+
+        assert_eq!(ob.get_best_ask().unwrap().size, 5);
+        assert_eq!(ob.last_trade_price, Some(101));
+    }
+
+    #[test]
+    fn test_state_digest_tracks_identical_books() {
+        let feed = [
+            limit(Side::Buy, 100, 10),
+            limit(Side::Buy, 100, 5),
+            limit(Side::Sell, 102, 8),
+            market(Side::Sell, 3),
+        ];
+
+        let mut primary = Orderbook::new();
+        let mut replica = Orderbook::new();
+        for ticket in feed {
+            primary.accept_order(ticket.clone()).unwrap();
+            replica.accept_order(ticket).unwrap();
+        }
+        assert_eq!(primary.state_digest(), replica.state_digest());
+
+        // same level totals but a different queue is still a divergence
+        let mut reordered = Orderbook::new();
+        reordered.accept_order(limit(Side::Buy, 100, 5)).unwrap();
+        reordered.accept_order(limit(Side::Buy, 100, 10)).unwrap();
+        reordered.accept_order(limit(Side::Sell, 102, 8)).unwrap();
+        reordered.accept_order(market(Side::Sell, 3)).unwrap();
+        assert_eq!(reordered.get_best_bid(), primary.get_best_bid());
+        assert_ne!(primary.state_digest(), reordered.state_digest());
+
+        replica.accept_order(limit(Side::Sell, 103, 1)).unwrap();
+        assert_ne!(primary.state_digest(), replica.state_digest());
+
+        // as is anything else the next order would match differently on
+        let book_with = |ticket: OrderTicket, locked_policy| {
+            let mut book = Orderbook::new();
+            book.set_locked_policy(locked_policy);
+            book.accept_order(ticket).unwrap();
+            book.state_digest()
+        };
+        let plain = book_with(limit(Side::Sell, 102, 8), LockedPolicy::Match);
+        assert_eq!(
+            plain,
+            book_with(limit(Side::Sell, 102, 8), LockedPolicy::Match)
+        );
+        assert_ne!(
+            plain,
+            book_with(limit(Side::Sell, 102, 8), LockedPolicy::Reject)
+        );
+        assert_ne!(
+            plain,
+            book_with(
+                OrderTicket {
+                    min_qty: Some(8),
+                    ..limit(Side::Sell, 102, 8)
+                },
+                LockedPolicy::Match
+            )
+        );
+        assert_ne!(
+            plain,
+            book_with(
+                OrderTicket {
+                    time_in_force: TimeInForce::Gtd(50),
+                    ..limit(Side::Sell, 102, 8)
+                },
+                LockedPolicy::Match
+            )
+        );
+    }
+
+    #[test]
+    fn test_quote_cache_follows_top_of_book() {
+        let cache = Arc::new(QuoteCache::new());
+        let mut ob = Orderbook::new();
+        ob.set_quote_cache(Some(cache.clone()));
+        assert_eq!(cache.read(), Quote::default());
+
+        ob.accept_order(limit(Side::Buy, 100, 10)).unwrap();
+        ob.accept_order(limit(Side::Sell, 102, 8)).unwrap();
+        let reader = {
+            let cache = cache.clone();
+            std::thread::spawn(move || cache.read())
+        };
+        assert_eq!(
+            reader.join().unwrap(),
+            Quote {
+                bid: ob.get_best_bid(),
+                ask: ob.get_best_ask(),
+            }
+        );
+
+        ob.accept_order(market(Side::Sell, 10)).unwrap();
+        assert_eq!(cache.read().bid, None);
+        assert_eq!(cache.read().ask.unwrap().price, 102);
+    }
+
+    #[test]
+    fn test_mass_quote_replaces_levels_in_one_pass() {
+        let mut ob = Orderbook::new();
+        ob.accept_order(limit(Side::Sell, 105, 3)).unwrap();
+
+        let quote = |side, price, size| QuoteLevel { side, price, size };
+        let first = ob
+            .mass_quote(
+                7,
+                &[],
+                &[
+                    quote(Side::Buy, 100, 10),
+                    quote(Side::Buy, 99, 10),
+                    quote(Side::Sell, 102, 10),
+                    quote(Side::Sell, 103, 10),
+                ],
+            )
+            .unwrap();
+        let ids: Vec<u64> = first.iter().map(|r| r.as_ref().unwrap().id).collect();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+        assert_eq!(ob.total_liquidity(Side::Buy), 20);
+
+        // requote: pull everything and post again, one level crosses and
+        // one is not a valid price but the rest still go in
+        // only the owner can pull them
+        assert!(ob.mass_quote(8, &ids, &[]).is_err());
+        assert_eq!(ob.total_liquidity(Side::Buy), 20);
+
+        let second = ob
+            .mass_quote(
+                7,
+                &ids,
+                &[
+                    quote(Side::Buy, 101, 5),
+                    quote(Side::Buy, 105, 5),
+                    quote(Side::Sell, 0, 5),
+                    quote(Side::Sell, 102, 5),
+                ],
+            )
+            .unwrap();
+        assert!(second[0].is_ok());
+        assert!(second[1].is_err());
+        assert!(second[2].is_err());
+        assert!(second[3].is_ok());
+
+        assert_eq!(
+            ob.get_best_bid().unwrap(),
+            PriceSize {
+                price: 101,
+                size: 5
+            }
+        );
+        assert_eq!(
+            ob.get_best_ask().unwrap(),
+            PriceSize {
+                price: 102,
+                size: 5
+            }
+        );
+        assert_eq!(ob.size_at(Side::Sell, 105), 3);
+        assert_eq!(ob.total_liquidity(Side::Buy), 5);
+        assert_eq!(ob.last_trade_price, None);
+
+        // quotes rest under their owner, so self-trade prevention sees them
+        let OrderResponse::Market(own) = ob
+            .accept_order(OrderTicket {
+                owner: 7,
+                ..market(Side::Sell, 5)
+            })
+            .unwrap()
+        else {
+            panic!("expected a market response");
+        };
+        assert_eq!(own.size, 0);
+        assert_eq!(ob.total_liquidity(Side::Buy), 0);
+    }
+
+    #[test]
+    fn test_odd_lots_trade_but_are_not_displayed() {
+        let mut ob = Orderbook::new();
+        ob.set_round_lot(Some(100));
+
+        ob.accept_order(limit(Side::Sell, 101, 40)).unwrap();
+        ob.accept_order(limit(Side::Sell, 102, 100)).unwrap();
+        ob.accept_order(limit(Side::Buy, 99, 200)).unwrap();
+
+        assert_eq!(ob.get_best_ask().unwrap().price, 101);
+        assert_eq!(
+            ob.displayed_best_ask().unwrap(),
+            PriceSize {
+                price: 102,
+                size: 100
+            }
+        );
+        assert_eq!(ob.displayed_depth(Side::Buy, 10).len(), 1);
+
+        // every feed agrees on what is shown
+        assert_eq!(
+            ob.published_top_of_book(Side::Sell),
+            ob.displayed_best_ask()
+        );
+        let updates = ob.drain_level_updates();
+        assert_eq!(updates[0].price, 101);
+        assert_eq!(updates[0].new_total_size, 0);
+        ob.accept_order(limit(Side::Sell, 102, 30)).unwrap();
+        assert_eq!(ob.drain_level_updates()[0].new_total_size, 100);
+
+        // the hidden odd lot is still first in line
+        match ob.accept_order(market(Side::Buy, 50)).unwrap() {
+            OrderResponse::Market(m) => assert_eq!(m.notional, 40 * 101 + 10 * 102),
+            _ => panic!("Expected market response"),
+        }
+        assert_eq!(ob.displayed_best_ask(), None);
+        assert_eq!(ob.published_top_of_book(Side::Sell), None);
+
+        ob.set_round_lot(None);
+        assert_eq!(ob.displayed_best_ask(), ob.get_best_ask());
+    }
+
+    #[test]
+    fn test_bucketed_depth_rounds_away_from_the_spread() {
+        let mut ob = Orderbook::new();
+        for (price, size) in [(100, 1), (99, 2), (96, 3), (94, 4), (90, 5)] {
+            ob.accept_order(limit(Side::Buy, price, size)).unwrap();
+        }
+        for (price, size) in [(101, 1), (104, 2), (106, 3)] {
+            ob.accept_order(limit(Side::Sell, price, size)).unwrap();
+        }
+
+        // ticks of 1 from a min of 1, so buckets of 5 are 96..=100, 91..=95
+        let bid = |price, size| PriceSize { price, size };
+        assert_eq!(
+            ob.bucketed_depth(Side::Buy, 5, 10),
+            vec![bid(96, 6), bid(91, 4), bid(86, 5)]
+        );
+        assert_eq!(ob.bucketed_depth(Side::Buy, 5, 2).len(), 2);
+        assert_eq!(
+            ob.bucketed_depth(Side::Sell, 5, 10),
+            vec![bid(105, 3), bid(110, 3)]
+        );
+        assert_eq!(
+            ob.bucketed_depth(Side::Sell, 1, 10),
+            ob.displayed_depth(Side::Sell, 10)
+        );
+    }
+
+    #[test]
+    fn test_depth_views_follow_every_change() {
+        let mut ob = Orderbook::new();
+        ob.enable_depth_views(&[1, 10]);
+        ob.accept_order(limit(Side::Sell, 101, 5)).unwrap();
+        ob.accept_order(limit(Side::Sell, 102, 5)).unwrap();
+
+        let views = ob.depth_views.as_ref().unwrap();
+        assert_eq!(
+            views.view(1).unwrap().1,
+            &ob.displayed_depth(Side::Sell, 1)[..]
+        );
+        assert_eq!(views.view(10).unwrap().1.len(), 2);
+
+        ob.accept_order(market(Side::Buy, 5)).unwrap();
+        let (_, asks) = ob.depth_views.as_ref().unwrap().view(10).unwrap();
+        assert_eq!(
+            asks,
+            &[PriceSize {
+                price: 102,
+                size: 5
+            }]
+        );
+    }
+
+    #[test]
+    fn test_trade_stats_follow_the_tape() {
+        let mut ob = Orderbook::new();
+        ob.enable_trade_stats(&[10, 100]);
+        ob.accept_order(limit(Side::Sell, 100, 1)).unwrap();
+        ob.accept_order(limit(Side::Sell, 110, 3)).unwrap();
+
+        ob.accept_order(market(Side::Buy, 1)).unwrap();
+        ob.set_clock(50);
+        ob.accept_order(market(Side::Buy, 3)).unwrap();
+
+        let stats = ob.trade_stats.as_ref().unwrap();
+        assert_eq!(stats.averages(100).unwrap().vwap(), Some(107.5));
+        assert_eq!(stats.averages(10).unwrap().vwap(), Some(110.0));
+        let volatility = stats.volatility(100).unwrap().volatility().unwrap();
+        assert!((volatility - (1.1f64).ln()).abs() < 1e-12);
+        assert!(stats.volatility(5).is_none());
+
+        // moving the clock ages trades out without a new one
+        ob.set_clock(200);
+        let stats = ob.trade_stats.as_ref().unwrap();
+        assert_eq!(stats.averages(100).unwrap().vwap(), None);
+        assert_eq!(stats.averages(100).unwrap().twap(200), Some(110.0));
+    }
+
+    #[test]
+    fn test_depth_shows_when_each_level_last_changed() {
+        let mut ob = Orderbook::new();
+        ob.set_clock(5);
+        ob.accept_order(limit(Side::Sell, 101, 5)).unwrap();
+        ob.accept_order(limit(Side::Sell, 102, 5)).unwrap();
+        ob.set_clock(9);
+        ob.accept_order(market(Side::Buy, 2)).unwrap();
+
+        let stamps: Vec<(i64, u64, u64)> = ob
+            .depth_sizes(Side::Sell, 5)
+            .iter()
+            .map(|level| (level.price, level.updated_at, level.updated_seq))
+            .collect();
+        assert_eq!(stamps, vec![(101, 9, 4), (102, 5, 2)]);
+
+        ob.cancel_order(1).unwrap();
+        assert_eq!(ob.depth_sizes(Side::Sell, 5).len(), 1);
+        assert_eq!(ob.top_of_book_sizes(Side::Sell).unwrap().updated_seq, 4);
+    }
+
+    #[test]
+    fn test_rewind_to_rebuilds_an_earlier_book() {
+        let mut ob = Orderbook::with_config(BookConfig {
+            lot_size: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        ob.accept_order(limit(Side::Sell, 101, 4)).unwrap();
+        ob.accept_order(limit(Side::Buy, 99, 6)).unwrap();
+        let digest = ob.state_digest();
+
+        // rejected by the lot size again when replayed
+        assert!(ob.accept_order(limit(Side::Sell, 101, 3)).is_err());
+        ob.accept_order(market(Side::Buy, 2)).unwrap();
+        ob.cancel_order(1).unwrap();
+
+        let rewound = ob.rewind_to(2).unwrap();
+        assert_eq!(rewound.state_digest(), digest);
+        assert_eq!(rewound.get_best_ask().unwrap().size, 4);
+        assert_eq!(ob.rewind_to(5).unwrap().state_digest(), ob.state_digest());
+        assert!(ob.rewind_to(6).is_err());
+    }
+
+    #[test]
+    fn test_bust_trade_gives_the_maker_its_size_back() {
+        let mut ob = Orderbook::new();
+        ob.accept_order(limit(Side::Sell, 101, 5)).unwrap();
+        ob.accept_order(limit(Side::Sell, 101, 3)).unwrap();
+        ob.accept_order(limit(Side::Sell, 101, 4)).unwrap();
+        ob.accept_order(market(Side::Buy, 6)).unwrap();
+        let trades = ob.drain_trades();
+        assert_eq!(trades.len(), 2);
+        ob.drain_level_updates();
+
+        // the partly filled maker gets its size back ahead of order 2
+        let bust = ob.bust_trade(trades[1].trade_id).unwrap();
+        assert_eq!(bust.restored_order_id, Some(1));
+        assert_eq!(ob.get_order(1).unwrap().1.size, 3);
+        assert_eq!(ob.asks.top_of_book_orders(), vec![(1, 3), (2, 4)]);
+        assert_eq!(
+            ob.drain_level_updates(),
+            vec![LevelUpdate {
+                side: Side::Sell,
+                price: 101,
+                new_total_size: 7
+            }]
+        );
+
+        // the filled maker is gone, so the restore is only recorded
+        let bust = ob.bust_trade(trades[0].trade_id).unwrap();
+        assert_eq!(bust.restored_order_id, None);
+        assert_eq!(ob.get_best_ask().unwrap().size, 7);
+        assert!(ob.bust_trade(trades[0].trade_id).is_err());
+        assert_eq!(ob.drain_busts().len(), 2);
+
+        let replayed = Orderbook::replay(ob.event_log.clone()).unwrap();
+        assert_eq!(replayed.state_digest(), ob.state_digest());
+    }
+
+    #[test]
+    fn test_market_data_mode_decides_whether_hidden_size_shows() {
+        let mut ob = Orderbook::new();
+        ob.enable_depth_views(&[1]);
+        ob.accept_order(limit(Side::Sell, 101, 5)).unwrap();
+        ob.accept_order(iceberg(Side::Sell, 101, 2, 10)).unwrap();
+        let visible = ob.depth_checksum(5);
+        assert_eq!(ob.drain_level_updates().last().unwrap().new_total_size, 7);
+
+        ob.set_market_data_mode(MarketDataMode::Full);
+        let full = PriceSize {
+            price: 101,
+            size: 15,
+        };
+        assert_eq!(ob.published_top_of_book(Side::Sell), Some(full));
+        assert_eq!(ob.depth_views.as_ref().unwrap().view(1).unwrap().1, &[full]);
+        assert_ne!(ob.depth_checksum(5), visible);
+
+        // matching sees the reserve either way, only the feed changes
+        ob.accept_order(market(Side::Buy, 3)).unwrap();
+        assert_eq!(ob.drain_level_updates().last().unwrap().new_total_size, 12);
+        assert_eq!(ob.get_best_ask().unwrap().size, 4);
+
+        ob.set_market_data_mode(MarketDataMode::VisibleOnly);
+        assert_eq!(ob.published_top_of_book(Side::Sell), ob.get_best_ask());
+        ob.accept_order(market(Side::Buy, 12)).unwrap();
+        assert_eq!(ob.get_best_ask(), None);
+        assert_eq!(ob.drain_level_updates().last().unwrap().new_total_size, 0);
+    }
+
+    #[test]
+    fn test_icebergs_enter_like_any_other_order() {
+        let mut ob = Orderbook::new();
+        ob.accept_order(limit(Side::Sell, 101, 3)).unwrap();
+        let retain = OrderTicket {
+            iceberg: Some(Iceberg {
+                display_size: 4,
+                refresh: IcebergRefresh {
+                    clip: ClipSize::Random { min: 2, max: 5 },
+                    priority: RefreshPriority::Retain,
+                },
+            }),
+            ..limit(Side::Sell, 101, 20)
+        };
+        let OrderResponse::Limit(response) = ob.accept_order(retain).unwrap() else {
+            panic!("the iceberg does not cross");
+        };
+        let level = ob.depth_sizes(Side::Sell, 1)[0];
+        assert_eq!((level.displayed_size, level.total_size), (7, 23));
+
+        // its next clip keeps its place ahead of the plain order behind it
+        ob.accept_order(limit(Side::Sell, 101, 1)).unwrap();
+        ob.accept_order(market(Side::Buy, 7)).unwrap();
+        let queue: Vec<u64> = ob.full_l3().asks.iter().map(|order| order.id).collect();
+        assert_eq!(queue, vec![response.id, 2]);
+
+        // what crosses trades first and only the rest hides behind a clip
+        ob.accept_order(iceberg(Side::Buy, 101, 5, 30)).unwrap();
+        assert_eq!(ob.get_best_bid().unwrap().size, 5);
+        assert!(ob.accept_order(iceberg(Side::Buy, 100, 6, 5)).is_err());
+        assert!(
+            ob.accept_order(OrderTicket {
+                iceberg: Some(Iceberg::default()),
+                ..market(Side::Buy, 1)
+            })
+            .is_err()
+        );
+
+        let replayed = Orderbook::replay(ob.event_log.clone()).unwrap();
+        assert_eq!(replayed.state_digest(), ob.state_digest());
+        let rewound = ob.rewind_to(2).unwrap();
+        assert_eq!(rewound.depth_sizes(Side::Sell, 1)[0].total_size, 23);
+    }
+
+    #[test]
+    fn test_locked_policy_decides_limits_at_the_opposite_best() {
+        let seeded = |policy| {
+            let mut ob = Orderbook::new();
+            ob.set_locked_policy(policy);
+            ob.accept_order(limit(Side::Sell, 101, 5)).unwrap();
+            ob
+        };
+
+        let mut ob = seeded(LockedPolicy::Match);
+        assert!(matches!(
+            ob.accept_order(limit(Side::Buy, 101, 2)).unwrap(),
+            OrderResponse::Market(_)
+        ));
+        assert_eq!(ob.size_at(Side::Sell, 101), 3);
+
+        let mut ob = seeded(LockedPolicy::RestAndLock);
+        ob.enable_crossed_book_detector(8);
+        assert!(matches!(
+            ob.accept_order(limit(Side::Buy, 101, 2)).unwrap(),
+            OrderResponse::Limit(_)
+        ));
+        assert_eq!(ob.get_best_bid().unwrap().price, 101);
+        assert_eq!(ob.get_best_ask().unwrap().price, 101);
+        assert!(ob.crossed_book_detector.unwrap().reports().is_empty());
+
+        // going through the lock still trades
+        let mut ob = seeded(LockedPolicy::RestAndLock);
+        ob.accept_order(limit(Side::Sell, 102, 5)).unwrap();
+        assert!(matches!(
+            ob.accept_order(limit(Side::Buy, 102, 6)).unwrap(),
+            OrderResponse::Market(_)
+        ));
+
+        let mut ob = seeded(LockedPolicy::Reject);
+        assert!(ob.accept_order(limit(Side::Buy, 101, 2)).is_err());
+        assert!(ob.accept_order(limit(Side::Buy, 100, 2)).is_ok());
+        assert_eq!(ob.size_at(Side::Sell, 101), 5);
+    }
+
+    #[test]
+    fn test_cancel_order_reports_what_came_off() {
+        let mut ob = Orderbook::new();
+        let quote_cache = Arc::new(QuoteCache::new());
+        ob.set_quote_cache(Some(quote_cache.clone()));
+        ob.accept_order(limit(Side::Buy, 99, 10)).unwrap();
+        let OrderResponse::Limit(resting) = ob.accept_order(limit(Side::Buy, 100, 4)).unwrap()
+        else {
+            panic!("expected the bid to rest");
+        };
+        ob.accept_order(market(Side::Sell, 1)).unwrap();
+
+        assert_eq!(
+            ob.cancel_order(resting.id).unwrap(),
+            CancelResponse {
+                id: resting.id,
+                side: Side::Buy,
+                price: 100,
+                size: 3,
+            }
+        );
+        assert!(ob.cancel_order(resting.id).is_err());
+        assert_eq!(ob.get_best_bid().unwrap().price, 99);
+        assert_eq!(quote_cache.read().bid.unwrap().price, 99);
+    }
+
+    #[test]
+    fn test_replace_order_keeps_priority_only_when_shrinking() {
+        let mut ob = Orderbook::new();
+        let rest = |ob: &mut Orderbook, price, size| match ob
+            .accept_order(limit(Side::Sell, price, size))
+            .unwrap()
+        {
+            OrderResponse::Limit(resting) => resting.id,
+            other => panic!("expected a resting order, got {:?}", other),
+        };
+        let first = rest(&mut ob, 101, 10);
+        let second = rest(&mut ob, 101, 10);
+        ob.accept_order(limit(Side::Buy, 99, 5)).unwrap();
+
+        assert_eq!(
+            ob.replace_order(first, 101, 6).unwrap(),
+            ReplaceResponse {
+                id: first,
+                requeued: false,
+            }
+        );
+        // still first in line
+        ob.accept_order(market(Side::Buy, 6)).unwrap();
+        assert!(ob.get_order(first).is_none());
+
+        let bigger = ob.replace_order(second, 101, 12).unwrap();
+        assert!(bigger.requeued);
+        assert_ne!(bigger.id, second);
+        assert!(ob.get_order(second).is_none());
+        assert_eq!(ob.size_at(Side::Sell, 101), 12);
+
+        let moved = ob.replace_order(bigger.id, 102, 12).unwrap();
+        assert!(moved.requeued);
+        assert_eq!(ob.get_best_ask().unwrap().price, 102);
+
+        // trading through the bid is not what an amend is for
+        assert!(ob.replace_order(moved.id, 99, 12).is_err());
+        assert!(ob.replace_order(moved.id, 102, 0).is_err());
+        assert_eq!(ob.get_order(moved.id).unwrap().1.size, 12);
+    }
+
+    #[test]
+    fn test_immediate_or_cancel_never_rests() {
+        let mut ob = Orderbook::new();
+        ob.accept_order(limit(Side::Sell, 101, 5)).unwrap();
+        ob.accept_order(limit(Side::Sell, 102, 5)).unwrap();
+        ob.accept_order(limit(Side::Sell, 104, 5)).unwrap();
+
+        let ioc = |price| OrderTicket {
+            side: Side::Buy,
+            size: 12,
+            order_type: OrderType::ImmediateOrCancel(price),
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        };
+        assert_eq!(
+            ob.accept_order(ioc(103)).unwrap(),
+            OrderResponse::Market(MarketOrderResponse {
+                id: 3,
+                notional: 5 * 101 + 5 * 102,
+                size: 10,
+                remaining: 2,
+                resting_id: None,
+            })
+        );
+        assert_eq!(ob.get_best_bid(), None);
+        assert_eq!(ob.get_best_ask().unwrap().price, 104);
+        assert_eq!(ob.session_state, SessionState::Continuous);
+
+        // nothing crosses, nothing happens
+        assert_eq!(
+            ob.accept_order(ioc(103)).unwrap(),
+            OrderResponse::Market(MarketOrderResponse {
+                id: 4,
+                notional: 0,
+                size: 0,
+                remaining: 12,
+                resting_id: None,
+            })
+        );
+        assert!(ob.accept_order(ioc(0)).is_err());
+    }
+
+    #[test]
+    fn test_stops_fire_when_the_last_trade_reaches_them() {
+        let mut ob = Orderbook::new();
+        for price in 101..=104 {
+            ob.accept_order(limit(Side::Sell, price, 5)).unwrap();
+        }
+        ob.accept_order(limit(Side::Buy, 99, 5)).unwrap();
+        ob.accept_order(market(Side::Buy, 1)).unwrap();
+
+        let stop = |trigger, size| OrderTicket {
+            side: Side::Buy,
+            size,
+            order_type: OrderType::Stop { trigger },
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        };
+        assert!(ob.accept_order(stop(101, 5)).is_err());
+        let OrderResponse::Limit(first) = ob.accept_order(stop(102, 5)).unwrap() else {
+            panic!("expected the stop to wait");
+        };
+        let OrderResponse::Limit(second) = ob.accept_order(stop(103, 10)).unwrap() else {
+            panic!("expected the stop to wait");
+        };
+        let OrderResponse::Limit(cancelled) = ob.accept_order(stop(104, 1)).unwrap() else {
+            panic!("expected the stop to wait");
+        };
+        assert_eq!(ob.cancel_order(cancelled.id).unwrap().price, 104);
+        assert_eq!(ob.stops.len(), 2);
+
+        // printing at 102 fires the first stop, which prints at 103 and
+        // fires the second
+        ob.accept_order(market(Side::Buy, 5)).unwrap();
+        assert!(ob.stops.is_empty());
+        assert!(ob.stops.get_order(first.id).is_none());
+        assert_eq!(ob.last_trade_price, Some(104));
+        // the second stop ran the asks dry
+        assert_eq!(ob.total_liquidity(Side::Sell), 0);
+
+        // and is told about what it could not fill
+        let reports: Vec<_> = ob
+            .drain_execution_reports()
+            .into_iter()
+            .filter(|report| report.order_id == second.id)
+            .map(|report| (report.exec_type, report.traded_size, report.price))
+            .collect();
+        assert_eq!(
+            reports,
+            vec![
+                (ExecType::PartialFill, 4, 103),
+                (ExecType::PartialFill, 5, 104),
+                (ExecType::Cancelled, 0, 103),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pegged_orders_follow_their_reference() {
+        let mut ob = Orderbook::new();
+        let peg = |side, side_ref, offset| OrderTicket {
+            side,
+            size: 5,
+            order_type: OrderType::Pegged { side_ref, offset },
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        };
+        assert!(
+            ob.accept_order(peg(Side::Buy, PegReference::Primary, 0))
+                .is_err()
+        );
+
+        ob.accept_order(limit(Side::Buy, 100, 10)).unwrap();
+        ob.accept_order(limit(Side::Sell, 110, 10)).unwrap();
+        let OrderResponse::Limit(joined) = ob
+            .accept_order(peg(Side::Buy, PegReference::Primary, 0))
+            .unwrap()
+        else {
+            panic!("expected the peg to rest");
+        };
+        let OrderResponse::Limit(inside) = ob
+            .accept_order(peg(Side::Sell, PegReference::Market, 5))
+            .unwrap()
+        else {
+            panic!("expected the peg to rest");
+        };
+        assert_eq!(ob.get_order(joined.id).unwrap().1.price, 100);
+        assert_eq!(ob.get_order(inside.id).unwrap().1.price, 105);
+
+        // a better bid drags both along
+        ob.accept_order(limit(Side::Buy, 102, 1)).unwrap();
+        assert_eq!(ob.get_order(joined.id).unwrap().1.price, 102);
+        assert_eq!(ob.get_order(inside.id).unwrap().1.price, 107);
+
+        // the peg alone at 102 does not hold its own reference up
+        ob.accept_order(market(Side::Sell, 1)).unwrap();
+        assert_eq!(ob.get_order(joined.id).unwrap().1.price, 100);
+        assert_eq!(ob.get_order(inside.id).unwrap().1.price, 105);
+
+        // growing a peg requeues it under a new id that still follows
+        let grown = ob.replace_order(inside.id, 105, 8).unwrap();
+        assert!(grown.requeued);
+        ob.accept_order(limit(Side::Buy, 101, 1)).unwrap();
+        assert_eq!(ob.get_order(grown.id).unwrap().1.price, 106);
+
+        ob.cancel_order(joined.id).unwrap();
+        assert_eq!(ob.pegs.len(), 1);
+    }
+
+    #[test]
+    fn test_expire_pulls_day_and_good_till_date_orders() {
+        let mut ob = Orderbook::new();
+        let timed = |price, time_in_force| OrderTicket {
+            time_in_force,
+            ..limit(Side::Buy, price, 5)
+        };
+
+        // nothing to expire day orders against yet
+        assert!(ob.accept_order(timed(95, TimeInForce::Day)).is_err());
+        ob.set_day_end(Some(1_000));
+
+        ob.accept_order(timed(99, TimeInForce::Gtd(300))).unwrap();
+        ob.accept_order(timed(98, TimeInForce::Gtd(100))).unwrap();
+        ob.accept_order(timed(97, TimeInForce::Day)).unwrap();
+        ob.accept_order(limit(Side::Buy, 96, 5)).unwrap();
+        ob.accept_order(timed(100, TimeInForce::Gtd(200))).unwrap();
+        // fills before it can expire
+        ob.accept_order(market(Side::Sell, 5)).unwrap();
+
+        assert!(ob.expire(99).is_empty());
+        let expired: Vec<i64> = ob.expire(300).iter().map(|c| c.price).collect();
+        assert_eq!(expired, vec![98, 99]);
+        assert_eq!(ob.get_best_bid().unwrap().price, 97);
+
+        assert_eq!(ob.expire(1_000).len(), 1);
+        assert_eq!(ob.get_best_bid().unwrap().price, 96);
+        assert!(ob.expire(u64::MAX).is_empty());
+
+        // losing queue priority does not lose the expiry
+        let OrderResponse::Limit(resting) =
+            ob.accept_order(timed(95, TimeInForce::Gtd(2_000))).unwrap()
+        else {
+            panic!("expected the order to rest");
+        };
+        let replaced = ob.replace_order(resting.id, 94, 8).unwrap();
+        assert!(replaced.requeued);
+        let expired = ob.expire(2_000);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id, replaced.id);
+        assert_eq!(ob.get_best_bid().unwrap().price, 96);
+    }
+
+    #[test]
+    fn test_all_or_none_orders_wait_for_a_large_enough_taker() {
+        let mut ob = Orderbook::new();
+        let all_or_none = |price, size| OrderTicket {
+            min_qty: Some(size),
+            ..limit(Side::Sell, price, size)
+        };
+        assert!(ob.accept_order(all_or_none(101, 0)).is_err());
+        assert!(
+            ob.accept_order(OrderTicket {
+                min_qty: Some(6),
+                ..limit(Side::Sell, 101, 5)
+            })
+            .is_err()
+        );
+
+        ob.accept_order(all_or_none(101, 10)).unwrap();
+        ob.accept_order(limit(Side::Sell, 102, 5)).unwrap();
+
+        // too small for the block, so it trades past it
+        assert_eq!(
+            ob.accept_order(market(Side::Buy, 4)).unwrap(),
+            OrderResponse::Market(MarketOrderResponse {
+                id: 2,
+                notional: 4 * 102,
+                size: 4,
+                remaining: 0,
+                resting_id: None,
+            })
+        );
+        assert_eq!(ob.get_best_ask().unwrap().size, 10);
+
+        assert_eq!(
+            ob.accept_order(market(Side::Buy, 11)).unwrap(),
+            OrderResponse::Market(MarketOrderResponse {
+                id: 3,
+                notional: 10 * 101 + 102,
+                size: 11,
+                remaining: 0,
+                resting_id: None,
+            })
+        );
+        assert_eq!(ob.get_best_ask(), None);
+
+        // a requeued block keeps its minimum
+        let OrderResponse::Limit(block) = ob.accept_order(all_or_none(101, 10)).unwrap() else {
+            panic!("expected the block to rest");
+        };
+        let grown = ob.replace_order(block.id, 101, 12).unwrap();
+        assert!(grown.requeued);
+        let OrderResponse::Market(fill) = ob.accept_order(market(Side::Buy, 9)).unwrap() else {
+            panic!("expected a market response");
+        };
+        assert_eq!(fill.size, 0);
+        assert_eq!(ob.get_best_ask().unwrap().size, 12);
+    }
+
+    #[test]
+    fn test_market_to_limit_rests_the_remainder_at_the_traded_price() {
+        let mut ob = Orderbook::with_config(BookConfig {
+            market_policy: MarketPolicy::ToLimit,
+            ..BookConfig::default()
+        })
+        .unwrap();
+        ob.accept_order(limit(Side::Sell, 101, 5)).unwrap();
+        ob.accept_order(limit(Side::Sell, 102, 5)).unwrap();
+
+        let OrderResponse::Market(response) = ob.accept_order(market(Side::Buy, 8)).unwrap() else {
+            panic!("market orders always trade");
+        };
+        assert_eq!(response.size, 5);
+        assert_eq!(response.notional, 5 * 101);
+        assert_eq!(response.remaining, 3);
+        let id = response.resting_id.unwrap();
+        assert_eq!(
+            ob.get_order(id),
+            Some((
+                Side::Buy,
+                PriceSize {
+                    price: 101,
+                    size: 3
+                }
+            ))
+        );
+        assert_eq!(ob.get_best_ask().unwrap().price, 102);
+
+        // filled at the first level, nothing rests
+        let OrderResponse::Market(response) = ob.accept_order(market(Side::Sell, 2)).unwrap()
+        else {
+            panic!("market orders always trade");
+        };
+        assert_eq!(response.resting_id, None);
+        assert_eq!(ob.get_order(id).unwrap().1.size, 1);
+
+        // nothing to trade against, nothing to price a limit from
+        let mut empty = Orderbook::with_config(BookConfig {
+            market_policy: MarketPolicy::ToLimit,
+            ..BookConfig::default()
+        })
+        .unwrap();
+        assert_eq!(
+            empty.accept_order(market(Side::Buy, 4)).unwrap(),
+            OrderResponse::Market(MarketOrderResponse {
+                id: 0,
+                notional: 0,
+                size: 0,
+                remaining: 4,
+                resting_id: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_self_trade_prevention_by_owner() {
+        let owned = |ticket: OrderTicket, owner| OrderTicket { owner, ..ticket };
+
+        let mut ob = Orderbook::new();
+        ob.accept_order(owned(limit(Side::Sell, 101, 5), 1))
+            .unwrap();
+        ob.accept_order(owned(limit(Side::Sell, 102, 5), 2))
+            .unwrap();
+
+        // our own offer is pulled and we trade behind it
+        assert_eq!(
+            ob.accept_order(owned(market(Side::Buy, 3), 1)).unwrap(),
+            OrderResponse::Market(MarketOrderResponse {
+                id: 2,
+                notional: 3 * 102,
+                size: 3,
+                remaining: 0,
+                resting_id: None,
+            })
+        );
+        assert_eq!(
+            ob.get_best_ask(),
+            Some(PriceSize {
+                price: 102,
+                size: 2
+            })
+        );
+
+        let mut ob = Orderbook::with_config(BookConfig {
+            self_trade_prevention: SelfTradePrevention::CancelAggressor,
+            ..BookConfig::default()
+        })
+        .unwrap();
+        ob.accept_order(owned(limit(Side::Buy, 99, 5), 3)).unwrap();
+        ob.accept_order(owned(limit(Side::Buy, 98, 5), 4)).unwrap();
+        let OrderResponse::Market(response) =
+            ob.accept_order(owned(limit(Side::Sell, 98, 8), 3)).unwrap()
+        else {
+            panic!("a crossing limit trades");
+        };
+        // nothing traded, nothing left to fill, nothing rests
+        assert_eq!((response.size, response.remaining), (0, 0));
+        assert_eq!(ob.get_best_bid(), Some(PriceSize { price: 99, size: 5 }));
+        assert_eq!(ob.get_best_ask(), None);
+    }
+
+    #[test]
+    fn test_makers_get_execution_reports() {
+        let mut ob = Orderbook::new();
+        let bid = ob.accept_order(limit(Side::Buy, 99, 5)).unwrap();
+        let ask = ob.accept_order(limit(Side::Sell, 101, 5)).unwrap();
+        let (OrderResponse::Limit(bid), OrderResponse::Limit(ask)) = (bid, ask) else {
+            panic!("neither order crosses");
+        };
+
+        ob.accept_order(market(Side::Buy, 2)).unwrap();
+        ob.cancel_order(bid.id).unwrap();
+        ob.accept_order(market(Side::Buy, 3)).unwrap();
+
+        assert_eq!(
+            ob.drain_execution_reports(),
+            vec![
+                ExecutionReport {
+                    order_id: bid.id,
+                    owner: 0,
+                    exec_type: ExecType::Cancelled,
+                    traded_size: 0,
+                    price: 99,
+                    rebate: 0,
+                },
+                ExecutionReport {
+                    order_id: ask.id,
+                    owner: 0,
+                    exec_type: ExecType::PartialFill,
+                    traded_size: 2,
+                    price: 101,
+                    rebate: 0,
+                },
+                ExecutionReport {
+                    order_id: ask.id,
+                    owner: 0,
+                    exec_type: ExecType::Fill,
+                    traded_size: 3,
+                    price: 101,
+                    rebate: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_every_match_is_on_the_trade_tape() {
+        let mut ob = Orderbook::new();
+        ob.accept_order(limit(Side::Sell, 101, 5)).unwrap();
+        ob.accept_order(limit(Side::Sell, 102, 5)).unwrap();
+        ob.set_clock(1_000);
+
+        let OrderResponse::Market(taker) = ob.accept_order(market(Side::Buy, 7)).unwrap() else {
+            panic!("market orders always trade");
+        };
+        let trade = |trade_id, price, size, maker_order_id| Trade {
+            trade_id,
+            price,
+            size,
+            aggressor_side: Side::Buy,
+            maker_order_id,
+            taker_order_id: taker.id,
+            maker_owner: 0,
+            taker_owner: 0,
+            timestamp: 1_000,
+        };
+        assert_eq!(
+            ob.drain_trades(),
+            vec![trade(0, 101, 5, 0), trade(1, 102, 2, 1)]
+        );
+        assert!(ob.drain_trades().is_empty());
+
+        // makers still get their own reports
+        assert_eq!(ob.drain_execution_reports().len(), 2);
+    }
+
+    #[test]
+    fn test_event_log_numbers_every_input() {
+        let mut ob = Orderbook::new();
+        ob.accept_order(limit(Side::Buy, 99, 5)).unwrap();
+        ob.replace_order(0, 99, 3).unwrap();
+        // rejected inputs are still part of the stream
+        assert!(ob.cancel_order(42).is_err());
+        ob.cancel_order(0).unwrap();
+
+        let kinds: Vec<EventKind> = ob.events_since(0).iter().map(|e| e.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                EventKind::Order(limit(Side::Buy, 99, 5)),
+                EventKind::Replace {
+                    id: 0,
+                    price: 99,
+                    size: 3
+                },
+                EventKind::Cancel(42),
+                EventKind::Cancel(0),
+            ]
+        );
+
+        let tail = ob.events_since(2);
+        assert_eq!(tail.len(), 2);
+        assert_eq!(tail[0].seq, 2);
+        assert!(ob.events_since(4).is_empty());
+        assert!(ob.events_since(u64::MAX).is_empty());
+    }
+
+    #[test]
+    fn test_depth_counts_every_resting_order() {
+        let mut ob = Orderbook::new();
+        ob.set_round_lot(Some(100));
+        ob.accept_order(limit(Side::Buy, 99, 50)).unwrap();
+        ob.accept_order(limit(Side::Buy, 98, 100)).unwrap();
+        ob.accept_order(limit(Side::Buy, 99, 100)).unwrap();
+        ob.accept_order(limit(Side::Buy, 90, 5)).unwrap();
+
+        assert_eq!(
+            ob.depth(Side::Buy, 2),
+            vec![
+                PriceSize {
+                    price: 99,
+                    size: 150
+                },
+                PriceSize {
+                    price: 98,
+                    size: 100
+                },
+            ]
+        );
+        assert_eq!(ob.depth(Side::Buy, 10).len(), 3);
+        assert_eq!(ob.displayed_depth(Side::Buy, 10).len(), 2);
+        assert!(ob.depth(Side::Sell, 10).is_empty());
+    }
+
+    #[test]
+    fn test_full_l3_lists_every_resting_order() {
+        let mut ob = Orderbook::new();
+        ob.accept_order(limit(Side::Buy, 98, 1)).unwrap();
+        ob.accept_order(limit(Side::Buy, 99, 2)).unwrap();
+        ob.accept_order(limit(Side::Buy, 99, 3)).unwrap();
+        ob.accept_order(limit(Side::Sell, 101, 4)).unwrap();
+
+        let view = |id, price, size, queue_position| OrderView {
+            id,
+            price,
+            size,
+            queue_position,
+            entered_at: 0,
+            age: 0,
+        };
+        assert_eq!(
+            ob.full_l3(),
+            L3Book {
+                bids: vec![view(1, 99, 2, 0), view(2, 99, 3, 1), view(0, 98, 1, 0)],
+                asks: vec![view(3, 101, 4, 0)],
+            }
+        );
+        assert_eq!(Orderbook::new().full_l3(), L3Book::default());
+    }
+
+    #[test]
+    fn test_l3_shows_when_orders_joined_the_queue() {
+        let mut ob = Orderbook::new();
+        ob.set_clock(10);
+        ob.accept_order(limit(Side::Buy, 99, 2)).unwrap();
+        ob.set_clock(25);
+        ob.accept_order(limit(Side::Buy, 99, 3)).unwrap();
+        ob.set_clock(40);
+
+        let ages = |ob: &Orderbook| -> Vec<(u64, u64, u64)> {
+            ob.full_l3()
+                .bids
+                .iter()
+                .map(|order| (order.id, order.entered_at, order.age))
+                .collect()
+        };
+        assert_eq!(ages(&ob), vec![(0, 10, 30), (1, 25, 15)]);
+
+        // shrinking keeps its place and age, a new price starts afresh
+        ob.replace_order(0, 99, 1).unwrap();
+        ob.replace_order(1, 98, 3).unwrap();
+        assert_eq!(ages(&ob), vec![(0, 10, 30), (2, 40, 0)]);
+    }
+
+    #[test]
+    fn test_bbo_observer_fires_only_when_the_top_changes() {
+        let mut ob = Orderbook::new();
+        ob.accept_order(limit(Side::Buy, 99, 5)).unwrap();
+
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        ob.on_bbo_change(move |bid, ask| sink.lock().unwrap().push((bid, ask)));
+
+        let top = |price, size| Some(PriceSize { price, size });
+        // behind the best bid, nothing to report
+        ob.accept_order(limit(Side::Buy, 98, 1)).unwrap();
+        ob.accept_order(limit(Side::Sell, 101, 3)).unwrap();
+        ob.accept_order(limit(Side::Buy, 99, 2)).unwrap();
+        ob.accept_order(market(Side::Buy, 3)).unwrap();
+        ob.cancel_order(1).unwrap();
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                (top(99, 5), top(101, 3)),
+                (top(99, 7), top(101, 3)),
+                (top(99, 7), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_post_trade_hook_sees_every_trade_with_its_fees() {
+        let mut ob = Orderbook::new();
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        ob.set_post_trade_hook(move |trade: &Trade, fees| {
+            sink.lock().unwrap().push((trade.clone(), fees))
+        });
+
+        ob.accept_order(OrderTicket {
+            owner: 7,
+            ..limit(Side::Sell, 1_000, 40)
+        })
+        .unwrap();
+        ob.accept_order(OrderTicket {
+            owner: 9,
+            ..market(Side::Buy, 10)
+        })
+        .unwrap();
+        ob.set_fee_schedule(FeeSchedule {
+            maker_bps: -1,
+            taker_bps: 3,
+        });
+        ob.accept_order(OrderTicket {
+            owner: 9,
+            ..market(Side::Buy, 20)
+        })
+        .unwrap();
+
+        let seen = seen.lock().unwrap();
+        let trades: Vec<Trade> = ob.drain_trades();
+        assert_eq!(seen.len(), 2);
+        assert_eq!(seen[0], (trades[0].clone(), TradeFees::default()));
+        assert_eq!(
+            seen[1],
+            (
+                trades[1].clone(),
+                TradeFees {
+                    maker: -2,
+                    taker: 6
+                }
+            )
+        );
+        assert_eq!((seen[1].0.maker_owner, seen[1].0.taker_owner), (7, 9));
+    }
+
+    #[test]
+    fn test_makers_accrue_rebates_until_settled() {
+        let mut ob = Orderbook::new();
+        ob.set_fee_schedule(FeeSchedule {
+            maker_bps: -5,
+            taker_bps: 10,
+        });
+        let owned = |owner, ticket| OrderTicket { owner, ..ticket };
+        ob.accept_order(owned(7, limit(Side::Sell, 2_000, 30)))
+            .unwrap();
+        ob.accept_order(owned(8, limit(Side::Sell, 2_000, 30)))
+            .unwrap();
+        ob.accept_order(owned(9, market(Side::Buy, 40))).unwrap();
+        ob.accept_order(owned(9, market(Side::Buy, 10))).unwrap();
+
+        // 5bps of 60_000, 20_000 and 20_000
+        assert_eq!(ob.rebate(7), 30);
+        assert_eq!(ob.rebate(8), 20);
+        assert_eq!(ob.rebate(9), 0);
+        let rebates: Vec<i64> = ob
+            .drain_execution_reports()
+            .iter()
+            .map(|report| report.rebate)
+            .collect();
+        assert_eq!(rebates, vec![30, 10, 10]);
+
+        let session = ob.settle_rebates();
+        assert_eq!(
+            session.into_iter().collect::<Vec<_>>(),
+            vec![(7, 30), (8, 20)]
+        );
+        assert_eq!(ob.rebate(7), 0);
+    }
+
+    #[test]
+    fn test_level_updates_follow_every_change() {
+        let mut ob = Orderbook::new();
+        ob.accept_order(limit(Side::Buy, 99, 5)).unwrap();
+        ob.accept_order(limit(Side::Sell, 101, 3)).unwrap();
+        ob.accept_order(limit(Side::Sell, 102, 4)).unwrap();
+        ob.accept_order(market(Side::Buy, 5)).unwrap();
+        ob.cancel_order(0).unwrap();
+
+        let update = |side, price, new_total_size| LevelUpdate {
+            side,
+            price,
+            new_total_size,
+        };
+        assert_eq!(
+            ob.drain_level_updates(),
+            vec![
+                update(Side::Buy, 99, 5),
+                update(Side::Sell, 101, 3),
+                update(Side::Sell, 102, 4),
+                update(Side::Sell, 101, 0),
+                update(Side::Sell, 102, 2),
+                update(Side::Buy, 99, 0),
+            ]
+        );
+        assert!(ob.drain_level_updates().is_empty());
+    }
+
+    #[test]
+    fn test_with_config_enforces_range_tick_and_lot() {
+        let mut ob = Orderbook::with_config(BookConfig {
+            min_price: 100,
+            max_price: 200,
+            tick_size: 5,
+            lot_size: 10,
+            symbol: "ABC".into(),
+            ..BookConfig::default()
+        })
+        .unwrap();
+        assert_eq!(ob.symbol, "ABC");
+
+        ob.accept_order(limit(Side::Buy, 150, 20)).unwrap();
+        assert!(ob.accept_order(limit(Side::Buy, 152, 20)).is_err());
+        assert!(ob.accept_order(limit(Side::Buy, 150, 15)).is_err());
+        assert!(ob.accept_order(limit(Side::Buy, 0, 10)).is_err());
+        assert!(ob.accept_order(limit(Side::Buy, 97, 10)).is_err());
+        assert!(ob.accept_order(market(Side::Sell, 5)).is_err());
+        assert!(ob.replace_order(0, 150, 15).is_err());
+        assert_eq!(
+            ob.get_best_bid(),
+            Some(PriceSize {
+                price: 150,
+                size: 20
+            })
+        );
+
+        let bad = |config: BookConfig| Orderbook::with_config(config).is_err();
+        assert!(bad(BookConfig {
+            tick_size: 0,
+            ..BookConfig::default()
+        }));
+        assert!(bad(BookConfig {
+            lot_size: -1,
+            ..BookConfig::default()
+        }));
+        assert!(bad(BookConfig {
+            min_price: 10,
+            max_price: 5,
+            ..BookConfig::default()
+        }));
+    }
+
+    #[test]
+    fn test_orders_above_the_configured_range_extend_it() {
+        let mut ob = Orderbook::with_config(BookConfig {
+            min_price: 100,
+            max_price: 200,
+            ..BookConfig::default()
+        })
+        .unwrap();
+        ob.accept_order(limit(Side::Buy, 150, 5)).unwrap();
+        ob.accept_order(limit(Side::Sell, 260, 5)).unwrap();
+        ob.accept_order(limit(Side::Buy, 240, 3)).unwrap();
+
+        let price_size = |price, size| Some(PriceSize { price, size });
+        assert_eq!(ob.get_best_bid(), price_size(240, 3));
+        assert_eq!(ob.get_best_ask(), price_size(260, 5));
+        assert_eq!(ob.size_at(Side::Buy, 150), 5);
+
+        ob.accept_order(market(Side::Sell, 4)).unwrap();
+        assert_eq!(ob.get_best_bid(), price_size(150, 4));
+
+        // and below it
+        ob.accept_order(limit(Side::Sell, 50, 2)).unwrap();
+        assert_eq!(ob.get_best_bid(), price_size(150, 2));
+        ob.accept_order(limit(Side::Buy, 40, 3)).unwrap();
+        ob.accept_order(market(Side::Sell, 4)).unwrap();
+        assert_eq!(ob.get_best_bid(), price_size(40, 1));
+        assert_eq!(ob.size_at(Side::Buy, 40), 1);
+        assert_eq!(ob.bids.min_price, 40);
+        assert_eq!(ob.asks.min_price, 40);
+        assert!(ob.accept_order(limit(Side::Buy, 0, 1)).is_err());
+    }
+
+    #[test]
+    fn test_huge_limit_orders_do_not_overflow_without_limits() {
+        let mut ob = Orderbook::new();
+        ob.accept_order(limit(Side::Buy, 100, i64::MAX)).unwrap();
+        assert_eq!(ob.get_best_bid().unwrap().size, i64::MAX);
+    }
+
+    #[test]
+    fn test_owner_limits_reject_with_the_breach() {
+        let mut ob = Orderbook::new();
+        ob.set_owner_limits(
+            7,
+            Some(OwnerLimits {
+                max_open_orders: Some(2),
+                max_resting_notional: Some(2_000),
+                max_net_position: Some(10),
+            }),
+        );
+        let owned = |ticket| OrderTicket { owner: 7, ..ticket };
+
+        ob.accept_order(owned(limit(Side::Buy, 100, 10))).unwrap();
+        assert!(ob.accept_order(owned(limit(Side::Buy, 100, 11))).is_err());
+        let OrderResponse::Limit(ask) = ob.accept_order(owned(limit(Side::Sell, 110, 5))).unwrap()
+        else {
+            panic!("the ask does not cross");
+        };
+        assert!(ob.accept_order(owned(limit(Side::Buy, 99, 1))).is_err());
+        assert!(ob.replace_order(ask.id, 110, 30).is_err());
+        // anyone else is uncapped
+        ob.accept_order(limit(Side::Buy, 99, 50)).unwrap();
+        assert_eq!(
+            ob.accept_order(owned(limit(Side::Buy, 100, i64::MAX))),
+            Err("notional overflow".to_string())
+        );
+
+        // trading against a resting order moves its owner's position
+        ob.accept_order(market(Side::Sell, 8)).unwrap();
+        assert_eq!(ob.position(7), 8);
+        let trade = ob.drain_trades().remove(0);
+        assert_eq!((trade.maker_owner, trade.taker_owner), (7, 0));
+        assert!(ob.accept_order(owned(market(Side::Buy, 3))).is_err());
+
+        ob.bust_trade(trade.trade_id).unwrap();
+        assert_eq!(ob.position(7), 0);
+
+        let breaches: Vec<LimitBreach> = ob
+            .drain_limit_rejects()
+            .into_iter()
+            .map(|reject| reject.breach)
+            .collect();
+        assert_eq!(
+            breaches,
+            vec![
+                LimitBreach::RestingNotional {
+                    side: Side::Buy,
+                    notional: 2_100,
+                    limit: 2_000
+                },
+                LimitBreach::OpenOrders { limit: 2 },
+                LimitBreach::RestingNotional {
+                    side: Side::Sell,
+                    notional: 3_300,
+                    limit: 2_000
+                },
+                LimitBreach::NetPosition {
+                    position: 11,
+                    limit: 10
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_speed_bump_holds_aggressors_until_the_clock_lets_them_through() {
+        let mut ob = Orderbook::new();
+        ob.accept_order(limit(Side::Sell, 101, 5)).unwrap();
+        ob.set_speed_bump(Some(SpeedBump {
+            delay: 5,
+            ..SpeedBump::default()
+        }));
+
+        let OrderResponse::Limit(delayed) = ob.accept_order(market(Side::Buy, 3)).unwrap() else {
+            panic!("a delayed order only gets its id back");
+        };
+        let crossing = ob.accept_order(limit(Side::Buy, 101, 2)).unwrap();
+        // passive orders go straight in
+        ob.accept_order(limit(Side::Buy, 99, 4)).unwrap();
+        assert_eq!(ob.total_liquidity(Side::Buy), 4);
+        assert_eq!(ob.delayed_orders().len(), 2);
+        assert!(ob.drain_trades().is_empty());
+
+        // the maker gets out of the way before the aggressors arrive
+        ob.cancel_order(0).unwrap();
+        ob.accept_order(limit(Side::Sell, 102, 5)).unwrap();
+        ob.set_clock(4);
+        assert!(ob.drain_released_orders().is_empty());
+        ob.set_clock(5);
+
+        let released = ob.drain_released_orders();
+        assert_eq!(released.len(), 2);
+        assert_eq!(released[0].delayed_id, delayed.id);
+        let trades = ob.drain_trades();
+        assert_eq!(trades.len(), 1);
+        assert_eq!((trades[0].price, trades[0].size), (102, 3));
+        assert_eq!(released[0].result, Ok(trades[0].taker_order_id));
+        // the limit no longer crosses by then and rests instead
+        let Ok(rested) = released[1].result else {
+            panic!("the limit is still good");
+        };
+        assert_eq!(
+            ob.get_order(rested),
+            Some((
+                Side::Buy,
+                PriceSize {
+                    price: 101,
+                    size: 2
+                }
+            ))
+        );
+        assert!(matches!(crossing, OrderResponse::Limit(_)));
+        assert!(ob.delayed_orders().is_empty());
+
+        // jitter is derived from the order so every replay agrees
+        ob.set_speed_bump(Some(SpeedBump {
+            delay: 5,
+            jitter: 100,
+            seed: 7,
+        }));
+        for _ in 0..20 {
+            ob.accept_order(market(Side::Sell, 1)).unwrap();
+        }
+        let release_at: Vec<u64> = ob
+            .delayed_orders()
+            .iter()
+            .map(|delayed| delayed.release_at)
+            .collect();
+        assert!(release_at.is_sorted());
+        assert!(release_at.iter().all(|at| (10..=110).contains(at)));
+        assert!(release_at.first() != release_at.last());
+    }
+
+    #[test]
+    fn test_orders_too_young_to_cancel() {
+        let mut ob = Orderbook::new();
+        ob.set_min_resting_time(Some(MinRestingTime {
+            min_time: 50,
+            action: EarlyCancelAction::Reject,
+        }));
+        ob.set_clock(100);
+        let OrderResponse::Limit(bid) = ob.accept_order(limit(Side::Buy, 99, 5)).unwrap() else {
+            panic!("the bid does not cross");
+        };
+        ob.set_clock(149);
+        assert!(ob.cancel_order(bid.id).is_err());
+        assert_eq!(ob.size_at(Side::Buy, 99), 5);
+        ob.set_clock(150);
+        assert!(ob.cancel_order(bid.id).is_ok());
+
+        // a queued cancel goes through once the order is old enough
+        ob.set_min_resting_time(Some(MinRestingTime {
+            min_time: 50,
+            action: EarlyCancelAction::Queue,
+        }));
+        let OrderResponse::Limit(ask) = ob.accept_order(limit(Side::Sell, 101, 5)).unwrap() else {
+            panic!("the ask does not cross");
+        };
+        ob.drain_execution_reports();
+        assert!(ob.cancel_order(ask.id).is_err());
+        ob.set_clock(199);
+        assert_eq!(ob.size_at(Side::Sell, 101), 5);
+        ob.set_clock(200);
+        assert_eq!(ob.size_at(Side::Sell, 101), 0);
+        let reports = ob.drain_execution_reports();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].order_id, ask.id);
+        assert_eq!(reports[0].exec_type, ExecType::Cancelled);
+
+        // a replay queues and releases it the same way
+        let mut replayed = ob.empty_copy();
+        for event in ob.event_log.clone() {
+            replayed.apply_event(event.kind);
+        }
+        assert_eq!(replayed.state_digest(), ob.state_digest());
+    }
+
+    #[test]
+    fn test_pegs_breaching_the_band() {
+        // the peg rests at 91 inside the band around 101, then the bid it
+        // follows trades away and the band moves to 90 to 110 around the
+        // last trade while the peg would follow 98 down to 89
+        let breached = |action| {
+            let mut ob = Orderbook::new();
+            ob.set_price_band(Some(PriceBand {
+                bps: 1_000,
+                after_halt: None,
+            }));
+            ob.set_peg_breach_action(action);
+            ob.accept_order(limit(Side::Buy, 100, 10)).unwrap();
+            ob.accept_order(limit(Side::Buy, 98, 5)).unwrap();
+            ob.accept_order(limit(Side::Sell, 102, 10)).unwrap();
+            let OrderResponse::Limit(peg) = ob
+                .accept_order(OrderTicket {
+                    order_type: OrderType::Pegged {
+                        side_ref: PegReference::Primary,
+                        offset: 9,
+                    },
+                    ..limit(Side::Buy, 0, 4)
+                })
+                .unwrap()
+            else {
+                panic!("pegs rest");
+            };
+            assert_eq!(ob.get_order(peg.id).unwrap().1.price, 91);
+            ob.accept_order(market(Side::Sell, 10)).unwrap();
+            (ob, peg.id)
+        };
+
+        let (ob, id) = breached(PegBreachAction::Hold);
+        assert_eq!(ob.get_order(id).unwrap().1.price, 91);
+
+        let (ob, id) = breached(PegBreachAction::Clamp);
+        assert_eq!(ob.get_order(id).unwrap().1.price, 90);
+
+        // parked off the book, back under the same id once 91 is good
+        let (mut ob, id) = breached(PegBreachAction::Park);
+        assert_eq!(ob.get_order(id), None);
+        assert_eq!(ob.size_at(Side::Buy, 89), 0);
+        ob.accept_order(limit(Side::Buy, 100, 1)).unwrap();
+        assert_eq!(
+            ob.get_order(id).unwrap().1,
+            PriceSize { price: 91, size: 4 }
+        );
+
+        let (mut ob, id) = breached(PegBreachAction::Cancel);
+        assert_eq!(ob.get_order(id), None);
+        let rejects = ob.drain_peg_rejects();
+        assert_eq!(rejects.len(), 1);
+        assert_eq!((rejects[0].id, rejects[0].price), (id, 89));
+        assert!(rejects[0].reason.contains("band"));
+        assert!(ob.cancel_order(id).is_err());
+    }
+
+    #[test]
+    fn test_mmp_pulls_quotes_once_tripped() {
+        let mut ob = Orderbook::new();
+        let quote = |side, price| QuoteLevel {
+            side,
+            price,
+            size: 5,
+        };
+        let quoted = ob
+            .mass_quote(
+                7,
+                &[],
+                &[
+                    quote(Side::Sell, 101),
+                    quote(Side::Sell, 102),
+                    quote(Side::Buy, 99),
+                ],
+            )
+            .unwrap();
+        let ids: Vec<u64> = quoted.into_iter().map(|quote| quote.unwrap().id).collect();
+        ob.set_mmp(
+            7,
+            Some(MmpLimits {
+                window: 100,
+                max_fills: Some(1),
+                max_volume: None,
+            }),
+        );
+
+        // the first fill drops out of the window before the second
+        ob.set_clock(10);
+        ob.accept_order(market(Side::Buy, 1)).unwrap();
+        ob.set_clock(110);
+        ob.accept_order(market(Side::Buy, 1)).unwrap();
+        assert!(ob.drain_mmp_triggers().is_empty());
+
+        ob.accept_order(market(Side::Buy, 1)).unwrap();
+        let triggers = ob.drain_mmp_triggers();
+        assert_eq!(triggers.len(), 1);
+        assert_eq!(triggers[0].owner, 7);
+        assert_eq!((triggers[0].fills, triggers[0].volume), (2, 2));
+        // bids then asks
+        assert_eq!(triggers[0].cancelled, vec![ids[2], ids[0], ids[1]]);
+        assert_eq!(ob.get_best_bid(), None);
+        assert_eq!(ob.get_best_ask(), None);
+    }
+
+    #[test]
+    fn test_quote_stats_track_churn_and_quote_life() {
+        let mut ob = Orderbook::new();
+        ob.enable_quote_stats();
+        let OrderResponse::Limit(bid) = ob.accept_order(limit(Side::Buy, 99, 5)).unwrap() else {
+            panic!("the bid does not cross");
+        };
+        ob.set_clock(10);
+        ob.accept_order(limit(Side::Sell, 101, 5)).unwrap();
+        ob.set_clock(20);
+        ob.cancel_order(bid.id).unwrap();
+        ob.set_clock(30);
+        ob.accept_order(market(Side::Buy, 5)).unwrap();
+        let OrderResponse::Limit(bid) = ob.accept_order(limit(Side::Buy, 99, 2)).unwrap() else {
+            panic!("the bid does not cross");
+        };
+        ob.set_clock(40);
+        ob.replace_order(bid.id, 98, 2).unwrap();
+
+        let stats = ob.quote_stats.as_ref().unwrap();
+        assert_eq!(
+            stats.level(Side::Buy, 99),
+            LevelChurn {
+                adds: 2,
+                cancels: 2,
+                fills: 0
+            }
+        );
+        assert_eq!(stats.level(Side::Buy, 98).adds, 1);
+        assert_eq!(stats.level(Side::Sell, 101).fills, 1);
+        assert_eq!(stats.levels(Side::Buy).count(), 2);
+
+        // cancelled after 20 and requeued after 10, filled after 20
+        assert_eq!(
+            stats.cancelled_life(),
+            QuoteLife {
+                quotes: 2,
+                total: 30
+            }
+        );
+        assert_eq!(stats.cancelled_life().average(), Some(15.0));
+        assert_eq!(stats.filled_life().average(), Some(20.0));
+    }
+
+    #[test]
+    fn test_order_history_follows_the_whole_lifecycle() {
+        let mut ob = Orderbook::new();
+        ob.enable_order_history();
+        let OrderResponse::Limit(ask) = ob.accept_order(limit(Side::Sell, 101, 10)).unwrap() else {
+            panic!("the ask does not cross");
+        };
+        ob.set_clock(5);
+        ob.accept_order(market(Side::Buy, 3)).unwrap();
+        ob.replace_order(ask.id, 101, 5).unwrap();
+        ob.bust_trade(0).unwrap();
+        ob.set_clock(8);
+        let replaced = ob.replace_order(ask.id, 102, 5).unwrap();
+        ob.accept_order(market(Side::Buy, 5)).unwrap();
+
+        let updates = |id| -> Vec<(u64, OrderUpdate)> {
+            ob.order_history(id)
+                .unwrap()
+                .iter()
+                .map(|entry| (entry.timestamp, entry.update))
+                .collect()
+        };
+        let mut lifecycle = vec![
+            (
+                0,
+                OrderUpdate::Rested {
+                    price: 101,
+                    size: 10,
+                },
+            ),
+            (
+                5,
+                OrderUpdate::PartialFill {
+                    price: 101,
+                    size: 3,
+                },
+            ),
+            (
+                5,
+                OrderUpdate::Amended {
+                    price: 101,
+                    size: 5,
+                },
+            ),
+            (5, OrderUpdate::Restored { size: 3 }),
+            (
+                8,
+                OrderUpdate::Replaced {
+                    new_id: replaced.id,
+                    price: 102,
+                    size: 5,
+                },
+            ),
+        ];
+        assert_eq!(updates(ask.id), lifecycle);
+
+        // the replacement carries the history on
+        lifecycle.push((
+            8,
+            OrderUpdate::Filled {
+                price: 102,
+                size: 5,
+            },
+        ));
+        assert_eq!(updates(replaced.id), lifecycle);
+        assert_eq!(ob.order_history(99), None);
+    }
 }
@@ -0,0 +1,310 @@
+use crate::{Result, Side, book::Orderbook};
+
+/// An order that never shows in any book and only trades at the mid
+#[derive(Debug, Clone, PartialEq)]
+pub struct HiddenOrder {
+    pub id: u64,
+    pub side: Side,
+    pub size: i64,
+    /// worst midpoint the order is still willing to trade at
+    pub limit_price: Option<i64>,
+}
+
+/// One execution in the pool, at the midpoint so downstream tape
+/// consumers can flag it
+#[derive(Debug, Clone, PartialEq)]
+pub struct MidpointCross {
+    pub buy_id: u64,
+    pub sell_id: u64,
+    /// twice the price, so a mid between two ticks stays exact
+    pub doubled_price: i64,
+    pub size: i64,
+    /// reported as a non-displayed execution, always set for the pool
+    pub non_displayed: bool,
+}
+
+/// Everything one scheduled crossing session matched, all at the same mid
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrossBatch {
+    /// the reference book's clock the session was due at
+    pub scheduled_at: u64,
+    pub crosses: Vec<MidpointCross>,
+}
+
+/// Crosses a pool in sessions at every multiple of `interval` of the
+/// reference book's clock instead of after every order. Poll it whenever
+/// the clock moves. Sessions the clock jumped over run once, not once
+/// each, and a session with nothing eligible still runs and comes back
+/// empty.
+#[derive(Debug, Default)]
+pub struct CrossSchedule {
+    pub interval: u64,
+    /// clock the next session is due at
+    next_session: u64,
+}
+
+impl CrossSchedule {
+    pub fn new(interval: u64) -> Result<Self> {
+        if interval == 0 {
+            return Err("Crossing interval must be positive".into());
+        }
+
+        Ok(Self {
+            interval,
+            ..Default::default()
+        })
+    }
+
+    /// Run the session if the reference book's clock has reached it
+    pub fn poll(&mut self, pool: &mut MidpointPool, reference: &Orderbook) -> Option<CrossBatch> {
+        if reference.clock < self.next_session {
+            return None;
+        }
+
+        let scheduled_at = reference.clock / self.interval * self.interval;
+        self.next_session = scheduled_at + self.interval;
+        Some(CrossBatch {
+            scheduled_at,
+            crosses: pool.cross(reference),
+        })
+    }
+}
+
+/// A dark pool that pegs to the midpoint of a lit reference book. Orders
+/// queue in time priority per side and `cross` matches whatever is
+/// eligible at the current reference mid.
+#[derive(Debug, Default)]
+pub struct MidpointPool {
+    buys: Vec<HiddenOrder>,
+    sells: Vec<HiddenOrder>,
+    next_id: u64,
+}
+
+impl MidpointPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn submit(&mut self, side: Side, size: i64, limit_price: Option<i64>) -> Result<u64> {
+        if size <= 0 {
+            return Err(format!("Size {} must be positive", size));
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let order = HiddenOrder {
+            id,
+            side,
+            size,
+            limit_price,
+        };
+        match side {
+            Side::Buy => self.buys.push(order),
+            Side::Sell => self.sells.push(order),
+        }
+        Ok(id)
+    }
+
+    pub fn cancel(&mut self, id: u64) -> Result<HiddenOrder> {
+        for queue in [&mut self.buys, &mut self.sells] {
+            if let Some(index) = queue.iter().position(|order| order.id == id) {
+                return Ok(queue.remove(index));
+            }
+        }
+        Err(format!("No hidden order with id {}", id))
+    }
+
+    pub fn get_order(&self, id: u64) -> Option<&HiddenOrder> {
+        self.buys
+            .iter()
+            .chain(self.sells.iter())
+            .find(|order| order.id == id)
+    }
+
+    /// Twice the reference mid, only when both sides are quoted and the
+    /// book is not locked or crossed. Doubled so a one tick spread still
+    /// has a mid to trade at.
+    pub fn doubled_midpoint(reference: &Orderbook) -> Option<i64> {
+        let bid = reference.get_best_bid()?.price;
+        let ask = reference.get_best_ask()?.price;
+        if bid >= ask {
+            return None;
+        }
+        Some(bid + ask)
+    }
+
+    /// Match eligible buys against eligible sells, oldest first, at the
+    /// reference mid. Nothing trades when there is no usable mid.
+    pub fn cross(&mut self, reference: &Orderbook) -> Vec<MidpointCross> {
+        let Some(doubled_price) = Self::doubled_midpoint(reference) else {
+            return Vec::new();
+        };
+
+        let buy_accepts = |order: &HiddenOrder| {
+            order
+                .limit_price
+                .is_none_or(|limit| 2 * limit >= doubled_price)
+        };
+        let sell_accepts = |order: &HiddenOrder| {
+            order
+                .limit_price
+                .is_none_or(|limit| 2 * limit <= doubled_price)
+        };
+
+        let mut crosses = Vec::new();
+        let mut buy = 0;
+        let mut sell = 0;
+        loop {
+            while buy < self.buys.len() && !buy_accepts(&self.buys[buy]) {
+                buy += 1;
+            }
+            while sell < self.sells.len() && !sell_accepts(&self.sells[sell]) {
+                sell += 1;
+            }
+            if buy == self.buys.len() || sell == self.sells.len() {
+                break;
+            }
+
+            let size = self.buys[buy].size.min(self.sells[sell].size);
+            crosses.push(MidpointCross {
+                buy_id: self.buys[buy].id,
+                sell_id: self.sells[sell].id,
+                doubled_price,
+                size,
+                non_displayed: true,
+            });
+
+            self.buys[buy].size -= size;
+            self.sells[sell].size -= size;
+            if self.buys[buy].size == 0 {
+                buy += 1;
+            }
+            if self.sells[sell].size == 0 {
+                sell += 1;
+            }
+        }
+
+        self.buys.retain(|order| order.size > 0);
+        self.sells.retain(|order| order.size > 0);
+        crosses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OrderTicket, OrderType, TimeInForce};
+
+    fn reference(bid: i64, ask: i64) -> Orderbook {
+        let mut book = Orderbook::new();
+        for (side, price) in [(Side::Buy, bid), (Side::Sell, ask)] {
+            book.accept_order(OrderTicket {
+                side,
+                size: 1,
+                order_type: OrderType::Limit(price),
+                time_in_force: TimeInForce::Gtc,
+                min_qty: None,
+                owner: 0,
+                iceberg: None,
+            })
+            .unwrap();
+        }
+        book
+    }
+
+    #[test]
+    fn crosses_at_the_reference_mid_in_time_priority() {
+        let mut pool = MidpointPool::new();
+        let first = pool.submit(Side::Sell, 5, None).unwrap();
+        let second = pool.submit(Side::Sell, 5, None).unwrap();
+        let buy = pool.submit(Side::Buy, 8, None).unwrap();
+
+        let crosses = pool.cross(&reference(98, 102));
+        assert_eq!(
+            crosses,
+            vec![
+                MidpointCross {
+                    buy_id: buy,
+                    sell_id: first,
+                    doubled_price: 200,
+                    size: 5,
+                    non_displayed: true,
+                },
+                MidpointCross {
+                    buy_id: buy,
+                    sell_id: second,
+                    doubled_price: 200,
+                    size: 3,
+                    non_displayed: true,
+                },
+            ]
+        );
+
+        assert_eq!(pool.get_order(second).unwrap().size, 2);
+        assert!(pool.get_order(first).is_none());
+        assert!(pool.get_order(buy).is_none());
+    }
+
+    #[test]
+    fn limits_and_unusable_mids_hold_orders_back() {
+        let mut pool = MidpointPool::new();
+        let picky = pool.submit(Side::Buy, 5, Some(99)).unwrap();
+        let buy = pool.submit(Side::Buy, 5, None).unwrap();
+        let sell = pool.submit(Side::Sell, 5, None).unwrap();
+
+        // the picky buy won't pay a mid of 99.5
+        let crosses = pool.cross(&reference(99, 100));
+        assert_eq!(crosses.len(), 1);
+        assert_eq!(crosses[0].buy_id, buy);
+        assert_eq!(crosses[0].sell_id, sell);
+        assert_eq!(crosses[0].doubled_price, 199);
+        assert_eq!(crosses[0].size, 5);
+        // no lit ask at all
+        assert!(pool.cross(&Orderbook::new()).is_empty());
+
+        let sell = pool.submit(Side::Sell, 5, None).unwrap();
+        assert!(pool.cross(&reference(100, 100)).is_empty());
+        let crosses = pool.cross(&reference(98, 100));
+        assert_eq!(crosses.len(), 1);
+        assert_eq!(crosses[0].buy_id, picky);
+        assert_eq!(crosses[0].sell_id, sell);
+        assert_eq!(crosses[0].doubled_price, 198);
+
+        let picky = pool.submit(Side::Buy, 5, Some(99)).unwrap();
+
+        assert_eq!(pool.cancel(picky).unwrap().size, 5);
+        assert!(pool.cancel(picky).is_err());
+        assert!(pool.submit(Side::Buy, 0, None).is_err());
+    }
+
+    #[test]
+    fn scheduled_sessions_cross_in_batches() {
+        let mut pool = MidpointPool::new();
+        let mut schedule = CrossSchedule::new(10).unwrap();
+        let mut lit = reference(98, 102);
+
+        let batch = schedule.poll(&mut pool, &lit).unwrap();
+        assert_eq!(batch.scheduled_at, 0);
+        assert!(batch.crosses.is_empty());
+
+        let sell = pool.submit(Side::Sell, 5, None).unwrap();
+        let buy = pool.submit(Side::Buy, 3, None).unwrap();
+        lit.set_clock(9);
+        // the orders wait for the next session instead of crossing now
+        assert!(schedule.poll(&mut pool, &lit).is_none());
+        assert_eq!(pool.get_order(buy).unwrap().size, 3);
+
+        // sessions at 10 and 20 were both missed and only one runs
+        lit.set_clock(27);
+        let batch = schedule.poll(&mut pool, &lit).unwrap();
+        assert_eq!(batch.scheduled_at, 20);
+        assert_eq!(batch.crosses.len(), 1);
+        assert_eq!((batch.crosses[0].sell_id, batch.crosses[0].size), (sell, 3));
+        assert!(schedule.poll(&mut pool, &lit).is_none());
+        lit.set_clock(30);
+        assert!(schedule.poll(&mut pool, &lit).is_some());
+
+        assert!(CrossSchedule::new(0).is_err());
+    }
+}
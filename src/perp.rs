@@ -0,0 +1,426 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    Error, OrderResponse, OrderTicket, OrderType, Result, Side, TimeInForce, book::Orderbook,
+};
+
+/// One account's funding payment for one interval. Positive payments are
+/// received, negative ones paid.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FundingEvent {
+    pub at: u64,
+    pub account: u64,
+    pub position: i64,
+    pub mark_price: i64,
+    pub rate_bps: i64,
+    pub payment: i64,
+}
+
+/// Mark price and funding for a perpetual traded on a book. The index is
+/// whatever external spot reference the caller feeds in.
+#[derive(Debug)]
+pub struct Funding {
+    /// time between funding settlements, in the caller's clock
+    pub interval: u64,
+    /// the rate for one interval never goes beyond this either way
+    pub max_rate_bps: i64,
+    next_settlement: u64,
+    positions: BTreeMap<u64, i64>,
+}
+
+impl Funding {
+    /// First settlement happens one interval after `start`
+    pub fn new(interval: u64, max_rate_bps: i64, start: u64) -> Result<Self> {
+        if interval == 0 {
+            return Err("Funding interval must be positive".into());
+        }
+
+        Ok(Self {
+            interval,
+            max_rate_bps,
+            next_settlement: start + interval,
+            positions: BTreeMap::new(),
+        })
+    }
+
+    /// The index clamped into the book's spread, so a thin or one sided
+    /// book can't drag the mark far from fair value
+    pub fn mark_price(book: &Orderbook, index: i64) -> i64 {
+        let mut mark = index;
+        if let Some(bid) = book.get_best_bid() {
+            mark = mark.max(bid.price);
+        }
+        if let Some(ask) = book.get_best_ask() {
+            mark = mark.min(ask.price);
+        }
+        mark
+    }
+
+    /// Premium of the mark over the index, capped at `max_rate_bps`
+    pub fn rate_bps(&self, mark_price: i64, index: i64) -> i64 {
+        if index <= 0 {
+            return 0;
+        }
+        ((mark_price - index) * 10_000 / index).clamp(-self.max_rate_bps, self.max_rate_bps)
+    }
+
+    /// Net position for an account, positive is long. Zero stops tracking it.
+    pub fn set_position(&mut self, account: u64, position: i64) {
+        if position == 0 {
+            self.positions.remove(&account);
+        } else {
+            self.positions.insert(account, position);
+        }
+    }
+
+    pub fn position(&self, account: u64) -> i64 {
+        self.positions.get(&account).copied().unwrap_or_default()
+    }
+
+    pub fn next_settlement(&self) -> u64 {
+        self.next_settlement
+    }
+
+    /// Settle every interval that ended by `now` at the current mark and
+    /// publish the payments on the book's funding feed, see
+    /// `Orderbook::drain_funding_events`. Longs pay shorts while the mark
+    /// trades over the index.
+    pub fn accrue(&mut self, now: u64, book: &mut Orderbook, index: i64) {
+        let mark_price = Self::mark_price(book, index);
+        let rate_bps = self.rate_bps(mark_price, index);
+
+        while self.next_settlement <= now {
+            for (account, position) in self.positions.iter() {
+                book.publish_funding(FundingEvent {
+                    at: self.next_settlement,
+                    account: *account,
+                    position: *position,
+                    mark_price,
+                    rate_bps,
+                    payment: -position * mark_price * rate_bps / 10_000,
+                });
+            }
+            self.next_settlement += self.interval;
+        }
+    }
+}
+
+/// Collateral and an open position on the perp
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarginAccount {
+    pub collateral: i64,
+    /// net position, positive is long
+    pub position: i64,
+    pub entry_price: i64,
+}
+
+impl MarginAccount {
+    /// Collateral plus unrealized profit at `mark_price`
+    pub fn equity(&self, mark_price: i64) -> i64 {
+        self.collateral + self.position * (mark_price - self.entry_price)
+    }
+
+    pub fn maintenance_margin(&self, mark_price: i64, maintenance_bps: i64) -> i64 {
+        self.position.abs() * mark_price * maintenance_bps / 10_000
+    }
+}
+
+/// A forced close sent through the book on an account's behalf
+#[derive(Debug, Clone, PartialEq)]
+pub struct Liquidation {
+    pub at: u64,
+    pub account: u64,
+    /// the taker order id on the trades it caused, which is how they are
+    /// told apart from ordinary flow on `Orderbook::drain_trades`
+    pub order_id: u64,
+    pub side: Side,
+    pub size: i64,
+    pub notional: i64,
+    pub mark_price: i64,
+}
+
+/// Closes out accounts whose equity falls below maintenance margin with
+/// reduce-only market orders, matched like any other flow
+#[derive(Debug)]
+pub struct Liquidator {
+    /// margin required to keep a position open, in bps of its value
+    pub maintenance_bps: i64,
+    accounts: BTreeMap<u64, MarginAccount>,
+    liquidations: Vec<Liquidation>,
+}
+
+impl Liquidator {
+    pub fn new(maintenance_bps: i64) -> Self {
+        Self {
+            maintenance_bps,
+            accounts: BTreeMap::new(),
+            liquidations: Vec::new(),
+        }
+    }
+
+    pub fn set_account(&mut self, account: u64, margin: MarginAccount) {
+        self.accounts.insert(account, margin);
+    }
+
+    pub fn get_account(&self, account: u64) -> Option<&MarginAccount> {
+        self.accounts.get(&account)
+    }
+
+    /// Book funding payments against collateral
+    pub fn apply_funding(&mut self, events: &[FundingEvent]) {
+        for event in events {
+            if let Some(margin) = self.accounts.get_mut(&event.account) {
+                margin.collateral += event.payment;
+            }
+        }
+    }
+
+    /// Liquidate every account under maintenance at the current mark.
+    /// Whatever the book can't absorb stays open for the next pass. An
+    /// account the book rejects is skipped and returned with the reason,
+    /// the rest are still liquidated.
+    pub fn run(&mut self, now: u64, book: &mut Orderbook, index: i64) -> Vec<(u64, Error)> {
+        let mark_price = Funding::mark_price(book, index);
+        let mut rejected = Vec::new();
+
+        for (account, margin) in self.accounts.iter_mut() {
+            if margin.position == 0
+                || margin.equity(mark_price)
+                    >= margin.maintenance_margin(mark_price, self.maintenance_bps)
+            {
+                continue;
+            }
+
+            let side = if margin.position > 0 {
+                Side::Sell
+            } else {
+                Side::Buy
+            };
+            // a market order for the position never flips it
+            let fill = match book.accept_order(OrderTicket {
+                side,
+                size: margin.position.abs(),
+                order_type: OrderType::Market,
+                time_in_force: TimeInForce::Gtc,
+                min_qty: None,
+                owner: *account,
+                iceberg: None,
+            }) {
+                Ok(OrderResponse::Market(fill)) => fill,
+                Ok(OrderResponse::Limit(_)) => continue,
+                Err(reason) => {
+                    rejected.push((*account, reason));
+                    continue;
+                }
+            };
+            // reduce-only, so nothing is left resting under `MarketPolicy::ToLimit`
+            if let Some(id) = fill.resting_id
+                && let Err(reason) = book.cancel_order(id)
+            {
+                rejected.push((*account, reason));
+            }
+            if fill.size == 0 {
+                continue;
+            }
+
+            // realized against the entry on the whole notional, not a
+            // rounded average price
+            let entry = fill.size * margin.entry_price;
+            margin.collateral += match side {
+                Side::Sell => fill.notional - entry,
+                Side::Buy => entry - fill.notional,
+            };
+            margin.position -= match side {
+                Side::Sell => fill.size,
+                Side::Buy => -fill.size,
+            };
+
+            self.liquidations.push(Liquidation {
+                at: now,
+                account: *account,
+                order_id: fill.id,
+                side,
+                size: fill.size,
+                notional: fill.notional,
+                mark_price,
+            });
+        }
+        rejected
+    }
+
+    /// Every liquidation since the last drain, oldest first
+    pub fn drain_liquidations(&mut self) -> Vec<Liquidation> {
+        std::mem::take(&mut self.liquidations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MarketPolicy, OrderTicket, OrderType, Side};
+
+    fn limit(side: Side, price: i64, size: i64) -> OrderTicket {
+        OrderTicket {
+            side,
+            size,
+            order_type: OrderType::Limit(price),
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        }
+    }
+
+    #[test]
+    fn mark_is_the_index_clamped_into_the_spread() {
+        let mut book = Orderbook::new();
+        assert_eq!(Funding::mark_price(&book, 1_000), 1_000);
+
+        book.accept_order(limit(Side::Buy, 1_010, 1)).unwrap();
+        assert_eq!(Funding::mark_price(&book, 1_000), 1_010);
+        book.accept_order(limit(Side::Sell, 1_020, 1)).unwrap();
+        assert_eq!(Funding::mark_price(&book, 1_015), 1_015);
+        assert_eq!(Funding::mark_price(&book, 1_100), 1_020);
+    }
+
+    #[test]
+    fn longs_pay_shorts_each_interval() {
+        let mut book = Orderbook::new();
+        book.accept_order(limit(Side::Buy, 1_010, 1)).unwrap();
+
+        let mut funding = Funding::new(8, 75, 0).unwrap();
+        assert!(Funding::new(0, 75, 0).is_err());
+        funding.set_position(1, 10);
+        funding.set_position(2, -4);
+        funding.set_position(3, 5);
+        funding.set_position(3, 0);
+
+        funding.accrue(7, &mut book, 1_000);
+        assert!(book.drain_funding_events().is_empty());
+
+        // two intervals have passed by 17, the 1% premium is capped at 75bps
+        funding.accrue(17, &mut book, 1_000);
+        let mut restored = Orderbook::from_snapshot(book.snapshot()).unwrap();
+        let events = book.drain_funding_events();
+        assert_eq!(restored.drain_funding_events(), events);
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0].at, 8);
+        assert_eq!(events[0].rate_bps, 75);
+        assert_eq!(events[0].payment, -75);
+        assert_eq!(events[1].account, 2);
+        assert_eq!(events[1].payment, 30);
+        assert_eq!(events[3].at, 16);
+        assert_eq!(funding.next_settlement(), 24);
+    }
+
+    #[test]
+    fn underwater_accounts_are_closed_through_the_book() {
+        let mut book = Orderbook::new();
+        book.accept_order(limit(Side::Buy, 950, 5)).unwrap();
+        book.accept_order(limit(Side::Buy, 949, 1)).unwrap();
+        book.accept_order(limit(Side::Buy, 940, 100)).unwrap();
+        book.accept_order(limit(Side::Sell, 960, 100)).unwrap();
+
+        let mut liquidator = Liquidator::new(500);
+        let long = MarginAccount {
+            collateral: 500,
+            position: 10,
+            entry_price: 1_000,
+        };
+        liquidator.set_account(1, long.clone());
+        liquidator.set_account(
+            2,
+            MarginAccount {
+                collateral: 10_000,
+                ..long
+            },
+        );
+
+        // equity 0 against 475 maintenance, account 2 is comfortably above
+        assert!(liquidator.run(5, &mut book, 950).is_empty());
+        let liquidations = liquidator.drain_liquidations();
+        let notional = 5 * 950 + 949 + 4 * 940;
+        assert_eq!(
+            liquidations,
+            vec![Liquidation {
+                at: 5,
+                account: 1,
+                order_id: liquidations[0].order_id,
+                side: Side::Sell,
+                size: 10,
+                notional,
+                mark_price: 950,
+            }]
+        );
+        // the trades carry the liquidation's order id
+        let trades = book.drain_trades();
+        assert_eq!(trades.len(), 3);
+        assert!(
+            trades
+                .iter()
+                .all(|trade| trade.taker_order_id == liquidations[0].order_id)
+        );
+
+        // an average of 945.9 is not rounded down to 945
+        let closed = liquidator.get_account(1).unwrap();
+        assert_eq!(closed.position, 0);
+        assert_eq!(closed.collateral, 500 + notional - 10 * 1_000);
+        assert_eq!(liquidator.get_account(2).unwrap().position, 10);
+
+        liquidator.apply_funding(&[FundingEvent {
+            at: 8,
+            account: 2,
+            position: 10,
+            mark_price: 950,
+            rate_bps: 10,
+            payment: -9,
+        }]);
+        assert_eq!(liquidator.get_account(2).unwrap().collateral, 9_991);
+    }
+
+    #[test]
+    fn liquidation_is_reduce_only_and_keeps_going_past_rejects() {
+        let mut book = Orderbook::with_config(crate::BookConfig {
+            market_policy: MarketPolicy::ToLimit,
+            lot_size: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        book.accept_order(limit(Side::Sell, 1_000, 2)).unwrap();
+
+        // account 1's position is off the lot size so the book turns its
+        // buy away, account 2 is still closed out after it
+        let mut liquidator = Liquidator::new(500);
+        let short = MarginAccount {
+            collateral: 0,
+            position: -5,
+            entry_price: 900,
+        };
+        liquidator.set_account(1, short.clone());
+        liquidator.set_account(
+            2,
+            MarginAccount {
+                position: -4,
+                ..short
+            },
+        );
+
+        let rejected = liquidator.run(1, &mut book, 1_000);
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].0, 1);
+        assert_eq!(liquidator.get_account(1).unwrap().position, -5);
+
+        let liquidations = liquidator.drain_liquidations();
+        assert_eq!(liquidations.len(), 1);
+        assert_eq!(liquidations[0].account, 2);
+        assert_eq!(liquidations[0].size, 2);
+        let closed = liquidator.get_account(2).unwrap();
+        assert_eq!(closed.position, -2);
+        assert_eq!(closed.collateral, 2 * 900 - 2 * 1_000);
+
+        // the two it couldn't buy didn't stay on the book as a bid
+        assert_eq!(book.get_best_bid(), None);
+        assert_eq!(book.get_best_ask(), None);
+    }
+}
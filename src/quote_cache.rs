@@ -0,0 +1,150 @@
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering, fence};
+
+use crate::PriceSize;
+
+/// an empty side is stored as a zero size, prices are always positive
+const EMPTY: i64 = 0;
+
+/// Best bid and offer as seen by a reader of the cache
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Quote {
+    pub bid: Option<PriceSize>,
+    pub ask: Option<PriceSize>,
+}
+
+/// Top of book shared with other threads through a seqlock. Only the
+/// matching thread writes, readers never block it and just retry when
+/// they raced a write. Share it with `Arc` and hand one to the book via
+/// `Orderbook::set_quote_cache`.
+#[derive(Debug, Default)]
+pub struct QuoteCache {
+    /// odd while a write is in progress
+    seq: AtomicU64,
+    bid_price: AtomicI64,
+    bid_size: AtomicI64,
+    ask_price: AtomicI64,
+    ask_size: AtomicI64,
+}
+
+impl QuoteCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Must only ever be called from one thread at a time
+    pub fn publish(&self, quote: Quote) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        let (bid_price, bid_size) = split(quote.bid);
+        let (ask_price, ask_size) = split(quote.ask);
+        self.bid_price.store(bid_price, Ordering::Relaxed);
+        self.bid_size.store(bid_size, Ordering::Relaxed);
+        self.ask_price.store(ask_price, Ordering::Relaxed);
+        self.ask_size.store(ask_size, Ordering::Relaxed);
+
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    /// A consistent quote, never half of one write and half of another
+    pub fn read(&self) -> Quote {
+        loop {
+            let before = self.seq.load(Ordering::Acquire);
+            if before % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+
+            let bid = join(
+                self.bid_price.load(Ordering::Relaxed),
+                self.bid_size.load(Ordering::Relaxed),
+            );
+            let ask = join(
+                self.ask_price.load(Ordering::Relaxed),
+                self.ask_size.load(Ordering::Relaxed),
+            );
+
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == before {
+                return Quote { bid, ask };
+            }
+        }
+    }
+
+    /// How many writes have been published, handy for spotting changes
+    pub fn version(&self) -> u64 {
+        self.seq.load(Ordering::Acquire) / 2
+    }
+}
+
+fn split(level: Option<PriceSize>) -> (i64, i64) {
+    level.map_or((EMPTY, EMPTY), |level| (level.price, level.size))
+}
+
+fn join(price: i64, size: i64) -> Option<PriceSize> {
+    (size != EMPTY).then_some(PriceSize { price, size })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn reads_back_what_was_published() {
+        let cache = QuoteCache::new();
+        assert_eq!(cache.read(), Quote::default());
+
+        let quote = Quote {
+            bid: Some(PriceSize {
+                price: 99,
+                size: 10,
+            }),
+            ask: None,
+        };
+        cache.publish(quote);
+
+        assert_eq!(cache.read(), quote);
+        assert_eq!(cache.version(), 1);
+    }
+
+    #[test]
+    fn readers_never_see_a_torn_quote() {
+        let cache = Arc::new(QuoteCache::new());
+
+        let writer = {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                for i in 1..=20_000 {
+                    // every field of one write carries the same value
+                    let level = Some(PriceSize { price: i, size: i });
+                    cache.publish(Quote {
+                        bid: level,
+                        ask: level,
+                    });
+                }
+            })
+        };
+
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    while cache.version() < 20_000 {
+                        let quote = cache.read();
+                        assert_eq!(quote.bid, quote.ask);
+                        if let Some(bid) = quote.bid {
+                            assert_eq!(bid.price, bid.size);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+}
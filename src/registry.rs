@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+
+use crate::{OrderTicket, OrderType, Result, book::Orderbook, tick::TickTable};
+
+/// When an instrument takes orders, in the book's clock, from `open` up
+/// to but not including `close`
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionTimes {
+    pub open: u64,
+    pub close: u64,
+}
+
+impl SessionTimes {
+    pub fn is_open(&self, now: u64) -> bool {
+        (self.open..self.close).contains(&now)
+    }
+}
+
+/// Reference data for one instrument
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Instrument {
+    /// handed out by the registry in the order instruments were added
+    pub id: u32,
+    pub symbol: String,
+    pub max_price: i64,
+    pub tick_table: TickTable,
+    pub lot_size: i64,
+    /// trades around the clock when None
+    pub session: Option<SessionTimes>,
+}
+
+impl Instrument {
+    /// An empty book set up for this instrument
+    pub fn book(&self) -> Orderbook {
+        let mut book = Orderbook::with_tick_table(self.max_price, self.tick_table.clone());
+        book.lot_size = self.lot_size;
+        book.symbol = self.symbol.clone();
+        book
+    }
+
+    /// Refuse an order that does not fit the instrument at `now`
+    pub fn validate(&self, ticket: &OrderTicket, now: u64) -> Result<()> {
+        if let Some(session) = self.session
+            && !session.is_open(now)
+        {
+            return Err(format!("{} is not in session at {}", self.symbol, now));
+        }
+        if ticket.order_type != OrderType::QuoteMarket && ticket.size % self.lot_size != 0 {
+            return Err(format!(
+                "Size {} of {} is not a multiple of the lot size {}",
+                ticket.size, self.symbol, self.lot_size
+            ));
+        }
+        if let OrderType::Limit(price) | OrderType::ImmediateOrCancel(price) = ticket.order_type
+            && !self.tick_table.is_valid_price(price)
+        {
+            return Err(format!("Price {} of {} is off tick", price, self.symbol));
+        }
+        Ok(())
+    }
+}
+
+/// Every instrument listed, looked up by symbol or id
+#[derive(Debug, Default, Clone)]
+pub struct InstrumentRegistry {
+    instruments: Vec<Instrument>,
+    ids: BTreeMap<String, u32>,
+}
+
+impl InstrumentRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// List a new instrument, handing back its id
+    pub fn register(
+        &mut self,
+        symbol: &str,
+        max_price: i64,
+        tick_table: TickTable,
+        lot_size: i64,
+        session: Option<SessionTimes>,
+    ) -> Result<u32> {
+        if self.ids.contains_key(symbol) {
+            return Err(format!("{} is already listed", symbol));
+        }
+        if lot_size <= 0 {
+            return Err(format!("Lot size {} must be positive", lot_size));
+        }
+        if max_price < tick_table.min_price() {
+            return Err(format!(
+                "Price range {} to {} is empty",
+                tick_table.min_price(),
+                max_price
+            ));
+        }
+        if let Some(session) = session
+            && session.close <= session.open
+        {
+            return Err(format!(
+                "Session closing at {} before it opens at {}",
+                session.close, session.open
+            ));
+        }
+
+        let id = self.instruments.len() as u32;
+        self.instruments.push(Instrument {
+            id,
+            symbol: symbol.to_string(),
+            max_price,
+            tick_table,
+            lot_size,
+            session,
+        });
+        self.ids.insert(symbol.to_string(), id);
+        Ok(id)
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&Instrument> {
+        let id = self.ids.get(symbol)?;
+        self.by_id(*id)
+    }
+
+    pub fn by_id(&self, id: u32) -> Option<&Instrument> {
+        self.instruments.get(id as usize)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Instrument> {
+        self.instruments.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Side, TimeInForce};
+
+    fn limit(price: i64, size: i64) -> OrderTicket {
+        OrderTicket {
+            side: Side::Buy,
+            size,
+            order_type: OrderType::Limit(price),
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        }
+    }
+
+    #[test]
+    fn looks_up_and_validates_by_instrument() {
+        let mut registry = InstrumentRegistry::new();
+        let session = SessionTimes {
+            open: 100,
+            close: 200,
+        };
+        let btc = registry
+            .register("BTC", 1_000, TickTable::fixed(5, 5), 10, Some(session))
+            .unwrap();
+        let eth = registry
+            .register("ETH", 1_000, TickTable::fixed(1, 1), 1, None)
+            .unwrap();
+        assert_eq!((btc, eth), (0, 1));
+        assert!(
+            registry
+                .register("BTC", 1_000, TickTable::fixed(1, 1), 1, None)
+                .is_err()
+        );
+        assert!(registry.get("SOL").is_none());
+        assert_eq!(registry.by_id(eth).unwrap().symbol, "ETH");
+
+        let btc = registry.get("BTC").unwrap();
+        assert!(btc.validate(&limit(500, 20), 150).is_ok());
+        assert!(btc.validate(&limit(500, 20), 200).is_err());
+        assert!(btc.validate(&limit(500, 25), 150).is_err());
+        assert!(btc.validate(&limit(502, 20), 150).is_err());
+
+        let book = btc.book();
+        assert_eq!((book.symbol.as_str(), book.lot_size), ("BTC", 10));
+    }
+}
@@ -0,0 +1,220 @@
+use std::io::{BufRead, Write};
+
+use orderbook::{
+    OrderResponse, OrderTicket, OrderType, Result, Side, TimeInForce, book::Orderbook,
+};
+
+const HELP: &str = "\
+buy <size> [@ <price>]   market order, or limit when a price is given
+sell <size> [@ <price>]  same for the ask side
+cancel <id>              pull a resting order
+depth <levels>           show the top levels of both sides
+help                     show this message
+quit                     leave";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Order(OrderTicket),
+    Cancel(u64),
+    Depth(usize),
+    Help,
+    Quit,
+}
+
+/// Read commands line by line and drive a live book until quit or EOF
+pub fn run(input: impl BufRead, mut output: impl Write) -> Result<()> {
+    let mut book = Orderbook::new();
+    let io_error = |e: std::io::Error| e.to_string();
+
+    write!(output, "> ").map_err(io_error)?;
+    output.flush().map_err(io_error)?;
+
+    for line in input.lines() {
+        let line = line.map_err(io_error)?;
+        if line.trim().is_empty() {
+            write!(output, "> ").map_err(io_error)?;
+            output.flush().map_err(io_error)?;
+            continue;
+        }
+
+        let reply = match parse(&line) {
+            Ok(Command::Quit) => break,
+            Ok(command) => execute(&mut book, command),
+            Err(e) => Err(e),
+        };
+
+        match reply {
+            Ok(reply) if reply.is_empty() => {}
+            Ok(reply) => writeln!(output, "{}", reply).map_err(io_error)?,
+            Err(e) => writeln!(output, "error: {}", e).map_err(io_error)?,
+        }
+        write!(output, "> ").map_err(io_error)?;
+        output.flush().map_err(io_error)?;
+    }
+
+    Ok(())
+}
+
+pub fn parse(line: &str) -> Result<Command> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    let number = |word: Option<&&str>| -> Result<i64> {
+        let word = word.ok_or("Missing a number")?;
+        word.parse::<i64>()
+            .map_err(|_| format!("{} is not a number", word))
+    };
+
+    match words.first().copied() {
+        None => Err("Empty command, try help".into()),
+        Some("buy") | Some("sell") => {
+            let side = if words[0] == "buy" {
+                Side::Buy
+            } else {
+                Side::Sell
+            };
+            let size = number(words.get(1))?;
+            let order_type = match words.get(2).copied() {
+                None => OrderType::Market,
+                Some("@") => OrderType::Limit(number(words.get(3))?),
+                Some(word) => return Err(format!("Expected @ but got {}", word)),
+            };
+
+            Ok(Command::Order(OrderTicket {
+                order_type,
+                size,
+                side,
+                time_in_force: TimeInForce::Gtc,
+                min_qty: None,
+                owner: 0,
+                iceberg: None,
+            }))
+        }
+        Some("cancel") => u64::try_from(number(words.get(1))?)
+            .map(Command::Cancel)
+            .map_err(|_| "Order ids are never negative".into()),
+        Some("depth") => usize::try_from(number(words.get(1))?)
+            .map(Command::Depth)
+            .map_err(|_| "Depth is never negative".into()),
+        Some("help") => Ok(Command::Help),
+        Some("quit") | Some("exit") => Ok(Command::Quit),
+        Some(word) => Err(format!("Unknown command {}, try help", word)),
+    }
+}
+
+pub fn execute(book: &mut Orderbook, command: Command) -> Result<String> {
+    match command {
+        Command::Order(ticket) => match book.accept_order(ticket)? {
+            OrderResponse::Limit(limit) => Ok(format!("resting as id {}", limit.id)),
+            OrderResponse::Market(market) => Ok(format!(
+                "filled {} for {} notional, {} unfilled",
+                market.size, market.notional, market.remaining
+            )),
+        },
+        Command::Cancel(id) => {
+            let cancelled = book.cancel_order(id)?;
+            Ok(format!(
+                "cancelled {} ({} @ {})",
+                cancelled.id, cancelled.size, cancelled.price
+            ))
+        }
+        Command::Depth(levels) => {
+            let mut lines: Vec<String> = book
+                .depth(Side::Sell, levels)
+                .iter()
+                .rev()
+                .map(|level| format!("ASK {:>8} x {}", level.price, level.size))
+                .collect();
+            lines.push("-".repeat(20));
+            lines.extend(
+                book.depth(Side::Buy, levels)
+                    .iter()
+                    .map(|level| format!("BID {:>8} x {}", level.price, level.size)),
+            );
+            Ok(lines.join("\n"))
+        }
+        Command::Help => Ok(HELP.to_string()),
+        Command::Quit => Ok(String::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_orders_and_commands() {
+        assert_eq!(
+            parse("buy 10 @ 10000").unwrap(),
+            Command::Order(OrderTicket {
+                order_type: OrderType::Limit(10_000),
+                size: 10,
+                side: Side::Buy,
+                time_in_force: TimeInForce::Gtc,
+                min_qty: None,
+                owner: 0,
+                iceberg: None,
+            })
+        );
+        assert_eq!(
+            parse("  sell 3 ").unwrap(),
+            Command::Order(OrderTicket {
+                order_type: OrderType::Market,
+                size: 3,
+                side: Side::Sell,
+                time_in_force: TimeInForce::Gtc,
+                min_qty: None,
+                owner: 0,
+                iceberg: None,
+            })
+        );
+        assert_eq!(parse("cancel 42").unwrap(), Command::Cancel(42));
+        assert_eq!(parse("depth 5").unwrap(), Command::Depth(5));
+        assert_eq!(parse("quit").unwrap(), Command::Quit);
+
+        assert!(parse("buy ten").is_err());
+        assert!(parse("buy 10 at 5").is_err());
+        assert!(parse("dance").is_err());
+        assert!(parse("cancel -1").is_err());
+    }
+
+    #[test]
+    fn drives_a_live_book() {
+        let mut book = Orderbook::new();
+        let mut run = |line: &str| execute(&mut book, parse(line).unwrap());
+
+        assert_eq!(run("buy 10 @ 99").unwrap(), "resting as id 0");
+        assert_eq!(run("buy 5 @ 98").unwrap(), "resting as id 1");
+        assert_eq!(run("sell 7 @ 101").unwrap(), "resting as id 2");
+        assert_eq!(
+            run("depth 5").unwrap(),
+            "ASK      101 x 7\n\
+             --------------------\n\
+             BID       99 x 10\n\
+             BID       98 x 5"
+        );
+
+        assert_eq!(
+            run("sell 12").unwrap(),
+            "filled 12 for 1186 notional, 0 unfilled"
+        );
+        assert_eq!(run("cancel 1").unwrap(), "cancelled 1 (3 @ 98)");
+        assert!(run("cancel 1").is_err());
+        assert_eq!(
+            run("depth 1").unwrap(),
+            "ASK      101 x 7\n--------------------"
+        );
+    }
+
+    #[test]
+    fn run_loop_reports_errors_and_stops_on_quit() {
+        let input = "buy 1 @ 5\n\nnonsense\nquit\nbuy 1 @ 6\n";
+        let mut output = Vec::new();
+
+        run(input.as_bytes(), &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "> resting as id 0\n> > error: Unknown command nonsense, try help\n> "
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{Event, Result, book::Orderbook};
+
+/// A hot standby for a primary book. The primary's sequenced event log is
+/// streamed to it and applied in order, so on failover it takes over with
+/// exactly the primary's state as of the last event it applied. Nothing it
+/// does is published while it stands by.
+#[derive(Debug)]
+pub struct Standby {
+    book: Orderbook,
+}
+
+impl Standby {
+    /// Stand by with `book`, set up like the primary was when the events
+    /// to be streamed started, e.g. `primary.empty_copy()` for a primary
+    /// that has not logged anything yet
+    pub fn new(book: Orderbook) -> Self {
+        Self { book }
+    }
+
+    /// The next event it expects from the primary
+    pub fn next_seq(&self) -> u64 {
+        self.book.events_drained + self.book.event_log.len() as u64
+    }
+
+    /// The last event applied, None before the first
+    pub fn last_applied(&self) -> Option<u64> {
+        self.next_seq().checked_sub(1)
+    }
+
+    /// Apply events from the primary in order. Anything already applied,
+    /// e.g. from a resend, is skipped. A gap stops at the event before it,
+    /// so the missing ones can be requested from `next_seq()`.
+    pub fn apply(&mut self, events: impl IntoIterator<Item = Event>) -> Result<()> {
+        let mut applied = Ok(());
+        for event in events {
+            let expected = self.next_seq();
+            if event.seq < expected {
+                continue;
+            }
+            if event.seq > expected {
+                applied = Err(format!(
+                    "Expected event {} but got event {}",
+                    expected, event.seq
+                ));
+                break;
+            }
+            self.book.apply_event(event.kind);
+        }
+
+        self.book.drain_events();
+        self.book.discard_feeds();
+        applied
+    }
+
+    /// Pick up whatever the primary logged since the last call
+    pub fn catch_up(&mut self, primary: &Orderbook) -> Result<()> {
+        self.apply(primary.events_since(self.next_seq()).to_vec())
+    }
+
+    /// Fail over. The book accepts orders from here on and numbers its
+    /// events on from the last one applied.
+    pub fn promote(self) -> Orderbook {
+        self.book
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_log::decode;
+
+    fn send(book: &mut Orderbook, line: &str) {
+        let _ = book.accept_order(decode(line).unwrap());
+    }
+
+    #[test]
+    fn standby_takes_over_where_the_primary_left_off() {
+        let mut primary = Orderbook::new();
+        let mut standby = Standby::new(primary.empty_copy());
+        assert_eq!(standby.last_applied(), None);
+
+        primary.set_day_end(Some(500));
+        for line in ["B L 100 10", "S L 103 5 D", "B T 104 2", "S M 3"] {
+            send(&mut primary, line);
+        }
+        standby.catch_up(&primary).unwrap();
+        assert_eq!(standby.last_applied(), Some(4));
+
+        // a resend overlapping what was applied is fine, a gap is not
+        primary.cancel_order(0).unwrap();
+        send(&mut primary, "B L 101 4");
+        let events = primary.events_since(0).to_vec();
+        assert!(standby.apply(events[6..].to_vec()).is_err());
+        standby.apply(events[3..].to_vec()).unwrap();
+        assert_eq!(standby.next_seq(), 7);
+
+        // the primary dies, the standby carries on identically
+        let mut promoted = standby.promote();
+        assert_eq!(promoted.state_digest(), primary.state_digest());
+        send(&mut promoted, "S L 101 1");
+        send(&mut primary, "S L 101 1");
+        assert_eq!(promoted.event_log[0].seq, 7);
+        assert_eq!(promoted.state_digest(), primary.state_digest());
+        assert_eq!(promoted.get_best_bid().unwrap().size, 3);
+        assert_eq!(promoted.drain_trades().len(), 1);
+        assert_eq!(promoted.get_best_ask().unwrap().price, 103);
+    }
+}
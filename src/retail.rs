@@ -0,0 +1,244 @@
+use crate::{OrderResponse, OrderTicket, OrderType, Result, Side, book::Orderbook};
+
+/// Liquidity offered to one retail order while its auction runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Improvement {
+    pub responder: u64,
+    pub price: i64,
+    pub size: i64,
+}
+
+/// A retail order waiting out its auction
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetailAuction {
+    pub id: u64,
+    pub ticket: OrderTicket,
+    /// the book's clock the auction closes at
+    pub ends_at: u64,
+    /// the opposite best when it started, responses have to beat it
+    pub to_beat: Option<i64>,
+    /// in the order they came in
+    pub responses: Vec<Improvement>,
+}
+
+/// How a retail order ended up executing once its auction closed
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetailExecution {
+    pub auction_id: u64,
+    /// responses it traded against, best price first
+    pub fills: Vec<Improvement>,
+    /// what the book answered for whatever the responses did not fill,
+    /// None when they filled it all
+    pub remainder: Option<Result<OrderResponse>>,
+}
+
+/// Holds orders flagged as retail, by coming in through here, for a short
+/// auction of `window` of the book's clock. Responders offer liquidity
+/// that improves on the opposite best as it was when the auction started,
+/// and when it closes the order takes the best of those that still beat
+/// the book, then goes to the book for the rest. Poll it whenever the
+/// clock moves.
+#[derive(Debug, Default)]
+pub struct RetailPriceImprovement {
+    pub window: u64,
+    /// open auctions, oldest first
+    auctions: Vec<RetailAuction>,
+    next_id: u64,
+}
+
+impl RetailPriceImprovement {
+    pub fn new(window: u64) -> Result<Self> {
+        if window == 0 {
+            return Err("Auction window must be positive".into());
+        }
+
+        Ok(Self {
+            window,
+            ..Default::default()
+        })
+    }
+
+    /// Start an auction for a retail market or limit order, handing
+    /// back its id for responders to address
+    pub fn submit(&mut self, book: &Orderbook, ticket: OrderTicket) -> Result<u64> {
+        if !matches!(ticket.order_type, OrderType::Market | OrderType::Limit(_)) {
+            return Err("Only market and limit orders can be auctioned".into());
+        }
+        if ticket.size <= 0 {
+            return Err(format!("Size {} must be positive", ticket.size));
+        }
+
+        let to_beat = match ticket.side {
+            Side::Buy => book.get_best_ask(),
+            Side::Sell => book.get_best_bid(),
+        }
+        .map(|best| best.price);
+        let id = self.next_id;
+        self.next_id += 1;
+        self.auctions.push(RetailAuction {
+            id,
+            ticket,
+            ends_at: book.clock.saturating_add(self.window),
+            to_beat,
+            responses: Vec::new(),
+        });
+        Ok(id)
+    }
+
+    /// Offer `size` at `price` to an open auction
+    pub fn respond(&mut self, auction_id: u64, improvement: Improvement) -> Result<()> {
+        let auction = self
+            .auctions
+            .iter_mut()
+            .find(|auction| auction.id == auction_id)
+            .ok_or_else(|| format!("No open auction with id {}", auction_id))?;
+        if improvement.size <= 0 {
+            return Err(format!("Size {} must be positive", improvement.size));
+        }
+        if !improves(auction.ticket.side, improvement.price, auction.to_beat)
+            || !within_limit(&auction.ticket, improvement.price)
+        {
+            return Err(format!(
+                "Price {} does not improve on {:?} within the order's limit",
+                improvement.price, auction.to_beat
+            ));
+        }
+
+        auction.responses.push(improvement);
+        Ok(())
+    }
+
+    pub fn auctions(&self) -> &[RetailAuction] {
+        &self.auctions
+    }
+
+    /// Close every auction whose window is over and execute its order
+    pub fn poll(&mut self, book: &mut Orderbook) -> Vec<RetailExecution> {
+        let (closed, open) = std::mem::take(&mut self.auctions)
+            .into_iter()
+            .partition(|auction| auction.ends_at <= book.clock);
+        self.auctions = open;
+
+        closed
+            .into_iter()
+            .map(|auction| execute(auction, book))
+            .collect()
+    }
+}
+
+fn execute(auction: RetailAuction, book: &mut Orderbook) -> RetailExecution {
+    let side = auction.ticket.side;
+    // the book may have come through the responses since they were made
+    let best = match side {
+        Side::Buy => book.get_best_ask(),
+        Side::Sell => book.get_best_bid(),
+    }
+    .map(|best| best.price);
+    let mut responses: Vec<Improvement> = auction
+        .responses
+        .into_iter()
+        .filter(|response| improves(side, response.price, best))
+        .collect();
+    // stable, so equal prices keep the order they came in
+    match side {
+        Side::Buy => responses.sort_by_key(|response| response.price),
+        Side::Sell => responses.sort_by_key(|response| std::cmp::Reverse(response.price)),
+    }
+
+    let mut left = auction.ticket.size;
+    let mut fills = Vec::new();
+    for response in responses {
+        if left == 0 {
+            break;
+        }
+        let size = response.size.min(left);
+        left -= size;
+        fills.push(Improvement { size, ..response });
+    }
+
+    let remainder = (left > 0).then(|| {
+        book.accept_order(OrderTicket {
+            size: left,
+            ..auction.ticket
+        })
+    });
+    RetailExecution {
+        auction_id: auction.id,
+        fills,
+        remainder,
+    }
+}
+
+/// Whether `price` is better for an order on `side` than `to_beat`
+fn improves(side: Side, price: i64, to_beat: Option<i64>) -> bool {
+    to_beat.is_none_or(|to_beat| match side {
+        Side::Buy => price < to_beat,
+        Side::Sell => price > to_beat,
+    })
+}
+
+fn within_limit(ticket: &OrderTicket, price: i64) -> bool {
+    match (ticket.order_type.clone(), ticket.side) {
+        (OrderType::Limit(limit), Side::Buy) => price <= limit,
+        (OrderType::Limit(limit), Side::Sell) => price >= limit,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_log::decode;
+
+    fn offer(responder: u64, price: i64, size: i64) -> Improvement {
+        Improvement {
+            responder,
+            price,
+            size,
+        }
+    }
+
+    #[test]
+    fn retail_orders_take_improvements_before_the_book() {
+        let mut book = Orderbook::new();
+        book.accept_order(decode("B L 95 10").unwrap()).unwrap();
+        book.accept_order(decode("S L 101 10").unwrap()).unwrap();
+        let mut rpi = RetailPriceImprovement::new(3).unwrap();
+
+        let buy = rpi.submit(&book, decode("B M 10").unwrap()).unwrap();
+        rpi.respond(buy, offer(1, 100, 4)).unwrap();
+        rpi.respond(buy, offer(2, 99, 2)).unwrap();
+        rpi.respond(buy, offer(3, 100, 3)).unwrap();
+        // no better than the book
+        assert!(rpi.respond(buy, offer(4, 101, 5)).is_err());
+        assert!(rpi.respond(buy, offer(4, 100, 0)).is_err());
+
+        let sell = rpi.submit(&book, decode("S L 97 5").unwrap()).unwrap();
+        // beyond the order's own limit
+        assert!(rpi.respond(sell, offer(1, 96, 5)).is_err());
+        rpi.respond(sell, offer(1, 98, 5)).unwrap();
+
+        book.set_clock(2);
+        assert!(rpi.poll(&mut book).is_empty());
+        // the book beats that bid before the auction closes
+        book.accept_order(decode("B L 98 5").unwrap()).unwrap();
+        book.set_clock(3);
+        let executions = rpi.poll(&mut book);
+        assert!(rpi.auctions().is_empty());
+        assert!(rpi.respond(buy, offer(1, 100, 1)).is_err());
+
+        assert_eq!(
+            executions[0].fills,
+            vec![offer(2, 99, 2), offer(1, 100, 4), offer(3, 100, 3)]
+        );
+        // the last unit goes to the book
+        let Some(Ok(OrderResponse::Market(market))) = &executions[0].remainder else {
+            panic!("the rest goes to the book");
+        };
+        assert_eq!((market.size, market.notional), (1, 101));
+
+        assert!(executions[1].fills.is_empty());
+        assert!(matches!(executions[1].remainder, Some(Ok(_))));
+        assert_eq!(book.total_liquidity(Side::Buy), 10);
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use crate::{Result, Side};
+
+/// A market maker's answer to an RFQ, firm until the RFQ closes
+#[derive(Debug, Clone, PartialEq)]
+pub struct RfqQuote {
+    pub responder: u64,
+    pub price: i64,
+    pub size: i64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rfq {
+    pub id: u64,
+    /// the requester's side, responders quote the opposite one
+    pub side: Side,
+    pub size: i64,
+    pub expires_at: u64,
+    /// in arrival order, which breaks ties between equal prices
+    pub quotes: Vec<RfqQuote>,
+}
+
+/// Requests for quote and the temporary mini-book of responses for each.
+/// Like the heatmap the desk has no clock of its own, every call takes
+/// the caller's `now` and the window is measured in the same units.
+#[derive(Debug, Default)]
+pub struct RfqDesk {
+    open: HashMap<u64, Rfq>,
+    next_id: u64,
+}
+
+impl RfqDesk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Broadcast a request, open for responses for `window` from `now`
+    pub fn request(&mut self, side: Side, size: i64, now: u64, window: u64) -> Result<u64> {
+        if size <= 0 {
+            return Err(format!("Size {} must be positive", size));
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.open.insert(
+            id,
+            Rfq {
+                id,
+                side,
+                size,
+                expires_at: now + window,
+                quotes: Vec::new(),
+            },
+        );
+        Ok(id)
+    }
+
+    pub fn get_rfq(&self, id: u64) -> Option<&Rfq> {
+        self.open.get(&id)
+    }
+
+    /// Quote into an open RFQ. Quoting again replaces the responder's
+    /// previous quote and puts it at the back for ties.
+    pub fn respond(&mut self, id: u64, quote: RfqQuote, now: u64) -> Result<()> {
+        if quote.size <= 0 || quote.price <= 0 {
+            return Err(format!("Invalid quote {:?}", quote));
+        }
+
+        let rfq = self.live_rfq(id, now)?;
+        rfq.quotes
+            .retain(|existing| existing.responder != quote.responder);
+        rfq.quotes.push(quote);
+        Ok(())
+    }
+
+    /// Trade against the best responses until the requested size is met or
+    /// the quotes run out. Closes the RFQ either way.
+    pub fn execute(&mut self, id: u64, now: u64) -> Result<Vec<RfqQuote>> {
+        let rfq = self.live_rfq(id, now)?;
+        let side = rfq.side;
+        let mut remaining = rfq.size;
+
+        let mut quotes = std::mem::take(&mut rfq.quotes);
+        self.open.remove(&id);
+
+        // stable, so earlier quotes win ties
+        match side {
+            Side::Buy => quotes.sort_by_key(|quote| quote.price),
+            Side::Sell => quotes.sort_by_key(|quote| std::cmp::Reverse(quote.price)),
+        }
+
+        let mut fills = Vec::new();
+        for quote in quotes {
+            if remaining == 0 {
+                break;
+            }
+            let size = quote.size.min(remaining);
+            remaining -= size;
+            fills.push(RfqQuote { size, ..quote });
+        }
+        Ok(fills)
+    }
+
+    pub fn cancel(&mut self, id: u64) -> Result<Rfq> {
+        self.open
+            .remove(&id)
+            .ok_or_else(|| format!("No open RFQ with id {}", id))
+    }
+
+    /// Drop every RFQ whose window has closed, returning their ids
+    pub fn expire(&mut self, now: u64) -> Vec<u64> {
+        let mut expired: Vec<u64> = self
+            .open
+            .values()
+            .filter(|rfq| now >= rfq.expires_at)
+            .map(|rfq| rfq.id)
+            .collect();
+        expired.sort_unstable();
+
+        for id in &expired {
+            self.open.remove(id);
+        }
+        expired
+    }
+
+    fn live_rfq(&mut self, id: u64, now: u64) -> Result<&mut Rfq> {
+        let rfq = self
+            .open
+            .get_mut(&id)
+            .ok_or_else(|| format!("No open RFQ with id {}", id))?;
+        if now >= rfq.expires_at {
+            return Err(format!("RFQ {} expired at {}", id, rfq.expires_at));
+        }
+        Ok(rfq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(responder: u64, price: i64, size: i64) -> RfqQuote {
+        RfqQuote {
+            responder,
+            price,
+            size,
+        }
+    }
+
+    #[test]
+    fn executes_against_the_best_responses() {
+        let mut desk = RfqDesk::new();
+        let id = desk.request(Side::Buy, 10, 1_000, 500).unwrap();
+
+        desk.respond(id, quote(1, 101, 6), 1_100).unwrap();
+        desk.respond(id, quote(2, 100, 4), 1_200).unwrap();
+        desk.respond(id, quote(3, 101, 6), 1_300).unwrap();
+        desk.respond(id, quote(4, 105, 50), 1_400).unwrap();
+        // a requote replaces the old one and loses its place in the tie
+        desk.respond(id, quote(1, 101, 6), 1_450).unwrap();
+
+        let fills = desk.execute(id, 1_499).unwrap();
+        assert_eq!(fills, vec![quote(2, 100, 4), quote(3, 101, 6)]);
+        assert!(desk.get_rfq(id).is_none());
+        assert!(desk.execute(id, 1_499).is_err());
+    }
+
+    #[test]
+    fn sell_requests_take_the_highest_bids() {
+        let mut desk = RfqDesk::new();
+        let id = desk.request(Side::Sell, 5, 0, 10).unwrap();
+
+        desk.respond(id, quote(1, 99, 3), 1).unwrap();
+        desk.respond(id, quote(2, 98, 10), 2).unwrap();
+
+        assert_eq!(
+            desk.execute(id, 3).unwrap(),
+            vec![quote(1, 99, 3), quote(2, 98, 2)]
+        );
+    }
+
+    #[test]
+    fn the_window_closes() {
+        let mut desk = RfqDesk::new();
+        let late = desk.request(Side::Buy, 1, 0, 10).unwrap();
+        let open = desk.request(Side::Buy, 1, 5, 100).unwrap();
+
+        assert!(desk.respond(late, quote(1, 100, 1), 10).is_err());
+        assert!(desk.execute(late, 10).is_err());
+        assert!(desk.respond(open, quote(1, 0, 1), 10).is_err());
+
+        assert_eq!(desk.expire(10), vec![late]);
+        assert!(desk.get_rfq(open).is_some());
+        assert_eq!(desk.cancel(open).unwrap().id, open);
+        assert!(desk.request(Side::Buy, 0, 0, 1).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::VecDeque;
+
+use crate::{OrderResponse, OrderTicket, OrderType, Result, Side, book::Orderbook, view::BookView};
+
+/// One stage of the pre-trade pipeline. `now` is the caller's clock,
+/// in whatever unit the checks were configured with.
+pub trait RiskCheck {
+    /// Err with the reason to block the order
+    fn check(&mut self, ticket: &OrderTicket, book: BookView<'_>, now: u64) -> Result<()>;
+
+    /// Called once the book has answered an order that passed every check
+    fn on_response(&mut self, _ticket: &OrderTicket, _response: &OrderResponse) {}
+}
+
+/// An order the gateway refused before it reached the book
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiskReject {
+    pub ticket: OrderTicket,
+    pub reason: String,
+    pub at: u64,
+}
+
+/// Sits in front of the book for flow from a sponsored, untrusted source.
+/// Every order runs the checks in the order they were added and the
+/// first failure rejects it onto the gateway's own reject feed.
+#[derive(Default)]
+pub struct RiskGateway {
+    checks: Vec<Box<dyn RiskCheck>>,
+    rejects: Vec<RiskReject>,
+}
+
+impl RiskGateway {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_check(&mut self, check: impl RiskCheck + 'static) {
+        self.checks.push(Box::new(check));
+    }
+
+    pub fn submit(
+        &mut self,
+        book: &mut Orderbook,
+        ticket: OrderTicket,
+        now: u64,
+    ) -> Result<OrderResponse> {
+        for check in self.checks.iter_mut() {
+            if let Err(reason) = check.check(&ticket, book.view(), now) {
+                self.rejects.push(RiskReject {
+                    ticket,
+                    reason: reason.clone(),
+                    at: now,
+                });
+                return Err(reason);
+            }
+        }
+
+        let response = book.accept_order(ticket.clone())?;
+        for check in self.checks.iter_mut() {
+            check.on_response(&ticket, &response);
+        }
+        Ok(response)
+    }
+
+    /// Everything rejected since the last drain, oldest first
+    pub fn drain_rejects(&mut self) -> Vec<RiskReject> {
+        std::mem::take(&mut self.rejects)
+    }
+}
+
+/// Fat finger guard on a single order's size
+#[derive(Debug)]
+pub struct MaxOrderSize(pub i64);
+
+impl RiskCheck for MaxOrderSize {
+    fn check(&mut self, ticket: &OrderTicket, _book: BookView<'_>, _now: u64) -> Result<()> {
+        if ticket.size > self.0 {
+            return Err(format!(
+                "Size {} is over the limit of {}",
+                ticket.size, self.0
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Limit prices must sit within `bps` of the book's reference price.
+/// Stricter than the book's own band and applied to this source only.
+#[derive(Debug)]
+pub struct PriceCollar {
+    pub bps: i64,
+}
+
+impl RiskCheck for PriceCollar {
+    fn check(&mut self, ticket: &OrderTicket, book: BookView<'_>, _now: u64) -> Result<()> {
+        let (OrderType::Limit(price) | OrderType::ImmediateOrCancel(price), Some(reference)) =
+            (&ticket.order_type, book.reference_price())
+        else {
+            return Ok(());
+        };
+
+        if (price - reference).abs() * 10_000 > reference * self.bps {
+            return Err(format!(
+                "Limit price {} is outside the {}bps collar around {}",
+                price, self.bps, reference
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// At most `max_orders` accepted within any `window`
+#[derive(Debug)]
+pub struct OrderRate {
+    pub max_orders: usize,
+    pub window: u64,
+    sent: VecDeque<u64>,
+}
+
+impl OrderRate {
+    pub fn new(max_orders: usize, window: u64) -> Self {
+        Self {
+            max_orders,
+            window,
+            sent: VecDeque::new(),
+        }
+    }
+}
+
+impl RiskCheck for OrderRate {
+    fn check(&mut self, _ticket: &OrderTicket, _book: BookView<'_>, now: u64) -> Result<()> {
+        while let Some(sent) = self.sent.front()
+            && now.saturating_sub(*sent) >= self.window
+        {
+            self.sent.pop_front();
+        }
+
+        if self.sent.len() >= self.max_orders {
+            return Err(format!(
+                "More than {} orders within {}",
+                self.max_orders, self.window
+            ));
+        }
+        self.sent.push_back(now);
+        Ok(())
+    }
+}
+
+/// Caps the net position built up through executions, counting the
+/// worst case of an order filling in full
+#[derive(Debug)]
+pub struct MaxPosition {
+    pub limit: i64,
+    pub position: i64,
+}
+
+impl MaxPosition {
+    pub fn new(limit: i64) -> Self {
+        Self { limit, position: 0 }
+    }
+}
+
+impl RiskCheck for MaxPosition {
+    fn check(&mut self, ticket: &OrderTicket, _book: BookView<'_>, _now: u64) -> Result<()> {
+        // a quote market order's size is a budget, not a quantity
+        if ticket.order_type == OrderType::QuoteMarket {
+            return Ok(());
+        }
+
+        let after = match ticket.side {
+            Side::Buy => self.position + ticket.size,
+            Side::Sell => self.position - ticket.size,
+        };
+        if after.abs() > self.limit && after.abs() > self.position.abs() {
+            return Err(format!(
+                "Position would reach {} against a limit of {}",
+                after, self.limit
+            ));
+        }
+        Ok(())
+    }
+
+    fn on_response(&mut self, ticket: &OrderTicket, response: &OrderResponse) {
+        if let OrderResponse::Market(market) = response {
+            match ticket.side {
+                Side::Buy => self.position += market.size,
+                Side::Sell => self.position -= market.size,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TimeInForce;
+
+    fn limit(side: Side, price: i64, size: i64) -> OrderTicket {
+        OrderTicket {
+            side,
+            size,
+            order_type: OrderType::Limit(price),
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        }
+    }
+
+    fn market(side: Side, size: i64) -> OrderTicket {
+        OrderTicket {
+            side,
+            size,
+            order_type: OrderType::Market,
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        }
+    }
+
+    #[test]
+    fn first_failing_check_rejects_onto_the_feed() {
+        let mut book = Orderbook::new();
+        // mid of 100
+        book.accept_order(limit(Side::Buy, 98, 50)).unwrap();
+        book.accept_order(limit(Side::Sell, 102, 50)).unwrap();
+
+        let mut gateway = RiskGateway::new();
+        gateway.add_check(MaxOrderSize(20));
+        gateway.add_check(PriceCollar { bps: 500 });
+
+        assert!(
+            gateway
+                .submit(&mut book, limit(Side::Buy, 96, 5), 1)
+                .is_ok()
+        );
+        assert!(
+            gateway
+                .submit(&mut book, limit(Side::Buy, 94, 5), 2)
+                .is_err()
+        );
+        assert!(
+            gateway
+                .submit(&mut book, limit(Side::Buy, 96, 25), 3)
+                .is_err()
+        );
+
+        let rejects = gateway.drain_rejects();
+        assert_eq!(rejects.len(), 2);
+        assert!(rejects[0].reason.contains("collar"));
+        assert!(rejects[1].reason.contains("Size 25"));
+        assert_eq!(rejects[1].at, 3);
+        assert!(gateway.drain_rejects().is_empty());
+
+        // nothing rejected ever reached the book
+        assert_eq!(book.total_liquidity(Side::Buy), 55);
+    }
+
+    #[test]
+    fn rate_and_position_limits() {
+        let mut book = Orderbook::new();
+        book.accept_order(limit(Side::Sell, 100, 50)).unwrap();
+        book.accept_order(limit(Side::Buy, 90, 50)).unwrap();
+
+        let mut gateway = RiskGateway::new();
+        gateway.add_check(OrderRate::new(2, 10));
+        gateway.add_check(MaxPosition::new(8));
+
+        assert!(gateway.submit(&mut book, market(Side::Buy, 5), 0).is_ok());
+        assert!(gateway.submit(&mut book, market(Side::Buy, 5), 1).is_err());
+        // the order blocked on position above still counted towards the rate
+        assert!(gateway.submit(&mut book, market(Side::Buy, 1), 2).is_err());
+        assert!(gateway.submit(&mut book, market(Side::Buy, 3), 10).is_ok());
+        assert!(
+            gateway
+                .submit(&mut book, market(Side::Sell, 20), 20)
+                .is_err()
+        );
+        assert!(gateway.submit(&mut book, market(Side::Sell, 2), 21).is_ok());
+
+        let reasons: Vec<String> = gateway
+            .drain_rejects()
+            .into_iter()
+            .map(|reject| reject.reason)
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                "Position would reach 10 against a limit of 8",
+                "More than 2 orders within 10",
+                "Position would reach -12 against a limit of 8",
+            ]
+        );
+    }
+}
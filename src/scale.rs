@@ -0,0 +1,100 @@
+use crate::{OrderTicket, OrderType, Result, Side, TimeInForce};
+
+/// Sizes are held internally as integers scaled up by `10^decimals`
+/// so that a size of "0.015" with 3 decimals is 15 units. Conversions
+/// go through strings so no float rounding ever sneaks in.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SizeScale {
+    pub decimals: u32,
+}
+
+impl SizeScale {
+    pub fn new(decimals: u32) -> Self {
+        Self { decimals }
+    }
+
+    /// Parse a display size like "1.25" into internal units
+    pub fn to_units(&self, display: &str) -> Result<i64> {
+        let (whole, fraction) = display.split_once('.').unwrap_or((display, ""));
+
+        let is_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+        if whole.is_empty() || !is_digits(whole) || !is_digits(fraction) {
+            return Err(format!("Size {} is not a decimal number", display));
+        }
+        if fraction.len() > self.decimals as usize {
+            return Err(format!(
+                "Size {} has more than {} decimals",
+                display, self.decimals
+            ));
+        }
+
+        let padding = self.decimals as usize - fraction.len();
+        format!("{}{}{}", whole, fraction, "0".repeat(padding))
+            .parse::<i64>()
+            .map_err(|_| format!("Size {} is too large", display))
+    }
+
+    /// Format internal units (sizes, or notionals which carry the same
+    /// scale) back into a display string with every decimal shown
+    pub fn to_display(&self, units: i64) -> String {
+        if self.decimals == 0 {
+            return units.to_string();
+        }
+
+        let sign = if units < 0 { "-" } else { "" };
+        let digits = format!(
+            "{:0>width$}",
+            units.unsigned_abs(),
+            width = self.decimals as usize + 1
+        );
+        let (whole, fraction) = digits.split_at(digits.len() - self.decimals as usize);
+        format!("{}{}.{}", sign, whole, fraction)
+    }
+
+    /// Build a ticket from a display size
+    pub fn ticket(&self, side: Side, order_type: OrderType, size: &str) -> Result<OrderTicket> {
+        Ok(OrderTicket {
+            order_type,
+            size: self.to_units(size)?,
+            side,
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_display_sizes() {
+        let scale = SizeScale::new(3);
+
+        assert_eq!(scale.to_units("1").unwrap(), 1_000);
+        assert_eq!(scale.to_units("0.015").unwrap(), 15);
+        assert_eq!(scale.to_units("12.5").unwrap(), 12_500);
+        assert_eq!(scale.to_units("3.").unwrap(), 3_000);
+
+        assert_eq!(scale.to_display(15), "0.015");
+        assert_eq!(scale.to_display(12_500), "12.500");
+        assert_eq!(scale.to_display(-1), "-0.001");
+        assert_eq!(SizeScale::default().to_display(42), "42");
+    }
+
+    #[test]
+    fn rejects_sizes_it_cannot_represent() {
+        let scale = SizeScale::new(2);
+
+        assert!(scale.to_units("0.001").is_err());
+        assert!(scale.to_units("-1").is_err());
+        assert!(scale.to_units(".5").is_err());
+        assert!(scale.to_units("1.2.3").is_err());
+        assert!(scale.to_units("abc").is_err());
+        assert!(scale.to_units("99999999999999999999").is_err());
+        assert!(SizeScale::default().to_units("1.5").is_err());
+    }
+}
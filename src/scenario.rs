@@ -0,0 +1,292 @@
+use crate::{
+    LimitOrderResponse, MarketOrderResponse, OrderResponse, OrderTicket, OrderType, PriceSize,
+    Result, Side, TimeInForce, book::Orderbook,
+};
+
+/// A matching test case written as text, one step per line. `#` starts a
+/// comment. Orders:
+///
+/// ```text
+/// buy 10 @ 100        limit
+/// sell 4              market
+/// ```
+///
+/// Expectations about the order just before them:
+///
+/// ```text
+/// expect rested 0
+/// expect filled 4 notional 400 remaining 0
+/// expect rejected
+/// ```
+///
+/// And about the book:
+///
+/// ```text
+/// expect bid 100 x 6
+/// expect ask none
+/// expect level sell 101 x 7
+/// expect last 100
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenario {
+    steps: Vec<(usize, Step)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Order(OrderTicket),
+    Expect(Expectation),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expectation {
+    Rested(u64),
+    Filled {
+        size: i64,
+        notional: i64,
+        remaining: i64,
+    },
+    Rejected,
+    Best(Side, Option<PriceSize>),
+    Level(Side, PriceSize),
+    LastTrade(Option<i64>),
+}
+
+impl Scenario {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut steps = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+
+            let step = parse_step(&words).map_err(|e| format!("line {}: {}", index + 1, e))?;
+            steps.push((index + 1, step));
+        }
+
+        Ok(Self { steps })
+    }
+
+    pub fn steps(&self) -> impl Iterator<Item = &Step> {
+        self.steps.iter().map(|(_, step)| step)
+    }
+
+    /// Run against a default book
+    pub fn run(&self) -> Result<()> {
+        self.run_on(&mut Orderbook::new())
+    }
+
+    /// Run against a book configured by the caller, stopping at the first
+    /// expectation that does not hold
+    pub fn run_on(&self, book: &mut Orderbook) -> Result<()> {
+        let mut last_response = None;
+
+        for (line, step) in &self.steps {
+            match step {
+                Step::Order(ticket) => last_response = Some(book.accept_order(ticket.clone())),
+                Step::Expect(expectation) => check(book, last_response.as_ref(), expectation)
+                    .map_err(|e| format!("line {}: {}", line, e))?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_step(words: &[&str]) -> Result<Step> {
+    let number = |index: usize| -> Result<i64> {
+        let word = words.get(index).ok_or("Missing a number")?;
+        word.parse::<i64>()
+            .map_err(|_| format!("{} is not a number", word))
+    };
+    let keyword = |index: usize, expected: &str| -> Result<()> {
+        match words.get(index) {
+            Some(word) if *word == expected => Ok(()),
+            other => Err(format!("Expected {} but got {:?}", expected, other)),
+        }
+    };
+    let side = |word: &str| match word {
+        "buy" | "bid" => Ok(Side::Buy),
+        "sell" | "ask" => Ok(Side::Sell),
+        _ => Err(format!("{} is not a side", word)),
+    };
+
+    match words {
+        ["buy" | "sell", ..] => {
+            let order_type = match words.len() {
+                2 => OrderType::Market,
+                4 => {
+                    keyword(2, "@")?;
+                    OrderType::Limit(number(3)?)
+                }
+                _ => return Err("Orders look like buy <size> [@ <price>]".into()),
+            };
+
+            Ok(Step::Order(OrderTicket {
+                order_type,
+                size: number(1)?,
+                side: side(words[0])?,
+                time_in_force: TimeInForce::Gtc,
+                min_qty: None,
+                owner: 0,
+                iceberg: None,
+            }))
+        }
+        ["expect", "rested", _] => Ok(Step::Expect(Expectation::Rested(
+            u64::try_from(number(2)?).map_err(|_| "Order ids are never negative")?,
+        ))),
+        ["expect", "filled", _, "notional", _, "remaining", _] => {
+            Ok(Step::Expect(Expectation::Filled {
+                size: number(2)?,
+                notional: number(4)?,
+                remaining: number(6)?,
+            }))
+        }
+        ["expect", "rejected"] => Ok(Step::Expect(Expectation::Rejected)),
+        ["expect", "bid" | "ask", "none"] => {
+            Ok(Step::Expect(Expectation::Best(side(words[1])?, None)))
+        }
+        ["expect", "bid" | "ask", _, "x", _] => Ok(Step::Expect(Expectation::Best(
+            side(words[1])?,
+            Some(PriceSize {
+                price: number(2)?,
+                size: number(4)?,
+            }),
+        ))),
+        ["expect", "level", _, _, "x", _] => Ok(Step::Expect(Expectation::Level(
+            side(words[2])?,
+            PriceSize {
+                price: number(3)?,
+                size: number(5)?,
+            },
+        ))),
+        ["expect", "last", "none"] => Ok(Step::Expect(Expectation::LastTrade(None))),
+        ["expect", "last", _] => Ok(Step::Expect(Expectation::LastTrade(Some(number(2)?)))),
+        _ => Err(format!("Cannot understand {:?}", words.join(" "))),
+    }
+}
+
+fn check(
+    book: &Orderbook,
+    last_response: Option<&Result<OrderResponse>>,
+    expectation: &Expectation,
+) -> Result<()> {
+    let mismatch = |expected: &dyn std::fmt::Debug, actual: &dyn std::fmt::Debug| {
+        Err(format!("expected {:?} but got {:?}", expected, actual))
+    };
+
+    match expectation {
+        Expectation::Rested(id) => {
+            let expected = Ok(OrderResponse::Limit(LimitOrderResponse { id: *id }));
+            match last_response {
+                Some(actual) if *actual == expected => Ok(()),
+                actual => mismatch(&expected, &actual),
+            }
+        }
+        Expectation::Filled {
+            size,
+            notional,
+            remaining,
+        } => {
+            // the aggressor's id is whatever the book handed out
+            let id = match last_response {
+                Some(Ok(OrderResponse::Market(market))) => market.id,
+                _ => 0,
+            };
+            let expected = Ok(OrderResponse::Market(MarketOrderResponse {
+                id,
+                notional: *notional,
+                size: *size,
+                remaining: *remaining,
+                resting_id: None,
+            }));
+            match last_response {
+                Some(actual) if *actual == expected => Ok(()),
+                actual => mismatch(&expected, &actual),
+            }
+        }
+        Expectation::Rejected => match last_response {
+            Some(Err(_)) => Ok(()),
+            actual => mismatch(&"a rejection", &actual),
+        },
+        Expectation::Best(side, expected) => {
+            let actual = match side {
+                Side::Buy => book.get_best_bid(),
+                Side::Sell => book.get_best_ask(),
+            };
+            if actual == *expected {
+                Ok(())
+            } else {
+                mismatch(expected, &actual)
+            }
+        }
+        Expectation::Level(side, expected) => {
+            let actual = book.size_at(*side, expected.price);
+            if actual == expected.size {
+                Ok(())
+            } else {
+                mismatch(&expected.size, &actual)
+            }
+        }
+        Expectation::LastTrade(expected) => {
+            if book.last_trade_price == *expected {
+                Ok(())
+            } else {
+                mismatch(expected, &book.last_trade_price)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn price_time_priority_scenario_passes() {
+        let scenario = Scenario::parse(
+            "
+            # two makers at the same price, the older one trades first
+            buy 10 @ 100
+            expect rested 0
+            buy 5 @ 100
+            buy 3 @ 99
+            expect bid 100 x 15
+            expect ask none
+            expect last none
+
+            sell 12
+            expect filled 12 notional 1200 remaining 0
+            expect bid 100 x 3
+            expect level buy 99 x 3
+            expect last 100
+
+            sell 1 @ 0
+            expect rejected
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(scenario.steps().count(), 14);
+        scenario.run().unwrap();
+    }
+
+    #[test]
+    fn failures_point_at_the_line() {
+        let scenario = Scenario::parse("buy 1 @ 100\nexpect bid 100 x 2").unwrap();
+        let error = scenario.run().unwrap_err();
+        assert!(error.starts_with("line 2: "), "{}", error);
+
+        let scenario = Scenario::parse("expect rejected").unwrap();
+        assert!(scenario.run().is_err());
+
+        assert!(Scenario::parse("buy 1 at 100").is_err());
+        assert_eq!(
+            Scenario::parse("\n\nexpect bid lots").unwrap_err(),
+            "line 3: Cannot understand \"expect bid lots\""
+        );
+    }
+}
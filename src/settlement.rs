@@ -0,0 +1,76 @@
+use crate::Trade;
+
+/// What either side of one trade pays, in price times size units.
+/// Negative is a rebate paid out to that side.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TradeFees {
+    pub maker: i64,
+    pub taker: i64,
+}
+
+/// Fees in basis points of each trade's notional, zero by default.
+/// A negative maker rate pays makers a rebate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FeeSchedule {
+    pub maker_bps: i64,
+    pub taker_bps: i64,
+}
+
+impl FeeSchedule {
+    pub fn fees(&self, trade: &Trade) -> TradeFees {
+        let notional = trade.price.saturating_mul(trade.size);
+        TradeFees {
+            maker: notional.saturating_mul(self.maker_bps) / 10_000,
+            taker: notional.saturating_mul(self.taker_bps) / 10_000,
+        }
+    }
+}
+
+/// Told about every trade as the book prints it, with both owners on the
+/// trade and the fees from the book's schedule, e.g. to feed a clearing
+/// system or a ledger
+pub trait PostTradeHook {
+    fn on_trade(&mut self, trade: &Trade, fees: TradeFees);
+}
+
+impl<F: FnMut(&Trade, TradeFees)> PostTradeHook for F {
+    fn on_trade(&mut self, trade: &Trade, fees: TradeFees) {
+        self(trade, fees)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Side;
+
+    #[test]
+    fn fees_are_basis_points_of_notional() {
+        let trade = Trade {
+            trade_id: 0,
+            price: 2_000,
+            size: 50,
+            aggressor_side: Side::Buy,
+            maker_order_id: 0,
+            taker_order_id: 1,
+            maker_owner: 1,
+            taker_owner: 2,
+            timestamp: 0,
+        };
+        assert_eq!(FeeSchedule::default().fees(&trade), TradeFees::default());
+
+        let schedule = FeeSchedule {
+            maker_bps: -2,
+            taker_bps: 5,
+        };
+        assert_eq!(
+            schedule.fees(&trade),
+            TradeFees {
+                maker: -20,
+                taker: 50
+            }
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use crate::{OrderResponse, OrderTicket, Result, Side, book::Orderbook, view::BookView};
+
+/// One of our own orders, queued behind the mirrored market but never
+/// actually placed in it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShadowOrder {
+    pub id: u64,
+    pub side: Side,
+    pub price: i64,
+    pub remaining: i64,
+    /// market size in front of us at our level
+    pub queue_ahead: i64,
+}
+
+/// A fill of a shadow order inferred from trades in the mirror
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShadowFill {
+    pub id: u64,
+    pub side: Side,
+    pub price: i64,
+    pub size: i64,
+}
+
+/// Paper trading against a live feed. The feed is mirrored into a plain
+/// book and our own orders sit alongside it, each one remembering how much
+/// of the level was ahead of it when it joined. Trades at our level eat
+/// that queue first and only then fill us, a print through our price fills
+/// us entirely.
+#[derive(Debug, Default)]
+pub struct ShadowBook {
+    mirror: Orderbook,
+    orders: Vec<ShadowOrder>,
+    next_id: u64,
+}
+
+impl ShadowBook {
+    pub fn new(mirror: Orderbook) -> Self {
+        Self {
+            mirror,
+            ..Self::default()
+        }
+    }
+
+    pub fn mirror(&self) -> BookView<'_> {
+        self.mirror.view()
+    }
+
+    /// Join the back of the queue at `price`
+    pub fn place(&mut self, side: Side, price: i64, size: i64) -> Result<u64> {
+        if size <= 0 {
+            return Err(format!("Size {} must be positive", size));
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.orders.push(ShadowOrder {
+            id,
+            side,
+            price,
+            remaining: size,
+            queue_ahead: self.mirror.size_at(side, price),
+        });
+        Ok(id)
+    }
+
+    pub fn cancel(&mut self, id: u64) -> Result<ShadowOrder> {
+        let index = self
+            .orders
+            .iter()
+            .position(|order| order.id == id)
+            .ok_or_else(|| format!("No shadow order with id {}", id))?;
+        Ok(self.orders.remove(index))
+    }
+
+    pub fn get_order(&self, id: u64) -> Option<&ShadowOrder> {
+        self.orders.iter().find(|order| order.id == id)
+    }
+
+    /// Mirror one market event and report whatever it would have filled
+    pub fn apply(&mut self, ticket: OrderTicket) -> Result<Vec<ShadowFill>> {
+        let before: Vec<i64> = self
+            .orders
+            .iter()
+            .map(|order| self.mirror.size_at(order.side, order.price))
+            .collect();
+
+        let aggressor = ticket.side;
+        let response = self.mirror.accept_order(ticket)?;
+        let traded = matches!(response, OrderResponse::Market(ref m) if m.size > 0);
+
+        let mut fills = Vec::new();
+        if traded {
+            let last_trade_price = self.mirror.last_trade_price;
+            for (order, before) in self.orders.iter_mut().zip(before) {
+                if order.side == aggressor {
+                    continue;
+                }
+
+                let taken = (before - self.mirror.size_at(order.side, order.price)).max(0);
+                let ahead = order.queue_ahead.min(taken);
+                order.queue_ahead -= ahead;
+
+                let traded_through = last_trade_price.is_some_and(|price| match order.side {
+                    Side::Buy => price < order.price,
+                    Side::Sell => price > order.price,
+                });
+                let size = if traded_through {
+                    order.remaining
+                } else {
+                    (taken - ahead).min(order.remaining)
+                };
+
+                if size > 0 {
+                    order.remaining -= size;
+                    fills.push(ShadowFill {
+                        id: order.id,
+                        side: order.side,
+                        price: order.price,
+                        size,
+                    });
+                }
+            }
+            self.orders.retain(|order| order.remaining > 0);
+        }
+
+        // whatever left a level without trading was cancelled, possibly
+        // from in front of us
+        for order in self.orders.iter_mut() {
+            order.queue_ahead = order
+                .queue_ahead
+                .min(self.mirror.size_at(order.side, order.price));
+        }
+
+        Ok(fills)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OrderType, TimeInForce};
+
+    fn limit(side: Side, price: i64, size: i64) -> OrderTicket {
+        OrderTicket {
+            side,
+            size,
+            order_type: OrderType::Limit(price),
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        }
+    }
+
+    fn market(side: Side, size: i64) -> OrderTicket {
+        OrderTicket {
+            side,
+            size,
+            order_type: OrderType::Market,
+            time_in_force: TimeInForce::Gtc,
+            min_qty: None,
+            owner: 0,
+            iceberg: None,
+        }
+    }
+
+    #[test]
+    fn trades_eat_the_queue_ahead_before_filling_us() {
+        let mut shadow = ShadowBook::default();
+        shadow.apply(limit(Side::Buy, 100, 10)).unwrap();
+
+        let id = shadow.place(Side::Buy, 100, 5).unwrap();
+        assert_eq!(shadow.get_order(id).unwrap().queue_ahead, 10);
+        // joining after us does not change our place
+        shadow.apply(limit(Side::Buy, 100, 20)).unwrap();
+        assert_eq!(shadow.get_order(id).unwrap().queue_ahead, 10);
+
+        assert!(shadow.apply(market(Side::Sell, 6)).unwrap().is_empty());
+        assert_eq!(shadow.get_order(id).unwrap().queue_ahead, 4);
+
+        let fills = shadow.apply(market(Side::Sell, 7)).unwrap();
+        assert_eq!(
+            fills,
+            vec![ShadowFill {
+                id,
+                side: Side::Buy,
+                price: 100,
+                size: 3,
+            }]
+        );
+        assert_eq!(shadow.get_order(id).unwrap().remaining, 2);
+        // the mirror itself never saw our order
+        assert_eq!(shadow.mirror().size_at(Side::Buy, 100), 17);
+    }
+
+    #[test]
+    fn a_print_through_our_price_fills_everything() {
+        let mut shadow = ShadowBook::default();
+        shadow.apply(limit(Side::Sell, 101, 10)).unwrap();
+        shadow.apply(limit(Side::Sell, 102, 10)).unwrap();
+
+        let id = shadow.place(Side::Sell, 101, 4).unwrap();
+        let fills = shadow.apply(market(Side::Buy, 12)).unwrap();
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].size, 4);
+        assert!(shadow.get_order(id).is_none());
+        assert!(shadow.cancel(id).is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, VecDeque},
+};
+
+use crate::{
+    DelayedOrder, Event, ExecutionReport, LevelSizes, LevelUpdate, LimitReject, LockedPolicy,
+    MarketDataMode, MarketPolicy, MinRestingTime, MmpLimits, MmpTrigger, OwnerLimits, ParkedPeg,
+    PegBreachAction, PegReject, PeggedOrder, PriceBand, PriceLimits, PriceMoveGuard, PriceSize,
+    ReleasedOrder, RestingOrder, SelfTradePrevention, SessionState, Side, SpeedBump, StatusEvent,
+    Trade, TradeBust, perp::FundingEvent, scale::SizeScale, settlement::FeeSchedule,
+    stop::StopBook, tick::TickTable,
+};
+
+/// One side of the book as a snapshot keeps it. Only populated levels
+/// and their orders are kept, the ladder itself is rebuilt from its
+/// bounds and tick table on restore.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HalfSnapshot {
+    pub side: Side,
+    pub min_price: i64,
+    pub max_price: i64,
+    pub tick_table: TickTable,
+    /// populated levels from the top of book outwards, with when they
+    /// last changed
+    pub levels: Vec<LevelSizes>,
+    /// every resting order in level order and FIFO within each level
+    pub orders: Vec<RestingOrder>,
+    /// fills and cancels of resting orders not yet drained
+    pub reports: Vec<ExecutionReport>,
+    /// (clock, event seq) the next level change is stamped with
+    pub stamp: (u64, u64),
+}
+
+/// Everything needed to pick a book back up where it left off. The quote
+/// cache, depth views, trade and quote stats, order history and crossed
+/// book detector belong to whoever is running the book and are set up
+/// again after restoring.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BookSnapshot {
+    pub bids: HalfSnapshot,
+    pub asks: HalfSnapshot,
+    pub event_log: Vec<Event>,
+    pub events_drained: u64,
+    pub current_id: u64,
+    pub last_trade_price: Option<i64>,
+    /// trades not yet drained when the snapshot was taken
+    pub trades: Vec<Trade>,
+    pub next_trade_id: u64,
+    /// the trades that can still be busted
+    pub recent_trades: VecDeque<Trade>,
+    /// busts not yet drained
+    pub busts: Vec<TradeBust>,
+    /// reports of fired stops not yet drained
+    pub stop_reports: Vec<ExecutionReport>,
+    /// level updates not yet drained
+    pub level_updates: Vec<LevelUpdate>,
+    /// funding payments not yet drained
+    pub funding_events: Vec<FundingEvent>,
+    pub clock: u64,
+    pub stops: StopBook,
+    pub pegs: Vec<PeggedOrder>,
+    pub peg_breach_action: PegBreachAction,
+    pub parked_pegs: Vec<ParkedPeg>,
+    /// pegs cancelled for breaching not yet drained
+    pub peg_rejects: Vec<PegReject>,
+    pub day_end: Option<u64>,
+    pub expiries: BinaryHeap<Reverse<(u64, u64)>>,
+    pub price_band: Option<PriceBand>,
+    pub resumed_at: Option<u64>,
+    pub price_limits: Option<PriceLimits>,
+    pub price_move_guard: Option<PriceMoveGuard>,
+    pub min_resting_time: Option<MinRestingTime>,
+    pub speed_bump: Option<SpeedBump>,
+    pub delayed_orders: Vec<DelayedOrder>,
+    /// delayed orders let through not yet drained
+    pub released_orders: Vec<ReleasedOrder>,
+    pub queued_cancels: BinaryHeap<Reverse<(u64, u64)>>,
+    pub owner_limits: BTreeMap<u64, OwnerLimits>,
+    /// net position of every owner holding one
+    pub positions: BTreeMap<u64, i64>,
+    /// maker rebates accrued this session
+    pub rebates: BTreeMap<u64, i64>,
+    /// owner limit rejects not yet drained
+    pub limit_rejects: Vec<LimitReject>,
+    pub mmp_limits: BTreeMap<u64, MmpLimits>,
+    /// fills of protected owners still within their window
+    pub maker_fills: BTreeMap<u64, VecDeque<(u64, i64)>>,
+    /// protection tripping not yet drained
+    pub mmp_triggers: Vec<MmpTrigger>,
+    pub session_state: SessionState,
+    /// halts and resumes not yet drained
+    pub status_events: Vec<StatusEvent>,
+    pub locked_policy: LockedPolicy,
+    pub market_data_mode: MarketDataMode,
+    pub market_policy: MarketPolicy,
+    pub self_trade_prevention: SelfTradePrevention,
+    pub lot_size: i64,
+    pub symbol: String,
+    pub size_scale: SizeScale,
+    pub round_lot: Option<i64>,
+    pub fee_schedule: FeeSchedule,
+}
+
+/// A resting order that differs between two snapshots. Added orders have
+/// nothing before, removed ones nothing after, and both are set for an
+/// order that was resized or moved.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrderDiff {
+    pub side: Side,
+    pub id: u64,
+    /// price and size including any iceberg reserve
+    pub before: Option<PriceSize>,
+    pub after: Option<PriceSize>,
+}
+
+/// A price level whose sizes or last change differ, None on the side
+/// where it was empty
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LevelDiff {
+    pub side: Side,
+    pub price: i64,
+    pub before: Option<LevelSizes>,
+    pub after: Option<LevelSizes>,
+}
+
+/// What changed on the book between two snapshots, bids before asks and
+/// each by id or price
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SnapshotDiff {
+    pub orders: Vec<OrderDiff>,
+    pub levels: Vec<LevelDiff>,
+}
+
+impl SnapshotDiff {
+    /// True when both snapshots rest the same orders the same way
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty() && self.levels.is_empty()
+    }
+}
+
+impl BookSnapshot {
+    /// Every order and level that differs from `self` to `other`, e.g. to
+    /// reconcile a replica or see where a regression run went its own way
+    pub fn diff(&self, other: &BookSnapshot) -> SnapshotDiff {
+        let mut diff = SnapshotDiff::default();
+        for (before, after) in [(&self.bids, &other.bids), (&self.asks, &other.asks)] {
+            let side = before.side;
+            for (id, (before, after)) in paired(resting_orders(before), resting_orders(after)) {
+                diff.orders.push(OrderDiff {
+                    side,
+                    id,
+                    before,
+                    after,
+                });
+            }
+
+            let levels = |half: &HalfSnapshot| -> BTreeMap<i64, LevelSizes> {
+                half.levels
+                    .iter()
+                    .map(|level| (level.price, *level))
+                    .collect()
+            };
+            for (price, (before, after)) in paired(levels(before), levels(after)) {
+                diff.levels.push(LevelDiff {
+                    side,
+                    price,
+                    before,
+                    after,
+                });
+            }
+        }
+        diff
+    }
+}
+
+fn resting_orders(half: &HalfSnapshot) -> BTreeMap<u64, PriceSize> {
+    half.orders
+        .iter()
+        .map(|order| {
+            let resting = PriceSize {
+                price: order.price,
+                size: order.size + order.reserve,
+            };
+            (order.id, resting)
+        })
+        .collect()
+}
+
+/// a value before and after, None where there was none
+type Change<V> = (Option<V>, Option<V>);
+
+/// Entries whose values differ, with whichever side is missing as None
+fn paired<K: Ord + Copy, V: PartialEq + Copy>(
+    before: BTreeMap<K, V>,
+    after: BTreeMap<K, V>,
+) -> Vec<(K, Change<V>)> {
+    let mut keys: Vec<K> = before.keys().chain(after.keys()).copied().collect();
+    keys.sort_unstable();
+    keys.dedup();
+    keys.into_iter()
+        .map(|key| (key, (before.get(&key).copied(), after.get(&key).copied())))
+        .filter(|(_, (before, after))| before != after)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OrderTicket, book::Orderbook, command_log::decode};
+
+    fn busy_book() -> Orderbook {
+        let mut book = Orderbook::new();
+        book.set_day_end(Some(500));
+        for line in [
+            "B L 100 10",
+            "S L 103 5 D",
+            "B T 104 2",
+            "S P P 1 4",
+            "S M 3",
+        ] {
+            book.accept_order(decode(line).unwrap()).unwrap();
+        }
+        book
+    }
+
+    fn continue_trading(book: &mut Orderbook) {
+        let tickets: Vec<OrderTicket> = ["B M 5", "S L 101 2", "B L 99 1 G 300"]
+            .into_iter()
+            .map(|line| decode(line).unwrap())
+            .collect();
+        for ticket in tickets {
+            book.accept_order(ticket).unwrap();
+        }
+        book.expire(600);
+    }
+
+    #[test]
+    fn restored_books_carry_on_exactly_like_the_original() {
+        let mut live = busy_book();
+        let mut restored = Orderbook::from_snapshot(live.snapshot()).unwrap();
+        assert_eq!(restored.state_digest(), live.state_digest());
+
+        continue_trading(&mut live);
+        continue_trading(&mut restored);
+        assert_eq!(restored.state_digest(), live.state_digest());
+        assert_eq!(restored.drain_trades(), live.drain_trades());
+        assert_eq!(restored.get_best_bid(), live.get_best_bid());
+        assert_eq!(
+            restored.total_liquidity(Side::Sell),
+            live.total_liquidity(Side::Sell)
+        );
+    }
+
+    #[test]
+    fn diff_reports_orders_and_levels_that_changed() {
+        let mut live = busy_book();
+        let before = live.snapshot();
+        assert!(before.diff(&live.snapshot()).is_empty());
+
+        // shrink the bid, pull the offer and add a new bid below
+        live.replace_order(0, 100, 4).unwrap();
+        live.cancel_order(1).unwrap();
+        live.accept_order(decode("B L 98 6").unwrap()).unwrap();
+        let diff = before.diff(&live.snapshot());
+
+        let at = |price, size| Some(PriceSize { price, size });
+        assert_eq!(
+            diff.orders,
+            vec![
+                OrderDiff {
+                    side: Side::Buy,
+                    id: 0,
+                    before: at(100, 7),
+                    after: at(100, 4),
+                },
+                OrderDiff {
+                    side: Side::Buy,
+                    id: 5,
+                    before: None,
+                    after: at(98, 6),
+                },
+                OrderDiff {
+                    side: Side::Sell,
+                    id: 1,
+                    before: at(103, 5),
+                    after: None,
+                },
+            ]
+        );
+        let prices: Vec<(Side, i64)> = diff
+            .levels
+            .iter()
+            .map(|level| (level.side, level.price))
+            .collect();
+        assert_eq!(
+            prices,
+            vec![(Side::Buy, 98), (Side::Buy, 100), (Side::Sell, 103)]
+        );
+        assert_eq!(diff.levels[2].after, None);
+    }
+
+    #[test]
+    fn snapshots_only_keep_what_rests() {
+        let live = busy_book();
+        let snapshot = live.snapshot();
+        let ids: Vec<u64> = snapshot.bids.orders.iter().map(|order| order.id).collect();
+        assert_eq!(ids, vec![0]);
+        assert_eq!(snapshot.asks.levels.len(), 2);
+
+        // an order that can't go back where it was is refused
+        let mut corrupt = live.snapshot();
+        corrupt.asks.orders[0].side = Side::Buy;
+        assert!(Orderbook::from_snapshot(corrupt).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn snapshots_survive_serialization() {
+        let live = busy_book();
+        let json = serde_json::to_string(&live.snapshot()).unwrap();
+        let restored = Orderbook::from_snapshot(serde_json::from_str(&json).unwrap()).unwrap();
+        assert_eq!(restored.state_digest(), live.state_digest());
+        assert_eq!(restored.event_log, live.event_log);
+
+        // an empty ladder costs nothing however wide it is
+        let json = serde_json::to_string(&Orderbook::new().snapshot()).unwrap();
+        assert!(json.len() < 4_096, "{} bytes", json.len());
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use crate::{Side, Trade};
+
+/// Realized volatility over a sliding time window, updated one trade at
+/// a time. Returns are log returns between consecutive trade prices and
+/// the estimate is the square root of their sum of squares, so it is not
+/// annualised. Timestamps are the caller's, in any monotonic unit.
+#[derive(Debug)]
+pub struct RealizedVolatility {
+    window: u64,
+    last_price: Option<i64>,
+    /// (timestamp, log return) of every return still in the window
+    returns: VecDeque<(u64, f64)>,
+    sum_of_squares: f64,
+}
+
+impl RealizedVolatility {
+    pub fn new(window: u64) -> Self {
+        Self {
+            window,
+            last_price: None,
+            returns: VecDeque::new(),
+            sum_of_squares: 0.0,
+        }
+    }
+
+    /// Feed the next trade, returning the log return it produced
+    pub fn record(&mut self, timestamp: u64, price: i64) -> Option<f64> {
+        let previous = self.last_price.replace(price);
+        self.evict(timestamp);
+
+        let log_return = (price as f64 / previous? as f64).ln();
+        self.returns.push_back((timestamp, log_return));
+        self.sum_of_squares += log_return * log_return;
+        Some(log_return)
+    }
+
+    /// Drop returns that have aged out without waiting for a new trade
+    pub fn evict(&mut self, now: u64) {
+        while let Some((timestamp, log_return)) = self.returns.front().copied() {
+            if now.saturating_sub(timestamp) < self.window {
+                break;
+            }
+            self.returns.pop_front();
+            self.sum_of_squares -= log_return * log_return;
+        }
+
+        // running sums drift, an empty window is exactly zero
+        if self.returns.is_empty() {
+            self.sum_of_squares = 0.0;
+        }
+    }
+
+    pub fn window(&self) -> u64 {
+        self.window
+    }
+
+    pub fn returns(&self) -> impl Iterator<Item = f64> + '_ {
+        self.returns.iter().map(|(_, log_return)| *log_return)
+    }
+
+    /// None until the window holds at least one return
+    pub fn volatility(&self) -> Option<f64> {
+        (!self.returns.is_empty()).then(|| self.sum_of_squares.max(0.0).sqrt())
+    }
+}
+
+/// Rolling VWAP and TWAP of executed trades over a sliding time window,
+/// one instance per window length (1m, 5m, ...) in the caller's units
+#[derive(Debug)]
+pub struct RollingAverages {
+    window: u64,
+    /// (timestamp, price, size) of every trade still in the window
+    trades: VecDeque<(u64, i64, i64)>,
+    /// price of the newest trade that aged out, it still holds at the
+    /// start of the window as far as TWAP is concerned
+    carried_price: Option<i64>,
+    notional: i128,
+    volume: i128,
+}
+
+impl RollingAverages {
+    pub fn new(window: u64) -> Self {
+        Self {
+            window,
+            trades: VecDeque::new(),
+            carried_price: None,
+            notional: 0,
+            volume: 0,
+        }
+    }
+
+    pub fn record(&mut self, timestamp: u64, price: i64, size: i64) {
+        self.evict(timestamp);
+        self.trades.push_back((timestamp, price, size));
+        self.notional += price as i128 * size as i128;
+        self.volume += size as i128;
+    }
+
+    pub fn window(&self) -> u64 {
+        self.window
+    }
+
+    pub fn evict(&mut self, now: u64) {
+        while let Some((timestamp, price, size)) = self.trades.front().copied() {
+            if now.saturating_sub(timestamp) < self.window {
+                break;
+            }
+            self.trades.pop_front();
+            self.carried_price = Some(price);
+            self.notional -= price as i128 * size as i128;
+            self.volume -= size as i128;
+        }
+    }
+
+    /// Volume weighted average price of the trades in the window
+    pub fn vwap(&self) -> Option<f64> {
+        (self.volume > 0).then(|| self.notional as f64 / self.volume as f64)
+    }
+
+    /// Time weighted average price over the window ending at `now`, each
+    /// trade price holding until the next trade
+    pub fn twap(&self, now: u64) -> Option<f64> {
+        let start = now.saturating_sub(self.window);
+
+        let mut weighted = 0.0;
+        let mut elapsed = 0;
+        let mut holding = self.carried_price.map(|price| (start, price));
+        for (timestamp, price, _) in &self.trades {
+            if let Some((since, held)) = holding {
+                let duration = timestamp.saturating_sub(since);
+                weighted += held as f64 * duration as f64;
+                elapsed += duration;
+            }
+            holding = Some(((*timestamp).max(start), *price));
+        }
+
+        let (since, held) = holding?;
+        let duration = now.saturating_sub(since);
+        weighted += held as f64 * duration as f64;
+        elapsed += duration;
+
+        // every trade landed on `now`, nothing has had time to weigh in
+        if elapsed == 0 {
+            return Some(held as f64);
+        }
+        Some(weighted / elapsed as f64)
+    }
+}
+
+/// Volatility, VWAP and TWAP at several window lengths, fed every trade
+/// the book prints, see `Orderbook::enable_trade_stats`
+#[derive(Debug)]
+pub struct TradeStats {
+    volatility: Vec<RealizedVolatility>,
+    averages: Vec<RollingAverages>,
+}
+
+impl TradeStats {
+    pub fn new(windows: &[u64]) -> Self {
+        Self {
+            volatility: windows
+                .iter()
+                .map(|window| RealizedVolatility::new(*window))
+                .collect(),
+            averages: windows
+                .iter()
+                .map(|window| RollingAverages::new(*window))
+                .collect(),
+        }
+    }
+
+    pub fn record(&mut self, trade: &Trade) {
+        for volatility in &mut self.volatility {
+            volatility.record(trade.timestamp, trade.price);
+        }
+        for averages in &mut self.averages {
+            averages.record(trade.timestamp, trade.price, trade.size);
+        }
+    }
+
+    pub fn evict(&mut self, now: u64) {
+        for volatility in &mut self.volatility {
+            volatility.evict(now);
+        }
+        for averages in &mut self.averages {
+            averages.evict(now);
+        }
+    }
+
+    /// The estimator for one of the windows it was set up with
+    pub fn volatility(&self, window: u64) -> Option<&RealizedVolatility> {
+        self.volatility
+            .iter()
+            .find(|volatility| volatility.window() == window)
+    }
+
+    pub fn averages(&self, window: u64) -> Option<&RollingAverages> {
+        self.averages
+            .iter()
+            .find(|averages| averages.window() == window)
+    }
+}
+
+/// What happened at one price level
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct LevelChurn {
+    /// orders that came to rest there, moved and requeued ones included
+    pub adds: u64,
+    /// orders that left without filling in full, moved ones included
+    pub cancels: u64,
+    /// fills against resting orders, partial ones included
+    pub fills: u64,
+}
+
+/// How long quotes lasted, from resting until they left
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct QuoteLife {
+    pub quotes: u64,
+    /// summed over every quote, in the book's clock
+    pub total: u64,
+}
+
+impl QuoteLife {
+    pub fn average(&self) -> Option<f64> {
+        (self.quotes > 0).then(|| self.total as f64 / self.quotes as f64)
+    }
+}
+
+/// Add and cancel churn per price level and how long quotes last, fed
+/// every order that rests on or leaves the book, see
+/// `Orderbook::enable_quote_stats`. Repricing a peg counts as a cancel
+/// at the old level and an add at the new one without ending its life,
+/// a replacement that requeues under a new id ends the old quote's.
+#[derive(Debug, Default)]
+pub struct QuoteStats {
+    bids: BTreeMap<i64, LevelChurn>,
+    asks: BTreeMap<i64, LevelChurn>,
+    /// when each resting order arrived, orders resting before the stats
+    /// were enabled count towards churn only
+    entered: HashMap<u64, u64>,
+    cancelled: QuoteLife,
+    filled: QuoteLife,
+}
+
+impl QuoteStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_add(&mut self, id: u64, side: Side, price: i64, now: u64) {
+        self.level_mut(side, price).adds += 1;
+        self.entered.insert(id, now);
+    }
+
+    pub fn record_cancel(&mut self, id: u64, side: Side, price: i64, now: u64) {
+        self.level_mut(side, price).cancels += 1;
+        if let Some(entered) = self.entered.remove(&id) {
+            self.cancelled.quotes += 1;
+            self.cancelled.total += now.saturating_sub(entered);
+        }
+    }
+
+    /// A fill against a resting order, `done` once nothing is left of it
+    pub fn record_fill(&mut self, id: u64, side: Side, price: i64, now: u64, done: bool) {
+        self.level_mut(side, price).fills += 1;
+        if done && let Some(entered) = self.entered.remove(&id) {
+            self.filled.quotes += 1;
+            self.filled.total += now.saturating_sub(entered);
+        }
+    }
+
+    /// A resting order moved to another level under the same id
+    pub fn record_move(&mut self, side: Side, from: i64, to: i64) {
+        self.level_mut(side, from).cancels += 1;
+        self.level_mut(side, to).adds += 1;
+    }
+
+    pub fn level(&self, side: Side, price: i64) -> LevelChurn {
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        levels.get(&price).copied().unwrap_or_default()
+    }
+
+    /// Every level with any churn on one side, lowest price first
+    pub fn levels(&self, side: Side) -> impl Iterator<Item = (i64, LevelChurn)> + '_ {
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        levels.iter().map(|(price, churn)| (*price, *churn))
+    }
+
+    /// Lifetimes of the quotes that were cancelled
+    pub fn cancelled_life(&self) -> QuoteLife {
+        self.cancelled
+    }
+
+    /// Lifetimes of the quotes that filled in full
+    pub fn filled_life(&self) -> QuoteLife {
+        self.filled
+    }
+
+    fn level_mut(&mut self, side: Side, price: i64) -> &mut LevelChurn {
+        let levels = match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        levels.entry(price).or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-12
+    }
+
+    #[test]
+    fn realized_volatility_from_log_returns() {
+        let mut volatility = RealizedVolatility::new(1_000);
+        assert_eq!(volatility.record(0, 100), None);
+        assert_eq!(volatility.volatility(), None);
+
+        let up = volatility.record(10, 110).unwrap();
+        let down = volatility.record(20, 99).unwrap();
+        assert!(close(up, (1.1f64).ln()));
+        assert!(close(down, (0.9f64).ln()));
+        assert!(close(
+            volatility.volatility().unwrap(),
+            (up * up + down * down).sqrt()
+        ));
+
+        // a flat trade adds a zero return
+        volatility.record(30, 99);
+        assert_eq!(volatility.returns().count(), 3);
+    }
+
+    #[test]
+    fn windows_slide_independently() {
+        let mut short = RealizedVolatility::new(10);
+        let mut long = RealizedVolatility::new(100);
+        for (timestamp, price) in [(0, 100), (5, 120), (50, 121)] {
+            short.record(timestamp, price);
+            long.record(timestamp, price);
+        }
+
+        let last = (121.0f64 / 120.0).ln();
+        assert!(close(short.volatility().unwrap(), last.abs()));
+        assert!(long.volatility().unwrap() > short.volatility().unwrap());
+
+        short.evict(1_000);
+        assert_eq!(short.volatility(), None);
+        assert_eq!(long.returns().count(), 2);
+    }
+
+    #[test]
+    fn rolling_vwap_and_twap() {
+        let mut averages = RollingAverages::new(100);
+        assert_eq!(averages.vwap(), None);
+        assert_eq!(averages.twap(0), None);
+
+        averages.record(0, 100, 1);
+        averages.record(50, 110, 3);
+        assert!(close(averages.vwap().unwrap(), 107.5));
+        // 100 for 50 ticks then 110 for 50
+        assert!(close(averages.twap(100).unwrap(), 105.0));
+
+        // the first trade ages out of VWAP but its price still covers
+        // the start of the TWAP window until the next trade
+        averages.record(120, 120, 1);
+        assert!(close(averages.vwap().unwrap(), 112.5));
+        assert!(close(
+            averages.twap(120).unwrap(),
+            (100.0 * 30.0 + 110.0 * 70.0) / 100.0
+        ));
+
+        averages.evict(1_000);
+        assert_eq!(averages.vwap(), None);
+        assert!(close(averages.twap(1_000).unwrap(), 120.0));
+    }
+}
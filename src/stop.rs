@@ -0,0 +1,90 @@
+use crate::{Side, digest::StateDigest};
+
+/// A market order waiting for the last trade to reach its trigger
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StopOrder {
+    pub id: u64,
+    pub side: Side,
+    pub trigger: i64,
+    pub size: i64,
+    /// owner of the market order it becomes
+    pub owner: u64,
+}
+
+impl StopOrder {
+    /// Buy stops fire at or above the trigger, sell stops at or below
+    pub fn is_triggered(&self, last_trade_price: i64) -> bool {
+        match self.side {
+            Side::Buy => last_trade_price >= self.trigger,
+            Side::Sell => last_trade_price <= self.trigger,
+        }
+    }
+}
+
+/// Stops kept out of the book until they fire, in the order they arrived
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StopBook {
+    orders: Vec<StopOrder>,
+}
+
+impl StopBook {
+    pub fn insert(&mut self, order: StopOrder) {
+        self.orders.push(order);
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<StopOrder> {
+        let index = self.orders.iter().position(|order| order.id == id)?;
+        Some(self.orders.remove(index))
+    }
+
+    pub fn get_order(&self, id: u64) -> Option<&StopOrder> {
+        self.orders.iter().find(|order| order.id == id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    /// How many of the waiting stops belong to `owner`
+    pub fn owned_by(&self, owner: u64) -> usize {
+        self.orders
+            .iter()
+            .filter(|order| order.owner == owner)
+            .count()
+    }
+
+    /// Take out every stop the last trade has reached, oldest first
+    pub fn take_triggered(&mut self, last_trade_price: i64) -> Vec<StopOrder> {
+        let (triggered, waiting) = std::mem::take(&mut self.orders)
+            .into_iter()
+            .partition(|order| order.is_triggered(last_trade_price));
+        self.orders = waiting;
+        triggered
+    }
+
+    /// Writes nothing while empty so books without stops digest as before
+    pub fn digest(&self, digest: &mut StateDigest) {
+        if self.orders.is_empty() {
+            return;
+        }
+        digest.write_u64(self.orders.len() as u64);
+        for order in self.orders.iter() {
+            digest.write_u64(order.id);
+            digest.write_u64(match order.side {
+                Side::Buy => 0,
+                Side::Sell => 1,
+            });
+            digest.write_i64(order.trigger);
+            digest.write_i64(order.size);
+            if order.owner != 0 {
+                digest.write_u64(order.owner);
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+
+use crate::Trade;
+
+/// A trade whose maker and taker are in the same account group
+#[derive(Debug, Clone, PartialEq)]
+pub struct WashTradeAlert {
+    pub group: u64,
+    pub trade: Trade,
+}
+
+/// Flags wash trades, where the maker and taker of a trade are the same
+/// owner or in the same account group. Self-trade prevention only stops
+/// an owner trading with itself, and not at all under
+/// `SelfTradePrevention::Allow`, so this catches what gets through.
+/// Anonymous orders are never flagged. Feed it the book's trades as they
+/// are drained.
+#[derive(Debug, Default)]
+pub struct WashTradeMonitor {
+    /// account group of each grouped owner, anyone else is a group of
+    /// their own
+    groups: BTreeMap<u64, u64>,
+    alerts: Vec<WashTradeAlert>,
+}
+
+impl WashTradeMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Put `owner` in account `group`, None to take them out again
+    pub fn set_group(&mut self, owner: u64, group: Option<u64>) {
+        match group {
+            Some(group) => self.groups.insert(owner, group),
+            None => self.groups.remove(&owner),
+        };
+    }
+
+    /// The group `owner` trades under, the owner itself when ungrouped
+    pub fn group_of(&self, owner: u64) -> u64 {
+        self.groups.get(&owner).copied().unwrap_or(owner)
+    }
+
+    /// Check trades as they come off the book, returning how many were
+    /// flagged
+    pub fn record(&mut self, trades: &[Trade]) -> usize {
+        let before = self.alerts.len();
+        for trade in trades {
+            if trade.maker_owner == 0 || trade.taker_owner == 0 {
+                continue;
+            }
+            let group = self.group_of(trade.maker_owner);
+            if group == self.group_of(trade.taker_owner) {
+                self.alerts.push(WashTradeAlert {
+                    group,
+                    trade: trade.clone(),
+                });
+            }
+        }
+        self.alerts.len() - before
+    }
+
+    /// Every alert so far, oldest first
+    pub fn alerts(&self) -> &[WashTradeAlert] {
+        &self.alerts
+    }
+
+    pub fn alerts_for_group(&self, group: u64) -> impl Iterator<Item = &WashTradeAlert> {
+        self.alerts.iter().filter(move |alert| alert.group == group)
+    }
+
+    /// Alerts on trades printed from `from` up to but not including `to`
+    /// on the book's clock
+    pub fn alerts_between(&self, from: u64, to: u64) -> impl Iterator<Item = &WashTradeAlert> {
+        self.alerts
+            .iter()
+            .filter(move |alert| (from..to).contains(&alert.trade.timestamp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OrderTicket, SelfTradePrevention, book::Orderbook, command_log::decode};
+
+    fn send(book: &mut Orderbook, owner: u64, line: &str) {
+        let ticket = OrderTicket {
+            owner,
+            ..decode(line).unwrap()
+        };
+        book.accept_order(ticket).unwrap();
+    }
+
+    #[test]
+    fn flags_trades_within_an_account_group() {
+        let mut book = Orderbook::new();
+        book.self_trade_prevention = SelfTradePrevention::Allow;
+        let mut monitor = WashTradeMonitor::new();
+        monitor.set_group(8, Some(100));
+        monitor.set_group(9, Some(100));
+
+        book.set_clock(10);
+        send(&mut book, 7, "S L 101 5");
+        send(&mut book, 7, "B M 1");
+        book.set_clock(20);
+        send(&mut book, 8, "B M 1");
+        send(&mut book, 0, "B M 1");
+        send(&mut book, 8, "S L 101 5");
+        send(&mut book, 9, "B M 3");
+        assert_eq!(monitor.record(&book.drain_trades()), 2);
+
+        // owner 7 traded with everyone but only once with itself
+        let alerts = monitor.alerts();
+        assert_eq!(alerts[0].group, 7);
+        assert_eq!(alerts[0].trade.timestamp, 10);
+        assert_eq!(alerts[1].group, 100);
+        assert_eq!(
+            (alerts[1].trade.maker_owner, alerts[1].trade.taker_owner),
+            (8, 9)
+        );
+        assert_eq!(monitor.alerts_for_group(100).count(), 1);
+        assert_eq!(monitor.alerts_between(15, 30).count(), 1);
+    }
+}
@@ -0,0 +1,305 @@
+use crate::Result;
+
+/// Every price from `from_price` up to the next band moves in `tick_size` steps
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TickBand {
+    pub from_price: i64,
+    pub tick_size: i64,
+}
+
+/// Exchange-style tick table, e.g. 1 below 1000 and 5 above.
+/// Maps valid prices onto a dense ladder index and back.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TickTable {
+    bands: Vec<TickBand>,
+    /// ladder index of the first price in each band
+    offsets: Vec<usize>,
+}
+
+impl TickTable {
+    /// Bands must be ascending and each boundary must land on a tick
+    /// of the band below it so the ladder has no gaps
+    pub fn new(bands: Vec<TickBand>) -> Result<Self> {
+        if bands.is_empty() {
+            return Err("A tick table needs at least one band".into());
+        }
+
+        let mut offsets = Vec::with_capacity(bands.len());
+        offsets.push(0);
+
+        for (i, band) in bands.iter().enumerate() {
+            if band.tick_size <= 0 {
+                return Err(format!("Tick size {} must be positive", band.tick_size));
+            }
+
+            if i == 0 {
+                continue;
+            }
+
+            let prev = bands[i - 1];
+            let span = band.from_price - prev.from_price;
+            if span <= 0 {
+                return Err(format!(
+                    "Tick band at {} is not above the band at {}",
+                    band.from_price, prev.from_price
+                ));
+            }
+            if span % prev.tick_size != 0 {
+                return Err(format!(
+                    "Tick band at {} is not on a tick of the band at {}",
+                    band.from_price, prev.from_price
+                ));
+            }
+
+            offsets.push(offsets[i - 1] + (span / prev.tick_size) as usize);
+        }
+
+        Ok(Self { bands, offsets })
+    }
+
+    /// A single tick size for every price
+    pub fn fixed(min_price: i64, tick_size: i64) -> Self {
+        Self {
+            bands: vec![TickBand {
+                from_price: min_price,
+                tick_size,
+            }],
+            offsets: vec![0],
+        }
+    }
+
+    pub fn min_price(&self) -> i64 {
+        self.bands[0].from_price
+    }
+
+    pub fn bands(&self) -> &[TickBand] {
+        &self.bands
+    }
+
+    /// The band a price falls in, None when below the table
+    fn band_of(&self, price: i64) -> Option<usize> {
+        self.bands
+            .partition_point(|band| band.from_price <= price)
+            .checked_sub(1)
+    }
+
+    pub fn tick_size_at(&self, price: i64) -> Option<i64> {
+        self.band_of(price).map(|band| self.bands[band].tick_size)
+    }
+
+    pub fn is_valid_price(&self, price: i64) -> bool {
+        self.band_of(price)
+            .map(|band| (price - self.bands[band].from_price) % self.bands[band].tick_size == 0)
+            .unwrap_or_default()
+    }
+
+    /// A positive price under the table on a tick of its lowest band,
+    /// one `extend_down` can take the table to
+    pub fn is_valid_below(&self, price: i64) -> bool {
+        let TickBand {
+            from_price,
+            tick_size,
+        } = self.bands[0];
+        price > 0 && price < from_price && (from_price - price) % tick_size == 0
+    }
+
+    /// Start the lowest band at `price` instead, returning how many
+    /// indexes every price already on the table moved up by
+    pub fn extend_down(&mut self, price: i64) -> Result<usize> {
+        if !self.is_valid_below(price) {
+            return Err(format!(
+                "Price {} is not a tick below {}",
+                price,
+                self.min_price()
+            ));
+        }
+
+        let added = ((self.bands[0].from_price - price) / self.bands[0].tick_size) as usize;
+        self.bands[0].from_price = price;
+        for offset in self.offsets.iter_mut().skip(1) {
+            *offset += added;
+        }
+        Ok(added)
+    }
+
+    /// The nearest valid price at or below `price`, carrying on with the
+    /// lowest band's ticks under the table
+    pub fn tick_at_or_below(&self, price: i64) -> i64 {
+        match self.band_of(price) {
+            Some(band) => {
+                let TickBand {
+                    from_price,
+                    tick_size,
+                } = self.bands[band];
+                from_price + (price - from_price) / tick_size * tick_size
+            }
+            None => {
+                let TickBand {
+                    from_price,
+                    tick_size,
+                } = self.bands[0];
+                let ticks = (from_price - price + tick_size - 1) / tick_size;
+                from_price - ticks * tick_size
+            }
+        }
+    }
+
+    /// The nearest valid price at or above `price`. Band boundaries sit on
+    /// a tick of the band below, so one tick up never skips a price.
+    pub fn tick_at_or_above(&self, price: i64) -> i64 {
+        let below = self.tick_at_or_below(price);
+        if below == price {
+            return price;
+        }
+        below + self.tick_size_at(below).unwrap_or(self.bands[0].tick_size)
+    }
+
+    /// Ladder index of a price, rounding down to the tick below
+    pub fn index_of(&self, price: i64) -> Option<usize> {
+        let band = self.band_of(price)?;
+        let TickBand {
+            from_price,
+            tick_size,
+        } = self.bands[band];
+        Some(self.offsets[band] + ((price - from_price) / tick_size) as usize)
+    }
+
+    pub fn price_of(&self, index: usize) -> i64 {
+        let band = self.offsets.partition_point(|offset| *offset <= index) - 1;
+        let TickBand {
+            from_price,
+            tick_size,
+        } = self.bands[band];
+        from_price + (index - self.offsets[band]) as i64 * tick_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_to_the_nearest_tick_either_way() {
+        let table = table();
+        assert_eq!(table.tick_at_or_below(103), 100);
+        assert_eq!(table.tick_at_or_above(103), 105);
+        assert_eq!(table.tick_at_or_above(99), 99);
+        assert_eq!(table.tick_at_or_above(100), 100);
+
+        // under the table the lowest band carries on
+        let table = TickTable::fixed(10, 4);
+        assert_eq!(table.tick_at_or_below(7), 6);
+        assert_eq!(table.tick_at_or_above(7), 10);
+        assert_eq!(table.tick_at_or_below(6), 6);
+    }
+
+    fn table() -> TickTable {
+        TickTable::new(vec![
+            TickBand {
+                from_price: 1,
+                tick_size: 1,
+            },
+            TickBand {
+                from_price: 100,
+                tick_size: 5,
+            },
+            TickBand {
+                from_price: 1_000,
+                tick_size: 10,
+            },
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn indexes_are_dense_across_bands() {
+        let table = table();
+
+        assert_eq!(table.index_of(1), Some(0));
+        assert_eq!(table.index_of(99), Some(98));
+        assert_eq!(table.index_of(100), Some(99));
+        assert_eq!(table.index_of(105), Some(100));
+        assert_eq!(table.index_of(995), Some(278));
+        assert_eq!(table.index_of(1_000), Some(279));
+        assert_eq!(table.index_of(1_010), Some(280));
+        assert_eq!(table.index_of(0), None);
+
+        for price in [1, 50, 99, 100, 105, 995, 1_000, 1_010, 5_000] {
+            assert_eq!(table.price_of(table.index_of(price).unwrap()), price);
+        }
+    }
+
+    #[test]
+    fn prices_must_sit_on_their_band_tick() {
+        let table = table();
+
+        assert!(table.is_valid_price(99));
+        assert!(table.is_valid_price(105));
+        assert!(!table.is_valid_price(101));
+        assert!(!table.is_valid_price(1_005));
+        assert!(!table.is_valid_price(0));
+
+        assert_eq!(table.tick_size_at(50), Some(1));
+        assert_eq!(table.tick_size_at(150), Some(5));
+        assert_eq!(table.tick_size_at(0), None);
+    }
+
+    #[test]
+    fn extending_down_shifts_every_index() {
+        let mut table = table();
+        assert!(!table.is_valid_below(0));
+        assert!(table.extend_down(1).is_err());
+        assert_eq!(table.index_of(100), Some(99));
+
+        let mut fives = TickTable::new(vec![
+            TickBand {
+                from_price: 100,
+                tick_size: 5,
+            },
+            TickBand {
+                from_price: 200,
+                tick_size: 10,
+            },
+        ])
+        .unwrap();
+        assert!(!fives.is_valid_below(97));
+        assert!(fives.extend_down(97).is_err());
+        assert_eq!(fives.extend_down(90), Ok(2));
+        assert_eq!(fives.min_price(), 90);
+        assert_eq!(fives.index_of(90), Some(0));
+        assert_eq!(fives.index_of(100), Some(2));
+        assert_eq!(fives.index_of(200), Some(22));
+        assert_eq!(fives.price_of(23), 210);
+    }
+
+    #[test]
+    fn malformed_tables_are_rejected() {
+        assert!(TickTable::new(vec![]).is_err());
+
+        let misaligned = TickTable::new(vec![
+            TickBand {
+                from_price: 1,
+                tick_size: 5,
+            },
+            TickBand {
+                from_price: 100,
+                tick_size: 10,
+            },
+        ]);
+        assert!(misaligned.is_err());
+
+        let descending = TickTable::new(vec![
+            TickBand {
+                from_price: 100,
+                tick_size: 1,
+            },
+            TickBand {
+                from_price: 1,
+                tick_size: 1,
+            },
+        ]);
+        assert!(descending.is_err());
+    }
+}
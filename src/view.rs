@@ -0,0 +1,127 @@
+use crate::{L3Book, LevelSizes, PriceSize, SessionState, Side, book::Orderbook};
+
+/// Read-only access to a book. Hand one of these to callbacks such as
+/// strategies and risk checks instead of the book itself, they can ask
+/// anything but have no way to reach the halves or change state.
+#[derive(Debug, Clone, Copy)]
+pub struct BookView<'a> {
+    book: &'a Orderbook,
+}
+
+impl<'a> From<&'a Orderbook> for BookView<'a> {
+    fn from(book: &'a Orderbook) -> Self {
+        Self { book }
+    }
+}
+
+impl BookView<'_> {
+    pub fn get_best_bid(&self) -> Option<PriceSize> {
+        self.book.get_best_bid()
+    }
+
+    pub fn get_best_ask(&self) -> Option<PriceSize> {
+        self.book.get_best_ask()
+    }
+
+    pub fn displayed_best_bid(&self) -> Option<PriceSize> {
+        self.book.displayed_best_bid()
+    }
+
+    pub fn displayed_best_ask(&self) -> Option<PriceSize> {
+        self.book.displayed_best_ask()
+    }
+
+    pub fn depth(&self, side: Side, levels: usize) -> Vec<PriceSize> {
+        self.book.depth(side, levels)
+    }
+
+    pub fn top_of_book_sizes(&self, side: Side) -> Option<LevelSizes> {
+        self.book.top_of_book_sizes(side)
+    }
+
+    pub fn depth_sizes(&self, side: Side, levels: usize) -> Vec<LevelSizes> {
+        self.book.depth_sizes(side, levels)
+    }
+
+    pub fn full_l3(&self) -> L3Book {
+        self.book.full_l3()
+    }
+
+    pub fn displayed_depth(&self, side: Side, levels: usize) -> Vec<PriceSize> {
+        self.book.displayed_depth(side, levels)
+    }
+
+    pub fn bucketed_depth(&self, side: Side, ticks: usize, buckets: usize) -> Vec<PriceSize> {
+        self.book.bucketed_depth(side, ticks, buckets)
+    }
+
+    pub fn size_at(&self, side: Side, price: i64) -> i64 {
+        self.book.size_at(side, price)
+    }
+
+    pub fn total_liquidity(&self, side: Side) -> i64 {
+        self.book.total_liquidity(side)
+    }
+
+    pub fn get_order(&self, id: u64) -> Option<(Side, PriceSize)> {
+        self.book.get_order(id)
+    }
+
+    pub fn last_trade_price(&self) -> Option<i64> {
+        self.book.last_trade_price
+    }
+
+    pub fn reference_price(&self) -> Option<i64> {
+        self.book.reference_price()
+    }
+
+    pub fn price_limit_band(&self) -> Option<(i64, i64)> {
+        self.book.price_limit_band()
+    }
+
+    pub fn session_state(&self) -> SessionState {
+        self.book.session_state
+    }
+
+    pub fn state_digest(&self) -> u64 {
+        self.book.state_digest()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{OrderTicket, OrderType, PriceSize, Side, TimeInForce, book::Orderbook};
+
+    #[test]
+    fn view_answers_like_the_book() {
+        let mut book = Orderbook::new();
+        let id = match book
+            .accept_order(OrderTicket {
+                side: Side::Buy,
+                size: 10,
+                order_type: OrderType::Limit(100),
+                time_in_force: TimeInForce::Gtc,
+                min_qty: None,
+                owner: 0,
+                iceberg: None,
+            })
+            .unwrap()
+        {
+            crate::OrderResponse::Limit(limit) => limit.id,
+            other => panic!("expected a resting order, got {:?}", other),
+        };
+
+        let view = book.view();
+        assert_eq!(
+            view.get_best_bid(),
+            Some(PriceSize {
+                price: 100,
+                size: 10
+            })
+        );
+        assert_eq!(view.get_best_ask(), None);
+        assert_eq!(view.size_at(Side::Buy, 100), 10);
+        assert_eq!(view.get_order(id), book.get_order(id));
+        assert_eq!(view.state_digest(), book.state_digest());
+    }
+}